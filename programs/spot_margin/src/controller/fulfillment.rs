@@ -0,0 +1,979 @@
+//! Venues the protocol's orders are filled on. A market's `fulfillment_type` selects its venue, and `SpotFulfillment`
+//! gives the order controller the same view of either venue: its book, in the venue's lots and ticks, and the orders
+//! the protocol places on it. Orders rest on the venue's book under the account that placed them, the signer orders of
+//! the trait, and are identified by a price in ticks and a sequence number, which the order stores while it is resting.
+//! On Phoenix each user places orders under their own trader PDA, so the signer orders are the user's own and orders of
+//! different users trade on the book. On OpenBook V2 all orders rest under the protocol's signer, which cannot tell the
+//! users apart. Orders that take are sent with the venue's DecrementTake self-trade behavior rather than aborting on a
+//! match with a signer order. Such a match only releases the funds locked in the signer order, and the order controller
+//! settles it between the two users itself.
+
+use anchor_lang::prelude::*;
+use anchor_spl::token;
+use phoenix::{
+    program::{
+        new_order::{
+            CondensedOrder,
+            FailedMultipleLimitOrderBehavior,
+            MultipleOrderPacket
+        },
+        CancelOrderParams
+    },
+    quantities::{
+        BaseLots,
+        QuoteLots,
+        Ticks,
+        WrapperU64
+    },
+    state::{
+        OrderPacket,
+        SelfTradeBehavior,
+        Side
+    }
+};
+use std::collections::{
+    BTreeMap,
+    BTreeSet
+};
+
+use crate::{
+    controller::{
+        openbook_v2::{
+            invoke_openbook_v2_cancel_order,
+            invoke_openbook_v2_place_order,
+            invoke_openbook_v2_settle_funds,
+            validate_openbook_v2_accounts
+        },
+        phoenix::{
+            invoke_phoenix_cancel_orders,
+            invoke_phoenix_place_limit_order,
+            invoke_phoenix_place_multiple_post_only_orders,
+            invoke_phoenix_reduce_order,
+            invoke_phoenix_withdraw_funds,
+            validate_phoenix_accounts,
+            PhoenixSigners
+        }
+    },
+    error::{
+        SpedXSpotResult,
+        ErrorCode
+    },
+    math::{
+        casting::Cast,
+        safe_math::SafeMath
+    },
+    state::{
+        config::State,
+        enums::{
+            PositionDirection,
+            PostOnlyTypes,
+            SpotFulfillmentType
+        },
+        market::Market,
+        openbook_v2::{
+            get_openbook_v2_fill_quote_asset_amount,
+            get_openbook_v2_fixed_orders,
+            get_openbook_v2_open_orders_atoms,
+            get_openbook_v2_order_id,
+            get_openbook_v2_price_lots,
+            get_openbook_v2_quote_lots,
+            get_openbook_v2_resting_order_base_lots,
+            get_price_from_openbook_v2_price_lots,
+            get_quote_asset_amount_from_openbook_v2_atoms,
+            load_openbook_v2_market,
+            load_openbook_v2_open_orders_account,
+            OpenBookV2Market,
+            OpenBookV2MarketAccounts,
+            OpenBookV2PlaceOrderArgs,
+            OpenBookV2PlaceOrderType,
+            OpenBookV2RestingOrder,
+            OpenBookV2SelfTradeBehavior,
+            OpenBookV2Side
+        },
+        order::{
+            Order,
+            OrderParams
+        },
+        phoenix::{
+            find_phoenix_resting_order,
+            get_next_phoenix_order_sequence_number,
+            get_phoenix_best_opposite_price_in_ticks,
+            get_phoenix_crossing_orders,
+            get_phoenix_resting_order_base_lots,
+            get_phoenix_signer_atoms,
+            get_phoenix_trader_address,
+            PhoenixMarketAccounts,
+            PhoenixMarketConverter
+        }
+    },
+    validate
+};
+
+/// Maximum number of resting orders an OpenBook V2 order matches against
+const OPENBOOK_V2_MATCH_LIMIT: u8 = 16;
+
+/// Part of an order that was filled on the venue, the quote including the venue's taker fee
+/// base asset amount precision: token mint precision, quote asset amount precision: QUOTE_PRECISION
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct ExternalSpotFill {
+    pub base_asset_amount: u64,
+    pub quote_asset_amount: u64
+}
+
+/// Outcome of placing an order on the venue's book
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct ExternalSpotPlacement {
+    /// Sequence number the venue assigned to the order, which together with its price identifies it on the book
+    pub order_sequence_number: u64,
+
+    /// Base lots of the order left resting on the book, the rest was filled immediately
+    pub resting_base_lots: u64,
+
+    /// Part of the order that was filled immediately, measured from the change of the signer's funds. Matches with the
+    /// signer's own orders move no funds and are not part of it.
+    pub fill: ExternalSpotFill
+}
+
+/// An order resting on the venue's book that an incoming order would match
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExternalCrossingOrder {
+    pub price_in_ticks: u64,
+    pub order_sequence_number: u64,
+    pub num_base_lots: u64,
+    /// Whether the order was placed by the account the fulfillment places orders under: the user's trader PDA on
+    /// Phoenix, the protocol's signer on OpenBook V2
+    pub is_signer: bool
+}
+
+/// A post-only quote of a batch placed on the venue's book
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExternalQuote {
+    pub side: Side,
+    pub price_in_ticks: u64,
+    pub num_base_lots: u64
+}
+
+pub trait SpotFulfillment {
+    fn get_fulfillment_type(&self) -> SpotFulfillmentType;
+
+    /// Checks that the venue supports the order
+    fn validate_order_params(&self, _params: &OrderParams) -> SpedXSpotResult {
+        Ok(())
+    }
+
+    /// Converts a base asset amount to base lots of the venue, rounding down to the nearest lot
+    /// base asset amount precision: token mint precision
+    fn base_atoms_to_lots(&self, base_atoms: u64) -> SpedXSpotResult<u64>;
+
+    /// Converts base lots of the venue to a base asset amount
+    /// base asset amount precision: token mint precision
+    fn base_lots_to_atoms(&self, num_base_lots: u64) -> SpedXSpotResult<u64>;
+
+    /// Rounds a base asset amount down to whole lots of the venue
+    /// precision: token mint precision
+    fn get_standardized_base_asset_amount(&self, base_asset_amount: u64) -> SpedXSpotResult<u64> {
+        self.base_lots_to_atoms(self.base_atoms_to_lots(base_asset_amount)?)
+    }
+
+    /// Converts a quote asset amount to quote lots of the venue. The quote the user pays rounds up and the quote the user
+    /// receives rounds down.
+    /// quote asset amount precision: QUOTE_PRECISION
+    fn quote_amount_to_lots(&self, quote_asset_amount: u64, direction: PositionDirection) -> SpedXSpotResult<u64>;
+
+    /// Converts a limit price to ticks of the venue. Bids round down and asks round up to the nearest tick.
+    /// price precision: PRICE_PRECISION
+    fn price_to_ticks(&self, price: u64, direction: PositionDirection) -> SpedXSpotResult<u64>;
+
+    /// Converts ticks of the venue to a limit price. Bids round down and asks round up to the nearest price unit.
+    /// price precision: PRICE_PRECISION
+    fn ticks_to_price(&self, price_in_ticks: u64, direction: PositionDirection) -> SpedXSpotResult<u64>;
+
+    /// Value of a fill of the given base asset amount at a price in ticks. The quote the user pays rounds up and the
+    /// quote the user receives rounds down.
+    /// base asset amount precision: token mint precision, quote asset amount precision: QUOTE_PRECISION
+    fn fill_quote_amount(&self, base_atoms: u64, price_in_ticks: u64, direction: PositionDirection) -> SpedXSpotResult<u64>;
+
+    /// Returns the best price on the opposite side of the book for an order on the given side, None if that side of the
+    /// book is empty
+    fn get_best_opposite_price_in_ticks(&self, side: Side) -> SpedXSpotResult<Option<u64>>;
+
+    /// Returns true if an order at the given price would cross the opposite side of the book
+    fn does_order_cross(&self, side: Side, price_in_ticks: u64) -> SpedXSpotResult<bool> {
+        Ok(match (side, self.get_best_opposite_price_in_ticks(side)?) {
+            (Side::Bid, Some(best_ask)) => best_ask <= price_in_ticks,
+            (Side::Ask, Some(best_bid)) => best_bid >= price_in_ticks,
+            (_, None) => false
+        })
+    }
+
+    /// Returns the price a sliding post-only order rests at: its own price if it does not cross the book, otherwise one
+    /// tick behind the best price on the opposite side
+    fn get_post_only_slide_price_in_ticks(&self, side: Side, price_in_ticks: u64) -> SpedXSpotResult<u64> {
+        match (side, self.get_best_opposite_price_in_ticks(side)?) {
+            (Side::Bid, Some(best_ask)) if best_ask <= price_in_ticks => best_ask.safe_sub(1),
+            (Side::Ask, Some(best_bid)) if best_bid >= price_in_ticks => best_bid.safe_add(1),
+            _ => Ok(price_in_ticks)
+        }
+    }
+
+    /// Returns the resting orders an order on the given side would match up to its limit price, in the order the venue
+    /// matches them: best price first, then oldest first. Without a limit price, the whole opposite side is returned.
+    fn get_crossing_orders(&self, side: Side, limit_price_in_ticks: Option<u64>) -> SpedXSpotResult<Vec<ExternalCrossingOrder>>;
+
+    /// Returns the base lots still resting on the book for a resting order, 0 if it is no longer on the book
+    fn get_resting_order_base_lots(&self, order: &Order) -> SpedXSpotResult<u64>;
+
+    /// Places an order on the book at the given price. The part that crosses the book is filled immediately, matches
+    /// with the signer's own orders trading without moving funds, and the rest rests on the book. The fill is measured
+    /// as in fill_order. Post-only orders are
+    /// rejected if they would cross.
+    fn place_order(&self, order: &Order, side: Side, price_in_ticks: u64, num_base_lots: u64) -> SpedXSpotResult<ExternalSpotPlacement>;
+
+    /// Fills an order against the book up to the given price, or through the whole book without one, and leaves nothing
+    /// resting. Fails if the fill is below the minimums. The fill is measured from the change of the signer's funds and
+    /// excludes matches with the signer's own orders, which move no funds, and so do the minimums.
+    fn fill_order(
+        &self,
+        order: &Order,
+        side: Side,
+        price_in_ticks: Option<u64>,
+        num_base_lots: u64,
+        min_base_lots_to_fill: u64,
+        min_quote_lots_to_fill: u64
+    ) -> SpedXSpotResult<ExternalSpotFill>;
+
+    /// Places a batch of post-only quotes. A quote that would cross the book rests at the closest price that does not
+    /// cross it. Returns the price and sequence number each quote rests under, None for a quote that was not placed.
+    fn place_quotes(&self, quotes: &[ExternalQuote]) -> SpedXSpotResult<Vec<Option<(u64, u64)>>>;
+
+    /// Reduces a resting order by the given base lots without a fill. Returns the price and sequence number the rest of
+    /// the order rests under, None if nothing of it is left on the book.
+    fn reduce_order(&self, order: &Order, num_base_lots: u64) -> SpedXSpotResult<Option<(u64, u64)>>;
+
+    /// Takes a resting order off the book
+    fn cancel_order(&self, order: &Order) -> SpedXSpotResult;
+
+    /// Moves the funds the signer holds on the venue, i.e fills of its resting orders, to the signer's token accounts
+    fn settle_funds(&self) -> SpedXSpotResult;
+}
+
+/// Accounts of the venue that fills a market's orders, passed as remaining accounts after the market's secondary oracle
+pub enum SpotFulfillmentAccounts<'info> {
+    PhoenixV1(Box<PhoenixMarketAccounts<'info>>),
+    OpenBookV2(Box<OpenBookV2MarketAccounts<'info>>)
+}
+
+impl<'info> SpotFulfillmentAccounts<'info> {
+    /// Loads the accounts of the market's venue from the remaining accounts of an instruction. The secondary oracle, if
+    /// the market has one, comes first and is skipped.
+    pub fn load(
+        market: &Market,
+        remaining_accounts: &[AccountInfo<'info>]
+    ) -> SpedXSpotResult<Self> {
+        let mut accounts = if market.has_secondary_oracle() {
+            remaining_accounts.get(1..).unwrap_or_default()
+        } else {
+            remaining_accounts
+        };
+
+        match market.fulfillment_type {
+            SpotFulfillmentType::PhoenixV1 => Ok(SpotFulfillmentAccounts::PhoenixV1(Box::new(
+                PhoenixMarketAccounts::try_accounts(&crate::ID, &mut accounts, &[], &mut BTreeMap::new(), &mut BTreeSet::new())
+                    .map_err(|error| {
+                        msg!("Invalid Phoenix accounts: {:?}", error);
+                        ErrorCode::InvalidPhoenixMarket
+                    })?
+            ))),
+            SpotFulfillmentType::OpenBookV2 => Ok(SpotFulfillmentAccounts::OpenBookV2(Box::new(
+                OpenBookV2MarketAccounts::try_accounts(&crate::ID, &mut accounts, &[], &mut BTreeMap::new(), &mut BTreeSet::new())
+                    .map_err(|error| {
+                        msg!("Invalid OpenBook V2 accounts: {:?}", error);
+                        ErrorCode::InvalidOpenBookV2Market
+                    })?
+            )))
+        }
+    }
+
+    /// Returns the fulfillment of the user's orders in the market through these accounts, after checking that they
+    /// belong to the market, to the protocol's signer and, on Phoenix, to the user's trader PDA
+    pub fn get_fulfillment<'a>(
+        &'a self,
+        state: &State,
+        market: &Market,
+        user: &Pubkey
+    ) -> SpedXSpotResult<Box<dyn SpotFulfillment + 'a>> {
+        Ok(match self {
+            SpotFulfillmentAccounts::PhoenixV1(phoenix_accounts) => Box::new(PhoenixFulfillment::new(state, market, user, phoenix_accounts)?),
+            SpotFulfillmentAccounts::OpenBookV2(openbook_v2_accounts) => Box::new(OpenBookV2Fulfillment::new(state, market, openbook_v2_accounts)?)
+        })
+    }
+}
+
+/// Returns the direction of an order on the given side
+fn get_side_direction(side: Side) -> PositionDirection {
+    match side {
+        Side::Bid => PositionDirection::Long,
+        Side::Ask => PositionDirection::Short
+    }
+}
+
+/// Returns the base and quote atoms filled by an order on the given side from the signer's funds before and after
+fn get_atoms_filled(
+    side: Side,
+    (base_atoms_before, quote_atoms_before): (u64, u64),
+    (base_atoms_after, quote_atoms_after): (u64, u64)
+) -> SpedXSpotResult<(u64, u64)> {
+    Ok(match side {
+        Side::Bid => (base_atoms_after.safe_sub(base_atoms_before)?, quote_atoms_before.safe_sub(quote_atoms_after)?),
+        Side::Ask => (base_atoms_before.safe_sub(base_atoms_after)?, quote_atoms_after.safe_sub(quote_atoms_before)?)
+    })
+}
+
+/// Fills a user's orders on the market's Phoenix market. The orders are placed under the user's trader PDA, so the
+/// signer orders of the trait are the user's own orders.
+pub struct PhoenixFulfillment<'a, 'info> {
+    phoenix_accounts: &'a PhoenixMarketAccounts<'info>,
+    converter: PhoenixMarketConverter,
+    signers: PhoenixSigners
+}
+
+impl<'a, 'info> PhoenixFulfillment<'a, 'info> {
+    pub fn new(
+        state: &State,
+        market: &Market,
+        user: &Pubkey,
+        phoenix_accounts: &'a PhoenixMarketAccounts<'info>
+    ) -> SpedXSpotResult<Self> {
+        let (trader, trader_bump) = get_phoenix_trader_address(user);
+
+        let market_header = validate_phoenix_accounts(state, market, &trader, phoenix_accounts)?;
+
+        Ok(PhoenixFulfillment {
+            phoenix_accounts,
+            converter: PhoenixMarketConverter::new(&market_header)?,
+            signers: PhoenixSigners {
+                user: *user,
+                trader_bump,
+                signer_nonce: state.signer_nonce
+            }
+        })
+    }
+
+    /// Returns the fill of an order on the given side from the signer's funds before and after it was sent
+    fn get_fill(
+        &self,
+        side: Side,
+        signer_atoms_before: (u64, u64),
+        signer_atoms_after: (u64, u64)
+    ) -> SpedXSpotResult<ExternalSpotFill> {
+        let (base_atoms_filled, quote_atoms_filled) = get_atoms_filled(side, signer_atoms_before, signer_atoms_after)?;
+
+        Ok(ExternalSpotFill {
+            base_asset_amount: base_atoms_filled,
+            quote_asset_amount: self.converter.quote_atoms_to_amount(quote_atoms_filled, get_side_direction(side))?
+        })
+    }
+}
+
+impl<'a, 'info> SpotFulfillment for PhoenixFulfillment<'a, 'info> {
+    fn get_fulfillment_type(&self) -> SpotFulfillmentType {
+        SpotFulfillmentType::PhoenixV1
+    }
+
+    fn base_atoms_to_lots(&self, base_atoms: u64) -> SpedXSpotResult<u64> {
+        self.converter.base_atoms_to_lots(base_atoms)
+    }
+
+    fn base_lots_to_atoms(&self, num_base_lots: u64) -> SpedXSpotResult<u64> {
+        self.converter.base_lots_to_atoms(num_base_lots)
+    }
+
+    fn quote_amount_to_lots(&self, quote_asset_amount: u64, direction: PositionDirection) -> SpedXSpotResult<u64> {
+        self.converter.quote_amount_to_lots(quote_asset_amount, direction)
+    }
+
+    fn price_to_ticks(&self, price: u64, direction: PositionDirection) -> SpedXSpotResult<u64> {
+        self.converter.price_to_ticks(price, direction)
+    }
+
+    fn ticks_to_price(&self, price_in_ticks: u64, direction: PositionDirection) -> SpedXSpotResult<u64> {
+        self.converter.ticks_to_price(price_in_ticks, direction)
+    }
+
+    fn fill_quote_amount(&self, base_atoms: u64, price_in_ticks: u64, direction: PositionDirection) -> SpedXSpotResult<u64> {
+        self.converter.fill_quote_amount(base_atoms, price_in_ticks, direction)
+    }
+
+    fn get_best_opposite_price_in_ticks(&self, side: Side) -> SpedXSpotResult<Option<u64>> {
+        get_phoenix_best_opposite_price_in_ticks(&self.phoenix_accounts.phoenix_market, side)
+    }
+
+    fn get_crossing_orders(&self, side: Side, limit_price_in_ticks: Option<u64>) -> SpedXSpotResult<Vec<ExternalCrossingOrder>> {
+        Ok(get_phoenix_crossing_orders(
+            &self.phoenix_accounts.phoenix_market,
+            side,
+            limit_price_in_ticks,
+            &self.phoenix_accounts.trader.key()
+        )?
+        .into_iter()
+        .map(|crossing_order| ExternalCrossingOrder {
+            price_in_ticks: crossing_order.price_in_ticks,
+            order_sequence_number: crossing_order.order_sequence_number,
+            num_base_lots: crossing_order.num_base_lots,
+            is_signer: crossing_order.is_trader
+        })
+        .collect())
+    }
+
+    fn get_resting_order_base_lots(&self, order: &Order) -> SpedXSpotResult<u64> {
+        get_phoenix_resting_order_base_lots(
+            &self.phoenix_accounts.phoenix_market,
+            order.get_phoenix_side(),
+            order.phoenix_price_in_ticks,
+            order.phoenix_order_sequence_number
+        )
+    }
+
+    fn place_order(&self, order: &Order, side: Side, price_in_ticks: u64, num_base_lots: u64) -> SpedXSpotResult<ExternalSpotPlacement> {
+        // Phoenix assigns the market's current sequence number to the order, which together with the price identifies it
+        let order_sequence_number = get_next_phoenix_order_sequence_number(&self.phoenix_accounts.phoenix_market, side)?;
+
+        let signer_atoms_before = get_phoenix_signer_atoms(self.phoenix_accounts, &self.converter)?;
+
+        invoke_phoenix_place_limit_order(
+            self.phoenix_accounts,
+            &get_phoenix_order_packet(order, side, price_in_ticks, num_base_lots),
+            &self.signers
+        )?;
+
+        let signer_atoms_after = get_phoenix_signer_atoms(self.phoenix_accounts, &self.converter)?;
+
+        let resting_base_lots = get_phoenix_resting_order_base_lots(
+            &self.phoenix_accounts.phoenix_market,
+            side,
+            price_in_ticks,
+            order_sequence_number
+        )?;
+
+        Ok(ExternalSpotPlacement {
+            order_sequence_number,
+            resting_base_lots,
+            fill: self.get_fill(side, signer_atoms_before, signer_atoms_after)?
+        })
+    }
+
+    fn fill_order(
+        &self,
+        order: &Order,
+        side: Side,
+        price_in_ticks: Option<u64>,
+        num_base_lots: u64,
+        min_base_lots_to_fill: u64,
+        min_quote_lots_to_fill: u64
+    ) -> SpedXSpotResult<ExternalSpotFill> {
+        let order_packet = OrderPacket::ImmediateOrCancel {
+            side,
+            price_in_ticks: price_in_ticks.map(Ticks::new),
+            num_base_lots: BaseLots::new(num_base_lots),
+            num_quote_lots: QuoteLots::new(0),
+            min_base_lots_to_fill: BaseLots::new(min_base_lots_to_fill),
+            min_quote_lots_to_fill: QuoteLots::new(min_quote_lots_to_fill),
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            match_limit: None,
+            client_order_id: order.order_id as u128,
+            use_only_deposited_funds: false,
+            last_valid_slot: None,
+            last_valid_unix_timestamp_in_seconds: None
+        };
+
+        let signer_atoms_before = get_phoenix_signer_atoms(self.phoenix_accounts, &self.converter)?;
+
+        invoke_phoenix_place_limit_order(self.phoenix_accounts, &order_packet, &self.signers)?;
+
+        let signer_atoms_after = get_phoenix_signer_atoms(self.phoenix_accounts, &self.converter)?;
+
+        self.get_fill(side, signer_atoms_before, signer_atoms_after)
+    }
+
+    fn place_quotes(&self, quotes: &[ExternalQuote]) -> SpedXSpotResult<Vec<Option<(u64, u64)>>> {
+        // Phoenix merges the quotes of a side that share a price into a single order
+        for side in [Side::Bid, Side::Ask] {
+            let mut prices_in_ticks: Vec<u64> = quotes
+                .iter()
+                .filter(|quote| quote.side == side)
+                .map(|quote| quote.price_in_ticks)
+                .collect();
+
+            let number_of_quotes = prices_in_ticks.len();
+            prices_in_ticks.sort_unstable();
+            prices_in_ticks.dedup();
+
+            validate!(
+                prices_in_ticks.len() == number_of_quotes,
+                ErrorCode::InvalidOrderPrice,
+                "Quotes on the same side cannot share a price"
+            )?;
+        }
+
+        // Phoenix places the bids and then the asks, each side from the lowest price up, every quote taking the next
+        // sequence number
+        let mut placement_order: Vec<usize> = (0..quotes.len()).collect();
+        placement_order.sort_by_key(|quote_index| (quotes[*quote_index].side == Side::Ask, quotes[*quote_index].price_in_ticks));
+
+        let first_order_sequence_number = get_next_phoenix_order_sequence_number(&self.phoenix_accounts.phoenix_market, Side::Ask)?;
+
+        let get_condensed_orders = |side: Side| -> Vec<CondensedOrder> {
+            quotes
+                .iter()
+                .filter(|quote| quote.side == side)
+                .map(|quote| CondensedOrder::new_default(quote.price_in_ticks, quote.num_base_lots))
+                .collect()
+        };
+
+        // a quote that would cross the book is moved to the closest price that does not instead of failing the batch
+        let multiple_order_packet = MultipleOrderPacket::new_with_failure_behavior(
+            get_condensed_orders(Side::Bid),
+            get_condensed_orders(Side::Ask),
+            None,
+            FailedMultipleLimitOrderBehavior::FailOnInsufficientFundsAndAmendOnCross
+        );
+
+        invoke_phoenix_place_multiple_post_only_orders(self.phoenix_accounts, &multiple_order_packet, &self.signers)?;
+
+        let mut resting_order_ids = vec![None; quotes.len()];
+
+        for (number, quote_index) in placement_order.into_iter().enumerate() {
+            let side = quotes[quote_index].side;
+
+            let order_sequence_number = first_order_sequence_number.safe_add(number as u64)?;
+            let order_sequence_number = match side {
+                Side::Bid => !order_sequence_number,
+                Side::Ask => order_sequence_number
+            };
+
+            // post-only quotes cannot fill when they are placed, a quote that is not on the book was not placed
+            resting_order_ids[quote_index] = find_phoenix_resting_order(&self.phoenix_accounts.phoenix_market, side, order_sequence_number)?
+                .map(|(price_in_ticks, _)| (price_in_ticks, order_sequence_number));
+        }
+
+        Ok(resting_order_ids)
+    }
+
+    fn reduce_order(&self, order: &Order, num_base_lots: u64) -> SpedXSpotResult<Option<(u64, u64)>> {
+        invoke_phoenix_reduce_order(
+            self.phoenix_accounts,
+            CancelOrderParams {
+                side: order.get_phoenix_side(),
+                price_in_ticks: order.phoenix_price_in_ticks,
+                order_sequence_number: order.phoenix_order_sequence_number
+            },
+            num_base_lots,
+            &self.signers
+        )?;
+
+        // Phoenix reduces the order in place, keeping its place in the queue
+        Ok((self.get_resting_order_base_lots(order)? > 0)
+            .then_some((order.phoenix_price_in_ticks, order.phoenix_order_sequence_number)))
+    }
+
+    fn cancel_order(&self, order: &Order) -> SpedXSpotResult {
+        invoke_phoenix_cancel_orders(
+            self.phoenix_accounts,
+            vec![CancelOrderParams {
+                side: order.get_phoenix_side(),
+                price_in_ticks: order.phoenix_price_in_ticks,
+                order_sequence_number: order.phoenix_order_sequence_number
+            }],
+            &self.signers
+        )
+    }
+
+    fn settle_funds(&self) -> SpedXSpotResult {
+        invoke_phoenix_withdraw_funds(self.phoenix_accounts, &self.signers)
+    }
+}
+
+/// Builds the Phoenix order packet for an order resting on the book. Orders are identified on Phoenix by the client
+/// order id, which is the order's id.
+fn get_phoenix_order_packet(
+    order: &Order,
+    side: Side,
+    price_in_ticks: u64,
+    num_base_lots: u64
+) -> OrderPacket {
+    if order.is_post_only() {
+        OrderPacket::PostOnly {
+            side,
+            price_in_ticks: Ticks::new(price_in_ticks),
+            num_base_lots: BaseLots::new(num_base_lots),
+            client_order_id: order.order_id as u128,
+            reject_post_only: order.post_only != PostOnlyTypes::PostOnlySlide,
+            use_only_deposited_funds: false,
+            last_valid_slot: None,
+            last_valid_unix_timestamp_in_seconds: None,
+            fail_silently_on_insufficient_funds: order.fail_silently_on_insufficient_funds_error
+        }
+    } else {
+        OrderPacket::Limit {
+            side,
+            price_in_ticks: Ticks::new(price_in_ticks),
+            num_base_lots: BaseLots::new(num_base_lots),
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            match_limit: None,
+            client_order_id: order.order_id as u128,
+            use_only_deposited_funds: false,
+            last_valid_slot: None,
+            last_valid_unix_timestamp_in_seconds: None,
+            fail_silently_on_insufficient_funds: order.fail_silently_on_insufficient_funds_error
+        }
+    }
+}
+
+/// Fills the market's orders on its OpenBook V2 market. Ticks are OpenBook V2 price lots and an order's sequence number
+/// is the lower half of its OpenBook V2 order id. The signer's funds are settled after every order placed or cancelled,
+/// so that they do not sit in its open orders account. Fills of resting orders are only credited to the open orders
+/// account once OpenBook V2's crank has consumed them, they are moved by settling the funds again.
+pub struct OpenBookV2Fulfillment<'a, 'info> {
+    openbook_v2_accounts: &'a OpenBookV2MarketAccounts<'info>,
+    openbook_v2_market: OpenBookV2Market,
+    signer_nonce: u8
+}
+
+impl<'a, 'info> OpenBookV2Fulfillment<'a, 'info> {
+    pub fn new(
+        state: &State,
+        market: &Market,
+        openbook_v2_accounts: &'a OpenBookV2MarketAccounts<'info>
+    ) -> SpedXSpotResult<Self> {
+        validate!(
+            market.fulfillment_type == SpotFulfillmentType::OpenBookV2,
+            ErrorCode::InvalidFulfillmentType,
+            "Market {} is not fulfilled by OpenBook V2",
+            market.market_index
+        )?;
+
+        let openbook_v2_market = validate_openbook_v2_accounts(state, market, openbook_v2_accounts)?;
+
+        let openbook_v2_fulfillment = OpenBookV2Fulfillment {
+            openbook_v2_accounts,
+            openbook_v2_market,
+            signer_nonce: state.signer_nonce
+        };
+
+        // checks that the open orders account belongs to the signer and the OpenBook V2 market
+        openbook_v2_fulfillment.get_signer_atoms()?;
+
+        Ok(openbook_v2_fulfillment)
+    }
+
+    /// Returns the base and quote atoms held by the protocol's signer: its token accounts plus the free and locked funds
+    /// of its open orders account. Funds locked in or released from resting orders do not change it, only trades do.
+    fn get_signer_atoms(&self) -> SpedXSpotResult<(u64, u64)> {
+        let open_orders_account = load_openbook_v2_open_orders_account(
+            &self.openbook_v2_accounts.open_orders_account,
+            &self.openbook_v2_accounts.signer.key(),
+            &self.openbook_v2_accounts.openbook_v2_market.key()
+        )?;
+
+        let (base_atoms_on_market, quote_atoms_on_market) =
+            get_openbook_v2_open_orders_atoms(&open_orders_account, &self.openbook_v2_market)?;
+
+        let base_token_amount = token::accessor::amount(&self.openbook_v2_accounts.base_account.to_account_info())
+            .map_err(|_| ErrorCode::OpenBookV2CpiFailed)?;
+        let quote_token_amount = token::accessor::amount(&self.openbook_v2_accounts.quote_account.to_account_info())
+            .map_err(|_| ErrorCode::OpenBookV2CpiFailed)?;
+
+        Ok((
+            base_token_amount.safe_add(base_atoms_on_market)?,
+            quote_token_amount.safe_add(quote_atoms_on_market)?
+        ))
+    }
+
+    /// Returns the book side account orders on the given side rest on
+    fn get_book_side(&self, side: Side) -> &AccountInfo<'info> {
+        match side {
+            Side::Bid => &self.openbook_v2_accounts.bids,
+            Side::Ask => &self.openbook_v2_accounts.asks
+        }
+    }
+
+    /// Returns the fixed price orders OpenBook V2 would match on a side of its book, i.e the ones that have not expired.
+    /// Oracle pegged orders are not considered, the protocol does not place any.
+    fn get_valid_orders(&self, side: Side) -> SpedXSpotResult<Vec<OpenBookV2RestingOrder>> {
+        let now = Clock::get().map_err(|_| ErrorCode::OpenBookV2CpiFailed)?.unix_timestamp.cast::<u64>()?;
+
+        Ok(get_openbook_v2_fixed_orders(self.get_book_side(side))?
+            .into_iter()
+            .filter(|resting_order| !resting_order.is_expired(now))
+            .collect())
+    }
+
+    /// Places an order on OpenBook V2 and settles the signer's funds. Returns the sequence number of the order, i.e the
+    /// lower half of its order id, the base lots left resting and the fill measured from the signer's funds.
+    fn invoke_place_order(
+        &self,
+        order_type: OpenBookV2PlaceOrderType,
+        side: Side,
+        price_lots: u64,
+        num_base_lots: u64,
+        client_order_id: u32
+    ) -> SpedXSpotResult<ExternalSpotPlacement> {
+        let openbook_v2_side = get_openbook_v2_side(side);
+
+        let args = OpenBookV2PlaceOrderArgs {
+            side: openbook_v2_side,
+            price_lots: price_lots.cast()?,
+            max_base_lots: num_base_lots.cast()?,
+            max_quote_lots_including_fees: i64::MAX,
+            client_order_id: client_order_id.cast()?,
+            order_type,
+            expiry_timestamp: 0,
+            self_trade_behavior: OpenBookV2SelfTradeBehavior::DecrementTake,
+            limit: OPENBOOK_V2_MATCH_LIMIT
+        };
+
+        // OpenBook V2 assigns the market's current sequence number to the order, which together with the price forms
+        // its order id
+        let seq_num = load_openbook_v2_market(&self.openbook_v2_accounts.openbook_v2_market)?.seq_num;
+
+        let signer_atoms_before = self.get_signer_atoms()?;
+
+        invoke_openbook_v2_place_order(self.openbook_v2_accounts, &args, self.signer_nonce)?;
+        invoke_openbook_v2_settle_funds(self.openbook_v2_accounts, self.signer_nonce)?;
+
+        let signer_atoms_after = self.get_signer_atoms()?;
+
+        let (base_atoms_filled, quote_atoms_filled) = get_atoms_filled(side, signer_atoms_before, signer_atoms_after)?;
+
+        let order_id = get_openbook_v2_order_id(openbook_v2_side, price_lots, seq_num);
+
+        Ok(ExternalSpotPlacement {
+            order_sequence_number: order_id as u64,
+            resting_base_lots: get_openbook_v2_resting_order_base_lots(self.get_book_side(side), order_id)?,
+            fill: ExternalSpotFill {
+                base_asset_amount: base_atoms_filled,
+                quote_asset_amount: get_quote_asset_amount_from_openbook_v2_atoms(
+                    quote_atoms_filled,
+                    get_side_direction(side),
+                    &self.openbook_v2_market
+                )?
+            }
+        })
+    }
+}
+
+/// Returns the side of an order on the OpenBook V2 book
+fn get_openbook_v2_side(side: Side) -> OpenBookV2Side {
+    match side {
+        Side::Bid => OpenBookV2Side::Bid,
+        Side::Ask => OpenBookV2Side::Ask
+    }
+}
+
+/// Returns the OpenBook V2 order id of an order resting on the book
+fn get_resting_openbook_v2_order_id(order: &Order) -> u128 {
+    ((order.phoenix_price_in_ticks as u128) << 64) | order.phoenix_order_sequence_number as u128
+}
+
+impl<'a, 'info> SpotFulfillment for OpenBookV2Fulfillment<'a, 'info> {
+    fn get_fulfillment_type(&self) -> SpotFulfillmentType {
+        SpotFulfillmentType::OpenBookV2
+    }
+
+    fn validate_order_params(&self, params: &OrderParams) -> SpedXSpotResult {
+        // the signer's own orders leave the book without moving funds, so OpenBook V2 cannot enforce a minimum fill
+        // that excludes them
+        validate!(
+            params.min_base_asset_amount_to_fill.is_none() && params.min_quote_asset_amount_to_fill.is_none(),
+            ErrorCode::OrderTypeNotSupported,
+            "Minimum fills are not supported on OpenBook V2 markets"
+        )
+    }
+
+    fn base_atoms_to_lots(&self, base_atoms: u64) -> SpedXSpotResult<u64> {
+        base_atoms.safe_div(self.openbook_v2_market.base_lot_size)
+    }
+
+    fn base_lots_to_atoms(&self, num_base_lots: u64) -> SpedXSpotResult<u64> {
+        num_base_lots.safe_mul(self.openbook_v2_market.base_lot_size)
+    }
+
+    fn quote_amount_to_lots(&self, quote_asset_amount: u64, direction: PositionDirection) -> SpedXSpotResult<u64> {
+        get_openbook_v2_quote_lots(quote_asset_amount, direction, &self.openbook_v2_market)
+    }
+
+    fn price_to_ticks(&self, price: u64, direction: PositionDirection) -> SpedXSpotResult<u64> {
+        get_openbook_v2_price_lots(price, direction, &self.openbook_v2_market)
+    }
+
+    fn ticks_to_price(&self, price_in_ticks: u64, direction: PositionDirection) -> SpedXSpotResult<u64> {
+        get_price_from_openbook_v2_price_lots(price_in_ticks, direction, &self.openbook_v2_market)
+    }
+
+    fn fill_quote_amount(&self, base_atoms: u64, price_in_ticks: u64, direction: PositionDirection) -> SpedXSpotResult<u64> {
+        get_openbook_v2_fill_quote_asset_amount(base_atoms, price_in_ticks, direction, &self.openbook_v2_market)
+    }
+
+    fn get_best_opposite_price_in_ticks(&self, side: Side) -> SpedXSpotResult<Option<u64>> {
+        let opposite_prices = self.get_valid_orders(side.opposite())?
+            .into_iter()
+            .map(|resting_order| (resting_order.order_id >> 64) as u64);
+
+        Ok(match side {
+            Side::Bid => opposite_prices.min(),
+            Side::Ask => opposite_prices.max()
+        })
+    }
+
+    fn get_crossing_orders(&self, side: Side, limit_price_in_ticks: Option<u64>) -> SpedXSpotResult<Vec<ExternalCrossingOrder>> {
+        let open_orders_account = self.openbook_v2_accounts.open_orders_account.key();
+
+        let mut crossing_orders: Vec<ExternalCrossingOrder> = self.get_valid_orders(side.opposite())?
+            .into_iter()
+            .map(|resting_order| ExternalCrossingOrder {
+                price_in_ticks: (resting_order.order_id >> 64) as u64,
+                order_sequence_number: resting_order.order_id as u64,
+                num_base_lots: resting_order.base_lots,
+                is_signer: resting_order.owner == open_orders_account
+            })
+            .filter(|crossing_order| match (side, limit_price_in_ticks) {
+                (Side::Bid, Some(limit_price_in_ticks)) => crossing_order.price_in_ticks <= limit_price_in_ticks,
+                (Side::Ask, Some(limit_price_in_ticks)) => crossing_order.price_in_ticks >= limit_price_in_ticks,
+                (_, None) => true
+            })
+            .collect();
+
+        // as on Phoenix, the sequence numbers of bids are inverted, so the larger one is the older bid
+        crossing_orders.sort_by(|a, b| match side {
+            Side::Bid => (a.price_in_ticks, a.order_sequence_number).cmp(&(b.price_in_ticks, b.order_sequence_number)),
+            Side::Ask => (b.price_in_ticks, b.order_sequence_number).cmp(&(a.price_in_ticks, a.order_sequence_number))
+        });
+
+        Ok(crossing_orders)
+    }
+
+    fn get_resting_order_base_lots(&self, order: &Order) -> SpedXSpotResult<u64> {
+        get_openbook_v2_resting_order_base_lots(
+            self.get_book_side(order.get_phoenix_side()),
+            get_resting_openbook_v2_order_id(order)
+        )
+    }
+
+    fn place_order(&self, order: &Order, side: Side, price_in_ticks: u64, num_base_lots: u64) -> SpedXSpotResult<ExternalSpotPlacement> {
+        // the order controller slides post-only orders itself, so that their price is known
+        let order_type = if order.is_post_only() {
+            OpenBookV2PlaceOrderType::PostOnly
+        } else {
+            OpenBookV2PlaceOrderType::Limit
+        };
+
+        self.invoke_place_order(order_type, side, price_in_ticks, num_base_lots, order.order_id)
+    }
+
+    fn fill_order(
+        &self,
+        order: &Order,
+        side: Side,
+        price_in_ticks: Option<u64>,
+        num_base_lots: u64,
+        min_base_lots_to_fill: u64,
+        min_quote_lots_to_fill: u64
+    ) -> SpedXSpotResult<ExternalSpotFill> {
+        let mut num_base_lots_remaining = num_base_lots;
+        let mut signer_base_lots: u64 = 0;
+
+        for crossing_order in self.get_crossing_orders(side, price_in_ticks)? {
+            if num_base_lots_remaining == 0 {
+                break;
+            }
+
+            let num_base_lots_matched = num_base_lots_remaining.min(crossing_order.num_base_lots);
+            num_base_lots_remaining = num_base_lots_remaining.safe_sub(num_base_lots_matched)?;
+
+            if crossing_order.is_signer {
+                signer_base_lots = signer_base_lots.safe_add(num_base_lots_matched)?;
+            }
+        }
+
+        // the minimums exclude the matches with the signer's orders, which OpenBook V2 does count as filled
+        validate!(
+            min_quote_lots_to_fill == 0
+                && (min_base_lots_to_fill == 0 || min_base_lots_to_fill.safe_add(signer_base_lots)? == num_base_lots),
+            ErrorCode::OrderTypeNotSupported,
+            "OpenBook V2 orders can only require a complete fill"
+        )?;
+
+        // a market order is priced at the far end of the book
+        let price_lots = match (price_in_ticks, side) {
+            (Some(price_in_ticks), _) => price_in_ticks,
+            (None, Side::Bid) => i64::MAX.cast()?,
+            (None, Side::Ask) => 1
+        };
+
+        let order_type = if min_base_lots_to_fill > 0 {
+            OpenBookV2PlaceOrderType::FillOrKill
+        } else {
+            OpenBookV2PlaceOrderType::ImmediateOrCancel
+        };
+
+        // matches with the signer's own orders only release the funds locked in them, so they are not part of the fill
+        Ok(self.invoke_place_order(order_type, side, price_lots, num_base_lots, order.order_id)?.fill)
+    }
+
+    fn place_quotes(&self, quotes: &[ExternalQuote]) -> SpedXSpotResult<Vec<Option<(u64, u64)>>> {
+        quotes
+            .iter()
+            .map(|quote| {
+                // a quote that would cross the book rests one tick behind the best price on the opposite side
+                let price_in_ticks = self.get_post_only_slide_price_in_ticks(quote.side, quote.price_in_ticks)?;
+
+                if price_in_ticks == 0 {
+                    return Ok(None)
+                }
+
+                let placement = self.invoke_place_order(
+                    OpenBookV2PlaceOrderType::PostOnly,
+                    quote.side,
+                    price_in_ticks,
+                    quote.num_base_lots,
+                    0
+                )?;
+
+                Ok((placement.resting_base_lots > 0).then_some((price_in_ticks, placement.order_sequence_number)))
+            })
+            .collect()
+    }
+
+    fn reduce_order(&self, order: &Order, num_base_lots: u64) -> SpedXSpotResult<Option<(u64, u64)>> {
+        let resting_base_lots = self.get_resting_order_base_lots(order)?;
+
+        self.cancel_order(order)?;
+
+        // OpenBook V2 cannot reduce an order in place, the rest of it is placed again at the same price, behind the
+        // orders already resting there
+        let num_base_lots_left = resting_base_lots.saturating_sub(num_base_lots);
+
+        if num_base_lots_left == 0 {
+            return Ok(None)
+        }
+
+        let placement = self.invoke_place_order(
+            OpenBookV2PlaceOrderType::PostOnly,
+            order.get_phoenix_side(),
+            order.phoenix_price_in_ticks,
+            num_base_lots_left,
+            order.order_id
+        )?;
+
+        Ok((placement.resting_base_lots > 0).then_some((order.phoenix_price_in_ticks, placement.order_sequence_number)))
+    }
+
+    fn cancel_order(&self, order: &Order) -> SpedXSpotResult {
+        invoke_openbook_v2_cancel_order(self.openbook_v2_accounts, get_resting_openbook_v2_order_id(order), self.signer_nonce)?;
+        invoke_openbook_v2_settle_funds(self.openbook_v2_accounts, self.signer_nonce)
+    }
+
+    fn settle_funds(&self) -> SpedXSpotResult {
+        invoke_openbook_v2_settle_funds(self.openbook_v2_accounts, self.signer_nonce)
+    }
+}
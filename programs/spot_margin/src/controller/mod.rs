@@ -0,0 +1,8 @@
+//! State transitions shared by instruction handlers. Handlers load and validate accounts, the controller mutates them.
+
+pub mod oracle;
+pub mod orders;
+pub mod phoenix;
+pub mod openbook_v2;
+pub mod fulfillment;
+pub mod spot_balance;
@@ -0,0 +1,274 @@
+//! CPIs into the OpenBook V2 program. All orders are placed by the protocol's signer PDA from a single open orders
+//! account per OpenBook V2 market. Fills and released funds are credited to the open orders account and moved to the
+//! signer's token accounts by settling its funds.
+
+use anchor_lang::prelude::*;
+use solana_program::{
+    instruction::{
+        AccountMeta,
+        Instruction
+    },
+    program::invoke_signed
+};
+
+use crate::{
+    error::{
+        SpedXSpotResult,
+        ErrorCode
+    },
+    state::{
+        config::State,
+        helpers::get_signer_seeds,
+        market::Market,
+        openbook_v2::{
+            load_openbook_v2_market,
+            openbook_v2_program,
+            OpenBookV2Market,
+            OpenBookV2MarketAccounts,
+            OpenBookV2PlaceOrderArgs,
+            OpenBookV2Side
+        }
+    },
+    validate
+};
+
+/// Anchor discriminators of the OpenBook V2 instructions the protocol invokes
+const PLACE_ORDER_DISCRIMINATOR: [u8; 8] = [51, 194, 155, 175, 109, 130, 96, 106];
+const CANCEL_ORDER_DISCRIMINATOR: [u8; 8] = [95, 129, 237, 240, 8, 49, 223, 132];
+const SETTLE_FUNDS_DISCRIMINATOR: [u8; 8] = [238, 64, 163, 96, 75, 171, 16, 33];
+const CREATE_OPEN_ORDERS_INDEXER_DISCRIMINATOR: [u8; 8] = [64, 64, 153, 255, 217, 71, 249, 133];
+const CREATE_OPEN_ORDERS_ACCOUNT_DISCRIMINATOR: [u8; 8] = [204, 181, 175, 222, 40, 125, 188, 71];
+
+/// Checks that the OpenBook V2 accounts passed to an order instruction belong to the market and to the protocol's
+/// signer. Returns the OpenBook V2 market.
+pub fn validate_openbook_v2_accounts(
+    state: &State,
+    market: &Market,
+    openbook_v2_accounts: &OpenBookV2MarketAccounts
+) -> SpedXSpotResult<OpenBookV2Market> {
+    validate!(
+        openbook_v2_accounts.signer.key() == state.signer,
+        ErrorCode::InvalidSigner,
+        "Signer {} is not the protocol signer",
+        openbook_v2_accounts.signer.key()
+    )?;
+
+    validate!(
+        market.openbook_v2_market != Pubkey::default() && openbook_v2_accounts.openbook_v2_market.key() == market.openbook_v2_market,
+        ErrorCode::InvalidOpenBookV2Market,
+        "OpenBook V2 market {} does not belong to market {}",
+        openbook_v2_accounts.openbook_v2_market.key(),
+        market.market_index
+    )?;
+
+    let openbook_v2_market = load_openbook_v2_market(&openbook_v2_accounts.openbook_v2_market)?;
+
+    validate!(
+        openbook_v2_accounts.bids.key() == openbook_v2_market.bids
+            && openbook_v2_accounts.asks.key() == openbook_v2_market.asks
+            && openbook_v2_accounts.event_heap.key() == openbook_v2_market.event_heap
+            && openbook_v2_accounts.openbook_v2_market_authority.key() == openbook_v2_market.market_authority
+            && openbook_v2_accounts.openbook_v2_base_vault.key() == openbook_v2_market.market_base_vault
+            && openbook_v2_accounts.openbook_v2_quote_vault.key() == openbook_v2_market.market_quote_vault,
+        ErrorCode::InvalidOpenBookV2Market,
+        "Accounts do not belong to OpenBook V2 market {}",
+        openbook_v2_accounts.openbook_v2_market.key()
+    )?;
+
+    Ok(openbook_v2_market)
+}
+
+/// Places an order on OpenBook V2. The funds the order locks are taken from the signer's token account of the side it
+/// pays with, the funds it receives are credited to the open orders account.
+pub fn invoke_openbook_v2_place_order(
+    openbook_v2_accounts: &OpenBookV2MarketAccounts,
+    args: &OpenBookV2PlaceOrderArgs,
+    signer_nonce: u8
+) -> SpedXSpotResult {
+    let (user_token_account, market_vault) = match args.side {
+        OpenBookV2Side::Bid => (openbook_v2_accounts.quote_account.key(), openbook_v2_accounts.openbook_v2_quote_vault.key()),
+        OpenBookV2Side::Ask => (openbook_v2_accounts.base_account.key(), openbook_v2_accounts.openbook_v2_base_vault.key())
+    };
+
+    // optional accounts that are not passed are replaced by the OpenBook V2 program id
+    let accounts = vec![
+        AccountMeta::new_readonly(openbook_v2_accounts.signer.key(), true),
+        AccountMeta::new(openbook_v2_accounts.open_orders_account.key(), false),
+        AccountMeta::new_readonly(openbook_v2_program::id(), false),
+        AccountMeta::new(user_token_account, false),
+        AccountMeta::new(openbook_v2_accounts.openbook_v2_market.key(), false),
+        AccountMeta::new(openbook_v2_accounts.bids.key(), false),
+        AccountMeta::new(openbook_v2_accounts.asks.key(), false),
+        AccountMeta::new(openbook_v2_accounts.event_heap.key(), false),
+        AccountMeta::new(market_vault, false),
+        AccountMeta::new_readonly(openbook_v2_program::id(), false),
+        AccountMeta::new_readonly(openbook_v2_program::id(), false),
+        AccountMeta::new_readonly(openbook_v2_accounts.token_program.key(), false),
+    ];
+
+    let mut data = PLACE_ORDER_DISCRIMINATOR.to_vec();
+    data.extend_from_slice(&args.try_to_vec().map_err(|_| ErrorCode::OpenBookV2CpiFailed)?);
+
+    invoke_openbook_v2(openbook_v2_accounts, accounts, data, signer_nonce)
+}
+
+/// Cancels an order of the signer on OpenBook V2 by its order id. The funds it locked are released to the open orders
+/// account.
+pub fn invoke_openbook_v2_cancel_order(
+    openbook_v2_accounts: &OpenBookV2MarketAccounts,
+    order_id: u128,
+    signer_nonce: u8
+) -> SpedXSpotResult {
+    let accounts = vec![
+        AccountMeta::new_readonly(openbook_v2_accounts.signer.key(), true),
+        AccountMeta::new(openbook_v2_accounts.open_orders_account.key(), false),
+        AccountMeta::new_readonly(openbook_v2_accounts.openbook_v2_market.key(), false),
+        AccountMeta::new(openbook_v2_accounts.bids.key(), false),
+        AccountMeta::new(openbook_v2_accounts.asks.key(), false),
+    ];
+
+    let mut data = CANCEL_ORDER_DISCRIMINATOR.to_vec();
+    data.extend_from_slice(&order_id.to_le_bytes());
+
+    invoke_openbook_v2(openbook_v2_accounts, accounts, data, signer_nonce)
+}
+
+/// Moves the free funds of the signer's open orders account to the signer's token accounts. The signer pays the event
+/// heap penalty OpenBook V2 charges accounts whose fills were consumed by the crank.
+pub fn invoke_openbook_v2_settle_funds(
+    openbook_v2_accounts: &OpenBookV2MarketAccounts,
+    signer_nonce: u8
+) -> SpedXSpotResult {
+    let accounts = vec![
+        AccountMeta::new(openbook_v2_accounts.signer.key(), true),
+        AccountMeta::new(openbook_v2_accounts.signer.key(), true),
+        AccountMeta::new(openbook_v2_accounts.open_orders_account.key(), false),
+        AccountMeta::new(openbook_v2_accounts.openbook_v2_market.key(), false),
+        AccountMeta::new_readonly(openbook_v2_accounts.openbook_v2_market_authority.key(), false),
+        AccountMeta::new(openbook_v2_accounts.openbook_v2_base_vault.key(), false),
+        AccountMeta::new(openbook_v2_accounts.openbook_v2_quote_vault.key(), false),
+        AccountMeta::new(openbook_v2_accounts.base_account.key(), false),
+        AccountMeta::new(openbook_v2_accounts.quote_account.key(), false),
+        AccountMeta::new_readonly(openbook_v2_program::id(), false),
+        AccountMeta::new_readonly(openbook_v2_accounts.token_program.key(), false),
+        AccountMeta::new_readonly(openbook_v2_accounts.system_program.key(), false),
+    ];
+
+    invoke_openbook_v2(openbook_v2_accounts, accounts, SETTLE_FUNDS_DISCRIMINATOR.to_vec(), signer_nonce)
+}
+
+/// Creates the signer's open orders account on an OpenBook V2 market, and the signer's open orders indexer if it has
+/// none yet. The payer pays the rent of both.
+pub fn invoke_openbook_v2_create_open_orders_account<'info>(
+    openbook_v2_program: &AccountInfo<'info>,
+    openbook_v2_market: &AccountInfo<'info>,
+    open_orders_indexer: &AccountInfo<'info>,
+    open_orders_account: &AccountInfo<'info>,
+    signer: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    signer_nonce: u8
+) -> SpedXSpotResult {
+    if open_orders_indexer.data_is_empty() {
+        let instruction = Instruction {
+            program_id: openbook_v2_program::id(),
+            accounts: vec![
+                AccountMeta::new(payer.key(), true),
+                AccountMeta::new_readonly(signer.key(), true),
+                AccountMeta::new(open_orders_indexer.key(), false),
+                AccountMeta::new_readonly(system_program.key(), false),
+            ],
+            data: CREATE_OPEN_ORDERS_INDEXER_DISCRIMINATOR.to_vec()
+        };
+
+        invoke_openbook_v2_instruction(
+            &instruction,
+            &[
+                openbook_v2_program.clone(),
+                payer.clone(),
+                signer.clone(),
+                open_orders_indexer.clone(),
+                system_program.clone(),
+            ],
+            signer_nonce
+        )?;
+    }
+
+    let mut data = CREATE_OPEN_ORDERS_ACCOUNT_DISCRIMINATOR.to_vec();
+    data.extend_from_slice(&"spot_margin".to_string().try_to_vec().map_err(|_| ErrorCode::OpenBookV2CpiFailed)?);
+
+    let instruction = Instruction {
+        program_id: openbook_v2_program::id(),
+        accounts: vec![
+            AccountMeta::new(payer.key(), true),
+            AccountMeta::new_readonly(signer.key(), true),
+            AccountMeta::new_readonly(openbook_v2_program::id(), false),
+            AccountMeta::new(open_orders_indexer.key(), false),
+            AccountMeta::new(open_orders_account.key(), false),
+            AccountMeta::new_readonly(openbook_v2_market.key(), false),
+            AccountMeta::new_readonly(system_program.key(), false),
+        ],
+        data
+    };
+
+    invoke_openbook_v2_instruction(
+        &instruction,
+        &[
+            openbook_v2_program.clone(),
+            payer.clone(),
+            signer.clone(),
+            open_orders_indexer.clone(),
+            open_orders_account.clone(),
+            openbook_v2_market.clone(),
+            system_program.clone(),
+        ],
+        signer_nonce
+    )
+}
+
+/// Invokes the OpenBook V2 program with the market's accounts, signing with the protocol's signer PDA
+fn invoke_openbook_v2(
+    openbook_v2_accounts: &OpenBookV2MarketAccounts,
+    accounts: Vec<AccountMeta>,
+    data: Vec<u8>,
+    signer_nonce: u8
+) -> SpedXSpotResult {
+    let instruction = Instruction {
+        program_id: openbook_v2_program::id(),
+        accounts,
+        data
+    };
+
+    let account_infos = [
+        openbook_v2_accounts.openbook_v2_program.to_account_info(),
+        openbook_v2_accounts.openbook_v2_market.to_account_info(),
+        openbook_v2_accounts.openbook_v2_market_authority.to_account_info(),
+        openbook_v2_accounts.bids.to_account_info(),
+        openbook_v2_accounts.asks.to_account_info(),
+        openbook_v2_accounts.event_heap.to_account_info(),
+        openbook_v2_accounts.open_orders_account.to_account_info(),
+        openbook_v2_accounts.signer.to_account_info(),
+        openbook_v2_accounts.base_account.to_account_info(),
+        openbook_v2_accounts.quote_account.to_account_info(),
+        openbook_v2_accounts.openbook_v2_base_vault.to_account_info(),
+        openbook_v2_accounts.openbook_v2_quote_vault.to_account_info(),
+        openbook_v2_accounts.token_program.to_account_info(),
+        openbook_v2_accounts.system_program.to_account_info(),
+    ];
+
+    invoke_openbook_v2_instruction(&instruction, &account_infos, signer_nonce)
+}
+
+/// Invokes an OpenBook V2 instruction, signing with the protocol's signer PDA
+fn invoke_openbook_v2_instruction(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signer_nonce: u8
+) -> SpedXSpotResult {
+    let signer_seeds = get_signer_seeds(&signer_nonce);
+
+    invoke_signed(instruction, account_infos, &[&signer_seeds]).map_err(|e| {
+        msg!("OpenBook V2 CPI failed: {:?}", e);
+        ErrorCode::OpenBookV2CpiFailed
+    })
+}
@@ -0,0 +1,353 @@
+//! Maintenance of the market's historical oracle data
+
+use anchor_lang::prelude::*;
+use phoenix::state::Side;
+
+use crate::{
+    error::{
+        SpedXSpotResult,
+        ErrorCode
+    },
+    math::{
+        casting::Cast,
+        constants::{
+            DEFAULT_MAX_TWAP_UPDATE_PRICE_BAND_DENOMINATOR,
+            FIVE_MINUTE,
+            ONE_HOUR,
+            PRICE_PRECISION_I64,
+            PRICE_PRECISION_U64
+        },
+        oracle_validity::{
+            is_oracle_valid_for_action,
+            oracle_validity
+        },
+        safe_math::SafeMath,
+        twap::calculate_twap
+    },
+    state::{
+        enums::{
+            Actions,
+            OracleValidity,
+            PositionDirection
+        },
+        guard_rails::OracleGuardRails,
+        market::Market,
+        oracle::{
+            HistoricalIndexData,
+            HistoricalPriceData,
+            OraclePriceData
+        },
+        phoenix::{
+            fetch_market_header,
+            get_phoenix_best_opposite_price_in_ticks,
+            PhoenixMarketConverter
+        }
+    },
+    validate
+};
+
+/// Updates the market's 1h and 5min oracle TWAPs with the current oracle price. Meant to be called from every
+/// interaction with a market that loads its oracle(fills, deposits, withdrawals, liquidations) as well as from the
+/// permissionless `update_market_oracle_twap` crank.
+/// 
+/// The update is skipped if the oracle is not valid for `Actions::UpdateTWAP`. Otherwise, the incoming price is clamped to
+/// within 1/DEFAULT_MAX_TWAP_UPDATE_PRICE_BAND_DENOMINATOR of the last TWAP, so that a single bad print can only move
+/// the TWAP by a bounded amount.
+/// `guard_rails` are the protocol-wide defaults from `State`, the market's overrides are applied on top of them.
+/// 
+/// Returns true if the TWAPs were updated.
+pub fn update_oracle_twap(
+    market: &mut Market,
+    oracle_price_data: &OraclePriceData,
+    guard_rails: &OracleGuardRails,
+    now: i64
+) -> SpedXSpotResult<bool> {
+    let guard_rails = market.oracle_guard_rails.resolve(guard_rails);
+
+    let historical_oracle_data = &mut market.historical_oracle_data;
+
+    let is_first_update = historical_oracle_data.last_oracle_twap == 0
+        || historical_oracle_data.last_oracle_twap_time_stamp == 0;
+
+    // without a twap there is nothing to judge the volatility of the first price against, it is compared with itself
+    let reference_twap = if is_first_update {
+        oracle_price_data.price
+    } else {
+        historical_oracle_data.last_oracle_twap
+    };
+
+    let validity = oracle_validity(
+        reference_twap,
+        oracle_price_data,
+        &guard_rails.validity
+    )?;
+
+    if !is_oracle_valid_for_action(Some(Actions::UpdateTWAP), validity)? {
+        msg!(
+            "Skipping oracle twap update for market {}, oracle validity: {:?}",
+            market.market_index,
+            validity
+        );
+        return Ok(false)
+    }
+
+    // the first valid price initializes the twaps
+    if is_first_update {
+        *historical_oracle_data = HistoricalPriceData {
+            last_oracle_twap_time_stamp: now,
+            ..HistoricalPriceData::default_with_current_oracle(oracle_price_data)
+        };
+
+        return Ok(true)
+    }
+
+    let last_oracle_twap = historical_oracle_data.last_oracle_twap;
+
+    // clamping the price to [last_twap - last_twap/3, last_twap + last_twap/3]
+    let price_band = last_oracle_twap
+        .unsigned_abs()
+        .safe_div(DEFAULT_MAX_TWAP_UPDATE_PRICE_BAND_DENOMINATOR.unsigned_abs())?
+        .cast::<i64>()?;
+
+    let clamped_price = oracle_price_data.price.clamp(
+        last_oracle_twap.safe_sub(price_band)?,
+        last_oracle_twap.safe_add(price_band)?
+    );
+
+    let oracle_twap = calculate_twap(
+        clamped_price,
+        now,
+        last_oracle_twap,
+        historical_oracle_data.last_oracle_twap_time_stamp,
+        ONE_HOUR
+    )?;
+
+    let oracle_twap_5min = calculate_twap(
+        clamped_price,
+        now,
+        historical_oracle_data.last_oracle_twap_5min,
+        historical_oracle_data.last_oracle_twap_time_stamp,
+        FIVE_MINUTE.cast()?
+    )?;
+
+    historical_oracle_data.last_oracle_price_data = oracle_price_data.price;
+    historical_oracle_data.last_oracle_conf = oracle_price_data.confidence;
+    historical_oracle_data.last_oracle_delay = oracle_price_data.delay;
+    historical_oracle_data.last_oracle_twap = oracle_twap;
+    historical_oracle_data.last_oracle_twap_5min = oracle_twap_5min;
+    historical_oracle_data.last_oracle_twap_time_stamp = now.max(historical_oracle_data.last_oracle_twap_time_stamp);
+
+    Ok(true)
+}
+
+/// Updates the market's index data, i.e its Phoenix mid price source, with the best bid and ask of the market's Phoenix
+/// book. Meant to be called from the `update_market_oracle_twap` crank.
+/// 
+/// Returns true if the index data was updated.
+pub fn update_phoenix_index_data(
+    market: &mut Market,
+    phoenix_market: &AccountInfo,
+    slot: u64,
+    now: i64
+) -> SpedXSpotResult<bool> {
+    validate!(
+        market.phoenix_market != Pubkey::default() && phoenix_market.key() == market.phoenix_market,
+        ErrorCode::InvalidPhoenixMarket,
+        "Phoenix market {} does not belong to market {}",
+        phoenix_market.key(),
+        market.market_index
+    )?;
+
+    let converter = PhoenixMarketConverter::new(&fetch_market_header(phoenix_market)?)?;
+
+    // the best bid is the best price an ask matches and vice versa, both rounded away from the mid
+    let bid_price = get_phoenix_best_opposite_price_in_ticks(phoenix_market, Side::Ask)?
+        .map(|price_in_ticks| converter.ticks_to_price(price_in_ticks, PositionDirection::Long))
+        .transpose()?;
+
+    let ask_price = get_phoenix_best_opposite_price_in_ticks(phoenix_market, Side::Bid)?
+        .map(|price_in_ticks| converter.ticks_to_price(price_in_ticks, PositionDirection::Short))
+        .transpose()?;
+
+    update_index_data(market, bid_price, ask_price, slot, now)
+}
+
+/// Records the best bid and ask of the index book and updates the 1h and 5min TWAPs of its mid price. A book with an empty
+/// side, or a crossed one, leaves the index data untouched, so that it goes stale instead.
+/// precision: PRICE_PRECISION
+/// 
+/// Returns true if the index data was updated.
+pub fn update_index_data(
+    market: &mut Market,
+    bid_price: Option<u64>,
+    ask_price: Option<u64>,
+    slot: u64,
+    now: i64
+) -> SpedXSpotResult<bool> {
+    let (bid_price, ask_price) = match (bid_price, ask_price) {
+        (Some(bid_price), Some(ask_price)) if bid_price > 0 && ask_price >= bid_price => (bid_price, ask_price),
+        _ => {
+            msg!("Skipping index data update for market {}, the book is empty or crossed", market.market_index);
+            return Ok(false)
+        }
+    };
+
+    let mid_price = bid_price.safe_add(ask_price)?.safe_div(2)?;
+
+    let historical_index_data = &mut market.historical_index_data;
+
+    // the first update initializes the twaps
+    if historical_index_data.last_index_price_twap_time_stamp == 0 {
+        *historical_index_data = HistoricalIndexData {
+            last_index_bid_price: bid_price,
+            last_index_ask_price: ask_price,
+            last_index_price_twap: mid_price,
+            last_index_price_twap_5min: mid_price,
+            last_index_price_twap_time_stamp: now
+        };
+    } else {
+        let index_price_twap = calculate_twap(
+            mid_price.cast()?,
+            now,
+            historical_index_data.last_index_price_twap.cast()?,
+            historical_index_data.last_index_price_twap_time_stamp,
+            ONE_HOUR
+        )?;
+
+        let index_price_twap_5min = calculate_twap(
+            mid_price.cast()?,
+            now,
+            historical_index_data.last_index_price_twap_5min.cast()?,
+            historical_index_data.last_index_price_twap_time_stamp,
+            FIVE_MINUTE.cast()?
+        )?;
+
+        historical_index_data.last_index_bid_price = bid_price;
+        historical_index_data.last_index_ask_price = ask_price;
+        historical_index_data.last_index_price_twap = index_price_twap.cast()?;
+        historical_index_data.last_index_price_twap_5min = index_price_twap_5min.cast()?;
+        historical_index_data.last_index_price_twap_time_stamp = now.max(historical_index_data.last_index_price_twap_time_stamp);
+    }
+
+    market.last_index_update_slot = slot;
+
+    Ok(true)
+}
+
+#[test]
+fn update_oracle_twap_clamps_and_skips_invalid_prices() {
+    let now = 1_700_000_000;
+    let guard_rails = OracleGuardRails::default();
+
+    let mut market = Market {
+        historical_oracle_data: HistoricalPriceData {
+            last_oracle_twap: 30 * PRICE_PRECISION_I64,
+            last_oracle_twap_5min: 30 * PRICE_PRECISION_I64,
+            last_oracle_twap_time_stamp: now - ONE_HOUR,
+            ..HistoricalPriceData::default()
+        },
+        ..Market::default()
+    };
+
+    let oracle_price_data = |price: i64| OraclePriceData {
+        price,
+        confidence: PRICE_PRECISION_U64 / 100,
+        delay: 1,
+        has_sufficient_data_points: true,
+    };
+
+    // a full period has elapsed, so the twap moves almost all the way to the incoming price
+    // (33 * 3600 + 30 * 1) / 3601
+    let updated = update_oracle_twap(&mut market, &oracle_price_data(33 * PRICE_PRECISION_I64), &guard_rails, now).unwrap();
+    assert!(updated);
+    assert_eq!(market.historical_oracle_data.last_oracle_twap, 32_999_166);
+    assert_eq!(market.historical_oracle_data.last_oracle_twap_5min, 32_999_166);
+    assert_eq!(market.historical_oracle_data.last_oracle_twap_time_stamp, now);
+
+    // a price 4x the twap is within the volatility guard rails, but is clamped to twap + twap/3 = 43_998_888
+    // (43_998_888 * 3600 + 32_999_166 * 1) / 3601
+    let now = now + ONE_HOUR;
+    let updated = update_oracle_twap(&mut market, &oracle_price_data(132 * PRICE_PRECISION_I64), &guard_rails, now).unwrap();
+    assert!(updated);
+    assert_eq!(market.historical_oracle_data.last_oracle_twap, 43_995_833);
+    assert_eq!(market.historical_oracle_data.last_oracle_price_data, 132 * PRICE_PRECISION_I64);
+
+    // an invalid price leaves the twaps untouched
+    let historical_oracle_data_before = market.historical_oracle_data;
+    let updated = update_oracle_twap(&mut market, &oracle_price_data(0), &guard_rails, now + 60).unwrap();
+    assert!(!updated);
+    assert_eq!(market.historical_oracle_data, historical_oracle_data_before);
+
+    // as does an uncertain one
+    let uncertain_oracle_price_data = OraclePriceData {
+        confidence: 10 * PRICE_PRECISION_U64,
+        ..oracle_price_data(44 * PRICE_PRECISION_I64)
+    };
+    let updated = update_oracle_twap(&mut market, &uncertain_oracle_price_data, &guard_rails, now + 60).unwrap();
+    assert!(!updated);
+    assert_eq!(market.historical_oracle_data, historical_oracle_data_before);
+}
+
+#[test]
+fn update_oracle_twap_first_update_requires_a_valid_price() {
+    let now = 1_700_000_000;
+    let guard_rails = OracleGuardRails::default();
+    let mut market = Market::default();
+
+    // an uncertain first price does not initialize the twaps
+    let uncertain_oracle_price_data = OraclePriceData {
+        price: 30 * PRICE_PRECISION_I64,
+        confidence: 10 * PRICE_PRECISION_U64,
+        delay: 1,
+        has_sufficient_data_points: true,
+    };
+    assert!(!update_oracle_twap(&mut market, &uncertain_oracle_price_data, &guard_rails, now).unwrap());
+    assert_eq!(market.historical_oracle_data, HistoricalPriceData::default());
+
+    // neither does one without sufficient data points
+    let insufficient_oracle_price_data = OraclePriceData {
+        confidence: PRICE_PRECISION_U64 / 100,
+        has_sufficient_data_points: false,
+        ..uncertain_oracle_price_data
+    };
+    assert!(!update_oracle_twap(&mut market, &insufficient_oracle_price_data, &guard_rails, now).unwrap());
+    assert_eq!(market.historical_oracle_data, HistoricalPriceData::default());
+
+    // a valid one does
+    let valid_oracle_price_data = OraclePriceData {
+        has_sufficient_data_points: true,
+        ..insufficient_oracle_price_data
+    };
+    assert!(update_oracle_twap(&mut market, &valid_oracle_price_data, &guard_rails, now).unwrap());
+    assert_eq!(market.historical_oracle_data.last_oracle_twap, 30 * PRICE_PRECISION_I64);
+    assert_eq!(market.historical_oracle_data.last_oracle_twap_time_stamp, now);
+}
+
+#[test]
+fn update_index_data_tracks_the_mid_price() {
+    let now = 1_700_000_000;
+    let mut market = Market::default();
+
+    // the first update initializes the twaps to the mid price
+    let updated = update_index_data(&mut market, Some(99 * PRICE_PRECISION_U64), Some(101 * PRICE_PRECISION_U64), 1_000, now).unwrap();
+    assert!(updated);
+    assert_eq!(market.historical_index_data.last_index_price_twap, 100 * PRICE_PRECISION_U64);
+    assert_eq!(market.historical_index_data.last_index_price_twap_5min, 100 * PRICE_PRECISION_U64);
+    assert_eq!(market.last_index_update_slot, 1_000);
+
+    // a full period later, the twap moves almost all the way to the new mid
+    // (110 * 3600 + 100 * 1) / 3601
+    let updated = update_index_data(&mut market, Some(109 * PRICE_PRECISION_U64), Some(111 * PRICE_PRECISION_U64), 8_200, now + ONE_HOUR).unwrap();
+    assert!(updated);
+    assert_eq!(market.historical_index_data.last_index_bid_price, 109 * PRICE_PRECISION_U64);
+    assert_eq!(market.historical_index_data.last_index_ask_price, 111 * PRICE_PRECISION_U64);
+    assert_eq!(market.historical_index_data.last_index_price_twap, 109_997_222);
+    assert_eq!(market.last_index_update_slot, 8_200);
+
+    // an empty side or a crossed book leaves the index data untouched
+    let historical_index_data_before = market.historical_index_data;
+    assert!(!update_index_data(&mut market, None, Some(111 * PRICE_PRECISION_U64), 8_300, now + ONE_HOUR + 60).unwrap());
+    assert!(!update_index_data(&mut market, Some(112 * PRICE_PRECISION_U64), Some(111 * PRICE_PRECISION_U64), 8_300, now + ONE_HOUR + 60).unwrap());
+    assert_eq!(market.historical_index_data, historical_index_data_before);
+    assert_eq!(market.last_index_update_slot, 8_200);
+}
@@ -0,0 +1,2476 @@
+//! Order lifecycle: placement on the market's fulfillment venue, settlement of fills, modification, cancellation and
+//! re-pricing of oracle pegged orders. On OpenBook V2 all orders rest on the venue's book under the protocol's signer,
+//! which cannot tell the users apart, so matches between two users' orders are settled here, see
+//! `apply_self_trade_behavior`. On Phoenix they trade on the book between the users' trader PDAs. Fills
+//! of a resting order are detected and settled the next time the order is touched, i.e when it is modified, cancelled
+//! or re-priced.
+
+use anchor_lang::prelude::*;
+use phoenix::{
+    program::new_order::CondensedOrder,
+    state::Side
+};
+
+use crate::{
+    controller::fulfillment::{
+        ExternalQuote,
+        SpotFulfillment
+    },
+    error::{
+        SpedXSpotResult,
+        ErrorCode
+    },
+    math::{
+        casting::Cast,
+        constants::{
+            DEFAULT_TRIGGER_MARKET_ORDER_SLIPPAGE_PCT,
+            MAX_OPEN_ORDERS,
+            MAX_ORACLE_PEG_DISTANCE_PCT,
+            PERCENTAGE_PRECISION,
+            TRIGGER_ORDER_KEEPER_FEE
+        },
+        margin::calculate_position_margin,
+        oracle_validity::{
+            is_oracle_valid_for_action,
+            oracle_validity
+        },
+        price::{
+            standardize_base_asset_amt,
+            standardize_price
+        },
+        safe_math::SafeMath,
+        safe_unwrap::SafeUnwrap,
+        spot_withdraw::check_user_borrow_limit
+    },
+    state::{
+        config::State,
+        enums::{
+            Actions,
+            OcoRole,
+            MarginRequirementType,
+            OrderStatus,
+            OrderTriggerConditions,
+            OrderType,
+            PositionDirection,
+            PostOnlyTypes,
+            SelfTradeBehaviorTypes
+        },
+        market::Market,
+        oracle::OraclePriceData,
+        order::{
+            ModifyOrderParams,
+            Order,
+            OrderParams
+        },
+        user::User
+    },
+    validate
+};
+
+/// Places an order for the user on the market's fulfillment venue. The part of the order that crosses the book is filled
+/// immediately, the rest rests on the book. The user must meet the initial margin requirement of the position assuming
+/// all of its open orders get filled, and an ask must keep the user's worst case borrow of the market's token within the
+/// market's per-user borrow cap. Returns the id of the order.
+pub fn place_order(
+    state: &State,
+    user: &mut User,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    fulfillment: &dyn SpotFulfillment,
+    params: OrderParams,
+    slot: u64,
+    now: i64
+) -> SpedXSpotResult<u32> {
+    place_order_in_oco_group(
+        state,
+        user,
+        market,
+        oracle_price_data,
+        fulfillment,
+        params,
+        0,
+        OcoRole::None,
+        slot,
+        now
+    )
+}
+
+/// Places an entry order together with a take-profit and/or a stop-loss leg in a new one-cancels-other group. The legs
+/// are reduce-only trigger orders in the opposite direction of the entry, the take-profit triggering on the profitable
+/// side of the entry price and the stop-loss on the other. They become triggerable once the entry has been (partially)
+/// filled, and they are cancelled with the entry if it is cancelled before any fill. When a leg triggers, the rest of
+/// the group is cancelled. Returns the id of the entry order.
+pub fn place_bracket_orders(
+    state: &State,
+    user: &mut User,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    fulfillment: &dyn SpotFulfillment,
+    entry: OrderParams,
+    take_profit: Option<OrderParams>,
+    stop_loss: Option<OrderParams>,
+    slot: u64,
+    now: i64
+) -> SpedXSpotResult<u32> {
+    validate!(
+        take_profit.is_some() || stop_loss.is_some(),
+        ErrorCode::InvalidBracketOrder,
+        "A bracket requires a take-profit or a stop-loss"
+    )?;
+
+    validate!(
+        !matches!(entry.order_type, OrderType::TriggerMarket | OrderType::TriggerLimit),
+        ErrorCode::InvalidBracketOrder,
+        "The entry of a bracket cannot be a trigger order"
+    )?;
+
+    // a take-profit of a long is triggered by the price rising, a stop-loss by the price falling, and vice versa
+    let take_profit_condition = match entry.direction {
+        PositionDirection::Long => OrderTriggerConditions::Above,
+        _ => OrderTriggerConditions::Below
+    };
+
+    let stop_loss_condition = match take_profit_condition {
+        OrderTriggerConditions::Above => OrderTriggerConditions::Below,
+        _ => OrderTriggerConditions::Above
+    };
+
+    let legs = [
+        take_profit.map(|leg| (leg, take_profit_condition)),
+        stop_loss.map(|leg| (leg, stop_loss_condition))
+    ];
+
+    for (leg, trigger_condition) in legs.iter().flatten() {
+        validate!(
+            matches!(leg.order_type, OrderType::TriggerMarket | OrderType::TriggerLimit),
+            ErrorCode::InvalidBracketOrder,
+            "Bracket legs must be trigger orders"
+        )?;
+
+        validate!(
+            leg.direction != entry.direction && leg.market_index == entry.market_index,
+            ErrorCode::InvalidBracketOrder,
+            "Bracket legs must close the entry in the same market"
+        )?;
+
+        validate!(
+            leg.trigger_condition == *trigger_condition,
+            ErrorCode::InvalidBracketOrder,
+            "Bracket leg must trigger {:?} the entry",
+            trigger_condition
+        )?;
+    }
+
+    let oco_group_id = user.get_next_oco_group_id()?;
+
+    // the legs are placed first, so that they are cancelled with the entry if it does not fill
+    for (leg, _) in legs.iter().flatten() {
+        place_order_in_oco_group(
+            state,
+            user,
+            market,
+            oracle_price_data,
+            fulfillment,
+            OrderParams {
+                reduce_only: true,
+                ..*leg
+            },
+            oco_group_id,
+            OcoRole::Leg,
+            slot,
+            now
+        )?;
+    }
+
+    place_order_in_oco_group(
+        state,
+        user,
+        market,
+        oracle_price_data,
+        fulfillment,
+        entry,
+        oco_group_id,
+        OcoRole::Entry,
+        slot,
+        now
+    )
+}
+
+fn place_order_in_oco_group(
+    state: &State,
+    user: &mut User,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    fulfillment: &dyn SpotFulfillment,
+    params: OrderParams,
+    oco_group_id: u8,
+    oco_role: OcoRole,
+    slot: u64,
+    now: i64
+) -> SpedXSpotResult<u32> {
+    validate!(
+        params.market_index == market.market_index,
+        ErrorCode::InvalidMarketIndex,
+        "Order market index {} does not match market {}",
+        params.market_index,
+        market.market_index
+    )?;
+
+    validate_market_for_orders(state, market, oracle_price_data, now)?;
+
+    validate!(
+        params.direction != PositionDirection::TwoWay,
+        ErrorCode::InvalidOrderDirection,
+        "TwoWay orders cannot be placed directly"
+    )?;
+
+    validate!(
+        matches!(
+            params.order_type,
+            OrderType::Limit
+                | OrderType::PostOnly
+                | OrderType::OraclePegged
+                | OrderType::TriggerMarket
+                | OrderType::TriggerLimit
+                | OrderType::ImmediateOrCancel
+                | OrderType::Twap
+        ),
+        ErrorCode::OrderTypeNotSupported,
+        "Order type {:?} is not supported",
+        params.order_type
+    )?;
+
+    fulfillment.validate_order_params(&params)?;
+
+    // the order size is rounded down to the market's step size and then to whole lots of the venue, so that an order
+    // can always be filled completely
+    let base_asset_amount = fulfillment.base_lots_to_atoms(
+        fulfillment.base_atoms_to_lots(standardize_base_asset_amt(params.base_asset_amount, market.order_step_size)?)?
+    )?;
+
+    validate!(
+        base_asset_amount > 0 && base_asset_amount >= market.min_order_size,
+        ErrorCode::InvalidOrderSize,
+        "Order size {} is below the minimum order size {}",
+        base_asset_amount,
+        market.min_order_size
+    )?;
+
+    let iceberg_clip_size = match params.iceberg_clip_size {
+        Some(iceberg_clip_size) => {
+            let iceberg_clip_size = fulfillment.base_lots_to_atoms(
+                fulfillment.base_atoms_to_lots(standardize_base_asset_amt(iceberg_clip_size, market.order_step_size)?)?
+            )?;
+
+            validate!(
+                iceberg_clip_size > 0 && iceberg_clip_size >= market.min_order_size && iceberg_clip_size < base_asset_amount,
+                ErrorCode::InvalidOrderSize,
+                "Iceberg clip size {} must be at least the minimum order size and below the order size {}",
+                iceberg_clip_size,
+                base_asset_amount
+            )?;
+
+            validate!(
+                !matches!(
+                    params.order_type,
+                    OrderType::TriggerMarket | OrderType::ImmediateOrCancel | OrderType::Twap
+                ),
+                ErrorCode::OrderTypeNotSupported,
+                "Immediate-or-cancel and TWAP orders cannot be iceberg orders"
+            )?;
+
+            // the shown clip would no longer match the order's size once decremented
+            validate!(
+                params.self_trade_behavior != SelfTradeBehaviorTypes::DecrementTake,
+                ErrorCode::OrderTypeNotSupported,
+                "Iceberg orders cannot decrement the user's own orders"
+            )?;
+
+            iceberg_clip_size
+        },
+        None => 0
+    };
+
+    // the spread of a TWAP order is the maximum slippage of its clips from the oracle price
+    let (price, oracle_limit_spread) = if params.order_type == OrderType::OraclePegged || params.order_type == OrderType::Twap {
+        let oracle_limit_spread = params.oracle_limit_spread.unwrap_or(0);
+
+        validate!(
+            oracle_limit_spread != 0,
+            ErrorCode::InvalidOrderPrice,
+            "Oracle pegged and TWAP orders require a non-zero oracle limit spread"
+        )?;
+
+        (0, oracle_limit_spread)
+    } else if params.order_type == OrderType::TriggerMarket || params.order_type == OrderType::ImmediateOrCancel {
+        // the price of a market order is optional and caps its slippage
+        (standardize_price(params.price, market.order_tick_size, params.direction)?, 0)
+    } else {
+        validate!(
+            params.price > 0,
+            ErrorCode::InvalidOrderPrice,
+            "Limit orders require a price"
+        )?;
+
+        (standardize_price(params.price, market.order_tick_size, params.direction)?, 0)
+    };
+
+    let is_trigger_order = matches!(
+        params.order_type,
+        OrderType::TriggerMarket | OrderType::TriggerLimit
+    );
+
+    let (price_for_trigger_orders, trigger_conditions) = if is_trigger_order {
+        let trigger_price = params.trigger_price.unwrap_or(0);
+
+        validate!(
+            trigger_price > 0,
+            ErrorCode::InvalidOrderPrice,
+            "Trigger orders require a trigger price"
+        )?;
+
+        validate!(
+            matches!(
+                params.trigger_condition,
+                OrderTriggerConditions::Above | OrderTriggerConditions::Below
+            ),
+            ErrorCode::InvalidOrderPrice,
+            "Trigger orders must be placed untriggered"
+        )?;
+
+        (trigger_price, params.trigger_condition)
+    } else {
+        (0, OrderTriggerConditions::Above)
+    };
+
+    let is_immediate_or_cancel = matches!(
+        params.order_type,
+        OrderType::ImmediateOrCancel | OrderType::TriggerMarket
+    );
+
+    validate!(
+        params.order_type == OrderType::ImmediateOrCancel
+            || (!params.fill_or_kill && params.min_base_asset_amount_to_fill.is_none() && params.min_quote_asset_amount_to_fill.is_none()),
+        ErrorCode::OrderTypeNotSupported,
+        "Fill-or-kill and minimum fills are only supported for immediate-or-cancel orders"
+    )?;
+
+    let time_in_force = match params.time_in_force {
+        Some(time_in_force) => time_in_force,
+        None if params.order_type == OrderType::ImmediateOrCancel => state.default_market_order_time_in_force.cast()?,
+        None => 0
+    };
+
+    validate!(
+        time_in_force >= 0,
+        ErrorCode::InvalidOrderExpiry,
+        "Time in force {} cannot be negative",
+        time_in_force
+    )?;
+
+    let expiry_ts = if time_in_force == 0 { 0 } else { now.safe_add(time_in_force)? };
+
+    validate!(
+        params.twap_num_clips.is_none() || params.order_type == OrderType::Twap,
+        ErrorCode::OrderTypeNotSupported,
+        "Only TWAP orders are executed in clips"
+    )?;
+
+    let (iceberg_clip_size, twap_clip_interval, expiry_ts) = if params.order_type == OrderType::Twap {
+        let twap_num_clips = params.twap_num_clips.unwrap_or(0);
+
+        validate!(
+            twap_num_clips > 0 && time_in_force > 0,
+            ErrorCode::InvalidOrderExpiry,
+            "TWAP orders require a number of clips and a duration"
+        )?;
+
+        let twap_clip_interval = time_in_force.safe_div(twap_num_clips.cast()?)?.cast::<u32>()?;
+
+        validate!(
+            twap_clip_interval > 0,
+            ErrorCode::InvalidOrderExpiry,
+            "TWAP duration {} is too short for {} clips",
+            time_in_force,
+            twap_num_clips
+        )?;
+
+        // clips are rounded up to whole lots, so the order may be executed in fewer clips than requested
+        let twap_clip_size = fulfillment.base_lots_to_atoms(
+            fulfillment.base_atoms_to_lots(base_asset_amount)?.safe_ceil_div(twap_num_clips.cast()?)?
+        )?;
+
+        validate!(
+            twap_clip_size >= market.min_order_size,
+            ErrorCode::InvalidOrderSize,
+            "TWAP clip size {} is below the minimum order size {}",
+            twap_clip_size,
+            market.min_order_size
+        )?;
+
+        let twap_num_clips = base_asset_amount.safe_ceil_div(twap_clip_size)?;
+        let twap_duration = twap_num_clips.safe_mul(twap_clip_interval.cast()?)?.cast::<i64>()?;
+
+        (twap_clip_size, twap_clip_interval, now.safe_add(twap_duration)?)
+    } else {
+        (iceberg_clip_size, 0, expiry_ts)
+    };
+
+    let order_index = user.get_next_order_index()?;
+    let order_id = user.get_next_order_id();
+    let existing_position_direction = user
+        .force_get_position_mut(market.market_index)?
+        .get_position_direction();
+
+    let mut order = Order {
+        slot,
+        price,
+        base_asset_amount,
+        order_id,
+        order_type: params.order_type,
+        market_index: market.market_index,
+        order_status: OrderStatus::Active,
+        user_order_id: params.user_order_id,
+        existing_position_direction,
+        pos_direction: params.direction,
+        reduce_only: params.reduce_only || market.market_in_reduce_only_mode() || user.is_reduce_only(),
+        post_only: match params.post_only {
+            _ if is_immediate_or_cancel || params.order_type == OrderType::Twap => PostOnlyTypes::NotPostOnly,
+            PostOnlyTypes::NotPostOnly if params.order_type == OrderType::PostOnly => PostOnlyTypes::MustBePostOnly,
+            post_only => post_only
+        },
+        immediate_or_cancel: is_immediate_or_cancel,
+        fill_or_kill: params.fill_or_kill,
+        treat_ioc_as_market: is_immediate_or_cancel && price == 0,
+        oracle_limit_spread,
+        price_for_trigger_orders,
+        trigger_conditions,
+        time_in_force: expiry_ts,
+        iceberg_clip_size,
+        twap_clip_interval,
+        self_trade_behavior: params.self_trade_behavior,
+        fail_silently_on_insufficient_funds_error: false,
+        oco_group_id,
+        oco_role,
+        ..Order::default()
+    };
+
+    // reduce-only orders are capped at the current position. Trigger orders are checked once they are triggered, as
+    // the position they protect may still change until then
+    if order.reduce_only && !is_trigger_order {
+        let base_asset_exposure = user
+            .force_get_position_mut(market.market_index)?
+            .get_base_asset_exposure(market)?;
+
+        let reduce_only_base_asset_amount = get_reduce_only_base_asset_amount(&order, base_asset_exposure, market, fulfillment)?;
+
+        validate!(
+            reduce_only_base_asset_amount > 0,
+            ErrorCode::ReduceOnlyOrderIncreasesPosition,
+            "Reduce-only order would increase the position of {}",
+            base_asset_exposure
+        )?;
+
+        order.base_asset_amount = reduce_only_base_asset_amount;
+    }
+
+    // a market order without a price has no limit price, a trigger market order only gets one once it is triggered. The
+    // clips of a TWAP order are priced off the oracle when they are executed
+    let limit_price = if (order.immediate_or_cancel && order.price == 0) || order.is_twap() {
+        0
+    } else {
+        order.force_get_limit_price(
+            Some(oracle_price_data.price),
+            None,
+            market.order_tick_size
+        )?
+    };
+
+    if order.is_oracle_pegged() {
+        validate!(
+            is_oracle_peg_distance_valid(limit_price, oracle_price_data.price)?,
+            ErrorCode::OraclePegDistanceTooLarge,
+            "Limit price {} is too far from the oracle price {}",
+            limit_price,
+            oracle_price_data.price
+        )?;
+    }
+
+    order.price = limit_price;
+    user.orders[order_index] = order;
+
+    let position = user.force_get_position_mut(market.market_index)?;
+    position.increase_open_orders(order.pos_direction, order.base_asset_amount)?;
+    position.num_open_orders = position.num_open_orders.safe_add(1)?;
+
+    validate!(
+        meets_initial_margin_requirement(user, market, oracle_price_data)?,
+        ErrorCode::InsufficientCollateral,
+        "User does not meet the initial margin requirement for the order"
+    )?;
+
+    // an ask borrows whatever of the market's token the position does not hold when it fills
+    if order.pos_direction == PositionDirection::Short {
+        check_user_borrow_limit(market, user.get_position(market.market_index)?)?;
+    }
+
+    // trigger orders are only submitted to the venue once a keeper triggers them, the first clip of a TWAP order is due
+    // right away
+    if params.order_type == OrderType::Twap {
+        execute_twap_clip(
+            user,
+            order_index,
+            market,
+            oracle_price_data,
+            fulfillment,
+            now
+        )?;
+    } else if params.order_type == OrderType::ImmediateOrCancel {
+        submit_immediate_or_cancel_order(
+            user,
+            order_index,
+            market,
+            fulfillment,
+            fulfillment.base_atoms_to_lots(params.min_base_asset_amount_to_fill.unwrap_or(0))?,
+            // rounded up, so that the order never fills for less quote than requested
+            fulfillment.quote_amount_to_lots(params.min_quote_asset_amount_to_fill.unwrap_or(0), PositionDirection::Long)?
+        )?;
+    } else if !is_trigger_order {
+        submit_order(user, order_index, market, fulfillment)?;
+    }
+
+    Ok(order_id)
+}
+
+/// Triggers a trigger order whose trigger condition is met at the oracle price and submits it to the venue. A triggered
+/// market order is filled against the book up to its price and the rest of it is cancelled. Triggering a leg of a
+/// one-cancels-other group cancels the other open orders of the group. The keeper is paid TRIGGER_ORDER_KEEPER_FEE by
+/// the user, unless paying it would leave the user's position below its maintenance margin requirement.
+pub fn trigger_order(
+    state: &State,
+    user: &mut User,
+    order_id: u32,
+    keeper: &mut User,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    fulfillment: &dyn SpotFulfillment,
+    slot: u64,
+    now: i64
+) -> SpedXSpotResult {
+    validate!(
+        market.is_market_active(now)? && market.are_fills_enabled(),
+        ErrorCode::MarketNotActive,
+        "Market {} is not active",
+        market.market_index
+    )?;
+
+    let order_index = user.get_order_index(order_id)?;
+    let order = user.orders[order_index];
+
+    validate!(
+        order.market_index == market.market_index,
+        ErrorCode::InvalidMarketIndex,
+        "Order {} does not belong to market {}",
+        order_id,
+        market.market_index
+    )?;
+
+    validate!(
+        order.order_has_trigger() && !order.has_trigger_order_triggered(),
+        ErrorCode::OrderNotTriggerable,
+        "Order {} is not an untriggered trigger order",
+        order_id
+    )?;
+
+    validate!(
+        !order.is_expired(now),
+        ErrorCode::OrderExpired,
+        "Order {} expired at {}",
+        order_id,
+        order.time_in_force
+    )?;
+
+    validate!(
+        is_market_oracle_valid_for_action(state, market, oracle_price_data, Actions::FillOrder)?,
+        ErrorCode::InvalidOracle,
+        "Oracle is not valid for triggering orders in market {}",
+        market.market_index
+    )?;
+
+    validate!(
+        order.should_trigger(oracle_price_data.price)?,
+        ErrorCode::OrderDidNotSatisfyTriggerCondition,
+        "Oracle price {} does not satisfy the trigger condition {:?} at {}",
+        oracle_price_data.price,
+        order.trigger_conditions,
+        order.price_for_trigger_orders
+    )?;
+
+    if order.oco_role == OcoRole::Leg {
+        validate!(
+            !is_oco_entry_pending(user, order.oco_group_id, fulfillment)?,
+            ErrorCode::OcoEntryOrderNotFilled,
+            "Entry order of the group of order {} has not been filled",
+            order_id
+        )?;
+
+        cancel_oco_group(user, order.oco_group_id, Some(order_index), market, fulfillment)?;
+    }
+
+    {
+        let order = &mut user.orders[order_index];
+        order.set_triggered();
+        order.slot = slot;
+
+        if order.price == 0 {
+            order.price = get_trigger_market_order_price(order.pos_direction, oracle_price_data.price, market.order_tick_size)?;
+        }
+    }
+
+    pay_trigger_order_keeper_fee(user, keeper, market, oracle_price_data)?;
+
+    submit_order(
+        user,
+        order_index,
+        market,
+        fulfillment
+    )
+}
+
+/// Limit price of a triggered market order placed without a price, DEFAULT_TRIGGER_MARKET_ORDER_SLIPPAGE_PCT away from
+/// the oracle price in the direction of the order
+/// precision: PRICE_PRECISION
+pub fn get_trigger_market_order_price(
+    direction: PositionDirection,
+    oracle_price: i64,
+    tick_size: u64
+) -> SpedXSpotResult<u64> {
+    let oracle_price = oracle_price.unsigned_abs();
+
+    let slippage = oracle_price
+        .cast::<u128>()?
+        .safe_mul(DEFAULT_TRIGGER_MARKET_ORDER_SLIPPAGE_PCT.cast()?)?
+        .safe_div(PERCENTAGE_PRECISION)?
+        .cast::<u64>()?;
+
+    let price = match direction {
+        PositionDirection::Short => oracle_price.safe_sub(slippage)?,
+        _ => oracle_price.safe_add(slippage)?
+    };
+
+    standardize_price(price, tick_size, direction)
+}
+
+/// Places a ladder of post-only quotes for the user on the market's fulfillment venue. The initial margin
+/// requirement is checked once for the whole batch. With `cancel_existing`, the user's open orders in the market, other
+/// than trigger orders, are cancelled first. A quote that would cross the book rests at the closest price that does not
+/// cross it. Returns the number of orders placed.
+pub fn place_multiple_orders(
+    state: &State,
+    user: &mut User,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    fulfillment: &dyn SpotFulfillment,
+    bids: Vec<CondensedOrder>,
+    asks: Vec<CondensedOrder>,
+    cancel_existing: bool,
+    slot: u64,
+    now: i64
+) -> SpedXSpotResult<u8> {
+    validate_market_for_orders(state, market, oracle_price_data, now)?;
+
+    let number_of_orders = bids.len().checked_add(asks.len()).safe_unwrap()?;
+
+    validate!(
+        number_of_orders > 0 && number_of_orders <= MAX_OPEN_ORDERS as usize,
+        ErrorCode::MaxNumberOfOrders,
+        "Cannot place {} orders at once",
+        number_of_orders
+    )?;
+
+    validate!(
+        !market.market_in_reduce_only_mode() && !user.is_reduce_only(),
+        ErrorCode::ReduceOnlyModeActive,
+        "Quotes cannot be placed while the market or the user is reduce-only"
+    )?;
+
+    if cancel_existing {
+        for order_index in 0..user.orders.len() {
+            let order = user.orders[order_index];
+
+            if order.is_order_open(market.market_index) && !order.order_has_trigger() {
+                cancel_order_by_index(user, order_index, market, fulfillment)?;
+            }
+        }
+    }
+
+    let existing_position_direction = user
+        .force_get_position_mut(market.market_index)?
+        .get_position_direction();
+
+    let mut order_indexes = Vec::with_capacity(number_of_orders);
+
+    for (side, condensed_order) in bids
+        .iter()
+        .map(|bid| (Side::Bid, bid))
+        .chain(asks.iter().map(|ask| (Side::Ask, ask)))
+    {
+        let base_asset_amount = fulfillment.base_lots_to_atoms(condensed_order.size_in_base_lots)?;
+
+        validate!(
+            base_asset_amount > 0 && base_asset_amount >= market.min_order_size,
+            ErrorCode::InvalidOrderSize,
+            "Order size {} is below the minimum order size {}",
+            base_asset_amount,
+            market.min_order_size
+        )?;
+
+        validate!(
+            condensed_order.price_in_ticks > 0 && condensed_order.last_valid_slot.is_none(),
+            ErrorCode::InvalidOrderPrice,
+            "Quotes need a price and cannot expire by slot"
+        )?;
+
+        let pos_direction = match side {
+            Side::Bid => PositionDirection::Long,
+            Side::Ask => PositionDirection::Short
+        };
+
+        let order_index = user.get_next_order_index()?;
+        let order_id = user.get_next_order_id();
+
+        user.orders[order_index] = Order {
+            slot,
+            price: fulfillment.ticks_to_price(condensed_order.price_in_ticks, pos_direction)?,
+            base_asset_amount,
+            order_id,
+            order_type: OrderType::PostOnly,
+            market_index: market.market_index,
+            order_status: OrderStatus::Active,
+            existing_position_direction,
+            pos_direction,
+            post_only: PostOnlyTypes::MustBePostOnly,
+            time_in_force: condensed_order.last_valid_unix_timestamp_in_seconds.unwrap_or(0).cast()?,
+            phoenix_price_in_ticks: condensed_order.price_in_ticks,
+            fail_silently_on_insufficient_funds_error: false,
+            treat_ioc_as_market: false,
+            ..Order::default()
+        };
+
+        let position = user.force_get_position_mut(market.market_index)?;
+        position.increase_open_orders(pos_direction, base_asset_amount)?;
+        position.num_open_orders = position.num_open_orders.safe_add(1)?;
+
+        order_indexes.push(order_index);
+    }
+
+    validate!(
+        meets_initial_margin_requirement(user, market, oracle_price_data)?,
+        ErrorCode::InsufficientCollateral,
+        "User does not meet the initial margin requirement for the orders"
+    )?;
+
+    if !asks.is_empty() {
+        check_user_borrow_limit(market, user.get_position(market.market_index)?)?;
+    }
+
+    let quotes: Vec<ExternalQuote> = order_indexes
+        .iter()
+        .map(|order_index| {
+            let order = user.orders[*order_index];
+
+            Ok(ExternalQuote {
+                side: order.get_phoenix_side(),
+                price_in_ticks: order.phoenix_price_in_ticks,
+                num_base_lots: fulfillment.base_atoms_to_lots(order.base_asset_amount)?
+            })
+        })
+        .collect::<SpedXSpotResult<_>>()?;
+
+    let resting_order_ids = fulfillment.place_quotes(&quotes)?;
+
+    for (order_index, resting_order_id) in order_indexes.into_iter().zip(resting_order_ids) {
+        let order = user.orders[order_index];
+
+        // post-only quotes cannot fill when they are placed, a quote that is not on the book was not placed
+        match resting_order_id {
+            Some((price_in_ticks, order_sequence_number)) => {
+                if price_in_ticks != order.phoenix_price_in_ticks {
+                    msg!("Quote {} would cross the book, it rests at {} ticks", order.order_id, price_in_ticks);
+                    user.orders[order_index].price = fulfillment.ticks_to_price(price_in_ticks, order.pos_direction)?;
+                }
+
+                user.orders[order_index].phoenix_price_in_ticks = price_in_ticks;
+                user.orders[order_index].phoenix_order_sequence_number = order_sequence_number;
+            },
+            None => {
+                user.orders[order_index].phoenix_price_in_ticks = 0;
+                cancel_order_by_index(user, order_index, market, fulfillment)?;
+            }
+        }
+    }
+
+    (number_of_orders as u64).cast()
+}
+
+/// Cancels an open order of the user. Fills that happened while the order was resting are settled first, the unfilled
+/// rest of the order is released from the position's open orders.
+pub fn cancel_order(
+    user: &mut User,
+    order_id: u32,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment
+) -> SpedXSpotResult {
+    let order_index = user.get_order_index(order_id)?;
+
+    validate!(
+        user.orders[order_index].market_index == market.market_index,
+        ErrorCode::InvalidMarketIndex,
+        "Order {} does not belong to market {}",
+        order_id,
+        market.market_index
+    )?;
+
+    cancel_order_by_index(user, order_index, market, fulfillment)
+}
+
+/// Amends an open order in place, keeping its order slot, order id and user order id. The order is pulled from the book
+/// first, settling the fills that happened while it was resting, so the new size applies on top of them. The margin
+/// reserved for the old order is released before the margin requirement of the modified order is checked. An order that
+/// was filled completely before it could be modified stays filled.
+pub fn modify_order(
+    state: &State,
+    user: &mut User,
+    order_id: u32,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    fulfillment: &dyn SpotFulfillment,
+    params: ModifyOrderParams,
+    slot: u64,
+    now: i64
+) -> SpedXSpotResult {
+    validate_market_for_orders(state, market, oracle_price_data, now)?;
+
+    let order_index = user.get_order_index(order_id)?;
+    let order = user.orders[order_index];
+
+    validate!(
+        order.market_index == market.market_index,
+        ErrorCode::InvalidMarketIndex,
+        "Order {} does not belong to market {}",
+        order_id,
+        market.market_index
+    )?;
+
+    validate!(
+        !order.is_twap(),
+        ErrorCode::OrderTypeNotSupported,
+        "TWAP orders cannot be modified"
+    )?;
+
+    let is_untriggered_trigger_order = order.order_has_trigger() && !order.has_trigger_order_triggered();
+
+    validate!(
+        params.trigger_price.is_none() || is_untriggered_trigger_order,
+        ErrorCode::OrderNotTriggerable,
+        "Order {} is not an untriggered trigger order",
+        order_id
+    )?;
+
+    validate!(
+        params.oracle_limit_spread.is_none() || order.is_oracle_pegged(),
+        ErrorCode::InvalidOrderPrice,
+        "Order {} is not an oracle pegged order",
+        order_id
+    )?;
+
+    validate!(
+        params.post_only.is_none() || !order.immediate_or_cancel,
+        ErrorCode::OrderTypeNotSupported,
+        "Immediate-or-cancel orders cannot be post-only"
+    )?;
+
+    pull_order_from_book(user, order_index, market, fulfillment)?;
+
+    if user.orders[order_index].order_status != OrderStatus::Active {
+        msg!("Order {} was filled before it could be modified", order_id);
+        return Ok(())
+    }
+
+    let mut order = user.orders[order_index];
+
+    // the modified order reserves its own unfilled size below
+    user.get_position_mut(market.market_index)?
+        .decrease_open_orders(order.pos_direction, order.get_base_asset_amount_unfilled()?)?;
+
+    if let Some(base_asset_amount) = params.base_asset_amount {
+        let base_asset_amount = fulfillment.base_lots_to_atoms(
+            fulfillment.base_atoms_to_lots(standardize_base_asset_amt(base_asset_amount, market.order_step_size)?)?
+        )?;
+
+        validate!(
+            base_asset_amount >= market.min_order_size && base_asset_amount > order.base_asset_filled,
+            ErrorCode::InvalidOrderSize,
+            "Order size {} must be at least the minimum order size {} and above the filled size {}",
+            base_asset_amount,
+            market.min_order_size,
+            order.base_asset_filled
+        )?;
+
+        order.base_asset_amount = base_asset_amount;
+    }
+
+    if let Some(price) = params.price {
+        if !order.is_oracle_pegged() {
+            validate!(
+                price > 0 || order.immediate_or_cancel,
+                ErrorCode::InvalidOrderPrice,
+                "Limit orders require a price"
+            )?;
+
+            order.price = standardize_price(price, market.order_tick_size, order.pos_direction)?;
+        }
+    }
+
+    if let Some(oracle_limit_spread) = params.oracle_limit_spread {
+        validate!(
+            oracle_limit_spread != 0,
+            ErrorCode::InvalidOrderPrice,
+            "Oracle pegged orders require a non-zero oracle limit spread"
+        )?;
+
+        order.oracle_limit_spread = oracle_limit_spread;
+    }
+
+    if order.is_oracle_pegged() {
+        let limit_price = order.force_get_limit_price(
+            Some(oracle_price_data.price),
+            None,
+            market.order_tick_size
+        )?;
+
+        validate!(
+            is_oracle_peg_distance_valid(limit_price, oracle_price_data.price)?,
+            ErrorCode::OraclePegDistanceTooLarge,
+            "Limit price {} is too far from the oracle price {}",
+            limit_price,
+            oracle_price_data.price
+        )?;
+
+        order.price = limit_price;
+    }
+
+    if let Some(trigger_price) = params.trigger_price {
+        validate!(
+            trigger_price > 0,
+            ErrorCode::InvalidOrderPrice,
+            "Trigger orders require a trigger price"
+        )?;
+
+        order.price_for_trigger_orders = trigger_price;
+    }
+
+    if let Some(post_only) = params.post_only {
+        order.post_only = match post_only {
+            PostOnlyTypes::NotPostOnly if order.order_type == OrderType::PostOnly => PostOnlyTypes::MustBePostOnly,
+            post_only => post_only
+        };
+    }
+
+    if let Some(reduce_only) = params.reduce_only {
+        order.reduce_only = reduce_only || market.market_in_reduce_only_mode() || user.is_reduce_only();
+    }
+
+    if let Some(time_in_force) = params.time_in_force {
+        validate!(
+            time_in_force >= 0,
+            ErrorCode::InvalidOrderExpiry,
+            "Time in force {} cannot be negative",
+            time_in_force
+        )?;
+
+        order.time_in_force = if time_in_force == 0 { 0 } else { now.safe_add(time_in_force)? };
+    }
+
+    validate!(
+        !order.is_expired(now),
+        ErrorCode::OrderExpired,
+        "Order {} has expired",
+        order_id
+    )?;
+
+    if order.reduce_only && !is_untriggered_trigger_order {
+        let base_asset_exposure = user
+            .get_position(market.market_index)?
+            .get_base_asset_exposure(market)?;
+
+        let reduce_only_base_asset_amount = get_reduce_only_base_asset_amount(&order, base_asset_exposure, market, fulfillment)?;
+
+        validate!(
+            reduce_only_base_asset_amount > 0,
+            ErrorCode::ReduceOnlyOrderIncreasesPosition,
+            "Reduce-only order would increase the position of {}",
+            base_asset_exposure
+        )?;
+
+        order.base_asset_amount = order.base_asset_filled.safe_add(reduce_only_base_asset_amount)?;
+    }
+
+    order.slot = slot;
+    user.orders[order_index] = order;
+
+    user.get_position_mut(market.market_index)?
+        .increase_open_orders(order.pos_direction, order.get_base_asset_amount_unfilled()?)?;
+
+    validate!(
+        meets_initial_margin_requirement(user, market, oracle_price_data)?,
+        ErrorCode::InsufficientCollateral,
+        "User does not meet the initial margin requirement for the modified order"
+    )?;
+
+    if order.pos_direction == PositionDirection::Short {
+        check_user_borrow_limit(market, user.get_position(market.market_index)?)?;
+    }
+
+    if !is_untriggered_trigger_order {
+        submit_order(user, order_index, market, fulfillment)?;
+    }
+
+    Ok(())
+}
+
+/// Re-prices the user's oracle pegged orders in a market to follow the oracle. An order is cancelled instead if it has
+/// expired, if the oracle is not valid for placing orders, if its limit price is too far from the oracle price, or if the
+/// user no longer meets the initial margin requirement. Post-only orders that would cross the book at the new price keep their current
+/// price. Returns the number of orders that were re-priced or cancelled.
+pub fn update_pegged_orders(
+    state: &State,
+    user: &mut User,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    fulfillment: &dyn SpotFulfillment,
+    slot: u64,
+    now: i64
+) -> SpedXSpotResult<u8> {
+    let is_oracle_valid = is_market_oracle_valid_for_action(state, market, oracle_price_data, Actions::OrderAdded)?;
+
+    let mut number_of_orders_updated: u8 = 0;
+
+    for order_index in 0..user.orders.len() {
+        let order = user.orders[order_index];
+
+        if !order.is_order_open(market.market_index) || !order.is_oracle_pegged() {
+            continue;
+        }
+
+        // the spread can drive the limit price to 0 or below, the order is cancelled in that case
+        let limit_price = order
+            .get_limit_price(Some(oracle_price_data.price), None, market.order_tick_size)
+            .ok()
+            .flatten();
+
+        let limit_price = match limit_price {
+            Some(limit_price) if is_oracle_valid
+                && !order.is_expired(now)
+                && is_oracle_peg_distance_valid(limit_price, oracle_price_data.price)? => limit_price,
+            _ => {
+                cancel_order_by_index(user, order_index, market, fulfillment)?;
+                number_of_orders_updated = number_of_orders_updated.safe_add(1)?;
+                continue;
+            }
+        };
+
+        let price_in_ticks = fulfillment.price_to_ticks(limit_price, order.pos_direction)?;
+
+        if order.is_resting_on_phoenix() && price_in_ticks == order.phoenix_price_in_ticks {
+            continue;
+        }
+
+        if order.is_post_only()
+            && order.post_only != PostOnlyTypes::PostOnlySlide
+            && fulfillment.does_order_cross(order.get_phoenix_side(), price_in_ticks)?
+        {
+            msg!("Post-only order {} would cross the book at {}, keeping its price", order.order_id, limit_price);
+            continue;
+        }
+
+        pull_order_from_book(user, order_index, market, fulfillment)?;
+
+        if user.orders[order_index].order_status == OrderStatus::Active {
+            if meets_initial_margin_requirement(user, market, oracle_price_data)? {
+                user.orders[order_index].price = limit_price;
+                user.orders[order_index].slot = slot;
+
+                submit_order(user, order_index, market, fulfillment)?;
+            } else {
+                cancel_order_by_index(user, order_index, market, fulfillment)?;
+            }
+        }
+
+        number_of_orders_updated = number_of_orders_updated.safe_add(1)?;
+    }
+
+    Ok(number_of_orders_updated)
+}
+
+/// Cancels the user's expired orders in a market, releasing the margin reserved for them. Fills that happened before
+/// the cancellation are settled. Returns the number of orders cancelled.
+pub fn expire_orders(
+    user: &mut User,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment,
+    now: i64
+) -> SpedXSpotResult<u8> {
+    let mut number_of_orders_expired: u8 = 0;
+
+    for order_index in 0..user.orders.len() {
+        let order = user.orders[order_index];
+
+        if !order.is_order_open(market.market_index) || !order.is_expired(now) {
+            continue;
+        }
+
+        cancel_order_by_index(user, order_index, market, fulfillment)?;
+
+        number_of_orders_expired = number_of_orders_expired.safe_add(1)?;
+    }
+
+    Ok(number_of_orders_expired)
+}
+
+/// Executes the clips of the user's TWAP orders in a market that are due. Each clip is an immediate-or-cancel order
+/// limited to the oracle price plus the order's maximum slippage, a clip that does not fill is caught up on with the
+/// next one. Returns the number of clips executed.
+pub fn fill_twap_orders(
+    state: &State,
+    user: &mut User,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    fulfillment: &dyn SpotFulfillment,
+    now: i64
+) -> SpedXSpotResult<u8> {
+    validate!(
+        market.is_market_active(now)? && market.are_fills_enabled(),
+        ErrorCode::MarketNotActive,
+        "Market {} is not active",
+        market.market_index
+    )?;
+
+    validate!(
+        is_market_oracle_valid_for_action(state, market, oracle_price_data, Actions::FillOrder)?,
+        ErrorCode::InvalidOracle,
+        "Oracle is not valid for filling orders in market {}",
+        market.market_index
+    )?;
+
+    let mut number_of_clips_executed: u8 = 0;
+
+    for order_index in 0..user.orders.len() {
+        let order = user.orders[order_index];
+
+        if !order.is_order_open(market.market_index) || !order.is_twap() || order.is_expired(now) {
+            continue;
+        }
+
+        if execute_twap_clip(user, order_index, market, oracle_price_data, fulfillment, now)? {
+            number_of_clips_executed = number_of_clips_executed.safe_add(1)?;
+        }
+    }
+
+    Ok(number_of_clips_executed)
+}
+
+/// Shows the next clip of the user's iceberg orders in a market whose visible clip has been filled completely. The clip is
+/// placed at the order's current price. Post-only orders that would cross the book are left for a later refill, unless
+/// they slide.
+/// Returns the number of orders refilled.
+pub fn refill_iceberg_orders(
+    user: &mut User,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment,
+    slot: u64,
+    now: i64
+) -> SpedXSpotResult<u8> {
+    validate!(
+        market.is_market_active(now)? && market.are_fills_enabled(),
+        ErrorCode::MarketNotActive,
+        "Market {} is not active",
+        market.market_index
+    )?;
+
+    let mut number_of_orders_refilled: u8 = 0;
+
+    for order_index in 0..user.orders.len() {
+        let order = user.orders[order_index];
+
+        if !order.is_order_open(market.market_index) || !order.is_iceberg() || order.is_expired(now) {
+            continue;
+        }
+
+        if order.is_resting_on_phoenix() {
+            let resting_base_lots = fulfillment.get_resting_order_base_lots(&order)?;
+
+            // the visible clip is still (partially) on the book
+            if resting_base_lots > 0 {
+                continue;
+            }
+        }
+
+        let price_in_ticks = fulfillment.price_to_ticks(order.price, order.pos_direction)?;
+
+        if order.is_post_only()
+            && order.post_only != PostOnlyTypes::PostOnlySlide
+            && fulfillment.does_order_cross(order.get_phoenix_side(), price_in_ticks)?
+        {
+            msg!("Post-only iceberg order {} would cross the book, refilling later", order.order_id);
+            continue;
+        }
+
+        // settles the filled clip, completing the order if it was the last one
+        pull_order_from_book(user, order_index, market, fulfillment)?;
+
+        if user.orders[order_index].order_status == OrderStatus::Active {
+            user.orders[order_index].slot = slot;
+
+            submit_order(user, order_index, market, fulfillment)?;
+        }
+
+        number_of_orders_refilled = number_of_orders_refilled.safe_add(1)?;
+    }
+
+    Ok(number_of_orders_refilled)
+}
+
+/// Checks that orders can be placed in the market: it must be active, have orders enabled and a valid oracle
+fn validate_market_for_orders(
+    state: &State,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    now: i64
+) -> SpedXSpotResult {
+    validate!(
+        market.is_market_active(now)? && market.are_fills_enabled(),
+        ErrorCode::MarketNotActive,
+        "Market {} is not active",
+        market.market_index
+    )?;
+
+    validate!(
+        market.orders_enabled,
+        ErrorCode::MarketOrdersDisabled,
+        "Orders are disabled for market {}",
+        market.market_index
+    )?;
+
+    validate!(
+        is_market_oracle_valid_for_action(state, market, oracle_price_data, Actions::OrderAdded)?,
+        ErrorCode::InvalidOracle,
+        "Oracle is not valid for placing orders in market {}",
+        market.market_index
+    )
+}
+
+/// Returns true if the oracle is valid for the action in the market, using the market's oracle guard rails
+pub fn is_market_oracle_valid_for_action(
+    state: &State,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    action: Actions
+) -> SpedXSpotResult<bool> {
+    let guard_rails = market.get_oracle_guard_rails(&state.oracle_guard_rails);
+
+    let validity = oracle_validity(
+        market.historical_oracle_data.last_oracle_twap,
+        oracle_price_data,
+        &guard_rails.validity
+    )?;
+
+    is_oracle_valid_for_action(Some(action), validity)
+}
+
+/// Returns true if the limit price of an oracle pegged order is within MAX_ORACLE_PEG_DISTANCE_PCT of the oracle price
+pub fn is_oracle_peg_distance_valid(
+    limit_price: u64,
+    oracle_price: i64
+) -> SpedXSpotResult<bool> {
+    let oracle_price = oracle_price.unsigned_abs();
+
+    if oracle_price == 0 {
+        return Ok(false)
+    }
+
+    // compared without dividing, so that a distance just over the maximum is not rounded down to it
+    let distance = limit_price
+        .abs_diff(oracle_price)
+        .cast::<u128>()?
+        .safe_mul(PERCENTAGE_PRECISION)?;
+
+    Ok(distance <= MAX_ORACLE_PEG_DISTANCE_PCT.cast::<u128>()?.safe_mul(oracle_price.cast()?)?)
+}
+
+/// Charges the user TRIGGER_ORDER_KEEPER_FEE and credits it to the keeper. The fee is not charged if it would leave the
+/// user's position below its maintenance margin requirement, so that triggering an order, e.g a stop loss, never makes
+/// a position liquidatable. Returns true if the fee was paid.
+fn pay_trigger_order_keeper_fee(
+    user: &mut User,
+    keeper: &mut User,
+    market: &Market,
+    oracle_price_data: &OraclePriceData
+) -> SpedXSpotResult<bool> {
+    let position = user.get_position_mut(market.market_index)?;
+    let quote_asset_amount = position.quote_asset_amount;
+    position.quote_asset_amount = quote_asset_amount.safe_sub(TRIGGER_ORDER_KEEPER_FEE.cast()?)?;
+
+    let meets_maintenance_margin_requirement = calculate_position_margin(
+        position,
+        market,
+        oracle_price_data,
+        MarginRequirementType::Maintenance
+    )?
+    .meets_margin_requirement()?;
+
+    if !meets_maintenance_margin_requirement {
+        msg!("Trigger order keeper fee would breach the maintenance margin of user {}, not charging it", user.authority);
+        user.get_position_mut(market.market_index)?.quote_asset_amount = quote_asset_amount;
+        return Ok(false)
+    }
+
+    let keeper_position = keeper.force_get_position_mut(market.market_index)?;
+    keeper_position.quote_asset_amount = keeper_position.quote_asset_amount.safe_add(TRIGGER_ORDER_KEEPER_FEE.cast()?)?;
+
+    Ok(true)
+}
+
+/// Returns true if the user's position in the market meets the initial margin requirement
+fn meets_initial_margin_requirement(
+    user: &User,
+    market: &Market,
+    oracle_price_data: &OraclePriceData
+) -> SpedXSpotResult<bool> {
+    calculate_position_margin(
+        user.get_position(market.market_index)?,
+        market,
+        oracle_price_data,
+        MarginRequirementType::Initial
+    )?
+    .meets_margin_requirement()
+}
+
+/// Places the unfilled part of an order on the market's venue at the order's price. The part that is filled immediately
+/// is settled at the price the venue measured, taker fee included, the venue's order id of the resting part is recorded
+/// on the order. Reduce-only orders are re-checked against the position first, as fills since the order was placed may
+/// have reduced it.
+fn submit_order(
+    user: &mut User,
+    order_index: usize,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment
+) -> SpedXSpotResult {
+    enforce_reduce_only(user, order_index, market, fulfillment)?;
+
+    let order = user.orders[order_index];
+
+    if order.order_status != OrderStatus::Active {
+        return Ok(())
+    }
+
+    if order.immediate_or_cancel {
+        return submit_immediate_or_cancel_order(
+            user,
+            order_index,
+            market,
+            fulfillment,
+            0,
+            0
+        )
+    }
+
+    let side = order.get_phoenix_side();
+
+    let price_in_ticks = fulfillment.price_to_ticks(order.price, order.pos_direction)?;
+    let num_base_lots = fulfillment.base_atoms_to_lots(order.get_base_asset_amount_to_show()?)?;
+
+    validate!(
+        price_in_ticks > 0,
+        ErrorCode::InvalidOrderPrice,
+        "Order price {} is below the venue's tick size",
+        order.price
+    )?;
+
+    validate!(
+        num_base_lots > 0,
+        ErrorCode::InvalidOrderSize,
+        "Order size is below the venue's lot size"
+    )?;
+
+    // a sliding post-only order is placed at the price it would slide to, so that its order id is known
+    let price_in_ticks = match order.post_only {
+        PostOnlyTypes::PostOnlySlide => fulfillment.get_post_only_slide_price_in_ticks(side, price_in_ticks)?,
+        PostOnlyTypes::CanBePostOnly if fulfillment.does_order_cross(side, price_in_ticks)? => {
+            msg!("Post-only order {} would cross the book, cancelling it", order.order_id);
+            return cancel_order_by_index(user, order_index, market, fulfillment)
+        },
+        _ => price_in_ticks
+    };
+
+    validate!(
+        price_in_ticks > 0,
+        ErrorCode::InvalidOrderPrice,
+        "Post-only order {} cannot slide below the first tick",
+        order.order_id
+    )?;
+
+    // post-only orders never take, so they cannot trade with the user's own orders
+    let (num_base_lots, internal_base_asset_amount, internal_quote_asset_amount) = if order.is_post_only() {
+        (num_base_lots, 0, 0)
+    } else {
+        apply_self_trade_behavior(
+            user,
+            order_index,
+            market,
+            fulfillment,
+            Some(price_in_ticks),
+            num_base_lots
+        )?
+    };
+
+    if num_base_lots == 0 || user.orders[order_index].order_status != OrderStatus::Active {
+        return Ok(())
+    }
+
+    let placement = fulfillment.place_order(&order, side, price_in_ticks, num_base_lots)?;
+
+    if placement.resting_base_lots > 0 {
+        user.orders[order_index].phoenix_price_in_ticks = price_in_ticks;
+        user.orders[order_index].phoenix_order_sequence_number = placement.order_sequence_number;
+    }
+
+    // matches with other users' orders move no funds on the venue, they are settled at the makers' prices
+    settle_order_fill(
+        user,
+        order_index,
+        market,
+        placement.fill.base_asset_amount.safe_add(internal_base_asset_amount)?,
+        placement.fill.quote_asset_amount.safe_add(internal_quote_asset_amount)?
+    )
+}
+
+/// Sends an immediate-or-cancel order to the market's venue. Nothing is left resting: the unfilled rest of the order is
+/// cancelled. The instruction fails if the fill is below the minimums, or below the whole order for fill-or-kill orders.
+fn submit_immediate_or_cancel_order(
+    user: &mut User,
+    order_index: usize,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment,
+    min_base_lots_to_fill: u64,
+    min_quote_lots_to_fill: u64
+) -> SpedXSpotResult {
+    enforce_reduce_only(user, order_index, market, fulfillment)?;
+
+    let order = user.orders[order_index];
+
+    if order.order_status != OrderStatus::Active {
+        return Ok(())
+    }
+
+    let num_base_lots = fulfillment.base_atoms_to_lots(order.get_base_asset_amount_unfilled()?)?;
+
+    validate!(
+        num_base_lots > 0,
+        ErrorCode::InvalidOrderSize,
+        "Order size is below the venue's lot size"
+    )?;
+
+    let price_in_ticks = if order.price == 0 {
+        None
+    } else {
+        Some(fulfillment.price_to_ticks(order.price, order.pos_direction)?)
+    };
+
+    let (num_base_lots_filled, num_quote_lots_filled) = fill_order_against_book(
+        user,
+        order_index,
+        market,
+        fulfillment,
+        num_base_lots,
+        price_in_ticks,
+        min_base_lots_to_fill,
+        min_quote_lots_to_fill
+    )?;
+
+    // the order may have shrunk against the user's own orders, what is left of a fill-or-kill order must be filled
+    if order.fill_or_kill {
+        validate!(
+            user.orders[order_index].get_base_asset_amount_unfilled()? == 0,
+            ErrorCode::FillOrKillOrderNotFilled,
+            "Fill-or-kill order filled {} of {} base lots",
+            num_base_lots_filled,
+            num_base_lots
+        )?;
+    } else {
+        validate!(
+            num_base_lots_filled >= min_base_lots_to_fill && num_quote_lots_filled >= min_quote_lots_to_fill,
+            ErrorCode::IOCOrderCancelledDueToNumBaseLotsNotFilled,
+            "Immediate-or-cancel order filled {} base lots, below the minimum of {} base lots or {} quote lots",
+            num_base_lots_filled,
+            min_base_lots_to_fill,
+            min_quote_lots_to_fill
+        )?;
+    }
+
+    // immediate-or-cancel orders never rest, whatever did not fill is cancelled
+    cancel_order_by_index(user, order_index, market, fulfillment)
+}
+
+/// Sends part of an order to the market's venue as an immediate-or-cancel order and settles what fills, leaving the
+/// order itself open. The venue's fill includes the price actually paid and the taker fee. Matches with other users'
+/// orders move no funds on the venue and are added at the makers' prices. The minimums cover those matches too, and a
+/// fill-or-kill order must fill everything left after its self-trade reduction. Returns the base and quote lots filled.
+fn fill_order_against_book(
+    user: &mut User,
+    order_index: usize,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment,
+    num_base_lots: u64,
+    price_in_ticks: Option<u64>,
+    min_base_lots_to_fill: u64,
+    min_quote_lots_to_fill: u64
+) -> SpedXSpotResult<(u64, u64)> {
+    let (num_base_lots, internal_base_asset_amount, internal_quote_asset_amount) = apply_self_trade_behavior(
+        user,
+        order_index,
+        market,
+        fulfillment,
+        price_in_ticks,
+        num_base_lots
+    )?;
+
+    let order = user.orders[order_index];
+
+    if num_base_lots == 0 || order.order_status != OrderStatus::Active {
+        return Ok((0, 0))
+    }
+
+    // the venue does not count matches with other users' orders as filled, so they are taken out of its minimums.
+    // The internal quote is rounded up, the check of the whole fill by the caller catches what that lets through
+    let internal_base_lots = fulfillment.base_atoms_to_lots(internal_base_asset_amount)?;
+    let internal_quote_lots = fulfillment.quote_amount_to_lots(internal_quote_asset_amount, PositionDirection::Long)?;
+
+    let min_base_lots_to_fill = if order.fill_or_kill {
+        num_base_lots.safe_sub(internal_base_lots)?
+    } else {
+        min_base_lots_to_fill.saturating_sub(internal_base_lots)
+    };
+    let min_quote_lots_to_fill = min_quote_lots_to_fill.saturating_sub(internal_quote_lots);
+
+    let fill = fulfillment.fill_order(
+        &order,
+        order.get_phoenix_side(),
+        price_in_ticks,
+        num_base_lots,
+        min_base_lots_to_fill,
+        min_quote_lots_to_fill
+    )?;
+
+    let base_asset_amount_filled = fill.base_asset_amount.safe_add(internal_base_asset_amount)?;
+    let quote_asset_amount_filled = fill.quote_asset_amount.safe_add(internal_quote_asset_amount)?;
+
+    settle_order_fill(
+        user,
+        order_index,
+        market,
+        base_asset_amount_filled,
+        quote_asset_amount_filled
+    )?;
+
+    // rounded down, so that rounding never meets the order's minimum quote to fill
+    Ok((
+        fulfillment.base_atoms_to_lots(base_asset_amount_filled)?,
+        fulfillment.quote_amount_to_lots(fill.quote_asset_amount, PositionDirection::Short)?
+            .safe_add(fulfillment.quote_amount_to_lots(internal_quote_asset_amount, PositionDirection::Short)?)?
+    ))
+}
+
+/// Applies the order's self-trade behavior before it is sent to the venue as a taker. The user's own orders the order
+/// would match are handled here: Abort fails the order, CancelProvide cancels them and DecrementTake reduces them and
+/// the order by the overlapping size without a trade. The order is then sent with DecrementTake. On OpenBook V2 the
+/// venue only sees the protocol's signer, so matches with other users' orders move no funds there and are settled
+/// between the two users instead, the makers when their order is next touched. On Phoenix other users' orders rest
+/// under their own trader PDAs and trade on the book. Returns the base lots left to send and the base and quote asset
+/// amounts matched against other users' orders.
+fn apply_self_trade_behavior(
+    user: &mut User,
+    order_index: usize,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment,
+    price_in_ticks: Option<u64>,
+    num_base_lots: u64
+) -> SpedXSpotResult<(u64, u64, u64)> {
+    let order = user.orders[order_index];
+
+    let crossing_orders = fulfillment.get_crossing_orders(order.get_phoenix_side(), price_in_ticks)?;
+
+    let mut num_base_lots_to_send = num_base_lots;
+    let mut num_base_lots_remaining = num_base_lots;
+    let mut internal_base_asset_amount: u64 = 0;
+    let mut internal_quote_asset_amount: u64 = 0;
+
+    for crossing_order in crossing_orders {
+        if num_base_lots_remaining == 0 {
+            break;
+        }
+
+        let num_base_lots_matched = num_base_lots_remaining.min(crossing_order.num_base_lots);
+        num_base_lots_remaining = num_base_lots_remaining.safe_sub(num_base_lots_matched)?;
+
+        if !crossing_order.is_signer {
+            continue;
+        }
+
+        let own_order_index = user.orders.iter().position(|own_order| {
+            own_order.is_order_open(order.market_index)
+                && own_order.phoenix_price_in_ticks == crossing_order.price_in_ticks
+                && own_order.phoenix_order_sequence_number == crossing_order.order_sequence_number
+        });
+
+        let base_asset_amount_matched = fulfillment.base_lots_to_atoms(num_base_lots_matched)?;
+
+        let own_order_index = match own_order_index {
+            Some(own_order_index) => own_order_index,
+            None => {
+                internal_base_asset_amount = internal_base_asset_amount.safe_add(base_asset_amount_matched)?;
+                internal_quote_asset_amount = internal_quote_asset_amount.safe_add(fulfillment.fill_quote_amount(
+                    base_asset_amount_matched,
+                    crossing_order.price_in_ticks,
+                    order.pos_direction
+                )?)?;
+
+                continue;
+            }
+        };
+
+        match order.self_trade_behavior {
+            SelfTradeBehaviorTypes::Abort => {
+                msg!("Order {} would match the user's order {}", order.order_id, user.orders[own_order_index].order_id);
+                return Err(ErrorCode::SelfTradeNotAllowed)
+            },
+            SelfTradeBehaviorTypes::CancelProvide => {
+                // the taker is not reduced, it moves on to the next order on the book
+                num_base_lots_remaining = num_base_lots_remaining.safe_add(num_base_lots_matched)?;
+
+                cancel_order_by_index(user, own_order_index, market, fulfillment)?;
+            },
+            SelfTradeBehaviorTypes::DecrementTake => {
+                let own_order = user.orders[own_order_index];
+
+                // the hidden size of an iceberg order cannot be reduced on the book, its clip is pulled instead and
+                // shown again by the refill crank
+                if own_order.is_iceberg() {
+                    pull_order_from_book(user, own_order_index, market, fulfillment)?;
+                } else if let Some((price_in_ticks, order_sequence_number)) = fulfillment.reduce_order(&own_order, num_base_lots_matched)? {
+                    // a venue that cannot reduce an order in place places the rest of it again under a new id
+                    user.orders[own_order_index].phoenix_price_in_ticks = price_in_ticks;
+                    user.orders[own_order_index].phoenix_order_sequence_number = order_sequence_number;
+                }
+
+                decrease_order_size(user, own_order_index, base_asset_amount_matched)?;
+                decrease_order_size(user, order_index, base_asset_amount_matched)?;
+
+                num_base_lots_to_send = num_base_lots_to_send.safe_sub(num_base_lots_matched)?;
+            }
+        }
+    }
+
+    Ok((num_base_lots_to_send, internal_base_asset_amount, internal_quote_asset_amount))
+}
+
+/// Reduces the size of an order without a fill, releasing the reduction from the position's open orders. An order with
+/// nothing left to fill is cancelled.
+fn decrease_order_size(
+    user: &mut User,
+    order_index: usize,
+    base_asset_amount: u64
+) -> SpedXSpotResult {
+    let order = user.orders[order_index];
+
+    if order.order_status != OrderStatus::Active {
+        return Ok(())
+    }
+
+    let base_asset_amount = base_asset_amount.min(order.get_base_asset_amount_unfilled()?);
+
+    let position = user.get_position_mut(order.market_index)?;
+    position.decrease_open_orders(order.pos_direction, base_asset_amount)?;
+
+    if base_asset_amount == order.get_base_asset_amount_unfilled()? {
+        position.num_open_orders = position.num_open_orders.safe_sub(1)?;
+
+        // nothing of the order is left on the book either
+        let order = &mut user.orders[order_index];
+        order.order_status = OrderStatus::Cancelled;
+        order.phoenix_price_in_ticks = 0;
+        order.phoenix_order_sequence_number = 0;
+    }
+
+    user.orders[order_index].base_asset_amount = order.base_asset_amount.safe_sub(base_asset_amount)?;
+
+    Ok(())
+}
+
+/// Fills the clips of a TWAP order that are due, up to the order's worst acceptable price relative to the oracle.
+/// Returns true if a clip was sent to the venue.
+fn execute_twap_clip(
+    user: &mut User,
+    order_index: usize,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    fulfillment: &dyn SpotFulfillment,
+    now: i64
+) -> SpedXSpotResult<bool> {
+    enforce_reduce_only(user, order_index, market, fulfillment)?;
+
+    let order = user.orders[order_index];
+
+    if order.order_status != OrderStatus::Active {
+        return Ok(false)
+    }
+
+    let base_asset_amount_due = order.get_twap_base_asset_amount_due(now)?;
+
+    if base_asset_amount_due <= order.base_asset_filled {
+        return Ok(false)
+    }
+
+    let num_base_lots = fulfillment.base_atoms_to_lots(base_asset_amount_due.safe_sub(order.base_asset_filled)?)?;
+
+    if num_base_lots == 0 {
+        return Ok(false)
+    }
+
+    let price_in_ticks = fulfillment.price_to_ticks(
+        order.get_twap_limit_price(oracle_price_data.price, market.order_tick_size)?,
+        order.pos_direction
+    )?;
+
+    validate!(
+        price_in_ticks > 0,
+        ErrorCode::InvalidOrderPrice,
+        "TWAP limit price is below the venue's tick size"
+    )?;
+
+    fill_order_against_book(
+        user,
+        order_index,
+        market,
+        fulfillment,
+        num_base_lots,
+        Some(price_in_ticks),
+        0,
+        0
+    )?;
+
+    Ok(true)
+}
+
+/// Unfilled size of a reduce-only order given the position's exposure, rounded down to whole lots of the venue
+/// precision: token mint precision
+fn get_reduce_only_base_asset_amount(
+    order: &Order,
+    base_asset_exposure: i64,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment
+) -> SpedXSpotResult<u64> {
+    fulfillment.base_lots_to_atoms(
+        fulfillment.base_atoms_to_lots(
+            order.get_standardized_base_amount_unfilled(Some(base_asset_exposure), market.order_step_size)?
+        )?
+    )
+}
+
+/// Shrinks a reduce-only order that is not on the book to the size of the position it reduces, releasing the excess
+/// from the position's open orders. The order is cancelled if it would only increase the position. Every order is
+/// treated as reduce-only while the market or the user is in reduce-only mode.
+fn enforce_reduce_only(
+    user: &mut User,
+    order_index: usize,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment
+) -> SpedXSpotResult {
+    if market.market_in_reduce_only_mode() || user.is_reduce_only() {
+        user.orders[order_index].reduce_only = true;
+    }
+
+    let order = user.orders[order_index];
+
+    if !order.reduce_only || order.order_status != OrderStatus::Active {
+        return Ok(())
+    }
+
+    let base_asset_exposure = user.get_position(order.market_index)?.get_base_asset_exposure(market)?;
+    let base_asset_amount_unfilled = order.get_base_asset_amount_unfilled()?;
+    let reduce_only_base_asset_amount = get_reduce_only_base_asset_amount(&order, base_asset_exposure, market, fulfillment)?;
+
+    if reduce_only_base_asset_amount >= base_asset_amount_unfilled {
+        return Ok(())
+    }
+
+    let base_asset_amount_released = base_asset_amount_unfilled.safe_sub(reduce_only_base_asset_amount)?;
+
+    let position = user.get_position_mut(order.market_index)?;
+    position.decrease_open_orders(order.pos_direction, base_asset_amount_released)?;
+
+    if reduce_only_base_asset_amount == 0 {
+        position.num_open_orders = position.num_open_orders.safe_sub(1)?;
+        user.orders[order_index].order_status = OrderStatus::Cancelled;
+
+        msg!("Reduce-only order {} would increase the position, cancelling it", order.order_id);
+    } else {
+        user.orders[order_index].base_asset_amount = order.base_asset_amount.safe_sub(base_asset_amount_released)?;
+    }
+
+    Ok(())
+}
+
+/// Takes an order off the venue's book, settling the fills that happened while it was resting at its resting price
+fn pull_order_from_book(
+    user: &mut User,
+    order_index: usize,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment
+) -> SpedXSpotResult {
+    let order = user.orders[order_index];
+
+    if !order.is_resting_on_phoenix() {
+        return Ok(())
+    }
+
+    let resting_base_lots = fulfillment.get_resting_order_base_lots(&order)?;
+
+    if resting_base_lots > 0 {
+        fulfillment.cancel_order(&order)?;
+    }
+
+    user.orders[order_index].phoenix_price_in_ticks = 0;
+    user.orders[order_index].phoenix_order_sequence_number = 0;
+
+    let shown_base_lots = fulfillment.base_atoms_to_lots(order.get_base_asset_amount_to_show()?)?;
+
+    let base_asset_amount_filled = fulfillment.base_lots_to_atoms(shown_base_lots.safe_sub(resting_base_lots)?)?;
+
+    settle_order_fill(
+        user,
+        order_index,
+        market,
+        base_asset_amount_filled,
+        fulfillment.fill_quote_amount(base_asset_amount_filled, order.phoenix_price_in_ticks, order.pos_direction)?
+    )
+}
+
+/// Cancels an open order by its index in the user's orders
+fn cancel_order_by_index(
+    user: &mut User,
+    order_index: usize,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment
+) -> SpedXSpotResult {
+    pull_order_from_book(user, order_index, market, fulfillment)?;
+
+    let order = user.orders[order_index];
+
+    // the order may have been filled completely while it was resting
+    if order.order_status != OrderStatus::Active {
+        return Ok(())
+    }
+
+    let position = user.get_position_mut(order.market_index)?;
+    position.decrease_open_orders(order.pos_direction, order.get_base_asset_amount_unfilled()?)?;
+    position.num_open_orders = position.num_open_orders.safe_sub(1)?;
+
+    user.orders[order_index].order_status = OrderStatus::Cancelled;
+
+    // the legs of a bracket have nothing to close if its entry never filled
+    if order.oco_role == OcoRole::Entry && order.base_asset_filled == 0 {
+        cancel_oco_group(user, order.oco_group_id, None, market, fulfillment)?;
+    }
+
+    Ok(())
+}
+
+/// Cancels the open orders of a one-cancels-other group, except the order at `except_order_index`
+fn cancel_oco_group(
+    user: &mut User,
+    oco_group_id: u8,
+    except_order_index: Option<usize>,
+    market: &Market,
+    fulfillment: &dyn SpotFulfillment
+) -> SpedXSpotResult {
+    for order_index in 0..user.orders.len() {
+        if Some(order_index) == except_order_index || !user.orders[order_index].is_in_oco_group(oco_group_id) {
+            continue;
+        }
+
+        msg!("Cancelling order {} of group {}", user.orders[order_index].order_id, oco_group_id);
+
+        cancel_order_by_index(user, order_index, market, fulfillment)?;
+    }
+
+    Ok(())
+}
+
+/// Returns true if the entry order of a one-cancels-other group is open and nothing of it has been filled yet, neither
+/// settled nor still unsettled on the venue's book
+fn is_oco_entry_pending(
+    user: &User,
+    oco_group_id: u8,
+    fulfillment: &dyn SpotFulfillment
+) -> SpedXSpotResult<bool> {
+    let entry = user
+        .orders
+        .iter()
+        .find(|order| order.is_in_oco_group(oco_group_id) && order.oco_role == OcoRole::Entry);
+
+    let entry = match entry {
+        Some(entry) if entry.base_asset_filled == 0 => entry,
+        _ => return Ok(false)
+    };
+
+    if !entry.is_resting_on_phoenix() {
+        return Ok(true)
+    }
+
+    let resting_base_lots = fulfillment.get_resting_order_base_lots(entry)?;
+
+    Ok(resting_base_lots >= fulfillment.base_atoms_to_lots(entry.get_base_asset_amount_to_show()?)?)
+}
+
+/// Settles a fill of an order on the user's position and completes the order once it is filled entirely
+/// quote asset amount precision: QUOTE_PRECISION
+fn settle_order_fill(
+    user: &mut User,
+    order_index: usize,
+    market: &Market,
+    base_asset_amount: u64,
+    quote_asset_amount: u64
+) -> SpedXSpotResult {
+    if base_asset_amount == 0 {
+        return Ok(())
+    }
+
+    let order = &mut user.orders[order_index];
+    order.base_asset_filled = order.base_asset_filled.safe_add(base_asset_amount)?;
+    order.quote_asset_filled = order.quote_asset_filled.safe_add(quote_asset_amount)?;
+
+    let is_order_filled = order.get_base_asset_amount_unfilled()? == 0;
+
+    if is_order_filled {
+        order.order_status = OrderStatus::Filled;
+        order.phoenix_price_in_ticks = 0;
+        order.phoenix_order_sequence_number = 0;
+    }
+
+    let direction = order.pos_direction;
+
+    let position = user.get_position_mut(market.market_index)?;
+    position.apply_fill(direction, base_asset_amount, quote_asset_amount)?;
+    position.decrease_open_orders(direction, base_asset_amount)?;
+
+    if is_order_filled {
+        position.num_open_orders = position.num_open_orders.safe_sub(1)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        math::constants::{
+            BASE_PRECISION_I64,
+            BASE_PRECISION_U64,
+            PRICE_PRECISION_I64,
+            PRICE_PRECISION_U64,
+            QUOTE_PRECISION_I64,
+            QUOTE_PRECISION_U64,
+            SPOT_CUMULATIVE_INTEREST_PRECISION,
+            SPOT_WEIGHT_PRECISION
+        },
+        state::{
+            enums::SpotFulfillmentType,
+            oracle::HistoricalPriceData,
+            user_position::Position
+        },
+        controller::fulfillment::{
+            ExternalCrossingOrder,
+            ExternalSpotFill,
+            ExternalSpotPlacement
+        }
+    };
+
+    /// Venue with whole-token base lots and $1 ticks and quote lots, whose asks are all resting under the signer or
+    /// under other traders. Fills behave like Phoenix with DecrementTake: matches with the signer's orders reduce the
+    /// taker without a fill and do not count towards the minimums. Takers pay a 10 bps fee.
+    struct MockFulfillment {
+        asks: Vec<ExternalCrossingOrder>
+    }
+
+    impl MockFulfillment {
+        /// Matches a bid against the asks up to its price, returning the base lots it matched and what it filled
+        fn match_bid(&self, price_in_ticks: Option<u64>, num_base_lots: u64) -> SpedXSpotResult<(u64, ExternalSpotFill)> {
+            let mut num_base_lots_remaining = num_base_lots;
+            let mut fill = ExternalSpotFill::default();
+
+            for crossing_order in self.get_crossing_orders(Side::Bid, price_in_ticks)? {
+                let num_base_lots_matched = num_base_lots_remaining.min(crossing_order.num_base_lots);
+                num_base_lots_remaining = num_base_lots_remaining.safe_sub(num_base_lots_matched)?;
+
+                if !crossing_order.is_signer {
+                    let base_asset_amount = self.base_lots_to_atoms(num_base_lots_matched)?;
+
+                    fill.base_asset_amount = fill.base_asset_amount.safe_add(base_asset_amount)?;
+                    fill.quote_asset_amount = fill.quote_asset_amount.safe_add(
+                        self.fill_quote_amount(base_asset_amount, crossing_order.price_in_ticks, PositionDirection::Long)?
+                    )?;
+                }
+            }
+
+            fill.quote_asset_amount = fill.quote_asset_amount.safe_add(fill.quote_asset_amount.safe_mul(10)?.safe_div(10_000)?)?;
+
+            Ok((num_base_lots.safe_sub(num_base_lots_remaining)?, fill))
+        }
+    }
+
+    impl SpotFulfillment for MockFulfillment {
+        fn get_fulfillment_type(&self) -> SpotFulfillmentType {
+            SpotFulfillmentType::PhoenixV1
+        }
+
+        fn base_atoms_to_lots(&self, base_atoms: u64) -> SpedXSpotResult<u64> {
+            base_atoms.safe_div(BASE_PRECISION_U64)
+        }
+
+        fn base_lots_to_atoms(&self, num_base_lots: u64) -> SpedXSpotResult<u64> {
+            num_base_lots.safe_mul(BASE_PRECISION_U64)
+        }
+
+        fn quote_amount_to_lots(&self, quote_asset_amount: u64, direction: PositionDirection) -> SpedXSpotResult<u64> {
+            match direction {
+                PositionDirection::Long => quote_asset_amount.safe_add(QUOTE_PRECISION_U64 - 1)?.safe_div(QUOTE_PRECISION_U64),
+                _ => quote_asset_amount.safe_div(QUOTE_PRECISION_U64)
+            }
+        }
+
+        fn price_to_ticks(&self, price: u64, _direction: PositionDirection) -> SpedXSpotResult<u64> {
+            price.safe_div(PRICE_PRECISION_U64)
+        }
+
+        fn ticks_to_price(&self, price_in_ticks: u64, _direction: PositionDirection) -> SpedXSpotResult<u64> {
+            price_in_ticks.safe_mul(PRICE_PRECISION_U64)
+        }
+
+        fn fill_quote_amount(&self, base_atoms: u64, price_in_ticks: u64, _direction: PositionDirection) -> SpedXSpotResult<u64> {
+            base_atoms.safe_div(BASE_PRECISION_U64)?.safe_mul(price_in_ticks)?.safe_mul(QUOTE_PRECISION_U64)
+        }
+
+        fn get_best_opposite_price_in_ticks(&self, side: Side) -> SpedXSpotResult<Option<u64>> {
+            Ok(match side {
+                Side::Bid => self.asks.first().map(|ask| ask.price_in_ticks),
+                Side::Ask => None
+            })
+        }
+
+        fn get_crossing_orders(&self, side: Side, limit_price_in_ticks: Option<u64>) -> SpedXSpotResult<Vec<ExternalCrossingOrder>> {
+            Ok(match side {
+                Side::Bid => self.asks
+                    .iter()
+                    .filter(|ask| !matches!(limit_price_in_ticks, Some(limit) if ask.price_in_ticks > limit))
+                    .copied()
+                    .collect(),
+                Side::Ask => vec![]
+            })
+        }
+
+        fn get_resting_order_base_lots(&self, _order: &Order) -> SpedXSpotResult<u64> {
+            Ok(0)
+        }
+
+        fn place_order(&self, _order: &Order, _side: Side, price_in_ticks: u64, num_base_lots: u64) -> SpedXSpotResult<ExternalSpotPlacement> {
+            let (num_base_lots_matched, fill) = self.match_bid(Some(price_in_ticks), num_base_lots)?;
+
+            Ok(ExternalSpotPlacement {
+                order_sequence_number: 3,
+                resting_base_lots: num_base_lots.safe_sub(num_base_lots_matched)?,
+                fill
+            })
+        }
+
+        fn fill_order(
+            &self,
+            _order: &Order,
+            _side: Side,
+            price_in_ticks: Option<u64>,
+            num_base_lots: u64,
+            min_base_lots_to_fill: u64,
+            min_quote_lots_to_fill: u64
+        ) -> SpedXSpotResult<ExternalSpotFill> {
+            let (_, fill) = self.match_bid(price_in_ticks, num_base_lots)?;
+
+            if self.base_atoms_to_lots(fill.base_asset_amount)? < min_base_lots_to_fill
+                || self.quote_amount_to_lots(fill.quote_asset_amount, PositionDirection::Short)? < min_quote_lots_to_fill
+            {
+                return Err(ErrorCode::PhoenixCpiFailed)
+            }
+
+            Ok(fill)
+        }
+
+        fn place_quotes(&self, _quotes: &[ExternalQuote]) -> SpedXSpotResult<Vec<Option<(u64, u64)>>> {
+            unimplemented!()
+        }
+
+        fn reduce_order(&self, _order: &Order, _num_base_lots: u64) -> SpedXSpotResult<Option<(u64, u64)>> {
+            unimplemented!()
+        }
+
+        fn cancel_order(&self, _order: &Order) -> SpedXSpotResult {
+            Ok(())
+        }
+
+        fn settle_funds(&self) -> SpedXSpotResult {
+            Ok(())
+        }
+    }
+
+    /// User with an open immediate-or-cancel bid for 5 tokens at up to $102
+    fn get_user_with_bid(fill_or_kill: bool) -> User {
+        let mut user = User::default();
+        user.orders[0] = Order {
+            order_id: 1,
+            order_type: OrderType::ImmediateOrCancel,
+            order_status: OrderStatus::Active,
+            pos_direction: PositionDirection::Long,
+            price: 102 * PRICE_PRECISION_U64,
+            base_asset_amount: 5 * BASE_PRECISION_U64,
+            immediate_or_cancel: true,
+            fill_or_kill,
+            self_trade_behavior: SelfTradeBehaviorTypes::DecrementTake,
+            ..Order::default()
+        };
+        user.positions[0] = Position {
+            open_bids: 5 * BASE_PRECISION_I64,
+            num_open_orders: 1,
+            ..Position::default()
+        };
+        user
+    }
+
+    /// Book with 2 tokens of another user's ask resting under the signer at $100, and 3 tokens at $101 from elsewhere
+    fn get_fulfillment_with_other_users_ask() -> MockFulfillment {
+        MockFulfillment {
+            asks: vec![
+                ExternalCrossingOrder { price_in_ticks: 100, order_sequence_number: 1, num_base_lots: 2, is_signer: true },
+                ExternalCrossingOrder { price_in_ticks: 101, order_sequence_number: 2, num_base_lots: 3, is_signer: false }
+            ]
+        }
+    }
+
+    #[test]
+    fn oracle_peg_distance() {
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+
+        // 10% away from the oracle is the maximum
+        assert!(is_oracle_peg_distance_valid(90_000_000, oracle_price).unwrap());
+        assert!(is_oracle_peg_distance_valid(110_000_000, oracle_price).unwrap());
+        assert!(is_oracle_peg_distance_valid(100_000_000, oracle_price).unwrap());
+
+        assert!(!is_oracle_peg_distance_valid(89_999_999, oracle_price).unwrap());
+        assert!(!is_oracle_peg_distance_valid(110_000_001, oracle_price).unwrap());
+
+        // without an oracle price no distance is valid
+        assert!(!is_oracle_peg_distance_valid(100_000_000, 0).unwrap());
+    }
+
+    #[test]
+    fn trigger_market_order_price() {
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+
+        // 5% through the oracle price, rounded towards the oracle to the tick size
+        assert_eq!(get_trigger_market_order_price(PositionDirection::Long, oracle_price, 1_000).unwrap(), 105_000_000);
+        assert_eq!(get_trigger_market_order_price(PositionDirection::Short, oracle_price, 1_000).unwrap(), 95_000_000);
+
+        assert_eq!(get_trigger_market_order_price(PositionDirection::Long, 100_001_000, 1_000).unwrap(), 105_001_000);
+        assert_eq!(get_trigger_market_order_price(PositionDirection::Short, 100_001_000, 1_000).unwrap(), 95_001_000);
+    }
+
+    #[test]
+    fn trigger_order_keeper_fee_respects_maintenance_margin() {
+        let market = Market {
+            decimals: 9,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION * 9 / 10,
+            maintenance_liability_weight: SPOT_WEIGHT_PRECISION * 11 / 10,
+            historical_oracle_data: HistoricalPriceData::default_price(100 * PRICE_PRECISION_I64),
+            ..Market::default()
+        };
+        let oracle_price_data = OraclePriceData {
+            price: 100 * PRICE_PRECISION_I64,
+            confidence: PRICE_PRECISION_U64,
+            delay: 1,
+            has_sufficient_data_points: true,
+        };
+        let keeper_fee: i64 = TRIGGER_ORDER_KEEPER_FEE.cast().unwrap();
+
+        // short 1 token: a maintenance requirement of $100 * 110%, exactly covered after the fee
+        let mut user = User::default();
+        user.positions[0] = Position {
+            base_asset_amount: -1_000_000_000,
+            quote_asset_amount: 110 * QUOTE_PRECISION_I64 + keeper_fee,
+            ..Position::default()
+        };
+        let mut keeper = User::default();
+
+        assert!(pay_trigger_order_keeper_fee(&mut user, &mut keeper, &market, &oracle_price_data).unwrap());
+        assert_eq!(user.positions[0].quote_asset_amount, 110 * QUOTE_PRECISION_I64);
+        assert_eq!(keeper.get_position(0).unwrap().quote_asset_amount, keeper_fee);
+
+        // the fee would leave the position 1 short of its requirement, neither side is charged
+        user.positions[0].quote_asset_amount = 110 * QUOTE_PRECISION_I64 + keeper_fee - 1;
+
+        assert!(!pay_trigger_order_keeper_fee(&mut user, &mut keeper, &market, &oracle_price_data).unwrap());
+        assert_eq!(user.positions[0].quote_asset_amount, 110 * QUOTE_PRECISION_I64 + keeper_fee - 1);
+        assert_eq!(keeper.get_position(0).unwrap().quote_asset_amount, keeper_fee);
+    }
+
+    #[test]
+    fn fill_or_kill_order_crosses_another_users_order() {
+        let market = Market::default();
+        let fulfillment = get_fulfillment_with_other_users_ask();
+
+        // 2 tokens match the other user's order at $100 without moving funds, the venue fills the other 3 at $101 plus
+        // its fee
+        let mut user = get_user_with_bid(true);
+        submit_immediate_or_cancel_order(&mut user, 0, &market, &fulfillment, 0, 0).unwrap();
+
+        assert_eq!(user.orders[0].order_status, OrderStatus::Filled);
+        assert_eq!(user.orders[0].base_asset_filled, 5 * BASE_PRECISION_U64);
+        assert_eq!(user.orders[0].quote_asset_filled, 503_303_000);
+        assert_eq!(user.positions[0].base_asset_amount, 5 * BASE_PRECISION_I64);
+        assert_eq!(user.positions[0].quote_asset_amount, -503_303_000);
+        assert_eq!(user.positions[0].open_bids, 0);
+        assert_eq!(user.positions[0].num_open_orders, 0);
+
+        // the minimums of an immediate-or-cancel order count the match with the other user's order as filled
+        let mut user = get_user_with_bid(false);
+        submit_immediate_or_cancel_order(&mut user, 0, &market, &fulfillment, 5, 503).unwrap();
+        assert_eq!(user.orders[0].order_status, OrderStatus::Filled);
+
+        // without enough liquidity behind the other user's order, the fill-or-kill order still fails
+        let fulfillment = MockFulfillment {
+            asks: vec![
+                fulfillment.asks[0],
+                ExternalCrossingOrder { num_base_lots: 2, ..fulfillment.asks[1] }
+            ]
+        };
+        let mut user = get_user_with_bid(true);
+        assert!(submit_immediate_or_cancel_order(&mut user, 0, &market, &fulfillment, 0, 0).is_err());
+    }
+
+    #[test]
+    fn limit_order_settles_the_fill_the_venue_measured() {
+        let market = Market::default();
+        let fulfillment = get_fulfillment_with_other_users_ask();
+
+        // a limit bid for 6 tokens at $102
+        let mut user = get_user_with_bid(false);
+        user.orders[0] = Order {
+            order_type: OrderType::Limit,
+            immediate_or_cancel: false,
+            base_asset_amount: 6 * BASE_PRECISION_U64,
+            ..user.orders[0]
+        };
+        user.positions[0].open_bids = 6 * BASE_PRECISION_I64;
+
+        submit_order(&mut user, 0, &market, &fulfillment).unwrap();
+
+        // 2 tokens at the other user's $100, 3 at the venue's $101 and its fee rather than at the $102 limit, 1 rests
+        assert_eq!(user.orders[0].order_status, OrderStatus::Active);
+        assert_eq!(user.orders[0].base_asset_filled, 5 * BASE_PRECISION_U64);
+        assert_eq!(user.orders[0].quote_asset_filled, 503_303_000);
+        assert_eq!(user.orders[0].phoenix_price_in_ticks, 102);
+        assert_eq!(user.orders[0].phoenix_order_sequence_number, 3);
+        assert_eq!(user.positions[0].base_asset_amount, 5 * BASE_PRECISION_I64);
+        assert_eq!(user.positions[0].quote_asset_amount, -503_303_000);
+        assert_eq!(user.positions[0].open_bids, BASE_PRECISION_I64);
+    }
+
+    /// Market with $1 ticks and whole-token steps, that accepts orders at a $100 oracle price
+    fn get_market_accepting_orders() -> Market {
+        Market {
+            decimals: 9,
+            orders_enabled: true,
+            order_tick_size: PRICE_PRECISION_U64,
+            order_step_size: BASE_PRECISION_U64,
+            min_order_size: BASE_PRECISION_U64,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION * 8 / 10,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION * 9 / 10,
+            initial_liability_weight: SPOT_WEIGHT_PRECISION * 12 / 10,
+            maintenance_liability_weight: SPOT_WEIGHT_PRECISION * 11 / 10,
+            historical_oracle_data: HistoricalPriceData::default_price(100 * PRICE_PRECISION_I64),
+            ..Market::default()
+        }
+    }
+
+    /// Immediate-or-cancel entry for 5 tokens at up to $102, a take-profit at $110 and a stop-loss at $90
+    fn get_bracket_params() -> (OrderParams, OrderParams, OrderParams) {
+        let entry = OrderParams {
+            order_type: OrderType::ImmediateOrCancel,
+            direction: PositionDirection::Long,
+            base_asset_amount: 5 * BASE_PRECISION_U64,
+            price: 102 * PRICE_PRECISION_U64,
+            ..OrderParams::default()
+        };
+        let take_profit = OrderParams {
+            order_type: OrderType::TriggerLimit,
+            direction: PositionDirection::Short,
+            base_asset_amount: 5 * BASE_PRECISION_U64,
+            price: 110 * PRICE_PRECISION_U64,
+            trigger_price: Some(110 * PRICE_PRECISION_U64),
+            trigger_condition: OrderTriggerConditions::Above,
+            ..OrderParams::default()
+        };
+        let stop_loss = OrderParams {
+            order_type: OrderType::TriggerMarket,
+            direction: PositionDirection::Short,
+            base_asset_amount: 5 * BASE_PRECISION_U64,
+            trigger_price: Some(90 * PRICE_PRECISION_U64),
+            trigger_condition: OrderTriggerConditions::Below,
+            ..OrderParams::default()
+        };
+        (entry, take_profit, stop_loss)
+    }
+
+    #[test]
+    fn asks_are_held_to_the_user_borrow_limit() {
+        let state = State::default();
+        let market = Market {
+            max_user_borrow_limit: 3 * BASE_PRECISION_U64,
+            ..get_market_accepting_orders()
+        };
+        let oracle_price_data = OraclePriceData {
+            price: 100 * PRICE_PRECISION_I64,
+            confidence: PRICE_PRECISION_U64 / 100,
+            delay: 1,
+            has_sufficient_data_points: true,
+        };
+        let fulfillment = MockFulfillment { asks: vec![] };
+
+        let place_ask = |base_asset_amount: u64| {
+            let mut user = User::default();
+            user.positions[0].quote_asset_amount = 10_000 * QUOTE_PRECISION_I64;
+
+            let params = OrderParams {
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount,
+                price: 105 * PRICE_PRECISION_U64,
+                ..OrderParams::default()
+            };
+
+            place_order(&state, &mut user, &market, &oracle_price_data, &fulfillment, params, 1, 1).map(|_| user)
+        };
+
+        // selling 4 tokens the user does not hold would borrow them once the ask fills
+        assert_eq!(place_ask(4 * BASE_PRECISION_U64).err(), Some(ErrorCode::UserBorrowLimitExceeded));
+
+        let user = place_ask(3 * BASE_PRECISION_U64).unwrap();
+        assert_eq!(user.positions[0].open_asks, -3 * BASE_PRECISION_I64);
+    }
+
+    #[test]
+    fn bracket_orders_require_closing_trigger_legs() {
+        let state = State::default();
+        let market = get_market_accepting_orders();
+        let oracle_price_data = OraclePriceData {
+            price: 100 * PRICE_PRECISION_I64,
+            confidence: PRICE_PRECISION_U64 / 100,
+            delay: 1,
+            has_sufficient_data_points: true,
+        };
+        let fulfillment = get_fulfillment_with_other_users_ask();
+        let (entry, take_profit, stop_loss) = get_bracket_params();
+
+        let place = |take_profit: Option<OrderParams>, stop_loss: Option<OrderParams>| {
+            let mut user = User::default();
+            place_bracket_orders(&state, &mut user, &market, &oracle_price_data, &fulfillment, entry, take_profit, stop_loss, 1, 1)
+        };
+
+        assert_eq!(place(None, None), Err(ErrorCode::InvalidBracketOrder));
+
+        // a take-profit of a long must trigger above the entry
+        let leg = OrderParams { trigger_condition: OrderTriggerConditions::Below, ..take_profit };
+        assert_eq!(place(Some(leg), None), Err(ErrorCode::InvalidBracketOrder));
+
+        // and close it
+        let leg = OrderParams { direction: PositionDirection::Long, ..take_profit };
+        assert_eq!(place(Some(leg), None), Err(ErrorCode::InvalidBracketOrder));
+
+        // legs only fill once triggered
+        let leg = OrderParams { order_type: OrderType::Limit, ..stop_loss };
+        assert_eq!(place(None, Some(leg)), Err(ErrorCode::InvalidBracketOrder));
+    }
+
+    #[test]
+    fn bracket_legs_stay_open_after_the_entry_fills_and_are_cancelled_without_a_fill() {
+        let state = State::default();
+        let market = get_market_accepting_orders();
+        let oracle_price_data = OraclePriceData {
+            price: 100 * PRICE_PRECISION_I64,
+            confidence: PRICE_PRECISION_U64 / 100,
+            delay: 1,
+            has_sufficient_data_points: true,
+        };
+        let (entry, take_profit, stop_loss) = get_bracket_params();
+
+        let get_user = || {
+            let mut user = User::default();
+            user.positions[0].quote_asset_amount = 10_000 * QUOTE_PRECISION_I64;
+            user
+        };
+
+        // the entry fills against the book, the reduce-only legs wait for their triggers
+        let fulfillment = get_fulfillment_with_other_users_ask();
+        let mut user = get_user();
+        let entry_id = place_bracket_orders(
+            &state, &mut user, &market, &oracle_price_data, &fulfillment, entry, Some(take_profit), Some(stop_loss), 1, 1
+        ).unwrap();
+
+        let entry_order = user.orders.iter().find(|order| order.order_id == entry_id).unwrap();
+        assert_eq!(entry_order.order_status, OrderStatus::Filled);
+        assert_eq!(entry_order.oco_role, OcoRole::Entry);
+        assert_eq!(user.positions[0].base_asset_amount, 5 * BASE_PRECISION_I64);
+
+        let legs: Vec<&Order> = user.orders.iter().filter(|order| order.oco_role == OcoRole::Leg).collect();
+        assert_eq!(legs.len(), 2);
+        for leg in legs {
+            assert_eq!(leg.order_status, OrderStatus::Active);
+            assert_eq!(leg.oco_group_id, entry_order.oco_group_id);
+            assert!(leg.reduce_only);
+        }
+        assert_eq!(user.positions[0].num_open_orders, 2);
+
+        // with an empty book the entry is cancelled unfilled, and takes its legs with it
+        let fulfillment = MockFulfillment { asks: vec![] };
+        let mut user = get_user();
+        place_bracket_orders(
+            &state, &mut user, &market, &oracle_price_data, &fulfillment, entry, Some(take_profit), Some(stop_loss), 1, 1
+        ).unwrap();
+
+        assert!(user.orders.iter().all(|order| order.order_status != OrderStatus::Active));
+        assert_eq!(user.positions[0].num_open_orders, 0);
+        assert_eq!(user.positions[0].open_bids, 0);
+        assert_eq!(user.positions[0].open_asks, 0);
+    }
+}
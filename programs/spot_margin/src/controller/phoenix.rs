@@ -0,0 +1,530 @@
+//! CPIs into the Phoenix program. Each user's orders are placed under the user's own trader PDA and its seat, funded by
+//! the protocol's signer PDA for the duration of the CPI. As the authority of the Phoenix markets, the signer also
+//! approves and evicts the seats of the trader PDAs.
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::{
+    self,
+    Transfer
+};
+use solana_program::{
+    instruction::{
+        AccountMeta,
+        Instruction
+    },
+    program::invoke_signed
+};
+
+use phoenix::{
+    program::{
+        get_seat_address,
+        instruction_builders::{
+            create_change_seat_status_instruction,
+            create_evict_seat_instruction,
+            create_request_seat_authorized_instruction
+        },
+        new_order::MultipleOrderPacket,
+        status::SeatApprovalStatus,
+        withdraw::WithdrawParams,
+        CancelMultipleOrdersByIdParams,
+        CancelOrderParams,
+        MarketHeader,
+        PhoenixInstruction,
+        ReduceOrderParams
+    },
+    state::OrderPacket
+};
+
+use crate::{
+    error::{
+        SpedXSpotResult,
+        ErrorCode
+    },
+    math::constants::QUOTE_SPOT_MARKET_INDEX,
+    state::{
+        config::State,
+        enums::SpotFulfillmentType,
+        helpers::{
+            get_phoenix_trader_seeds,
+            get_signer_seeds
+        },
+        market::Market,
+        phoenix::{
+            fetch_market_header,
+            get_phoenix_trader_address,
+            validate_phoenix_market_header,
+            PhoenixMarketAccounts,
+            PhoenixSeatAccounts
+        }
+    },
+    load,
+    validate
+};
+
+/// Seeds of the PDAs that sign the Phoenix CPIs of a user's orders: the user's trader PDA, which places them, and the
+/// protocol's signer, which funds them
+#[derive(Clone, Copy, Debug)]
+pub struct PhoenixSigners {
+    pub user: Pubkey,
+    pub trader_bump: u8,
+    pub signer_nonce: u8
+}
+
+/// Checks that the Phoenix accounts passed to an order instruction belong to the market, to the protocol's signer and
+/// to the user's trader PDA
+pub fn validate_phoenix_accounts(
+    state: &State,
+    market: &Market,
+    trader: &Pubkey,
+    phoenix_accounts: &PhoenixMarketAccounts
+) -> SpedXSpotResult<MarketHeader> {
+    validate!(
+        phoenix_accounts.signer.key() == state.signer,
+        ErrorCode::InvalidSigner,
+        "Signer {} is not the protocol signer",
+        phoenix_accounts.signer.key()
+    )?;
+
+    validate!(
+        phoenix_accounts.trader.key() == *trader,
+        ErrorCode::InvalidPhoenixTrader,
+        "Phoenix trader {} does not belong to the user",
+        phoenix_accounts.trader.key()
+    )?;
+
+    validate!(
+        market.fulfillment_type == SpotFulfillmentType::PhoenixV1,
+        ErrorCode::InvalidFulfillmentType,
+        "Market {} is not fulfilled by Phoenix",
+        market.market_index
+    )?;
+
+    validate!(
+        market.phoenix_market != Pubkey::default() && phoenix_accounts.phoenix_market.key() == market.phoenix_market,
+        ErrorCode::InvalidPhoenixMarket,
+        "Phoenix market {} does not belong to market {}",
+        phoenix_accounts.phoenix_market.key(),
+        market.market_index
+    )?;
+
+    let quote_market = load!(phoenix_accounts.quote_market)?;
+
+    validate!(
+        quote_market.market_index == QUOTE_SPOT_MARKET_INDEX,
+        ErrorCode::InvalidPhoenixMarket,
+        "Market {} is not the quote market",
+        quote_market.market_index
+    )?;
+
+    let market_header = fetch_market_header(&phoenix_accounts.phoenix_market)?;
+
+    validate_phoenix_market_header(market, &market_header, &quote_market.token_mint)?;
+
+    validate!(
+        phoenix_accounts.base_account.mint == market_header.base_params.mint_key,
+        ErrorCode::InvalidPhoenixMarket,
+        "Base account mint {} does not match the Phoenix market's base mint",
+        phoenix_accounts.base_account.mint
+    )?;
+
+    validate!(
+        phoenix_accounts.quote_account.mint == quote_market.token_mint,
+        ErrorCode::InvalidPhoenixMarket,
+        "Quote account mint {} does not match the quote market's mint",
+        phoenix_accounts.quote_account.mint
+    )?;
+
+    validate!(
+        phoenix_accounts.trader_base_account.mint == phoenix_accounts.base_account.mint
+            && phoenix_accounts.trader_quote_account.mint == phoenix_accounts.quote_account.mint,
+        ErrorCode::InvalidPhoenixMarket,
+        "Token accounts of trader {} do not match the Phoenix market's mints",
+        phoenix_accounts.trader.key()
+    )?;
+
+    Ok(market_header)
+}
+
+/// Places a limit or post-only order on Phoenix. Requires the trader to have an approved seat on the market.
+pub fn invoke_phoenix_place_limit_order(
+    phoenix_accounts: &PhoenixMarketAccounts,
+    order_packet: &OrderPacket,
+    signers: &PhoenixSigners
+) -> SpedXSpotResult {
+    let mut data = vec![PhoenixInstruction::PlaceLimitOrder as u8];
+    data.extend_from_slice(&order_packet.try_to_vec().map_err(|_| ErrorCode::PhoenixCpiFailed)?);
+
+    fund_phoenix_trader(phoenix_accounts, signers)?;
+
+    invoke_phoenix(phoenix_accounts, get_place_order_account_metas(phoenix_accounts), data, signers)
+}
+
+/// Places multiple post-only orders on Phoenix in a single instruction. Requires the trader to have an approved seat on
+/// the market.
+pub fn invoke_phoenix_place_multiple_post_only_orders(
+    phoenix_accounts: &PhoenixMarketAccounts,
+    multiple_order_packet: &MultipleOrderPacket,
+    signers: &PhoenixSigners
+) -> SpedXSpotResult {
+    let mut data = vec![PhoenixInstruction::PlaceMultiplePostOnlyOrders as u8];
+    data.extend_from_slice(&multiple_order_packet.try_to_vec().map_err(|_| ErrorCode::PhoenixCpiFailed)?);
+
+    fund_phoenix_trader(phoenix_accounts, signers)?;
+
+    invoke_phoenix(phoenix_accounts, get_place_order_account_metas(phoenix_accounts), data, signers)
+}
+
+/// Cancels orders of the trader on Phoenix by their order ids. Released funds are withdrawn to the signer's token
+/// accounts.
+pub fn invoke_phoenix_cancel_orders(
+    phoenix_accounts: &PhoenixMarketAccounts,
+    orders: Vec<CancelOrderParams>,
+    signers: &PhoenixSigners
+) -> SpedXSpotResult {
+    if orders.is_empty() {
+        return Ok(())
+    }
+
+    let params = CancelMultipleOrdersByIdParams { orders };
+
+    let mut data = vec![PhoenixInstruction::CancelMultipleOrdersById as u8];
+    data.extend_from_slice(&params.try_to_vec().map_err(|_| ErrorCode::PhoenixCpiFailed)?);
+
+    invoke_phoenix(phoenix_accounts, get_cancel_order_account_metas(phoenix_accounts), data, signers)
+}
+
+/// Reduces an order of the trader on Phoenix by a number of base lots, keeping its place in the queue. Released funds
+/// are withdrawn to the signer's token accounts.
+pub fn invoke_phoenix_reduce_order(
+    phoenix_accounts: &PhoenixMarketAccounts,
+    order: CancelOrderParams,
+    num_base_lots: u64,
+    signers: &PhoenixSigners
+) -> SpedXSpotResult {
+    let params = ReduceOrderParams {
+        base_params: order,
+        size: num_base_lots
+    };
+
+    let mut data = vec![PhoenixInstruction::ReduceOrder as u8];
+    data.extend_from_slice(&params.try_to_vec().map_err(|_| ErrorCode::PhoenixCpiFailed)?);
+
+    invoke_phoenix(phoenix_accounts, get_cancel_order_account_metas(phoenix_accounts), data, signers)
+}
+
+/// Withdraws all free funds of the trader on Phoenix, i.e fills of its resting orders, to the signer's token accounts
+pub fn invoke_phoenix_withdraw_funds(
+    phoenix_accounts: &PhoenixMarketAccounts,
+    signers: &PhoenixSigners
+) -> SpedXSpotResult {
+    let params = WithdrawParams {
+        quote_lots_to_withdraw: None,
+        base_lots_to_withdraw: None
+    };
+
+    let mut data = vec![PhoenixInstruction::WithdrawFunds as u8];
+    data.extend_from_slice(&params.try_to_vec().map_err(|_| ErrorCode::PhoenixCpiFailed)?);
+
+    invoke_phoenix(phoenix_accounts, get_cancel_order_account_metas(phoenix_accounts), data, signers)
+}
+
+/// Checks that the seat accounts belong to the market, to the protocol's signer and to the user's trader PDA
+pub fn validate_phoenix_seat_accounts(
+    state: &State,
+    market: &Market,
+    user: &Pubkey,
+    seat_accounts: &PhoenixSeatAccounts
+) -> SpedXSpotResult {
+    validate!(
+        seat_accounts.signer.key() == state.signer,
+        ErrorCode::InvalidSigner,
+        "Signer {} is not the protocol signer",
+        seat_accounts.signer.key()
+    )?;
+
+    validate!(
+        market.phoenix_market != Pubkey::default() && seat_accounts.phoenix_market.key() == market.phoenix_market,
+        ErrorCode::InvalidPhoenixMarket,
+        "Phoenix market {} does not belong to market {}",
+        seat_accounts.phoenix_market.key(),
+        market.market_index
+    )?;
+
+    validate!(
+        seat_accounts.trader.key() == get_phoenix_trader_address(user).0,
+        ErrorCode::InvalidPhoenixTrader,
+        "Phoenix trader {} does not belong to user {}",
+        seat_accounts.trader.key(),
+        user
+    )?;
+
+    validate!(
+        seat_accounts.seat.key() == get_seat_address(&seat_accounts.phoenix_market.key(), &seat_accounts.trader.key()).0,
+        ErrorCode::InvalidPhoenixTrader,
+        "Seat {} does not belong to trader {}",
+        seat_accounts.seat.key(),
+        seat_accounts.trader.key()
+    )
+}
+
+/// Requests a seat on the Phoenix market for the user's trader PDA and approves it. The payer pays the rent of the seat.
+pub fn invoke_phoenix_request_seat<'info>(
+    seat_accounts: &PhoenixSeatAccounts<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    signer_nonce: u8
+) -> SpedXSpotResult {
+    let request_seat_instruction = create_request_seat_authorized_instruction(
+        &seat_accounts.signer.key(),
+        &payer.key(),
+        &seat_accounts.phoenix_market.key(),
+        &seat_accounts.trader.key()
+    );
+
+    let account_infos = [
+        seat_accounts.phoenix_program.to_account_info(),
+        seat_accounts.phoenix_log_authority.to_account_info(),
+        seat_accounts.phoenix_market.to_account_info(),
+        seat_accounts.signer.to_account_info(),
+        payer.clone(),
+        seat_accounts.trader.to_account_info(),
+        seat_accounts.seat.to_account_info(),
+        system_program.clone(),
+    ];
+
+    invoke_phoenix_instruction(&request_seat_instruction, &account_infos, signer_nonce)?;
+
+    invoke_phoenix_change_seat_status(seat_accounts, SeatApprovalStatus::Approved, signer_nonce)
+}
+
+/// Retires the seat of the user's trader PDA and evicts it from the Phoenix market. Phoenix requires the trader to have
+/// no open orders, its free funds are withdrawn to the trader's token accounts. Phoenix keeps the seat account itself.
+pub fn invoke_phoenix_evict_seat<'info>(
+    seat_accounts: &PhoenixSeatAccounts<'info>,
+    trader_base_account: &AccountInfo<'info>,
+    trader_quote_account: &AccountInfo<'info>,
+    phoenix_base_vault: &AccountInfo<'info>,
+    phoenix_quote_vault: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    signer_nonce: u8
+) -> SpedXSpotResult {
+    invoke_phoenix_change_seat_status(seat_accounts, SeatApprovalStatus::Retired, signer_nonce)?;
+
+    let market_header = fetch_market_header(&seat_accounts.phoenix_market)?;
+
+    let evict_seat_instruction = create_evict_seat_instruction(
+        &seat_accounts.signer.key(),
+        &seat_accounts.phoenix_market.key(),
+        &seat_accounts.trader.key(),
+        &market_header.base_params.mint_key,
+        &market_header.quote_params.mint_key
+    );
+
+    let account_infos = [
+        seat_accounts.phoenix_program.to_account_info(),
+        seat_accounts.phoenix_log_authority.to_account_info(),
+        seat_accounts.phoenix_market.to_account_info(),
+        seat_accounts.signer.to_account_info(),
+        seat_accounts.trader.to_account_info(),
+        seat_accounts.seat.to_account_info(),
+        trader_base_account.clone(),
+        trader_quote_account.clone(),
+        phoenix_base_vault.clone(),
+        phoenix_quote_vault.clone(),
+        token_program.clone(),
+    ];
+
+    invoke_phoenix_instruction(&evict_seat_instruction, &account_infos, signer_nonce)
+}
+
+/// Moves the funds an evicted trader PDA was paid out to its token accounts back to the signer
+pub fn sweep_evicted_phoenix_trader<'info>(
+    seat_accounts: &PhoenixSeatAccounts<'info>,
+    transfers: [(AccountInfo<'info>, AccountInfo<'info>); 2],
+    token_program: &AccountInfo<'info>,
+    user: &Pubkey,
+    trader_bump: u8
+) -> SpedXSpotResult {
+    let trader_seeds = get_phoenix_trader_seeds(user, &trader_bump);
+
+    transfer_all(token_program, transfers, seat_accounts.trader.to_account_info(), &trader_seeds)
+}
+
+/// Changes the approval status of the seat of the user's trader PDA
+fn invoke_phoenix_change_seat_status<'info>(
+    seat_accounts: &PhoenixSeatAccounts<'info>,
+    status: SeatApprovalStatus,
+    signer_nonce: u8
+) -> SpedXSpotResult {
+    let change_seat_status_instruction = create_change_seat_status_instruction(
+        &seat_accounts.signer.key(),
+        &seat_accounts.phoenix_market.key(),
+        &seat_accounts.trader.key(),
+        status
+    );
+
+    let account_infos = [
+        seat_accounts.phoenix_program.to_account_info(),
+        seat_accounts.phoenix_log_authority.to_account_info(),
+        seat_accounts.phoenix_market.to_account_info(),
+        seat_accounts.signer.to_account_info(),
+        seat_accounts.seat.to_account_info(),
+    ];
+
+    invoke_phoenix_instruction(&change_seat_status_instruction, &account_infos, signer_nonce)
+}
+
+/// Accounts of Phoenix's order cancellation and withdrawal instructions
+fn get_cancel_order_account_metas(phoenix_accounts: &PhoenixMarketAccounts) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(phoenix_accounts.phoenix_program.key(), false),
+        AccountMeta::new_readonly(phoenix_accounts.phoenix_log_authority.key(), false),
+        AccountMeta::new(phoenix_accounts.phoenix_market.key(), false),
+        AccountMeta::new_readonly(phoenix_accounts.trader.key(), true),
+        AccountMeta::new(phoenix_accounts.trader_base_account.key(), false),
+        AccountMeta::new(phoenix_accounts.trader_quote_account.key(), false),
+        AccountMeta::new(phoenix_accounts.phoenix_base_vault.key(), false),
+        AccountMeta::new(phoenix_accounts.phoenix_quote_vault.key(), false),
+        AccountMeta::new_readonly(phoenix_accounts.token_program.key(), false),
+    ]
+}
+
+/// Accounts of Phoenix's order placement instructions
+fn get_place_order_account_metas(phoenix_accounts: &PhoenixMarketAccounts) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(phoenix_accounts.phoenix_program.key(), false),
+        AccountMeta::new_readonly(phoenix_accounts.phoenix_log_authority.key(), false),
+        AccountMeta::new(phoenix_accounts.phoenix_market.key(), false),
+        AccountMeta::new_readonly(phoenix_accounts.trader.key(), true),
+        AccountMeta::new_readonly(phoenix_accounts.phoenix_seat.key(), false),
+        AccountMeta::new(phoenix_accounts.trader_base_account.key(), false),
+        AccountMeta::new(phoenix_accounts.trader_quote_account.key(), false),
+        AccountMeta::new(phoenix_accounts.phoenix_base_vault.key(), false),
+        AccountMeta::new(phoenix_accounts.phoenix_quote_vault.key(), false),
+        AccountMeta::new_readonly(phoenix_accounts.token_program.key(), false),
+    ]
+}
+
+/// Invokes the Phoenix program, signing with the user's trader PDA. Whatever the CPI leaves in the trader's token
+/// accounts is moved back to the signer's.
+fn invoke_phoenix(
+    phoenix_accounts: &PhoenixMarketAccounts,
+    accounts: Vec<AccountMeta>,
+    data: Vec<u8>,
+    signers: &PhoenixSigners
+) -> SpedXSpotResult {
+    let instruction = Instruction {
+        program_id: phoenix::id(),
+        accounts,
+        data
+    };
+
+    let account_infos = [
+        phoenix_accounts.phoenix_program.to_account_info(),
+        phoenix_accounts.phoenix_log_authority.to_account_info(),
+        phoenix_accounts.phoenix_market.to_account_info(),
+        phoenix_accounts.trader.to_account_info(),
+        phoenix_accounts.phoenix_seat.to_account_info(),
+        phoenix_accounts.trader_base_account.to_account_info(),
+        phoenix_accounts.trader_quote_account.to_account_info(),
+        phoenix_accounts.phoenix_base_vault.to_account_info(),
+        phoenix_accounts.phoenix_quote_vault.to_account_info(),
+        phoenix_accounts.token_program.to_account_info(),
+    ];
+
+    let trader_seeds = get_phoenix_trader_seeds(&signers.user, &signers.trader_bump);
+
+    invoke_signed(&instruction, &account_infos, &[&trader_seeds]).map_err(|e| {
+        msg!("Phoenix CPI failed: {:?}", e);
+        ErrorCode::PhoenixCpiFailed
+    })?;
+
+    sweep_phoenix_trader(phoenix_accounts, signers)
+}
+
+/// Moves all of the signer's funds in the Phoenix market's mints to the trader, so that Phoenix can take what the order
+/// being placed needs
+fn fund_phoenix_trader(
+    phoenix_accounts: &PhoenixMarketAccounts,
+    signers: &PhoenixSigners
+) -> SpedXSpotResult {
+    let signer_seeds = get_signer_seeds(&signers.signer_nonce);
+
+    transfer_all(
+        &phoenix_accounts.token_program.to_account_info(),
+        [
+            (phoenix_accounts.base_account.to_account_info(), phoenix_accounts.trader_base_account.to_account_info()),
+            (phoenix_accounts.quote_account.to_account_info(), phoenix_accounts.trader_quote_account.to_account_info())
+        ],
+        phoenix_accounts.signer.to_account_info(),
+        &signer_seeds
+    )
+}
+
+/// Moves everything left in the trader's token accounts back to the signer
+fn sweep_phoenix_trader(
+    phoenix_accounts: &PhoenixMarketAccounts,
+    signers: &PhoenixSigners
+) -> SpedXSpotResult {
+    let trader_seeds = get_phoenix_trader_seeds(&signers.user, &signers.trader_bump);
+
+    transfer_all(
+        &phoenix_accounts.token_program.to_account_info(),
+        [
+            (phoenix_accounts.trader_base_account.to_account_info(), phoenix_accounts.base_account.to_account_info()),
+            (phoenix_accounts.trader_quote_account.to_account_info(), phoenix_accounts.quote_account.to_account_info())
+        ],
+        phoenix_accounts.trader.to_account_info(),
+        &trader_seeds
+    )
+}
+
+/// Transfers the whole balance of each source token account to its destination
+fn transfer_all<'info>(
+    token_program: &AccountInfo<'info>,
+    transfers: [(AccountInfo<'info>, AccountInfo<'info>); 2],
+    authority: AccountInfo<'info>,
+    authority_seeds: &[&[u8]]
+) -> SpedXSpotResult {
+    for (from, to) in transfers {
+        // the amount is read from the account data, the deserialized accounts are stale after a CPI
+        let amount = token::accessor::amount(&from).map_err(|_| ErrorCode::PhoenixCpiFailed)?;
+
+        if amount == 0 {
+            continue;
+        }
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                token_program.clone(),
+                Transfer {
+                    from,
+                    to,
+                    authority: authority.clone()
+                },
+                &[authority_seeds]
+            ),
+            amount
+        )
+        .map_err(|e| {
+            msg!("Phoenix trader transfer failed: {:?}", e);
+            ErrorCode::PhoenixCpiFailed
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Invokes a Phoenix instruction, signing with the protocol's signer PDA
+fn invoke_phoenix_instruction(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signer_nonce: u8
+) -> SpedXSpotResult {
+    let signer_seeds = get_signer_seeds(&signer_nonce);
+
+    invoke_signed(instruction, account_infos, &[&signer_seeds]).map_err(|e| {
+        msg!("Phoenix CPI failed: {:?}", e);
+        ErrorCode::PhoenixCpiFailed
+    })
+}
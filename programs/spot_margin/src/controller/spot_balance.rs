@@ -0,0 +1,711 @@
+//! Deposits and withdrawals of a user's position, and maintenance of the market's balance data
+
+use solana_program::msg;
+
+use crate::{
+    controller::orders::is_market_oracle_valid_for_action,
+    error::{
+        ErrorCode,
+        SpedXSpotResult
+    },
+    math::{
+        balance::{
+            calculate_accumulated_interest,
+            calculate_utilization,
+            get_amount_of_tokens,
+            get_spot_asset_balance
+        },
+        casting::Cast,
+        constants::SPOT_MARKET_TOKEN_TWAP_WINDOW,
+        margin::calculate_position_margin,
+        safe_math::SafeMath,
+        spot_withdraw::{
+            check_borrow_limits,
+            check_user_borrow_limit,
+            check_withdraw_limits
+        },
+        twap::calculate_twap
+    },
+    state::{
+        config::State,
+        enums::{
+            Actions,
+            MarginRequirementType,
+            MarketStatus,
+            SpotBalanceType
+        },
+        market::Market,
+        oracle::OraclePriceData,
+        traits::SpotBalance,
+        user::User,
+        user_position::Position
+    },
+    validate
+};
+
+/// Deposits tokens into the user's position in the market. A borrow is repaid first, the rest is deposited. Fails if
+/// the market's deposits would exceed its deposit limit.
+/// token amount precision: token mint
+pub fn deposit(
+    user: &mut User,
+    market: &mut Market,
+    token_amount: u64,
+    now: i64
+) -> SpedXSpotResult {
+    validate!(
+        token_amount > 0,
+        ErrorCode::InvalidTokenAmount,
+        "Deposit amount must be positive"
+    )?;
+
+    validate!(
+        market.is_market_active(now)?,
+        ErrorCode::MarketNotActive,
+        "Market {} is not active",
+        market.market_index
+    )?;
+
+    update_market_cumulative_interest(market, now)?;
+
+    let position = user.force_get_position_mut(market.market_index)?;
+
+    update_spot_balances(token_amount.cast()?, &SpotBalanceType::Deposits, market, position)?;
+
+    position.cumulative_deposits_for_market = position.cumulative_deposits_for_market.safe_add(token_amount.cast()?)?;
+
+    if market.max_deposit_limit != 0 {
+        let deposit_token_amount = get_amount_of_tokens(market.deposit_balance, market, &SpotBalanceType::Deposits)?;
+
+        validate!(
+            deposit_token_amount <= market.max_deposit_limit.cast()?,
+            ErrorCode::MaxDepositExceeded,
+            "Market {} deposits {} would exceed the deposit limit {}",
+            market.market_index,
+            deposit_token_amount,
+            market.max_deposit_limit
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Withdraws tokens from the user's position in the market. Withdrawing more than the position's deposit borrows the
+/// rest. The market's token TWAPs are updated before the balances change, the market's balances are then checked
+/// against its withdraw limits, a borrow against the market's borrow caps, and the position must still meet its
+/// initial margin requirement.
+/// token amount precision: token mint
+pub fn withdraw(
+    state: &State,
+    user: &mut User,
+    market: &mut Market,
+    oracle_price_data: &OraclePriceData,
+    token_amount: u64,
+    now: i64
+) -> SpedXSpotResult {
+    validate!(
+        token_amount > 0,
+        ErrorCode::InvalidTokenAmount,
+        "Withdraw amount must be positive"
+    )?;
+
+    validate!(
+        market.is_market_active(now)?,
+        ErrorCode::MarketNotActive,
+        "Market {} is not active",
+        market.market_index
+    )?;
+
+    validate!(
+        market.status != MarketStatus::WithdrawPaused,
+        ErrorCode::MarketWithdrawPaused,
+        "Withdrawals are paused for market {}",
+        market.market_index
+    )?;
+
+    validate!(
+        is_market_oracle_valid_for_action(state, market, oracle_price_data, Actions::MarginCalculation)?,
+        ErrorCode::InvalidOracle,
+        "Oracle is not valid for withdrawing from market {}",
+        market.market_index
+    )?;
+
+    update_market_cumulative_interest(market, now)?;
+    update_market_token_twaps(market, now)?;
+
+    let position = user.force_get_position_mut(market.market_index)?;
+
+    update_spot_balances(token_amount.cast()?, &SpotBalanceType::Borrows, market, position)?;
+
+    position.cumulative_deposits_for_market = position.cumulative_deposits_for_market.safe_sub(token_amount.cast()?)?;
+
+    check_withdraw_limits(market)?;
+
+    // a withdrawal within the deposit still adds to the borrow of a position that sold more than it holds on the book
+    if position.bal_type == SpotBalanceType::Borrows {
+        check_borrow_limits(market, position)?;
+    } else {
+        check_user_borrow_limit(market, position)?;
+    }
+
+    let meets_initial_margin_requirement = calculate_position_margin(
+        position,
+        market,
+        oracle_price_data,
+        MarginRequirementType::Initial
+    )?
+    .meets_margin_requirement()?;
+
+    validate!(
+        meets_initial_margin_requirement,
+        ErrorCode::InsufficientCollateral,
+        "User does not meet the initial margin requirement after withdrawing {} from market {}",
+        token_amount,
+        market.market_index
+    )?;
+
+    Ok(())
+}
+
+/// Moves a token amount into(Deposits) or out of(Borrows) the position's balance and the market's balances. Taking out
+/// more than the position's deposit borrows the rest, and putting in more than its borrow repays it and deposits the
+/// rest. Balances are rounded in the market's favour: withdrawals and borrows up, deposits and repayments down.
+/// token amount precision: token mint
+pub fn update_spot_balances(
+    token_amount: u128,
+    update_direction: &SpotBalanceType,
+    market: &mut Market,
+    position: &mut Position
+) -> SpedXSpotResult {
+    if token_amount == 0 {
+        return Ok(());
+    }
+
+    // an empty balance takes the direction of the update
+    if position.scaled_balance == 0 {
+        position.update_balance_type(*update_direction)?;
+    }
+
+    let balance_type = position.bal_type;
+
+    if balance_type == *update_direction {
+        let balance = get_spot_asset_balance(
+            token_amount,
+            market,
+            &balance_type,
+            balance_type == SpotBalanceType::Borrows
+        )?;
+
+        position.increase_balance(balance)?;
+
+        return increase_market_balance(market, &balance_type, balance);
+    }
+
+    let position_token_amount = position.get_token_amount(market)?;
+
+    if token_amount < position_token_amount {
+        let balance = get_spot_asset_balance(
+            token_amount,
+            market,
+            &balance_type,
+            balance_type == SpotBalanceType::Deposits
+        )?
+        .min(position.balance());
+
+        position.decrease_balance(balance)?;
+
+        return decrease_market_balance(market, &balance_type, balance);
+    }
+
+    // the whole balance is withdrawn or repaid, whatever is left of the amount goes the other way
+    let balance = position.balance();
+
+    position.decrease_balance(balance)?;
+    decrease_market_balance(market, &balance_type, balance)?;
+
+    position.update_balance_type(*update_direction)?;
+
+    update_spot_balances(token_amount.safe_sub(position_token_amount)?, update_direction, market, position)
+}
+
+fn increase_market_balance(market: &mut Market, balance_type: &SpotBalanceType, balance: u128) -> SpedXSpotResult {
+    match balance_type {
+        SpotBalanceType::Deposits => market.deposit_balance = market.deposit_balance.safe_add(balance)?,
+        SpotBalanceType::Borrows => market.borrow_balance = market.borrow_balance.safe_add(balance)?
+    }
+    Ok(())
+}
+
+fn decrease_market_balance(market: &mut Market, balance_type: &SpotBalanceType, balance: u128) -> SpedXSpotResult {
+    match balance_type {
+        SpotBalanceType::Deposits => market.deposit_balance = market.deposit_balance.safe_sub(balance)?,
+        SpotBalanceType::Borrows => market.borrow_balance = market.borrow_balance.safe_sub(balance)?
+    }
+    Ok(())
+}
+
+/// Accrues the market's interest since its last update into its cumulative deposit and borrow interest. The borrow
+/// interest that the take rate leaves out of the deposit interest is credited to the revenue pool, so that the deposit
+/// interest and the protocol's revenue add up to the borrow interest. Must be called before the market's balances or
+/// interest rate model change.
+pub fn update_market_cumulative_interest(market: &mut Market, now: i64) -> SpedXSpotResult {
+    let now_ts: u64 = now.cast()?;
+
+    // the first update only starts the accrual, there is no earlier timestamp to accrue from
+    if market.last_interest_ts == 0 {
+        market.last_interest_ts = now_ts;
+        return Ok(());
+    }
+
+    if now_ts <= market.last_interest_ts {
+        return Ok(());
+    }
+
+    let interest = calculate_accumulated_interest(market, now)?;
+
+    let deposit_token_amount_before = get_amount_of_tokens(market.deposit_balance, market, &SpotBalanceType::Deposits)?;
+    let borrow_token_amount_before = get_amount_of_tokens(market.borrow_balance, market, &SpotBalanceType::Borrows)?;
+
+    market.cumulative_deposit_interest = market.cumulative_deposit_interest.safe_add(interest.deposits_interest)?;
+    market.cumulative_borrow_interest = market.cumulative_borrow_interest.safe_add(interest.borrows_interest)?;
+    market.last_interest_ts = now_ts;
+
+    let deposit_interest_token_amount = get_amount_of_tokens(market.deposit_balance, market, &SpotBalanceType::Deposits)?
+        .safe_sub(deposit_token_amount_before)?;
+    let borrow_interest_token_amount = get_amount_of_tokens(market.borrow_balance, market, &SpotBalanceType::Borrows)?
+        .safe_sub(borrow_token_amount_before)?;
+
+    // whatever the borrowers paid that the depositors did not receive, taken as the difference rather than applying the
+    // take rate again so that no token is lost between the two. Rounding can only leave the deposit interest a token
+    // above the borrow interest on a fully utilized market without take rate, in which case there is no revenue.
+    let revenue_token_amount = borrow_interest_token_amount.saturating_sub(deposit_interest_token_amount);
+
+    update_revenue_pool_balances(revenue_token_amount, market)
+}
+
+/// Credits a token amount to the market's revenue pool. The revenue pool is a deposit of the market, so the market's
+/// deposit balance grows with it. The balance is rounded down, so the pool is never credited more than it earned; the
+/// dust stays in the vault unclaimed.
+pub fn update_revenue_pool_balances(token_amount: u128, market: &mut Market) -> SpedXSpotResult {
+    let balance = get_spot_asset_balance(token_amount, market, &SpotBalanceType::Deposits, false)?;
+
+    if balance == 0 {
+        return Ok(());
+    }
+
+    market.revenue_pool.increase_balance(balance)?;
+    market.deposit_balance = market.deposit_balance.safe_add(balance)?;
+
+    Ok(())
+}
+
+/// Updates the market's 24h deposit, borrow and utilization TWAPs with its current balances. Called by `withdraw` before
+/// the balances change, so that `check_withdraw_limits` compares against TWAPs that do not include the withdrawal
+/// itself, as well as from the permissionless `update_market_oracle_twap` crank.
+pub fn update_market_token_twaps(market: &mut Market, now: i64) -> SpedXSpotResult {
+    let deposit_token_amount = get_amount_of_tokens(market.deposit_balance, market, &SpotBalanceType::Deposits)?;
+    let borrow_token_amount = get_amount_of_tokens(market.borrow_balance, market, &SpotBalanceType::Borrows)?;
+    let utilization = calculate_utilization(deposit_token_amount, borrow_token_amount)?;
+
+    // the first update starts the twaps at the current balances rather than averaging them with zero
+    if market.last_twap_ts == 0 {
+        market.token_deposit_twap = deposit_token_amount.cast()?;
+        market.token_borrow_twap = borrow_token_amount.cast()?;
+        market.utilization_twap = utilization.cast()?;
+        market.last_twap_ts = now.cast()?;
+
+        return Ok(());
+    }
+
+    let last_twap_ts: i64 = market.last_twap_ts.cast()?;
+
+    market.token_deposit_twap = calculate_twap(
+        deposit_token_amount.cast()?,
+        now,
+        market.token_deposit_twap.cast()?,
+        last_twap_ts,
+        SPOT_MARKET_TOKEN_TWAP_WINDOW
+    )?
+    .cast()?;
+
+    market.token_borrow_twap = calculate_twap(
+        borrow_token_amount.cast()?,
+        now,
+        market.token_borrow_twap.cast()?,
+        last_twap_ts,
+        SPOT_MARKET_TOKEN_TWAP_WINDOW
+    )?
+    .cast()?;
+
+    market.utilization_twap = calculate_twap(
+        utilization.cast()?,
+        now,
+        market.utilization_twap.cast()?,
+        last_twap_ts,
+        SPOT_MARKET_TOKEN_TWAP_WINDOW
+    )?
+    .cast()?;
+
+    market.last_twap_ts = now.max(last_twap_ts).cast()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        controller::spot_balance::{
+            deposit,
+            update_market_cumulative_interest,
+            withdraw
+        },
+        error::ErrorCode,
+        math::{
+            balance::get_amount_of_tokens,
+            constants::{
+                ONE_YEAR,
+                PERCENTAGE_PRECISION,
+                PRICE_PRECISION_I64,
+                PRICE_PRECISION_U64,
+                QUOTE_PRECISION_I64,
+                SPOT_BALANCE_PRECISION,
+                SPOT_CUMULATIVE_INTEREST_PRECISION,
+                SPOT_WEIGHT_PRECISION
+            }
+        },
+        state::{
+            config::State,
+            enums::SpotBalanceType,
+            market::Market,
+            oracle::{
+                HistoricalPriceData,
+                OraclePriceData
+            },
+            user::User
+        }
+    };
+
+    const ONE_TOKEN: u128 = 1_000_000_000;
+
+    /// Market with 1000 tokens deposited and 500 borrowed, i.e 50% utilization and a 6.25% borrow rate
+    fn market(interest_take_rate: u32) -> Market {
+        Market {
+            decimals: 9,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            deposit_balance: 1_000 * SPOT_BALANCE_PRECISION,
+            borrow_balance: 500 * SPOT_BALANCE_PRECISION,
+            optimal_utilization: 800_000,
+            optimal_borrow_rate: 100_000,
+            max_borrow_rate: 1_000_000,
+            interest_take_rate,
+            last_interest_ts: 1,
+            ..Market::default()
+        }
+    }
+
+    fn tokens(balance: u128, market: &Market, balance_type: SpotBalanceType) -> u128 {
+        get_amount_of_tokens(balance, market, &balance_type).unwrap()
+    }
+
+    #[test]
+    fn interest_take_rate() {
+        // 10% of the borrow interest goes to the protocol
+        let mut market = market(100_000);
+
+        update_market_cumulative_interest(&mut market, 1 + ONE_YEAR as i64).unwrap();
+
+        assert_eq!(market.cumulative_deposit_interest, 10_281_250_000);
+        assert_eq!(market.cumulative_borrow_interest, 10_625_000_001);
+        assert_eq!(market.last_interest_ts, 1 + ONE_YEAR as u64);
+
+        // borrowers pay 31.25 tokens(and 50 atoms of rounding), depositors receive 90% of it
+        let borrow_interest = tokens(market.borrow_balance, &market, SpotBalanceType::Borrows) - 500 * ONE_TOKEN;
+        let deposit_interest = tokens(1_000 * SPOT_BALANCE_PRECISION, &market, SpotBalanceType::Deposits) - 1_000 * ONE_TOKEN;
+        assert_eq!(borrow_interest, 31_250_000_050);
+        assert_eq!(deposit_interest, 28_125_000_000);
+
+        // the revenue pool is credited the rest, less an atom lost to the rounding of its balance
+        assert_eq!(market.revenue_pool.scaled_balance, 3_039_513_726);
+        assert_eq!(market.deposit_balance, 1_000 * SPOT_BALANCE_PRECISION + 3_039_513_726);
+        assert_eq!(
+            tokens(market.revenue_pool.scaled_balance, &market, SpotBalanceType::Deposits),
+            borrow_interest - deposit_interest - 1
+        );
+    }
+
+    #[test]
+    fn full_interest_take_rate() {
+        let mut market = market(PERCENTAGE_PRECISION as u32);
+
+        update_market_cumulative_interest(&mut market, 1 + ONE_YEAR as i64).unwrap();
+
+        // depositors receive nothing, the protocol all of the borrow interest
+        assert_eq!(market.cumulative_deposit_interest, SPOT_CUMULATIVE_INTEREST_PRECISION);
+        assert_eq!(
+            tokens(market.revenue_pool.scaled_balance, &market, SpotBalanceType::Deposits),
+            31_250_000_050
+        );
+    }
+
+    #[test]
+    fn interest_accrual_start() {
+        let mut market = Market {
+            last_interest_ts: 0,
+            ..market(100_000)
+        };
+
+        // the first update only records the timestamp
+        update_market_cumulative_interest(&mut market, 100).unwrap();
+        assert_eq!(market.last_interest_ts, 100);
+        assert_eq!(market.cumulative_borrow_interest, SPOT_CUMULATIVE_INTEREST_PRECISION);
+
+        // no time has passed
+        update_market_cumulative_interest(&mut market, 100).unwrap();
+        assert_eq!(market.cumulative_borrow_interest, SPOT_CUMULATIVE_INTEREST_PRECISION);
+        assert_eq!(market.revenue_pool.scaled_balance, 0);
+    }
+
+    /// Deterministic xorshift generator, so that the properties are checked on the same values on every run
+    struct Generator(u64);
+
+    impl Generator {
+        fn next_below(&mut self, max: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % max
+        }
+    }
+
+    #[test]
+    fn interest_revenue_rounding() {
+        let mut generator = Generator(0x2545_F491_4F6C_DD1D);
+
+        for _ in 0..2_000 {
+            let decimals = [6_u32, 9][generator.next_below(2) as usize];
+            let precision_downtick = 10_u128.pow(19 - decimals);
+
+            let cumulative_deposit_interest =
+                SPOT_CUMULATIVE_INTEREST_PRECISION + generator.next_below(10_000_000_000) as u128;
+            let cumulative_borrow_interest = cumulative_deposit_interest + generator.next_below(10_000_000_000) as u128;
+
+            // borrows of at most the deposits, so that utilization stays within 100%
+            let deposit_balance = 1 + generator.next_below(1_000_000_000_000_000) as u128;
+            let deposit_token_amount = deposit_balance * cumulative_deposit_interest / precision_downtick;
+            let borrow_token_amount = generator.next_below(deposit_token_amount as u64 + 1) as u128;
+            let borrow_balance = borrow_token_amount * precision_downtick / cumulative_borrow_interest;
+
+            let interest_take_rate = generator.next_below(PERCENTAGE_PRECISION as u64 + 1) as u32;
+            let time_since_last_update = 1 + generator.next_below(ONE_YEAR as u64) as i64;
+
+            let optimal_utilization = 1 + generator.next_below(PERCENTAGE_PRECISION as u64 - 1) as u32;
+            let optimal_borrow_rate = generator.next_below(PERCENTAGE_PRECISION as u64) as u32;
+            let max_borrow_rate = optimal_borrow_rate + generator.next_below(3 * PERCENTAGE_PRECISION as u64) as u32;
+
+            let mut market = Market {
+                decimals,
+                cumulative_deposit_interest,
+                cumulative_borrow_interest,
+                deposit_balance,
+                borrow_balance,
+                optimal_utilization,
+                optimal_borrow_rate,
+                max_borrow_rate,
+                interest_take_rate,
+                last_interest_ts: 1,
+                ..Market::default()
+            };
+
+            let deposit_token_amount = tokens(deposit_balance, &market, SpotBalanceType::Deposits);
+            let borrow_token_amount = tokens(borrow_balance, &market, SpotBalanceType::Borrows);
+
+            update_market_cumulative_interest(&mut market, 1 + time_since_last_update).unwrap();
+
+            let deposit_interest = tokens(deposit_balance, &market, SpotBalanceType::Deposits) - deposit_token_amount;
+            let borrow_interest = tokens(borrow_balance, &market, SpotBalanceType::Borrows) - borrow_token_amount;
+            let revenue = tokens(market.revenue_pool.scaled_balance, &market, SpotBalanceType::Deposits);
+
+            // depositors and the protocol never receive more than the borrowers pay
+            assert!(deposit_interest + revenue <= borrow_interest);
+
+            // and at most the rounding of the revenue pool's balance is left unclaimed
+            assert!(
+                borrow_interest - deposit_interest - revenue
+                    <= market.cumulative_deposit_interest / precision_downtick + 1
+            );
+
+            assert_eq!(market.deposit_balance, deposit_balance + market.revenue_pool.scaled_balance);
+        }
+    }
+
+    /// Market of a $100 token with 1000 tokens deposited, 400 of them by the user, and the deposit TWAP at the same amount
+    fn market_with_deposits() -> (Market, User) {
+        let market = Market {
+            decimals: 9,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            deposit_balance: 1_000 * SPOT_BALANCE_PRECISION,
+            token_deposit_twap: (1_000 * ONE_TOKEN) as u64,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION * 8 / 10,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION * 9 / 10,
+            initial_liability_weight: SPOT_WEIGHT_PRECISION * 12 / 10,
+            maintenance_liability_weight: SPOT_WEIGHT_PRECISION * 11 / 10,
+            historical_oracle_data: HistoricalPriceData::default_price(100 * PRICE_PRECISION_I64),
+            last_interest_ts: 1,
+            last_twap_ts: 1,
+            ..Market::default()
+        };
+
+        let mut user = User::default();
+        user.positions[0].scaled_balance = (400 * SPOT_BALANCE_PRECISION) as u64;
+
+        (market, user)
+    }
+
+    fn oracle_price_data() -> OraclePriceData {
+        OraclePriceData {
+            price: 100 * PRICE_PRECISION_I64,
+            confidence: PRICE_PRECISION_U64 / 100,
+            delay: 1,
+            has_sufficient_data_points: true,
+        }
+    }
+
+    #[test]
+    fn withdraw_past_deposit_borrows() {
+        let (market, mut user) = market_with_deposits();
+
+        // a withdraw limit large enough for the withdrawal, and collateral for the borrow
+        let mut market = Market {
+            withdraw_limit: (500 * ONE_TOKEN) as u64,
+            ..market
+        };
+        user.positions[0].quote_asset_amount = 10_000 * QUOTE_PRECISION_I64;
+
+        let amount = (450 * ONE_TOKEN) as u64;
+        withdraw(&State::default(), &mut user, &mut market, &oracle_price_data(), amount, 1).unwrap();
+
+        let position = user.positions[0];
+        assert_eq!(position.bal_type, SpotBalanceType::Borrows);
+        assert_eq!(position.get_token_amount(&market).unwrap(), 50 * ONE_TOKEN + 1);
+        assert_eq!(position.cumulative_deposits_for_market, -(450 * ONE_TOKEN as i64));
+        assert_eq!(tokens(market.deposit_balance, &market, SpotBalanceType::Deposits), 600 * ONE_TOKEN);
+        assert_eq!(tokens(market.borrow_balance, &market, SpotBalanceType::Borrows), 50 * ONE_TOKEN + 1);
+
+        // depositing repays the borrow before depositing the rest
+        deposit(&mut user, &mut market, (80 * ONE_TOKEN) as u64, 1).unwrap();
+
+        let position = user.positions[0];
+        assert_eq!(position.bal_type, SpotBalanceType::Deposits);
+        assert_eq!(position.get_token_amount(&market).unwrap(), 30 * ONE_TOKEN - 1);
+        assert_eq!(tokens(market.deposit_balance, &market, SpotBalanceType::Deposits), 630 * ONE_TOKEN - 1);
+        assert_eq!(market.borrow_balance, 0);
+    }
+
+    #[test]
+    fn withdraw_over_the_withdraw_limits_fails() {
+        // deposits may fall to 75% of their 1000 token twap, the withdrawn balance is rounded up by an atom
+        let (mut market, mut user) = market_with_deposits();
+        assert_eq!(
+            withdraw(&State::default(), &mut user, &mut market, &oracle_price_data(), (250 * ONE_TOKEN) as u64, 1),
+            Err(ErrorCode::WithdrawLimitExceeded)
+        );
+
+        let (mut market, mut user) = market_with_deposits();
+        withdraw(&State::default(), &mut user, &mut market, &oracle_price_data(), (249 * ONE_TOKEN) as u64, 1).unwrap();
+        assert_eq!(tokens(market.deposit_balance, &market, SpotBalanceType::Deposits), 751 * ONE_TOKEN - 1);
+
+        // the twap is updated before the withdrawal, a day later it has caught up with the lower deposits
+        withdraw(&State::default(), &mut user, &mut market, &oracle_price_data(), (150 * ONE_TOKEN) as u64, 1 + 86_400)
+            .unwrap();
+    }
+
+    #[test]
+    fn borrow_over_the_borrow_limits_fails() {
+        let get_market_and_user = |max_borrow_limit: u128, max_user_borrow_limit: u128| {
+            let (market, mut user) = market_with_deposits();
+            let market = Market {
+                withdraw_limit: (500 * ONE_TOKEN) as u64,
+                max_borrow_limit: max_borrow_limit as u64,
+                max_user_borrow_limit: max_user_borrow_limit as u64,
+                ..market
+            };
+            user.positions[0].quote_asset_amount = 10_000 * QUOTE_PRECISION_I64;
+            (market, user)
+        };
+
+        // withdrawing 450 of the user's 400 tokens borrows 50 tokens and an atom
+        let withdraw_amount = (450 * ONE_TOKEN) as u64;
+
+        let (mut market, mut user) = get_market_and_user(100 * ONE_TOKEN, 50 * ONE_TOKEN);
+        assert_eq!(
+            withdraw(&State::default(), &mut user, &mut market, &oracle_price_data(), withdraw_amount, 1),
+            Err(ErrorCode::UserBorrowLimitExceeded)
+        );
+
+        let (mut market, mut user) = get_market_and_user(50 * ONE_TOKEN, 0);
+        assert_eq!(
+            withdraw(&State::default(), &mut user, &mut market, &oracle_price_data(), withdraw_amount, 1),
+            Err(ErrorCode::MarketBorrowLimitExceeded)
+        );
+
+        let (mut market, mut user) = get_market_and_user(100 * ONE_TOKEN, 51 * ONE_TOKEN);
+        withdraw(&State::default(), &mut user, &mut market, &oracle_price_data(), withdraw_amount, 1).unwrap();
+
+        // withdrawing within the deposit is not held to the market's cap
+        let (market, mut user) = get_market_and_user(100 * ONE_TOKEN, 50 * ONE_TOKEN);
+        let market = Market {
+            borrow_balance: 200 * SPOT_BALANCE_PRECISION,
+            token_borrow_twap: (200 * ONE_TOKEN) as u64,
+            ..market
+        };
+        withdraw(&State::default(), &mut user, &mut market.clone(), &oracle_price_data(), (100 * ONE_TOKEN) as u64, 1)
+            .unwrap();
+
+        // but it is to the user's once the position sold more tokens on the book than it keeps deposited: 440 sold
+        // against the 400 deposited is a borrow of 40 tokens, that the withdrawal would raise to 60
+        let (_, mut user) = market_with_deposits();
+        user.positions[0].base_asset_amount = -(440 * ONE_TOKEN as i64);
+        assert_eq!(
+            withdraw(&State::default(), &mut user, &mut market.clone(), &oracle_price_data(), (20 * ONE_TOKEN) as u64, 1),
+            Err(ErrorCode::UserBorrowLimitExceeded)
+        );
+    }
+
+    #[test]
+    fn withdraw_below_the_initial_margin_fails() {
+        let (market, mut user) = market_with_deposits();
+        let mut market = Market {
+            withdraw_limit: (500 * ONE_TOKEN) as u64,
+            ..market
+        };
+
+        // a borrow without collateral
+        assert_eq!(
+            withdraw(&State::default(), &mut user, &mut market, &oracle_price_data(), (401 * ONE_TOKEN) as u64, 1),
+            Err(ErrorCode::InsufficientCollateral)
+        );
+    }
+
+    #[test]
+    fn deposit_over_the_deposit_limit_fails() {
+        let get_market_with_deposit_limit = || {
+            let (market, user) = market_with_deposits();
+            let market = Market {
+                max_deposit_limit: (1_100 * ONE_TOKEN) as u64,
+                ..market
+            };
+            (market, user)
+        };
+
+        let (mut market, mut user) = get_market_with_deposit_limit();
+        assert_eq!(
+            deposit(&mut user, &mut market, (101 * ONE_TOKEN) as u64, 1),
+            Err(ErrorCode::MaxDepositExceeded)
+        );
+
+        let (mut market, mut user) = get_market_with_deposit_limit();
+        deposit(&mut user, &mut market, (100 * ONE_TOKEN) as u64, 1).unwrap();
+        assert_eq!(user.positions[0].get_token_amount(&market).unwrap(), 500 * ONE_TOKEN);
+    }
+}
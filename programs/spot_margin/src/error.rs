@@ -49,5 +49,97 @@ pub enum ErrorCode {
     #[msg("Unable to load phoenix program")]
     CannotLoadPhoenixProgram,
     #[msg("Failed to deserialize phoenix market")]
-    FailedToDeserializePhoenixMarket
-}
\ No newline at end of file
+    FailedToDeserializePhoenixMarket,
+    #[msg("Invalid market oracle guard rails")]
+    InvalidMarketOracleGuardRails,
+    #[msg("Invalid confidence interval multiplier")]
+    InvalidConfidenceIntervalMultiplier,
+    #[msg("User has no position in market")]
+    UserHasNoPositionInMarket,
+    #[msg("User has no free position slot")]
+    NoPositionAvailable,
+    #[msg("Order does not exist")]
+    OrderDoesNotExist,
+    #[msg("User has reached the maximum number of open orders")]
+    MaxNumberOfOrders,
+    #[msg("Phoenix CPI failed")]
+    PhoenixCpiFailed,
+    #[msg("Order type is not supported")]
+    OrderTypeNotSupported,
+    #[msg("Invalid order direction")]
+    InvalidOrderDirection,
+    #[msg("Invalid order size")]
+    InvalidOrderSize,
+    #[msg("Invalid order price")]
+    InvalidOrderPrice,
+    #[msg("Orders are disabled for the market")]
+    MarketOrdersDisabled,
+    #[msg("Market is not active")]
+    MarketNotActive,
+    #[msg("Oracle pegged order is too far from the oracle price")]
+    OraclePegDistanceTooLarge,
+    #[msg("Market index does not match the market")]
+    InvalidMarketIndex,
+    #[msg("Phoenix market does not match the market")]
+    InvalidPhoenixMarket,
+    #[msg("Signer does not match the protocol signer")]
+    InvalidSigner,
+    #[msg("Insufficient collateral to meet the margin requirement")]
+    InsufficientCollateral,
+    #[msg("Order is not a trigger order or has already been triggered")]
+    OrderNotTriggerable,
+    #[msg("Order trigger condition is not met")]
+    OrderDidNotSatisfyTriggerCondition,
+    #[msg("Keeper cannot trigger its own orders")]
+    InvalidKeeper,
+    #[msg("Invalid order expiry")]
+    InvalidOrderExpiry,
+    #[msg("Order has expired")]
+    OrderExpired,
+    #[msg("Reduce-only order would increase the position")]
+    ReduceOnlyOrderIncreasesPosition,
+    #[msg("Fill-or-kill order was not filled completely")]
+    FillOrKillOrderNotFilled,
+    #[msg("Market or user is in reduce-only mode")]
+    ReduceOnlyModeActive,
+    #[msg("Invalid bracket order")]
+    InvalidBracketOrder,
+    #[msg("Entry order of the bracket has not been filled")]
+    OcoEntryOrderNotFilled,
+    #[msg("Order would match the user's own order")]
+    SelfTradeNotAllowed,
+    #[msg("Phoenix trader does not belong to the user")]
+    InvalidPhoenixTrader,
+    #[msg("Phoenix seat has not been evicted")]
+    PhoenixSeatNotEvicted,
+    #[msg("Token account still holds tokens")]
+    TokenAccountNotEmpty,
+    #[msg("Market is not fulfilled by this venue")]
+    InvalidFulfillmentType,
+    #[msg("OpenBook V2 market does not match the market")]
+    InvalidOpenBookV2Market,
+    #[msg("Failed to deserialize OpenBook V2 account")]
+    FailedToDeserializeOpenBookV2Account,
+    #[msg("OpenBook V2 CPI failed")]
+    OpenBookV2CpiFailed,
+    #[msg("Withdrawal exceeds the market's withdraw limits")]
+    WithdrawLimitExceeded,
+    #[msg("Invalid interest rate model")]
+    InvalidInterestRateModel,
+    #[msg("Invalid interest take rate")]
+    InvalidInterestTakeRate,
+    #[msg("Borrow exceeds the market's borrow limit")]
+    MarketBorrowLimitExceeded,
+    #[msg("Borrow exceeds the market's per-user borrow limit")]
+    UserBorrowLimitExceeded,
+    #[msg("Invalid borrow limit")]
+    InvalidBorrowLimit,
+    #[msg("Invalid token amount")]
+    InvalidTokenAmount,
+    #[msg("Withdrawals are paused for the market")]
+    MarketWithdrawPaused,
+    #[msg("Deposit exceeds the market's deposit limit")]
+    MaxDepositExceeded,
+    #[msg("Token account is not the market's vault")]
+    InvalidMarketVault,
+}
@@ -0,0 +1,467 @@
+//! Admin-gated instructions. Every context here requires the signer to be the `admin` stored in `State`.
+
+use anchor_lang::prelude::*;
+use phoenix::program::status::MarketStatus as PhoenixMarketStatus;
+
+use crate::{
+    controller,
+    error::ErrorCode,
+    math::{
+        casting::Cast,
+        constants::{
+            MAX_BORROW_RATE_KINKS,
+            MAX_CONFIDENCE_INTERVAL_MULTIPLIER,
+            PERCENTAGE_PRECISION,
+            QUOTE_SPOT_MARKET_INDEX
+        },
+        interest_rate::validate_interest_rate_model
+    },
+    state::{
+        config::State,
+        market::{
+            BorrowRateKink,
+            Market
+        },
+        openbook_v2::{
+            load_openbook_v2_market,
+            load_openbook_v2_open_orders_account,
+            OpenBookV2
+        },
+        phoenix::{
+            fetch_market_header,
+            get_phoenix_market_status,
+            validate_phoenix_market_header
+        },
+        guard_rails::MarketOracleGuardRails,
+        enums::{
+            OracleType,
+            OracleSourcePolicy,
+            SpotFulfillmentType,
+            InterestRateModel
+        }
+    },
+    load,
+    load_mut,
+    validate
+};
+
+/// Sets the per-market oracle guard rail overrides. Passing a zeroed struct resets the market to the `State` defaults.
+pub fn handle_update_market_oracle_guard_rails(
+    ctx: Context<AdminUpdateMarket>,
+    oracle_guard_rails: MarketOracleGuardRails
+) -> Result<()> {
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    oracle_guard_rails.validate()?;
+
+    msg!(
+        "market {} oracle guard rails {:?} -> {:?}",
+        market.market_index,
+        market.oracle_guard_rails,
+        oracle_guard_rails
+    );
+
+    market.oracle_guard_rails = oracle_guard_rails;
+
+    Ok(())
+}
+
+/// Configures the secondary oracle, the Phoenix mid source, and how the market combines its oracle sources.
+/// Passing Pubkey::default() as the secondary oracle removes it.
+pub fn handle_update_market_oracle_sources(
+    ctx: Context<AdminUpdateMarket>,
+    secondary_oracle: Pubkey,
+    secondary_oracle_type: OracleType,
+    use_phoenix_mid_oracle: bool,
+    oracle_source_policy: OracleSourcePolicy,
+    oracle_quorum: u8
+) -> Result<()> {
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    validate!(
+        secondary_oracle != market.oracle,
+        ErrorCode::InvalidOracle,
+        "secondary oracle must differ from the primary oracle"
+    )?;
+
+    validate!(
+        secondary_oracle_type != OracleType::Switchboard,
+        ErrorCode::InvalidOracle,
+        "switchboard oracles are not supported as secondary oracle"
+    )?;
+
+    validate!(
+        !use_phoenix_mid_oracle || market.phoenix_market != Pubkey::default(),
+        ErrorCode::InvalidPhoenixMarket,
+        "market {} has no phoenix market to take a mid price from",
+        market.market_index
+    )?;
+
+    // without a secondary oracle the type is unused, it must be left at its default
+    validate!(
+        secondary_oracle != Pubkey::default() || secondary_oracle_type == OracleType::default(),
+        ErrorCode::InvalidOracle,
+        "secondary oracle type {:?} requires a secondary oracle",
+        secondary_oracle_type
+    )?;
+
+    msg!(
+        "market {} oracle sources: secondary {} -> {}, phoenix mid {} -> {}, policy {:?} -> {:?}, quorum {} -> {}",
+        market.market_index,
+        market.secondary_oracle,
+        secondary_oracle,
+        market.use_phoenix_mid_oracle,
+        use_phoenix_mid_oracle,
+        market.oracle_source_policy,
+        oracle_source_policy,
+        market.oracle_quorum,
+        oracle_quorum
+    );
+
+    market.secondary_oracle = secondary_oracle;
+    market.secondary_oracle_type = secondary_oracle_type;
+    market.use_phoenix_mid_oracle = use_phoenix_mid_oracle;
+    market.oracle_source_policy = oracle_source_policy;
+    market.oracle_quorum = oracle_quorum;
+
+    // a quorum that can never be met would leave the market without sufficient data points forever
+    validate!(
+        oracle_quorum <= market.number_of_oracle_sources(),
+        ErrorCode::InvalidOracle,
+        "oracle quorum {} exceeds the number of oracle sources {}",
+        oracle_quorum,
+        market.number_of_oracle_sources()
+    )?;
+
+    Ok(())
+}
+
+/// Sets the multiplier(k) applied to the oracle confidence interval when valuing balances for initial margin.
+/// 0 disables the confidence adjustment.
+pub fn handle_update_market_confidence_interval_multiplier(
+    ctx: Context<AdminUpdateMarket>,
+    confidence_interval_multiplier: u32
+) -> Result<()> {
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    validate!(
+        confidence_interval_multiplier <= MAX_CONFIDENCE_INTERVAL_MULTIPLIER,
+        ErrorCode::InvalidConfidenceIntervalMultiplier,
+        "confidence interval multiplier {} exceeds max {}",
+        confidence_interval_multiplier,
+        MAX_CONFIDENCE_INTERVAL_MULTIPLIER
+    )?;
+
+    msg!(
+        "market {} confidence interval multiplier {} -> {}",
+        market.market_index,
+        market.confidence_interval_multiplier,
+        confidence_interval_multiplier
+    );
+
+    market.confidence_interval_multiplier = confidence_interval_multiplier;
+
+    Ok(())
+}
+
+/// Sets the Phoenix market that the market's orders are placed on. The Phoenix market's base mint must be the market's
+/// token mint, its quote mint the quote market's token mint, and it must be active.
+pub fn handle_update_market_phoenix_market(ctx: Context<AdminUpdateMarketPhoenixMarket>) -> Result<()> {
+    let market = &mut load_mut!(ctx.accounts.market)?;
+    let quote_market = load!(ctx.accounts.quote_market)?;
+    let market_header = fetch_market_header(&ctx.accounts.phoenix_market)?;
+
+    validate!(
+        quote_market.market_index == QUOTE_SPOT_MARKET_INDEX,
+        ErrorCode::InvalidPhoenixMarket,
+        "market {} is not the quote market",
+        quote_market.market_index
+    )?;
+
+    validate_phoenix_market_header(market, &market_header, &quote_market.token_mint)?;
+
+    validate!(
+        get_phoenix_market_status(&market_header)? == PhoenixMarketStatus::Active,
+        ErrorCode::InvalidPhoenixMarket,
+        "phoenix market {} is not active",
+        ctx.accounts.phoenix_market.key()
+    )?;
+
+    msg!(
+        "market {} phoenix market {} -> {}",
+        market.market_index,
+        market.phoenix_market,
+        ctx.accounts.phoenix_market.key()
+    );
+
+    market.phoenix_market = ctx.accounts.phoenix_market.key();
+
+    Ok(())
+}
+
+/// Sets the OpenBook V2 market that the market's orders are placed on when it is fulfilled by OpenBook V2. The OpenBook
+/// V2 market's base mint must be the market's token mint. Creates the protocol signer's open orders account on the
+/// OpenBook V2 market if it does not exist yet, the admin pays its rent.
+pub fn handle_update_market_openbook_v2_market(ctx: Context<AdminUpdateMarketOpenBookV2Market>) -> Result<()> {
+    let state = &ctx.accounts.state;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+    let openbook_v2_market = load_openbook_v2_market(&ctx.accounts.openbook_v2_market)?;
+
+    validate!(
+        openbook_v2_market.base_mint == market.token_mint,
+        ErrorCode::InvalidOpenBookV2Market,
+        "OpenBook V2 market base mint {} does not match market mint {}",
+        openbook_v2_market.base_mint,
+        market.token_mint
+    )?;
+
+    validate!(
+        ctx.accounts.signer.key() == state.signer,
+        ErrorCode::InvalidSigner,
+        "Signer {} is not the protocol signer",
+        ctx.accounts.signer.key()
+    )?;
+
+    if ctx.accounts.open_orders_account.data_is_empty() {
+        controller::openbook_v2::invoke_openbook_v2_create_open_orders_account(
+            &ctx.accounts.openbook_v2_program.to_account_info(),
+            &ctx.accounts.openbook_v2_market.to_account_info(),
+            &ctx.accounts.open_orders_indexer.to_account_info(),
+            &ctx.accounts.open_orders_account.to_account_info(),
+            &ctx.accounts.signer.to_account_info(),
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            state.signer_nonce
+        )?;
+    }
+
+    load_openbook_v2_open_orders_account(
+        &ctx.accounts.open_orders_account,
+        &state.signer,
+        &ctx.accounts.openbook_v2_market.key()
+    )?;
+
+    msg!(
+        "market {} openbook v2 market {} -> {}",
+        market.market_index,
+        market.openbook_v2_market,
+        ctx.accounts.openbook_v2_market.key()
+    );
+
+    market.openbook_v2_market = ctx.accounts.openbook_v2_market.key();
+
+    Ok(())
+}
+
+/// Sets the venue the market's orders are placed on. The venue's market must have been set. Orders resting on the
+/// previous venue can no longer be managed, so orders should be disabled and cancelled before switching.
+pub fn handle_update_market_fulfillment_type(
+    ctx: Context<AdminUpdateMarket>,
+    fulfillment_type: SpotFulfillmentType
+) -> Result<()> {
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    let venue_market = match fulfillment_type {
+        SpotFulfillmentType::PhoenixV1 => market.phoenix_market,
+        SpotFulfillmentType::OpenBookV2 => market.openbook_v2_market
+    };
+
+    validate!(
+        venue_market != Pubkey::default(),
+        ErrorCode::InvalidFulfillmentType,
+        "market {} has no {:?} market",
+        market.market_index,
+        fulfillment_type
+    )?;
+
+    msg!(
+        "market {} fulfillment type {:?} -> {:?}",
+        market.market_index,
+        market.fulfillment_type,
+        fulfillment_type
+    );
+
+    market.fulfillment_type = fulfillment_type;
+
+    Ok(())
+}
+
+/// Sets the curve that derives the market's borrow rate from its utilization. The single-kink curve uses the optimal
+/// utilization and borrow rate, the multi-kink curve uses up to MAX_BORROW_RATE_KINKS kinks in increasing utilization,
+/// both end at the max borrow rate at 100% utilization. The min borrow rate floors either curve.
+pub fn handle_update_market_interest_rate_model(
+    ctx: Context<AdminUpdateMarket>,
+    interest_rate_model: InterestRateModel,
+    optimal_utilization: u32,
+    optimal_borrow_rate: u32,
+    max_borrow_rate: u32,
+    min_borrow_rate: u32,
+    borrow_rate_kinks: Vec<BorrowRateKink>
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    // interest accrued so far is owed at the previous rates
+    controller::spot_balance::update_market_cumulative_interest(market, clock.unix_timestamp)?;
+
+    validate!(
+        borrow_rate_kinks.len() <= MAX_BORROW_RATE_KINKS,
+        ErrorCode::InvalidInterestRateModel,
+        "{} kinks exceeds max {}",
+        borrow_rate_kinks.len(),
+        MAX_BORROW_RATE_KINKS
+    )?;
+
+    let mut kinks = [BorrowRateKink::default(); MAX_BORROW_RATE_KINKS];
+    kinks[..borrow_rate_kinks.len()].copy_from_slice(&borrow_rate_kinks);
+
+    msg!(
+        "market {} interest rate model {:?} -> {:?}, optimal utilization {} -> {}, optimal borrow rate {} -> {}, max borrow rate {} -> {}, min borrow rate {} -> {}, kinks {:?} -> {:?}",
+        market.market_index,
+        market.interest_rate_model,
+        interest_rate_model,
+        market.optimal_utilization,
+        optimal_utilization,
+        market.optimal_borrow_rate,
+        optimal_borrow_rate,
+        market.max_borrow_rate,
+        max_borrow_rate,
+        market.min_borrow_rate,
+        min_borrow_rate,
+        market.borrow_rate_kinks,
+        kinks
+    );
+
+    market.interest_rate_model = interest_rate_model;
+    market.optimal_utilization = optimal_utilization;
+    market.optimal_borrow_rate = optimal_borrow_rate;
+    market.max_borrow_rate = max_borrow_rate;
+    market.min_borrow_rate = min_borrow_rate;
+    market.borrow_rate_kinks = kinks;
+
+    validate_interest_rate_model(market)?;
+
+    Ok(())
+}
+
+/// Sets the share of the market's accrued borrow interest that is credited to its revenue pool instead of the depositors
+pub fn handle_update_market_interest_take_rate(
+    ctx: Context<AdminUpdateMarket>,
+    interest_take_rate: u32
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    validate!(
+        interest_take_rate.cast::<u128>()? <= PERCENTAGE_PRECISION,
+        ErrorCode::InvalidInterestTakeRate,
+        "interest take rate {} exceeds {}",
+        interest_take_rate,
+        PERCENTAGE_PRECISION
+    )?;
+
+    // interest accrued so far is split at the previous take rate
+    controller::spot_balance::update_market_cumulative_interest(market, clock.unix_timestamp)?;
+
+    msg!(
+        "market {} interest take rate {} -> {}",
+        market.market_index,
+        market.interest_take_rate,
+        interest_take_rate
+    );
+
+    market.interest_take_rate = interest_take_rate;
+
+    Ok(())
+}
+
+/// Sets the market's borrow cap across all users and its per-user borrow cap. 0 removes a cap. Lowering a cap below the
+/// current borrows only blocks further borrows, existing borrows can still be repaid.
+pub fn handle_update_market_borrow_limits(
+    ctx: Context<AdminUpdateMarket>,
+    max_borrow_limit: u64,
+    max_user_borrow_limit: u64
+) -> Result<()> {
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    // a single user can never borrow more than the market's borrow cap allows
+    validate!(
+        max_borrow_limit == 0 || max_user_borrow_limit <= max_borrow_limit,
+        ErrorCode::InvalidBorrowLimit,
+        "per-user borrow limit {} exceeds the market borrow limit {}",
+        max_user_borrow_limit,
+        max_borrow_limit
+    )?;
+
+    msg!(
+        "market {} borrow limit {} -> {}, per-user borrow limit {} -> {}",
+        market.market_index,
+        market.max_borrow_limit,
+        max_borrow_limit,
+        market.max_user_borrow_limit,
+        max_user_borrow_limit
+    );
+
+    market.max_borrow_limit = max_borrow_limit;
+    market.max_user_borrow_limit = max_user_borrow_limit;
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct AdminUpdateMarket<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(has_one = admin)]
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+}
+
+#[derive(Accounts)]
+pub struct AdminUpdateMarketPhoenixMarket<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(has_one = admin)]
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+
+    /// Market of the protocol's quote asset, checked against its market index
+    pub quote_market: AccountLoader<'info, Market>,
+
+    /// CHECK: checked in fetch_market_header and validate_phoenix_market_header
+    pub phoenix_market: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AdminUpdateMarketOpenBookV2Market<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(has_one = admin)]
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+
+    /// CHECK: checked in load_openbook_v2_market
+    pub openbook_v2_market: UncheckedAccount<'info>,
+
+    pub openbook_v2_program: Program<'info, OpenBookV2>,
+
+    /// CHECK: Protocol PDA that trades on OpenBook V2, checked against the state's signer
+    pub signer: UncheckedAccount<'info>,
+
+    /// CHECK: Open orders indexer of the signer, checked by the OpenBook V2 program
+    #[account(mut)]
+    pub open_orders_indexer: UncheckedAccount<'info>,
+
+    /// CHECK: Open orders account of the signer on the OpenBook V2 market, checked in load_openbook_v2_open_orders_account
+    #[account(mut)]
+    pub open_orders_account: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
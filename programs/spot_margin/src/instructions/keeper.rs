@@ -0,0 +1,353 @@
+//! Permissionless instructions that keepers crank to keep the protocol's state fresh.
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    controller::{
+        self,
+        fulfillment::{
+            OpenBookV2Fulfillment,
+            SpotFulfillment,
+            SpotFulfillmentAccounts
+        }
+    },
+    error::ErrorCode,
+    state::{
+        config::State,
+        market::Market,
+        openbook_v2::{
+            OpenBookV2MarketAccounts,
+            __client_accounts_open_book_v2_market_accounts,
+            __cpi_client_accounts_open_book_v2_market_accounts
+        },
+        oracle::get_market_oracle_price_data,
+        user::User
+    },
+    load,
+    load_mut,
+    validate
+};
+
+/// Updates a market's oracle TWAPs, accrues its interest and updates its deposit, borrow and utilization TWAPs. The
+/// secondary oracle, if the market has one, is passed as the first remaining account. Markets that use the Phoenix mid
+/// price as an oracle source also update it from their Phoenix market, passed as the next remaining account.
+pub fn handle_update_market_oracle_twap(ctx: Context<UpdateMarketOracleTwap>) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    if market.use_phoenix_mid_oracle {
+        let phoenix_market = ctx
+            .remaining_accounts
+            .get(market.has_secondary_oracle() as usize)
+            .ok_or(ErrorCode::InvalidPhoenixMarket)?;
+
+        controller::oracle::update_phoenix_index_data(market, phoenix_market, clock.slot, clock.unix_timestamp)?;
+    }
+
+    let oracle_account_info = ctx.accounts.oracle.to_account_info();
+
+    let oracle_price_data = get_market_oracle_price_data(
+        market,
+        Some(&oracle_account_info),
+        ctx.remaining_accounts.first(),
+        &state.oracle_guard_rails,
+        clock.slot
+    )?;
+
+    controller::oracle::update_oracle_twap(
+        market,
+        &oracle_price_data,
+        &state.oracle_guard_rails,
+        clock.unix_timestamp
+    )?;
+
+    controller::spot_balance::update_market_cumulative_interest(market, clock.unix_timestamp)?;
+
+    controller::spot_balance::update_market_token_twaps(market, clock.unix_timestamp)?;
+
+    Ok(())
+}
+
+/// Re-prices a user's oracle pegged orders in a market to follow the oracle, cancelling the ones that can no longer be
+/// kept on the book. The secondary oracle, if the market has one, is passed as the first remaining account, followed by
+/// the accounts of the market's fulfillment venue.
+pub fn handle_update_pegged_orders<'info>(ctx: Context<'_, '_, '_, 'info, UpdatePeggedOrders<'info>>) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    let oracle_account_info = ctx.accounts.oracle.to_account_info();
+
+    let oracle_price_data = get_market_oracle_price_data(
+        market,
+        Some(&oracle_account_info),
+        ctx.remaining_accounts.first(),
+        &state.oracle_guard_rails,
+        clock.slot
+    )?;
+
+    controller::oracle::update_oracle_twap(
+        market,
+        &oracle_price_data,
+        &state.oracle_guard_rails,
+        clock.unix_timestamp
+    )?;
+
+    let fulfillment_accounts = SpotFulfillmentAccounts::load(market, ctx.remaining_accounts)?;
+    let fulfillment = fulfillment_accounts.get_fulfillment(state, market, &ctx.accounts.user.key())?;
+
+    let number_of_orders_updated = controller::orders::update_pegged_orders(
+        state,
+        user,
+        market,
+        &oracle_price_data,
+        fulfillment.as_ref(),
+        clock.slot,
+        clock.unix_timestamp
+    )?;
+
+    msg!("updated {} pegged orders in market {}", number_of_orders_updated, market.market_index);
+
+    Ok(())
+}
+
+/// Triggers a user's trigger order once the oracle crosses its trigger price and submits it to the market's fulfillment
+/// venue. The keeper's user account receives the keeper fee. The secondary oracle, if the market has one, is passed as
+/// the first remaining account, followed by the accounts of the market's fulfillment venue.
+pub fn handle_trigger_order<'info>(ctx: Context<'_, '_, '_, 'info, TriggerOrder<'info>>, order_id: u32) -> Result<()> {
+    validate!(
+        ctx.accounts.keeper.key() != ctx.accounts.user.key(),
+        ErrorCode::InvalidKeeper,
+        "keeper cannot trigger its own orders"
+    )?;
+
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let keeper = &mut load_mut!(ctx.accounts.keeper)?;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    let oracle_account_info = ctx.accounts.oracle.to_account_info();
+
+    let oracle_price_data = get_market_oracle_price_data(
+        market,
+        Some(&oracle_account_info),
+        ctx.remaining_accounts.first(),
+        &state.oracle_guard_rails,
+        clock.slot
+    )?;
+
+    controller::oracle::update_oracle_twap(
+        market,
+        &oracle_price_data,
+        &state.oracle_guard_rails,
+        clock.unix_timestamp
+    )?;
+
+    let fulfillment_accounts = SpotFulfillmentAccounts::load(market, ctx.remaining_accounts)?;
+    let fulfillment = fulfillment_accounts.get_fulfillment(state, market, &ctx.accounts.user.key())?;
+
+    controller::orders::trigger_order(
+        state,
+        user,
+        order_id,
+        keeper,
+        market,
+        &oracle_price_data,
+        fulfillment.as_ref(),
+        clock.slot,
+        clock.unix_timestamp
+    )?;
+
+    Ok(())
+}
+
+/// Cancels a user's expired orders in a market and releases the margin reserved for them. The accounts of the market's
+/// fulfillment venue are passed as remaining accounts, after the market's secondary oracle if it has one.
+pub fn handle_expire_orders<'info>(ctx: Context<'_, '_, '_, 'info, ExpireOrders<'info>>) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let market = &load!(ctx.accounts.market)?;
+
+    let fulfillment_accounts = SpotFulfillmentAccounts::load(market, ctx.remaining_accounts)?;
+    let fulfillment = fulfillment_accounts.get_fulfillment(state, market, &ctx.accounts.user.key())?;
+
+    let number_of_orders_expired = controller::orders::expire_orders(
+        user,
+        market,
+        fulfillment.as_ref(),
+        clock.unix_timestamp
+    )?;
+
+    msg!("expired {} orders in market {}", number_of_orders_expired, market.market_index);
+
+    Ok(())
+}
+
+/// Shows the next clip of a user's iceberg orders in a market once their visible clip has been filled. The accounts of
+/// the market's fulfillment venue are passed as remaining accounts, after the market's secondary oracle if it has one.
+pub fn handle_refill_iceberg_orders<'info>(ctx: Context<'_, '_, '_, 'info, RefillIcebergOrders<'info>>) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let market = &load!(ctx.accounts.market)?;
+
+    let fulfillment_accounts = SpotFulfillmentAccounts::load(market, ctx.remaining_accounts)?;
+    let fulfillment = fulfillment_accounts.get_fulfillment(state, market, &ctx.accounts.user.key())?;
+
+    let number_of_orders_refilled = controller::orders::refill_iceberg_orders(
+        user,
+        market,
+        fulfillment.as_ref(),
+        clock.slot,
+        clock.unix_timestamp
+    )?;
+
+    msg!("refilled {} iceberg orders in market {}", number_of_orders_refilled, market.market_index);
+
+    Ok(())
+}
+
+/// Executes the clips of a user's TWAP orders in a market that are due. The secondary oracle, if the market has one, is
+/// passed as the first remaining account, followed by the accounts of the market's fulfillment venue.
+pub fn handle_fill_twap_orders<'info>(ctx: Context<'_, '_, '_, 'info, FillTwapOrders<'info>>) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    let oracle_account_info = ctx.accounts.oracle.to_account_info();
+
+    let oracle_price_data = get_market_oracle_price_data(
+        market,
+        Some(&oracle_account_info),
+        ctx.remaining_accounts.first(),
+        &state.oracle_guard_rails,
+        clock.slot
+    )?;
+
+    controller::oracle::update_oracle_twap(
+        market,
+        &oracle_price_data,
+        &state.oracle_guard_rails,
+        clock.unix_timestamp
+    )?;
+
+    let fulfillment_accounts = SpotFulfillmentAccounts::load(market, ctx.remaining_accounts)?;
+    let fulfillment = fulfillment_accounts.get_fulfillment(state, market, &ctx.accounts.user.key())?;
+
+    let number_of_clips_executed = controller::orders::fill_twap_orders(
+        state,
+        user,
+        market,
+        &oracle_price_data,
+        fulfillment.as_ref(),
+        clock.unix_timestamp
+    )?;
+
+    msg!("executed {} TWAP clips in market {}", number_of_clips_executed, market.market_index);
+
+    Ok(())
+}
+
+/// Moves the protocol's free funds on the market's fulfillment venue, i.e fills of resting orders that OpenBook V2's crank
+/// has consumed, to the protocol's token accounts
+pub fn handle_settle_openbook_v2_funds(ctx: Context<SettleOpenBookV2Funds>) -> Result<()> {
+    let state = &ctx.accounts.state;
+    let market = &load!(ctx.accounts.market)?;
+
+    OpenBookV2Fulfillment::new(state, market, &ctx.accounts.openbook_v2)?.settle_funds()?;
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct UpdateMarketOracleTwap<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+
+    /// CHECK: checked against the market's oracle when loading the price
+    pub oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePeggedOrders<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut)]
+    pub user: AccountLoader<'info, User>,
+
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+
+    /// CHECK: checked against the market's oracle when loading the price
+    pub oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TriggerOrder<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    pub authority: Signer<'info>,
+
+    /// User account of the keeper, receives the keeper fee
+    #[account(mut, has_one = authority)]
+    pub keeper: AccountLoader<'info, User>,
+
+    #[account(mut)]
+    pub user: AccountLoader<'info, User>,
+
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+
+    /// CHECK: checked against the market's oracle when loading the price
+    pub oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExpireOrders<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut)]
+    pub user: AccountLoader<'info, User>,
+
+    pub market: AccountLoader<'info, Market>,
+}
+
+#[derive(Accounts)]
+pub struct RefillIcebergOrders<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut)]
+    pub user: AccountLoader<'info, User>,
+
+    pub market: AccountLoader<'info, Market>,
+}
+
+#[derive(Accounts)]
+pub struct FillTwapOrders<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut)]
+    pub user: AccountLoader<'info, User>,
+
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+
+    /// CHECK: checked against the market's oracle when loading the price
+    pub oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SettleOpenBookV2Funds<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    pub market: AccountLoader<'info, Market>,
+
+    pub openbook_v2: OpenBookV2MarketAccounts<'info>,
+}
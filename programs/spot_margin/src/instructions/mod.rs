@@ -0,0 +1,9 @@
+//! Instruction handlers and their account contexts. `lib.rs` dispatches to the handlers defined here.
+
+pub mod admin;
+pub mod keeper;
+pub mod user;
+
+pub use admin::*;
+pub use keeper::*;
+pub use user::*;
@@ -0,0 +1,700 @@
+//! Instructions signed by the authority of a user account.
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{
+        self,
+        CloseAccount,
+        Mint,
+        Token,
+        TokenAccount,
+        Transfer
+    }
+};
+use phoenix::program::new_order::CondensedOrder;
+
+use crate::{
+    controller::{
+        self,
+        fulfillment::SpotFulfillmentAccounts
+    },
+    error::ErrorCode,
+    state::{
+        config::State,
+        helpers::{
+            get_phoenix_trader_seeds,
+            get_signer_seeds
+        },
+        market::Market,
+        oracle::get_market_oracle_price_data,
+        order::{
+            ModifyOrderParams,
+            OrderParams
+        },
+        phoenix::{
+            fetch_market_header,
+            get_phoenix_trader_address,
+            is_phoenix_trader_registered,
+            PhoenixSeatAccounts,
+            __client_accounts_phoenix_seat_accounts,
+            __cpi_client_accounts_phoenix_seat_accounts
+        },
+        traits::Size,
+        user::User
+    },
+    load,
+    load_mut,
+    validate
+};
+
+/// Creates a user account for the authority. A wallet can hold several accounts, told apart by the sub account id.
+pub fn handle_initialize_user(
+    ctx: Context<InitializeUser>,
+    sub_account_id: u16
+) -> Result<()> {
+    let mut user = ctx.accounts.user.load_init()?;
+
+    *user = User {
+        authority: ctx.accounts.authority.key(),
+        sub_account_id,
+        next_order_id: 1,
+        ..User::default()
+    };
+
+    Ok(())
+}
+
+/// Deposits tokens from the authority's token account into the market's vault, repaying the user's borrow in the market
+/// first.
+pub fn handle_deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+    let clock = Clock::get()?;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    validate!(
+        ctx.accounts.market_vault.key() == market.vault,
+        ErrorCode::InvalidMarketVault,
+        "Token account {} is not the vault of market {}",
+        ctx.accounts.market_vault.key(),
+        market.market_index
+    )?;
+
+    controller::spot_balance::deposit(user, market, amount, clock.unix_timestamp)?;
+
+    token::transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.user_token_account.to_account_info(),
+                to: ctx.accounts.market_vault.to_account_info(),
+                authority: ctx.accounts.authority.to_account_info()
+            }
+        ),
+        amount
+    )?;
+
+    msg!("deposited {} in market {}", amount, market.market_index);
+
+    Ok(())
+}
+
+/// Withdraws tokens from the market's vault to a token account of the market's mint, borrowing what exceeds the user's
+/// deposit. The secondary oracle, if the market has one, is passed as the first remaining account.
+pub fn handle_withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    validate!(
+        ctx.accounts.market_vault.key() == market.vault,
+        ErrorCode::InvalidMarketVault,
+        "Token account {} is not the vault of market {}",
+        ctx.accounts.market_vault.key(),
+        market.market_index
+    )?;
+
+    validate!(
+        ctx.accounts.signer.key() == state.signer,
+        ErrorCode::InvalidSigner,
+        "Signer {} is not the protocol signer",
+        ctx.accounts.signer.key()
+    )?;
+
+    let oracle_account_info = ctx.accounts.oracle.to_account_info();
+
+    let oracle_price_data = get_market_oracle_price_data(
+        market,
+        Some(&oracle_account_info),
+        ctx.remaining_accounts.first(),
+        &state.oracle_guard_rails,
+        clock.slot
+    )?;
+
+    controller::oracle::update_oracle_twap(
+        market,
+        &oracle_price_data,
+        &state.oracle_guard_rails,
+        clock.unix_timestamp
+    )?;
+
+    controller::spot_balance::withdraw(state, user, market, &oracle_price_data, amount, clock.unix_timestamp)?;
+
+    let signer_seeds = get_signer_seeds(&state.signer_nonce);
+
+    token::transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.market_vault.to_account_info(),
+                to: ctx.accounts.user_token_account.to_account_info(),
+                authority: ctx.accounts.signer.to_account_info()
+            },
+            &[&signer_seeds]
+        ),
+        amount
+    )?;
+
+    msg!("withdrew {} from market {}", amount, market.market_index);
+
+    Ok(())
+}
+
+/// Places an order on the market's fulfillment venue. The secondary oracle, if the market has one, is passed as the first
+/// remaining account, followed by the accounts of the market's fulfillment venue.
+pub fn handle_place_order<'info>(
+    ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>,
+    params: OrderParams
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    let oracle_account_info = ctx.accounts.oracle.to_account_info();
+
+    let oracle_price_data = get_market_oracle_price_data(
+        market,
+        Some(&oracle_account_info),
+        ctx.remaining_accounts.first(),
+        &state.oracle_guard_rails,
+        clock.slot
+    )?;
+
+    controller::oracle::update_oracle_twap(
+        market,
+        &oracle_price_data,
+        &state.oracle_guard_rails,
+        clock.unix_timestamp
+    )?;
+
+    let fulfillment_accounts = SpotFulfillmentAccounts::load(market, ctx.remaining_accounts)?;
+    let fulfillment = fulfillment_accounts.get_fulfillment(state, market, &ctx.accounts.user.key())?;
+
+    let order_id = controller::orders::place_order(
+        state,
+        user,
+        market,
+        &oracle_price_data,
+        fulfillment.as_ref(),
+        params,
+        clock.slot,
+        clock.unix_timestamp
+    )?;
+
+    msg!("placed order {} in market {}", order_id, market.market_index);
+
+    Ok(())
+}
+
+/// Places a ladder of post-only quotes on the market's fulfillment venue. The secondary oracle, if the market has one, is
+/// passed as the first remaining account, followed by the accounts of the market's fulfillment venue.
+pub fn handle_place_multiple_orders<'info>(
+    ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>,
+    bids: Vec<CondensedOrder>,
+    asks: Vec<CondensedOrder>,
+    cancel_existing: bool
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    let oracle_account_info = ctx.accounts.oracle.to_account_info();
+
+    let oracle_price_data = get_market_oracle_price_data(
+        market,
+        Some(&oracle_account_info),
+        ctx.remaining_accounts.first(),
+        &state.oracle_guard_rails,
+        clock.slot
+    )?;
+
+    controller::oracle::update_oracle_twap(
+        market,
+        &oracle_price_data,
+        &state.oracle_guard_rails,
+        clock.unix_timestamp
+    )?;
+
+    let fulfillment_accounts = SpotFulfillmentAccounts::load(market, ctx.remaining_accounts)?;
+    let fulfillment = fulfillment_accounts.get_fulfillment(state, market, &ctx.accounts.user.key())?;
+
+    let number_of_orders = controller::orders::place_multiple_orders(
+        state,
+        user,
+        market,
+        &oracle_price_data,
+        fulfillment.as_ref(),
+        bids,
+        asks,
+        cancel_existing,
+        clock.slot,
+        clock.unix_timestamp
+    )?;
+
+    msg!("placed {} orders in market {}", number_of_orders, market.market_index);
+
+    Ok(())
+}
+
+/// Places an entry order with a take-profit and/or a stop-loss attached to it. The secondary oracle, if the market has
+/// one, is passed as the first remaining account, followed by the accounts of the market's fulfillment venue.
+pub fn handle_place_bracket_orders<'info>(
+    ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>,
+    entry: OrderParams,
+    take_profit: Option<OrderParams>,
+    stop_loss: Option<OrderParams>
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    let oracle_account_info = ctx.accounts.oracle.to_account_info();
+
+    let oracle_price_data = get_market_oracle_price_data(
+        market,
+        Some(&oracle_account_info),
+        ctx.remaining_accounts.first(),
+        &state.oracle_guard_rails,
+        clock.slot
+    )?;
+
+    controller::oracle::update_oracle_twap(
+        market,
+        &oracle_price_data,
+        &state.oracle_guard_rails,
+        clock.unix_timestamp
+    )?;
+
+    let fulfillment_accounts = SpotFulfillmentAccounts::load(market, ctx.remaining_accounts)?;
+    let fulfillment = fulfillment_accounts.get_fulfillment(state, market, &ctx.accounts.user.key())?;
+
+    let order_id = controller::orders::place_bracket_orders(
+        state,
+        user,
+        market,
+        &oracle_price_data,
+        fulfillment.as_ref(),
+        entry,
+        take_profit,
+        stop_loss,
+        clock.slot,
+        clock.unix_timestamp
+    )?;
+
+    msg!("placed bracket order {} in market {}", order_id, market.market_index);
+
+    Ok(())
+}
+
+/// Amends the price, size or other parameters of an open order without cancelling it. The secondary oracle, if the
+/// market has one, is passed as the first remaining account, followed by the accounts of the market's fulfillment venue.
+pub fn handle_modify_order<'info>(
+    ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>,
+    order_id: u32,
+    params: ModifyOrderParams
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let market = &mut load_mut!(ctx.accounts.market)?;
+
+    let oracle_account_info = ctx.accounts.oracle.to_account_info();
+
+    let oracle_price_data = get_market_oracle_price_data(
+        market,
+        Some(&oracle_account_info),
+        ctx.remaining_accounts.first(),
+        &state.oracle_guard_rails,
+        clock.slot
+    )?;
+
+    controller::oracle::update_oracle_twap(
+        market,
+        &oracle_price_data,
+        &state.oracle_guard_rails,
+        clock.unix_timestamp
+    )?;
+
+    let fulfillment_accounts = SpotFulfillmentAccounts::load(market, ctx.remaining_accounts)?;
+    let fulfillment = fulfillment_accounts.get_fulfillment(state, market, &ctx.accounts.user.key())?;
+
+    controller::orders::modify_order(
+        state,
+        user,
+        order_id,
+        market,
+        &oracle_price_data,
+        fulfillment.as_ref(),
+        params,
+        clock.slot,
+        clock.unix_timestamp
+    )?;
+
+    msg!("modified order {} in market {}", order_id, market.market_index);
+
+    Ok(())
+}
+
+/// Cancels an open order of the user. The secondary oracle, if the market has one, is passed as the first remaining
+/// account, followed by the accounts of the market's fulfillment venue.
+pub fn handle_cancel_order<'info>(
+    ctx: Context<'_, '_, '_, 'info, CancelOrder<'info>>,
+    order_id: u32
+) -> Result<()> {
+    let state = &ctx.accounts.state;
+    let user = &mut load_mut!(ctx.accounts.user)?;
+    let market = &load!(ctx.accounts.market)?;
+
+    let fulfillment_accounts = SpotFulfillmentAccounts::load(market, ctx.remaining_accounts)?;
+    let fulfillment = fulfillment_accounts.get_fulfillment(state, market, &ctx.accounts.user.key())?;
+
+    controller::orders::cancel_order(
+        user,
+        order_id,
+        market,
+        fulfillment.as_ref()
+    )?;
+
+    Ok(())
+}
+
+/// Gives the user's trader PDA an approved seat on the market's Phoenix market and creates the trader's token accounts.
+/// The user's orders are placed under the trader PDA, its token accounts carry the signer's funds through each Phoenix
+/// CPI and receive the seat's funds when it is evicted.
+pub fn handle_request_phoenix_seat(ctx: Context<RequestPhoenixSeat>) -> Result<()> {
+    let state = &ctx.accounts.state;
+    let market = &load!(ctx.accounts.market)?;
+
+    controller::phoenix::validate_phoenix_seat_accounts(state, market, &ctx.accounts.user.key(), &ctx.accounts.phoenix)?;
+
+    let market_header = fetch_market_header(&ctx.accounts.phoenix.phoenix_market)?;
+
+    validate!(
+        ctx.accounts.base_mint.key() == market_header.base_params.mint_key
+            && ctx.accounts.quote_mint.key() == market_header.quote_params.mint_key,
+        ErrorCode::InvalidPhoenixMarket,
+        "Mints do not match the mints of phoenix market {}",
+        ctx.accounts.phoenix.phoenix_market.key()
+    )?;
+
+    controller::phoenix::invoke_phoenix_request_seat(
+        &ctx.accounts.phoenix,
+        &ctx.accounts.payer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        state.signer_nonce
+    )?;
+
+    msg!("approved seat of trader {} in market {}", ctx.accounts.phoenix.trader.key(), market.market_index);
+
+    Ok(())
+}
+
+/// Evicts the user's trader PDA from the market's Phoenix market. Its free funds are withdrawn to its token accounts and
+/// swept back to the signer.
+pub fn handle_evict_phoenix_seat(ctx: Context<EvictPhoenixSeat>) -> Result<()> {
+    let state = &ctx.accounts.state;
+    let market = &load!(ctx.accounts.market)?;
+
+    let user_key = ctx.accounts.user.key();
+
+    controller::phoenix::validate_phoenix_seat_accounts(state, market, &user_key, &ctx.accounts.phoenix)?;
+
+    let (_, trader_bump) = get_phoenix_trader_address(&user_key);
+
+    controller::phoenix::invoke_phoenix_evict_seat(
+        &ctx.accounts.phoenix,
+        &ctx.accounts.trader_base_account.to_account_info(),
+        &ctx.accounts.trader_quote_account.to_account_info(),
+        &ctx.accounts.phoenix_base_vault.to_account_info(),
+        &ctx.accounts.phoenix_quote_vault.to_account_info(),
+        &ctx.accounts.token_program.to_account_info(),
+        state.signer_nonce
+    )?;
+
+    controller::phoenix::sweep_evicted_phoenix_trader(
+        &ctx.accounts.phoenix,
+        [
+            (ctx.accounts.trader_base_account.to_account_info(), ctx.accounts.base_account.to_account_info()),
+            (ctx.accounts.trader_quote_account.to_account_info(), ctx.accounts.quote_account.to_account_info())
+        ],
+        &ctx.accounts.token_program.to_account_info(),
+        &user_key,
+        trader_bump
+    )?;
+
+    msg!("evicted seat of trader {} in market {}", ctx.accounts.phoenix.trader.key(), market.market_index);
+
+    Ok(())
+}
+
+/// Closes the empty token accounts of the user's trader PDA once its seat has been evicted, returning their rent to the
+/// authority. The seat account itself stays with Phoenix and is not closed.
+pub fn handle_close_phoenix_trader_token_accounts(ctx: Context<ClosePhoenixTraderTokenAccounts>) -> Result<()> {
+    let state = &ctx.accounts.state;
+    let market = &load!(ctx.accounts.market)?;
+    let user_key = ctx.accounts.user.key();
+
+    controller::phoenix::validate_phoenix_seat_accounts(state, market, &user_key, &ctx.accounts.phoenix)?;
+
+    validate!(
+        !is_phoenix_trader_registered(&ctx.accounts.phoenix.phoenix_market, &ctx.accounts.phoenix.trader.key())?,
+        ErrorCode::PhoenixSeatNotEvicted,
+        "Trader {} still has a seat on phoenix market {}",
+        ctx.accounts.phoenix.trader.key(),
+        ctx.accounts.phoenix.phoenix_market.key()
+    )?;
+
+    validate!(
+        ctx.accounts.trader_base_account.amount == 0 && ctx.accounts.trader_quote_account.amount == 0,
+        ErrorCode::TokenAccountNotEmpty,
+        "Token accounts of trader {} are not empty",
+        ctx.accounts.phoenix.trader.key()
+    )?;
+
+    let (_, trader_bump) = get_phoenix_trader_address(&user_key);
+    let trader_seeds = get_phoenix_trader_seeds(&user_key, &trader_bump);
+
+    for token_account in [&ctx.accounts.trader_base_account, &ctx.accounts.trader_quote_account] {
+        token::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: token_account.to_account_info(),
+                destination: ctx.accounts.authority.to_account_info(),
+                authority: ctx.accounts.phoenix.trader.to_account_info()
+            },
+            &[&trader_seeds]
+        ))?;
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(sub_account_id: u16)]
+pub struct InitializeUser<'info> {
+    #[account(
+        init,
+        seeds = [b"user", authority.key.as_ref(), sub_account_id.to_le_bytes().as_ref()],
+        space = User::SIZE,
+        bump,
+        payer = payer
+    )]
+    pub user: AccountLoader<'info, User>,
+
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub rent: Sysvar<'info, Rent>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut, has_one = authority)]
+    pub user: AccountLoader<'info, User>,
+
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+
+    /// Token account holding the market's deposits, checked against the market's vault
+    #[account(mut)]
+    pub market_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::authority = authority, token::mint = market_vault.mint)]
+    pub user_token_account: Box<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut, has_one = authority)]
+    pub user: AccountLoader<'info, User>,
+
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+
+    /// CHECK: checked against the market's oracle when loading the price
+    pub oracle: AccountInfo<'info>,
+
+    /// CHECK: Protocol PDA and authority of the market's vault, checked against the state's signer
+    pub signer: UncheckedAccount<'info>,
+
+    /// Token account holding the market's deposits, checked against the market's vault
+    #[account(mut, token::authority = signer)]
+    pub market_vault: Box<Account<'info, TokenAccount>>,
+
+    /// Receives the withdrawn tokens
+    #[account(mut, token::mint = market_vault.mint)]
+    pub user_token_account: Box<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct PlaceOrder<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut, has_one = authority)]
+    pub user: AccountLoader<'info, User>,
+
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub market: AccountLoader<'info, Market>,
+
+    /// CHECK: checked against the market's oracle when loading the price
+    pub oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOrder<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(mut, has_one = authority)]
+    pub user: AccountLoader<'info, User>,
+
+    pub authority: Signer<'info>,
+
+    pub market: AccountLoader<'info, Market>,
+}
+
+#[derive(Accounts)]
+pub struct RequestPhoenixSeat<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(has_one = authority)]
+    pub user: AccountLoader<'info, User>,
+
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub market: AccountLoader<'info, Market>,
+
+    pub phoenix: PhoenixSeatAccounts<'info>,
+
+    pub base_mint: Box<Account<'info, Mint>>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = payer,
+        associated_token::mint = base_mint,
+        associated_token::authority = phoenix.trader
+    )]
+    pub trader_base_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = payer,
+        associated_token::mint = quote_mint,
+        associated_token::authority = phoenix.trader
+    )]
+    pub trader_quote_account: Box<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub rent: Sysvar<'info, Rent>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EvictPhoenixSeat<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(has_one = authority)]
+    pub user: AccountLoader<'info, User>,
+
+    pub authority: Signer<'info>,
+
+    pub market: AccountLoader<'info, Market>,
+
+    pub phoenix: PhoenixSeatAccounts<'info>,
+
+    #[account(mut, token::authority = phoenix.trader)]
+    pub trader_base_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::authority = phoenix.trader)]
+    pub trader_quote_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::authority = phoenix.signer, token::mint = trader_base_account.mint)]
+    pub base_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::authority = phoenix.signer, token::mint = trader_quote_account.mint)]
+    pub quote_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: Phoenix base vault, checked by the Phoenix program
+    #[account(mut)]
+    pub phoenix_base_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Phoenix quote vault, checked by the Phoenix program
+    #[account(mut)]
+    pub phoenix_quote_vault: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePhoenixTraderTokenAccounts<'info> {
+    pub state: Box<Account<'info, State>>,
+
+    #[account(has_one = authority)]
+    pub user: AccountLoader<'info, User>,
+
+    /// Receives the rent of the trader's token accounts
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub market: AccountLoader<'info, Market>,
+
+    pub phoenix: PhoenixSeatAccounts<'info>,
+
+    #[account(mut, token::authority = phoenix.trader)]
+    pub trader_base_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::authority = phoenix.trader)]
+    pub trader_quote_account: Box<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
@@ -3,6 +3,25 @@ pub mod state;
 pub mod macros;
 pub mod error;
 pub mod math;
+pub mod instructions;
+pub mod controller;
+
+use instructions::*;
+use phoenix::program::new_order::CondensedOrder;
+use state::{
+    guard_rails::MarketOracleGuardRails,
+    market::BorrowRateKink,
+    order::{
+        ModifyOrderParams,
+        OrderParams
+    },
+    enums::{
+        OracleType,
+        OracleSourcePolicy,
+        SpotFulfillmentType,
+        InterestRateModel
+    }
+};
 
 declare_id!("AKiHde3YE4KWPPPYbHSP8nbKcZ37DVQUrfgfAsFXziwv");
 
@@ -19,6 +38,174 @@ pub mod spot_margin {
     ) -> Result<()> {
         Ok(())
     }
+
+    pub fn update_market_oracle_guard_rails(
+        ctx: Context<AdminUpdateMarket>,
+        oracle_guard_rails: MarketOracleGuardRails
+    ) -> Result<()> {
+        handle_update_market_oracle_guard_rails(ctx, oracle_guard_rails)
+    }
+
+    pub fn update_market_oracle_sources(
+        ctx: Context<AdminUpdateMarket>,
+        secondary_oracle: Pubkey,
+        secondary_oracle_type: OracleType,
+        use_phoenix_mid_oracle: bool,
+        oracle_source_policy: OracleSourcePolicy,
+        oracle_quorum: u8
+    ) -> Result<()> {
+        handle_update_market_oracle_sources(
+            ctx,
+            secondary_oracle,
+            secondary_oracle_type,
+            use_phoenix_mid_oracle,
+            oracle_source_policy,
+            oracle_quorum
+        )
+    }
+
+    pub fn update_market_confidence_interval_multiplier(
+        ctx: Context<AdminUpdateMarket>,
+        confidence_interval_multiplier: u32
+    ) -> Result<()> {
+        handle_update_market_confidence_interval_multiplier(ctx, confidence_interval_multiplier)
+    }
+
+    pub fn update_market_phoenix_market(ctx: Context<AdminUpdateMarketPhoenixMarket>) -> Result<()> {
+        handle_update_market_phoenix_market(ctx)
+    }
+
+    pub fn update_market_openbook_v2_market(ctx: Context<AdminUpdateMarketOpenBookV2Market>) -> Result<()> {
+        handle_update_market_openbook_v2_market(ctx)
+    }
+
+    pub fn update_market_fulfillment_type(
+        ctx: Context<AdminUpdateMarket>,
+        fulfillment_type: SpotFulfillmentType
+    ) -> Result<()> {
+        handle_update_market_fulfillment_type(ctx, fulfillment_type)
+    }
+
+    pub fn update_market_interest_rate_model(
+        ctx: Context<AdminUpdateMarket>,
+        interest_rate_model: InterestRateModel,
+        optimal_utilization: u32,
+        optimal_borrow_rate: u32,
+        max_borrow_rate: u32,
+        min_borrow_rate: u32,
+        borrow_rate_kinks: Vec<BorrowRateKink>
+    ) -> Result<()> {
+        handle_update_market_interest_rate_model(
+            ctx,
+            interest_rate_model,
+            optimal_utilization,
+            optimal_borrow_rate,
+            max_borrow_rate,
+            min_borrow_rate,
+            borrow_rate_kinks
+        )
+    }
+
+    pub fn update_market_interest_take_rate(
+        ctx: Context<AdminUpdateMarket>,
+        interest_take_rate: u32
+    ) -> Result<()> {
+        handle_update_market_interest_take_rate(ctx, interest_take_rate)
+    }
+
+    pub fn update_market_borrow_limits(
+        ctx: Context<AdminUpdateMarket>,
+        max_borrow_limit: u64,
+        max_user_borrow_limit: u64
+    ) -> Result<()> {
+        handle_update_market_borrow_limits(ctx, max_borrow_limit, max_user_borrow_limit)
+    }
+
+    pub fn update_market_oracle_twap(ctx: Context<UpdateMarketOracleTwap>) -> Result<()> {
+        handle_update_market_oracle_twap(ctx)
+    }
+
+    pub fn update_pegged_orders<'info>(ctx: Context<'_, '_, '_, 'info, UpdatePeggedOrders<'info>>) -> Result<()> {
+        handle_update_pegged_orders(ctx)
+    }
+
+    pub fn trigger_order<'info>(ctx: Context<'_, '_, '_, 'info, TriggerOrder<'info>>, order_id: u32) -> Result<()> {
+        handle_trigger_order(ctx, order_id)
+    }
+
+    pub fn expire_orders<'info>(ctx: Context<'_, '_, '_, 'info, ExpireOrders<'info>>) -> Result<()> {
+        handle_expire_orders(ctx)
+    }
+
+    pub fn refill_iceberg_orders<'info>(ctx: Context<'_, '_, '_, 'info, RefillIcebergOrders<'info>>) -> Result<()> {
+        handle_refill_iceberg_orders(ctx)
+    }
+
+    pub fn fill_twap_orders<'info>(ctx: Context<'_, '_, '_, 'info, FillTwapOrders<'info>>) -> Result<()> {
+        handle_fill_twap_orders(ctx)
+    }
+
+    pub fn settle_openbook_v2_funds(ctx: Context<SettleOpenBookV2Funds>) -> Result<()> {
+        handle_settle_openbook_v2_funds(ctx)
+    }
+
+    pub fn initialize_user(ctx: Context<InitializeUser>, sub_account_id: u16) -> Result<()> {
+        handle_initialize_user(ctx, sub_account_id)
+    }
+
+    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+        handle_deposit(ctx, amount)
+    }
+
+    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        handle_withdraw(ctx, amount)
+    }
+
+    pub fn request_phoenix_seat(ctx: Context<RequestPhoenixSeat>) -> Result<()> {
+        handle_request_phoenix_seat(ctx)
+    }
+
+    pub fn evict_phoenix_seat(ctx: Context<EvictPhoenixSeat>) -> Result<()> {
+        handle_evict_phoenix_seat(ctx)
+    }
+
+    pub fn close_phoenix_trader_token_accounts(ctx: Context<ClosePhoenixTraderTokenAccounts>) -> Result<()> {
+        handle_close_phoenix_trader_token_accounts(ctx)
+    }
+
+    pub fn place_order<'info>(ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>, params: OrderParams) -> Result<()> {
+        handle_place_order(ctx, params)
+    }
+
+    pub fn place_multiple_orders<'info>(
+        ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>,
+        bids: Vec<CondensedOrder>,
+        asks: Vec<CondensedOrder>,
+        cancel_existing: bool
+    ) -> Result<()> {
+        handle_place_multiple_orders(ctx, bids, asks, cancel_existing)
+    }
+
+    pub fn place_bracket_orders<'info>(
+        ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>,
+        entry: OrderParams,
+        take_profit: Option<OrderParams>,
+        stop_loss: Option<OrderParams>
+    ) -> Result<()> {
+        handle_place_bracket_orders(ctx, entry, take_profit, stop_loss)
+    }
+
+    pub fn modify_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>,
+        order_id: u32,
+        params: ModifyOrderParams
+    ) -> Result<()> {
+        handle_modify_order(ctx, order_id, params)
+    }
+
+    pub fn cancel_order<'info>(ctx: Context<'_, '_, '_, 'info, CancelOrder<'info>>, order_id: u32) -> Result<()> {
+        handle_cancel_order(ctx, order_id)
+    }
 }
 
 #[derive(Accounts)]
@@ -6,6 +6,18 @@ macro_rules! load {
         $account_loader.load().map_err(|_| {
             let error_code = ErrorCode::UnableToLoadAccountLoader;
             msg!("Error {} thrown at {}:{}", error_code, file!(), line!());
+            error_code
+        })
+    }};
+}
+
+#[macro_export]
+macro_rules! load_mut {
+    ($account_loader: expr) => {{
+        $account_loader.load_mut().map_err(|_| {
+            let error_code = ErrorCode::UnableToLoadAccountLoader;
+            msg!("Error {} thrown at {}:{}", error_code, file!(), line!());
+            error_code
         })
     }};
 }
@@ -10,10 +10,13 @@ use crate::{
     math::{
         casting::Cast,
         constants::{
+            CONFIDENCE_INTERVAL_MULTIPLIER_PRECISION,
             ONE_YEAR,
+            PERCENTAGE_PRECISION,
             SPOT_RATE_PRECISION,
             SPOT_UTILIZATION_PRECISION
         },
+        interest_rate::calculate_borrow_rate,
         safe_math::{
             SafeFloorDiv,
             SafeMath
@@ -180,64 +183,8 @@ pub fn calculate_accumulated_interest(
         )
     }
 
-    // however, if utilization > 0 && utilization > optimal utilization, calculate the surplus utilization.
-    let borrow_rate = if utilization > market.optimal_utilization.cast()? {
-        let surplus = utilization.safe_sub(market.optimal_utilization.cast()?)?;
-    
-        // calculating the slope of the borrow rate curve. calculated using the formula:
-        // (max_borrow_rate-optimal_borrow_rate)*utilization_precision/(utilization_precision-optimal_utilization)
-        let borrow_rate_slope = market
-            .max_borrow_rate
-            .cast::<u128>()?
-            .safe_sub(market.optimal_borrow_rate.cast()?)?
-            .safe_mul(SPOT_UTILIZATION_PRECISION)?
-            .safe_div(
-                SPOT_UTILIZATION_PRECISION
-                    .safe_sub(market.optimal_utilization.cast()?)?,
-            )?;
-        
-        // after calculating the slope, we calculate the borrow rate(in case of surplus utilization) using the formula:
-        // (optimal_borrow_rate + (surplus*slope/utilization_precision)). We divide by utilization_precision to
-        // cancel out the multiplication of utilization_precision in the borrow_rate_slope calculation. So, the end result would be
-        // (optimal_borrow_rate + (surplus_utilization*slope))
-        market.optimal_borrow_rate.cast::<u128>()?.safe_add(
-            surplus
-                .safe_mul(borrow_rate_slope)?
-                .safe_div(SPOT_UTILIZATION_PRECISION)?
-        )?
-    } else {
-        // else, if the utilization < optimal_utilization, we tweak the formula to
-        // (optimal_borrow_rate*utilizarion_precision/optimal_utilization).
-        let borrow_rate_slope = market
-             .optimal_borrow_rate
-            .cast::<u128>()?
-            .safe_mul(SPOT_UTILIZATION_PRECISION)?
-            .safe_div(market.optimal_utilization.cast()?)?;
-    
-        // after calculating the slope, we calculate the utilization(in case of deficit utilization) using the formula:
-        // (utilization*slope/utilization_precision). We divide by utilization_precision to
-        // cancel out the multiplication of utilization_precision in the borrow_rate_slope calculation. So, the end result would be
-        // (utilization*slope)
-
-        utilization
-            .safe_mul(borrow_rate_slope)?
-            .safe_div(SPOT_UTILIZATION_PRECISION)?
-
-        // Some notes with regards to borrow rates, utilization and borrow rate slopes:
-        // -> Interest rates in general are calculated based on utilization. so, higher utilization -> higher rates
-        // -> the typical formula of a borrow rate curve is optimal_borrow_rate/optimal_utilization. 
-        // -> Meaning that it is calculated from the graph of borrow rate and utilization. 
-        // -> In order to prevent calculation of borrow rate slope using highly skewed values, we only take into account
-        //    optimal borrow rates and optimal utilization.
-
-        // -> However, in the scenarios of utilization > optimal utilization, the formula is tweaked and the numerator
-        // -> is now represented by the difference of the maximum attainable borrow rate for a market and its optimal borrow rate.
-        // -> We use the maximum attainable borrow rate, accounting for the fact that the curr_utilization > optimal_utilization
-        //    and thus the curr borrow rate would naturally be higher than the optimal borrow rate.
-        // -> After obtaining the difference between the max borrow rate and optimal borrrow rate, we divide it by the difference
-        // -> between the utilization precision and optimal utilization. This is to account for the fact the current utilization is 
-        // -> higher than the optimal utilization and we are using a smaller value(as it is a difference) in the numerator
-    };
+    // borrow rate of the market's interest rate model at the current utilization, floored at its min borrow rate
+    let borrow_rate = calculate_borrow_rate(market, utilization)?;
 
     // calculating the time since the interest was last updated. formula: now_timestamp - last_interest_timestamp
     let time_since_last_update_of_interest = now_ts
@@ -253,10 +200,13 @@ pub fn calculate_accumulated_interest(
     // (new_borrow_rate*utilization)/utilization_precision. We divide by utilization_precision to cancel out the multiplication
     // of utilization_precision in the borrow_rate_slope calculation. in case of deficit calculation, the new_deposit_ratio would
     // evaluate to utilization^2*slope. In case of surplus calculation, the new_deposit_ratio would evaluate to
-    // optimal_borrow_rate + utilization*surplus*slope
+    // optimal_borrow_rate + utilization*surplus*slope. The protocol's take rate is then deducted, the borrow interest it
+    // leaves out of the deposit interest is credited to the revenue pool in `update_market_cumulative_interest`
     let new_deposit_rate = new_borrow_rate
         .safe_mul(utilization)?
-        .safe_div(SPOT_UTILIZATION_PRECISION)?;
+        .safe_div(SPOT_UTILIZATION_PRECISION)?
+        .safe_mul(PERCENTAGE_PRECISION.safe_sub(market.interest_take_rate.cast()?)?)?
+        .safe_div(PERCENTAGE_PRECISION)?;
 
     // calculating cumulative borrow interest using the formula:
     // -> (cumulative_borrow_interest*new_borrow_rate/ONE_YEAR*rate_precision) + 1. We divide by ONE_YEAR to cancel out the multiplication
@@ -293,6 +243,20 @@ pub fn get_strict_value(
     decimals: u32,
     oracle_price_data: &OraclePriceData,
     oracle_price_twap: i64
+) -> SpedXSpotResult<i128> {
+    get_confidence_adjusted_strict_value(amount, decimals, oracle_price_data, oracle_price_twap, 0)
+}
+
+/// Function to get the strict value of a given amount of tokens, additionally widened by the oracle confidence interval.
+/// Assets(amount > 0) are priced at min(price, twap) - k*conf and liabilities(amount < 0) at max(price, twap) + k*conf,
+/// where k is the `confidence_interval_multiplier`. Asset prices are floored at 0.
+/// A multiplier of 0 returns the same value as `get_strict_value`.
+pub fn get_confidence_adjusted_strict_value(
+    amount: i128,
+    decimals: u32,
+    oracle_price_data: &OraclePriceData,
+    oracle_price_twap: i64,
+    confidence_interval_multiplier: u32
 ) -> SpedXSpotResult<i128> {
     if amount == 0 {
         return Ok(0)
@@ -308,18 +272,26 @@ pub fn get_strict_value(
         oracle_price_twap
     )?;
 
+    // k*conf, in PRICE_PRECISION
+    let confidence_adjustment = oracle_price_data
+        .confidence
+        .cast::<u128>()?
+        .safe_mul(confidence_interval_multiplier.cast()?)?
+        .safe_div(CONFIDENCE_INTERVAL_MULTIPLIER_PRECISION.cast()?)?
+        .cast::<i64>()?;
+
+    // assets take the lower of the oracle price and twap, liabilities the higher
     let price = if amount > 0 {
-        if oracle_price_data.price < oracle_price_twap {
-            oracle_price_data.price
-        } else {
-            oracle_price_twap
-        }
+        oracle_price_data
+            .price
+            .min(oracle_price_twap)
+            .safe_sub(confidence_adjustment)?
+            .max(0)
     } else {
-        if oracle_price_data.price > oracle_price_twap {
-            oracle_price_data.price
-        } else {
-            oracle_price_twap
-        }
+        oracle_price_data
+            .price
+            .max(oracle_price_twap)
+            .safe_add(confidence_adjustment)?
     };
 
     let token_value = amount.safe_mul(price.cast()?)?;
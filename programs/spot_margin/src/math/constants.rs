@@ -69,6 +69,9 @@ pub const SPOT_UTILIZATION_PRECISION_U32: u32 = PERCENTAGE_PRECISION as u32; //
 pub const SPOT_RATE_PRECISION: u128 = PERCENTAGE_PRECISION;
 pub const SPOT_RATE_PRECISION_U32: u32 = PERCENTAGE_PRECISION as u32;
 
+// Maximum number of kinks of a multi-kink interest rate curve
+pub const MAX_BORROW_RATE_KINKS: usize = 4;
+
 // Refers to the precision of fees incurred during liquidation of an asset.
 pub const LIQUIDATION_FEE_PRECISION_U32: u32 = PERCENTAGE_PRECISION as u32;
 pub const LIQUIDATION_FEE_PRECISION_U128: u128 = LIQUIDATION_FEE_PRECISION_U32 as u128;
@@ -136,6 +139,12 @@ pub const MAX_MARK_TWAP_DIVERGENCE: u128 = 500_000; // exponent = -3
 pub const MAX_MARGIN_RATIO: u32 = MARGIN_PRECISION as u32; // 1x or no leverage
 pub const MIN_MARGIN_RATIO: u32 = MARGIN_PRECISION as u32 /50; // max leverage, 50x leverage
 
+// Precision of the per-market multiplier applied to the oracle confidence interval when valuing collateral for initial margin.
+// A multiplier of 2 * CONFIDENCE_INTERVAL_MULTIPLIER_PRECISION values assets at price - 2*conf and liabilities at price + 2*conf
+pub const CONFIDENCE_INTERVAL_MULTIPLIER_PRECISION: u32 = 100; // exponent = -2
+pub const CONFIDENCE_INTERVAL_MULTIPLIER_PRECISION_U64: u64 = CONFIDENCE_INTERVAL_MULTIPLIER_PRECISION as u64;
+pub const MAX_CONFIDENCE_INTERVAL_MULTIPLIER: u32 = 10 * CONFIDENCE_INTERVAL_MULTIPLIER_PRECISION; // 10x
+
 // Refers to the minimum margin requirement for opening a position
 pub const OPEN_ORDER_MARGIN_REQUIREMENT: u128 = QUOTE_PRECISION/100;
 
@@ -152,6 +161,15 @@ pub const DEFAULT_MAX_TWAP_UPDATE_PRICE_BAND_DENOMINATOR: i64 = 3;
 // Refers to the maximum bid-ask spread
 pub const DEFAULT_LARGE_BID_ASK_FACTOR: u64 = 10 * BID_ASK_SPREAD_PRECISION;
 
+// Refers to the maximum distance of an oracle pegged order's limit price from the oracle price, which is 10%
+pub const MAX_ORACLE_PEG_DISTANCE_PCT: u64 = PERCENTAGE_PRECISION_U64 / 10;
+
+// Refers to the slippage from the oracle price allowed for triggered market orders placed without a price, which is 5%
+pub const DEFAULT_TRIGGER_MARKET_ORDER_SLIPPAGE_PCT: u64 = PERCENTAGE_PRECISION_U64 / 20;
+
+// Refers to the fee paid by the user to the keeper that triggers one of the user's trigger orders, which is $0.01
+pub const TRIGGER_ORDER_KEEPER_FEE: u64 = QUOTE_PRECISION_U64 / 100;
+
 // For the calculation of the minimum amount of balance required to be held in our account to keep the position open,
 // we use a maintenance margin + a liquidation buffer ratio formula. This constant is for the calculation of the 
 // liquidation buffer ratio.
@@ -167,6 +185,15 @@ pub const DEFAULT_QUOTE_ASSET_AMOUNT_TICK_SIZE: u64 =
 // Withdraws
 pub const SPOT_MARKET_TOKEN_TWAP_WINDOW: i64 = TWENTY_FOUR_HOUR;
 
+// Withdrawals may not push a market's deposits below 75% of their 24h TWAP
+pub const SPOT_MARKET_MIN_DEPOSIT_TWAP_PCT: u128 = PERCENTAGE_PRECISION * 3 / 4;
+
+// Borrows may not push a market's borrows above 120% of their 24h TWAP
+pub const SPOT_MARKET_MAX_BORROW_TWAP_PCT: u128 = PERCENTAGE_PRECISION * 6 / 5;
+
+// Withdrawals and borrows may not push a market's utilization above 90%
+pub const SPOT_MARKET_MAX_UTILIZATION: u128 = SPOT_UTILIZATION_PRECISION * 9 / 10;
+
 // Oracle staleness
 pub const STALENESS_THRESHOLD: u64 = 60;
 
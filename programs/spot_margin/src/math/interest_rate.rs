@@ -0,0 +1,394 @@
+//! Interest rate models of a market, mapping its utilization to a borrow rate
+
+use solana_program::msg;
+
+use crate::{
+    error::{
+        SpedXSpotResult,
+        ErrorCode
+    },
+    math::{
+        casting::Cast,
+        constants::SPOT_UTILIZATION_PRECISION,
+        safe_math::SafeMath
+    },
+    state::{
+        enums::InterestRateModel,
+        market::{
+            BorrowRateKink,
+            Market
+        }
+    },
+    validate
+};
+
+/// Borrow rate of the market at the given utilization, following the market's interest rate model and floored at its
+/// min borrow rate.
+/// precision: SPOT_RATE_PRECISION
+pub fn calculate_borrow_rate(market: &Market, utilization: u128) -> SpedXSpotResult<u128> {
+    let borrow_rate = match market.interest_rate_model {
+        InterestRateModel::SingleKink => calculate_single_kink_borrow_rate(market, utilization)?,
+        InterestRateModel::MultiKink => calculate_multi_kink_borrow_rate(market, utilization)?
+    };
+
+    Ok(borrow_rate.max(market.min_borrow_rate.cast()?))
+}
+
+/// Borrow rate of the single-kink curve through `optimal_utilization` at `optimal_borrow_rate` and 100% utilization at
+/// `max_borrow_rate`.
+/// precision: SPOT_RATE_PRECISION
+pub fn calculate_single_kink_borrow_rate(market: &Market, utilization: u128) -> SpedXSpotResult<u128> {
+    // if utilization > optimal utilization, calculate the surplus utilization.
+    let borrow_rate = if utilization > market.optimal_utilization.cast()? {
+        let surplus = utilization.safe_sub(market.optimal_utilization.cast()?)?;
+    
+        // calculating the slope of the borrow rate curve. calculated using the formula:
+        // (max_borrow_rate-optimal_borrow_rate)*utilization_precision/(utilization_precision-optimal_utilization)
+        let borrow_rate_slope = market
+            .max_borrow_rate
+            .cast::<u128>()?
+            .safe_sub(market.optimal_borrow_rate.cast()?)?
+            .safe_mul(SPOT_UTILIZATION_PRECISION)?
+            .safe_div(
+                SPOT_UTILIZATION_PRECISION
+                    .safe_sub(market.optimal_utilization.cast()?)?,
+            )?;
+        
+        // after calculating the slope, we calculate the borrow rate(in case of surplus utilization) using the formula:
+        // (optimal_borrow_rate + (surplus*slope/utilization_precision)). We divide by utilization_precision to
+        // cancel out the multiplication of utilization_precision in the borrow_rate_slope calculation. So, the end result would be
+        // (optimal_borrow_rate + (surplus_utilization*slope))
+        market.optimal_borrow_rate.cast::<u128>()?.safe_add(
+            surplus
+                .safe_mul(borrow_rate_slope)?
+                .safe_div(SPOT_UTILIZATION_PRECISION)?
+        )?
+    } else {
+        // else, if the utilization < optimal_utilization, we tweak the formula to
+        // (optimal_borrow_rate*utilizarion_precision/optimal_utilization).
+        let borrow_rate_slope = market
+             .optimal_borrow_rate
+            .cast::<u128>()?
+            .safe_mul(SPOT_UTILIZATION_PRECISION)?
+            .safe_div(market.optimal_utilization.cast()?)?;
+    
+        // after calculating the slope, we calculate the utilization(in case of deficit utilization) using the formula:
+        // (utilization*slope/utilization_precision). We divide by utilization_precision to
+        // cancel out the multiplication of utilization_precision in the borrow_rate_slope calculation. So, the end result would be
+        // (utilization*slope)
+
+        utilization
+            .safe_mul(borrow_rate_slope)?
+            .safe_div(SPOT_UTILIZATION_PRECISION)?
+
+        // Some notes with regards to borrow rates, utilization and borrow rate slopes:
+        // -> Interest rates in general are calculated based on utilization. so, higher utilization -> higher rates
+        // -> the typical formula of a borrow rate curve is optimal_borrow_rate/optimal_utilization. 
+        // -> Meaning that it is calculated from the graph of borrow rate and utilization. 
+        // -> In order to prevent calculation of borrow rate slope using highly skewed values, we only take into account
+        //    optimal borrow rates and optimal utilization.
+
+        // -> However, in the scenarios of utilization > optimal utilization, the formula is tweaked and the numerator
+        // -> is now represented by the difference of the maximum attainable borrow rate for a market and its optimal borrow rate.
+        // -> We use the maximum attainable borrow rate, accounting for the fact that the curr_utilization > optimal_utilization
+        //    and thus the curr borrow rate would naturally be higher than the optimal borrow rate.
+        // -> After obtaining the difference between the max borrow rate and optimal borrrow rate, we divide it by the difference
+        // -> between the utilization precision and optimal utilization. This is to account for the fact the current utilization is 
+        // -> higher than the optimal utilization and we are using a smaller value(as it is a difference) in the numerator
+    };
+
+    Ok(borrow_rate)
+}
+
+/// Borrow rate of the multi-kink curve, interpolated linearly between 0% at 0% utilization, the market's used
+/// `borrow_rate_kinks` and `max_borrow_rate` at 100% utilization. Utilization above 100% is charged the max borrow rate.
+/// precision: SPOT_RATE_PRECISION
+pub fn calculate_multi_kink_borrow_rate(market: &Market, utilization: u128) -> SpedXSpotResult<u128> {
+    let utilization = utilization.min(SPOT_UTILIZATION_PRECISION);
+
+    let last_kink = BorrowRateKink {
+        utilization: SPOT_UTILIZATION_PRECISION.cast()?,
+        borrow_rate: market.max_borrow_rate
+    };
+
+    let mut previous_kink = BorrowRateKink::default();
+
+    for kink in market
+        .borrow_rate_kinks
+        .iter()
+        .filter(|kink| kink.is_used())
+        .chain(std::iter::once(&last_kink))
+    {
+        let kink_utilization: u128 = kink.utilization.cast()?;
+
+        if utilization <= kink_utilization {
+            let previous_utilization: u128 = previous_kink.utilization.cast()?;
+            let previous_borrow_rate: u128 = previous_kink.borrow_rate.cast()?;
+
+            // previous_borrow_rate + (utilization - previous_utilization) * slope, with the slope's division done last
+            return previous_borrow_rate.safe_add(
+                utilization
+                    .safe_sub(previous_utilization)?
+                    .safe_mul(kink.borrow_rate.cast::<u128>()?.safe_sub(previous_borrow_rate)?)?
+                    .safe_div(kink_utilization.safe_sub(previous_utilization)?)?
+            );
+        }
+
+        previous_kink = *kink;
+    }
+
+    market.max_borrow_rate.cast()
+}
+
+/// Sanity checks the market's interest rate model before it is used to accrue interest
+pub fn validate_interest_rate_model(market: &Market) -> SpedXSpotResult {
+    validate!(
+        market.optimal_borrow_rate <= market.max_borrow_rate,
+        ErrorCode::InvalidInterestRateModel,
+        "optimal borrow rate {} exceeds max borrow rate {}",
+        market.optimal_borrow_rate,
+        market.max_borrow_rate
+    )?;
+
+    validate!(
+        market.min_borrow_rate <= market.max_borrow_rate,
+        ErrorCode::InvalidInterestRateModel,
+        "min borrow rate {} exceeds max borrow rate {}",
+        market.min_borrow_rate,
+        market.max_borrow_rate
+    )?;
+
+    match market.interest_rate_model {
+        InterestRateModel::SingleKink => {
+            // the curve divides by both the optimal utilization and what is left of it up to 100%
+            validate!(
+                market.optimal_utilization > 0
+                    && market.optimal_utilization.cast::<u128>()? < SPOT_UTILIZATION_PRECISION,
+                ErrorCode::InvalidInterestRateModel,
+                "optimal utilization {} must be between 0 and {} exclusive",
+                market.optimal_utilization,
+                SPOT_UTILIZATION_PRECISION
+            )?;
+        }
+        InterestRateModel::MultiKink => {
+            let number_of_kinks = market
+                .borrow_rate_kinks
+                .iter()
+                .take_while(|kink| kink.is_used())
+                .count();
+
+            validate!(
+                number_of_kinks > 0,
+                ErrorCode::InvalidInterestRateModel,
+                "multi-kink curve has no kinks"
+            )?;
+
+            validate!(
+                market.borrow_rate_kinks[number_of_kinks..]
+                    .iter()
+                    .all(|kink| *kink == BorrowRateKink::default()),
+                ErrorCode::InvalidInterestRateModel,
+                "unused kinks must be zeroed and come after the used kinks"
+            )?;
+
+            let mut previous_kink = BorrowRateKink::default();
+
+            for kink in market.borrow_rate_kinks[..number_of_kinks].iter() {
+                validate!(
+                    kink.utilization > previous_kink.utilization
+                        && kink.utilization.cast::<u128>()? < SPOT_UTILIZATION_PRECISION,
+                    ErrorCode::InvalidInterestRateModel,
+                    "kink utilization {} must be above {} and below {}",
+                    kink.utilization,
+                    previous_kink.utilization,
+                    SPOT_UTILIZATION_PRECISION
+                )?;
+
+                // the borrow rate never decreases as utilization rises
+                validate!(
+                    kink.borrow_rate >= previous_kink.borrow_rate && kink.borrow_rate <= market.max_borrow_rate,
+                    ErrorCode::InvalidInterestRateModel,
+                    "kink borrow rate {} must be between {} and max borrow rate {}",
+                    kink.borrow_rate,
+                    previous_kink.borrow_rate,
+                    market.max_borrow_rate
+                )?;
+
+                previous_kink = *kink;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::ErrorCode,
+        math::interest_rate::{
+            calculate_borrow_rate,
+            validate_interest_rate_model
+        },
+        state::{
+            enums::InterestRateModel,
+            market::{
+                BorrowRateKink,
+                Market
+            }
+        }
+    };
+
+    fn kink(utilization: u32, borrow_rate: u32) -> BorrowRateKink {
+        BorrowRateKink {
+            utilization,
+            borrow_rate
+        }
+    }
+
+    /// Volatile market: 10% at 80% utilization, up to 100% at full utilization
+    fn single_kink_market() -> Market {
+        Market {
+            interest_rate_model: InterestRateModel::SingleKink,
+            optimal_utilization: 800_000,
+            optimal_borrow_rate: 100_000,
+            max_borrow_rate: 1_000_000,
+            ..Market::default()
+        }
+    }
+
+    /// Stablecoin market: flat up to 80% utilization, steep past 95%, up to 300% at full utilization
+    fn multi_kink_market() -> Market {
+        Market {
+            interest_rate_model: InterestRateModel::MultiKink,
+            max_borrow_rate: 3_000_000,
+            borrow_rate_kinks: [
+                kink(500_000, 40_000),
+                kink(800_000, 100_000),
+                kink(950_000, 500_000),
+                BorrowRateKink::default()
+            ],
+            ..Market::default()
+        }
+    }
+
+    #[test]
+    fn single_kink_borrow_rate() {
+        let market = single_kink_market();
+
+        assert_eq!(calculate_borrow_rate(&market, 400_000).unwrap(), 50_000);
+        assert_eq!(calculate_borrow_rate(&market, 800_000).unwrap(), 100_000);
+        assert_eq!(calculate_borrow_rate(&market, 900_000).unwrap(), 550_000);
+        assert_eq!(calculate_borrow_rate(&market, 1_000_000).unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn multi_kink_borrow_rate() {
+        let market = multi_kink_market();
+
+        assert_eq!(calculate_borrow_rate(&market, 0).unwrap(), 0);
+        assert_eq!(calculate_borrow_rate(&market, 250_000).unwrap(), 20_000);
+        assert_eq!(calculate_borrow_rate(&market, 500_000).unwrap(), 40_000);
+        assert_eq!(calculate_borrow_rate(&market, 650_000).unwrap(), 70_000);
+        assert_eq!(calculate_borrow_rate(&market, 800_000).unwrap(), 100_000);
+        assert_eq!(calculate_borrow_rate(&market, 900_000).unwrap(), 366_666);
+        assert_eq!(calculate_borrow_rate(&market, 975_000).unwrap(), 1_750_000);
+        assert_eq!(calculate_borrow_rate(&market, 1_000_000).unwrap(), 3_000_000);
+
+        // borrows above deposits are charged the max borrow rate
+        assert_eq!(calculate_borrow_rate(&market, 1_200_000).unwrap(), 3_000_000);
+    }
+
+    #[test]
+    fn min_borrow_rate() {
+        let market = Market {
+            min_borrow_rate: 30_000,
+            ..multi_kink_market()
+        };
+
+        assert_eq!(calculate_borrow_rate(&market, 0).unwrap(), 30_000);
+        assert_eq!(calculate_borrow_rate(&market, 250_000).unwrap(), 30_000);
+        assert_eq!(calculate_borrow_rate(&market, 650_000).unwrap(), 70_000);
+
+        let market = Market {
+            min_borrow_rate: 60_000,
+            ..single_kink_market()
+        };
+
+        assert_eq!(calculate_borrow_rate(&market, 400_000).unwrap(), 60_000);
+        assert_eq!(calculate_borrow_rate(&market, 900_000).unwrap(), 550_000);
+    }
+
+    #[test]
+    fn interest_rate_model_validation() {
+        assert_eq!(validate_interest_rate_model(&single_kink_market()), Ok(()));
+        assert_eq!(validate_interest_rate_model(&multi_kink_market()), Ok(()));
+
+        let invalid_markets = [
+            Market {
+                optimal_utilization: 0,
+                ..single_kink_market()
+            },
+            Market {
+                optimal_utilization: 1_000_000,
+                ..single_kink_market()
+            },
+            Market {
+                optimal_borrow_rate: 2_000_000,
+                ..single_kink_market()
+            },
+            Market {
+                min_borrow_rate: 4_000_000,
+                ..multi_kink_market()
+            },
+            Market {
+                borrow_rate_kinks: [BorrowRateKink::default(); 4],
+                ..multi_kink_market()
+            },
+            // an unused kink in between used kinks
+            Market {
+                borrow_rate_kinks: [
+                    kink(500_000, 40_000),
+                    BorrowRateKink::default(),
+                    kink(950_000, 500_000),
+                    BorrowRateKink::default()
+                ],
+                ..multi_kink_market()
+            },
+            // decreasing utilization
+            Market {
+                borrow_rate_kinks: [
+                    kink(800_000, 40_000),
+                    kink(500_000, 100_000),
+                    BorrowRateKink::default(),
+                    BorrowRateKink::default()
+                ],
+                ..multi_kink_market()
+            },
+            // decreasing borrow rate
+            Market {
+                borrow_rate_kinks: [
+                    kink(500_000, 100_000),
+                    kink(800_000, 40_000),
+                    BorrowRateKink::default(),
+                    BorrowRateKink::default()
+                ],
+                ..multi_kink_market()
+            },
+            // a kink at 100% utilization
+            Market {
+                borrow_rate_kinks: [
+                    kink(500_000, 40_000),
+                    kink(1_000_000, 100_000),
+                    BorrowRateKink::default(),
+                    BorrowRateKink::default()
+                ],
+                ..multi_kink_market()
+            }
+        ];
+
+        for market in invalid_markets.iter() {
+            assert_eq!(validate_interest_rate_model(market), Err(ErrorCode::InvalidInterestRateModel));
+        }
+    }
+}
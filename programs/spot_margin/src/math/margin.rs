@@ -0,0 +1,232 @@
+//! Margin calculations for isolated positions. Every position is margined against its own market only.
+
+use crate::{
+    error::SpedXSpotResult,
+    math::{
+        casting::Cast,
+        constants::SPOT_WEIGHT_PRECISION_U128,
+        safe_math::SafeMath,
+        balance::{
+            get_confidence_adjusted_strict_value,
+            get_token_value
+        }
+    },
+    state::{
+        enums::MarginRequirementType,
+        market::Market,
+        oracle::OraclePriceData,
+        user_position::Position
+    }
+};
+
+/// Result of a margin calculation for a single isolated position
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PositionMarginCalculation {
+    /// Weighted value of the position's assets
+    /// precision: QUOTE_PRECISION
+    pub total_collateral: i128,
+
+    /// Weighted value of the position's liabilities, plus the fixed requirement for open orders
+    /// precision: QUOTE_PRECISION
+    pub margin_requirement: u128,
+}
+
+impl PositionMarginCalculation {
+    /// Returns true if the collateral covers the margin requirement
+    pub fn meets_margin_requirement(&self) -> SpedXSpotResult<bool> {
+        Ok(self.total_collateral >= self.margin_requirement.cast()?)
+    }
+
+    /// Collateral left after covering the margin requirement, 0 if the requirement is not met
+    /// precision: QUOTE_PRECISION
+    pub fn get_free_collateral(&self) -> SpedXSpotResult<u128> {
+        Ok(self
+            .total_collateral
+            .safe_sub(self.margin_requirement.cast()?)?
+            .max(0)
+            .unsigned_abs())
+    }
+}
+
+/// Values an amount of the market's token for a margin calculation.
+/// Initial margin uses the conservative valuation: the worse of the oracle price and the 5min twap, further widened by
+/// the market's confidence interval multiplier. Maintenance margin uses the oracle(mid) price, so that a wide confidence
+/// interval on its own never pushes an account into liquidation.
+/// precision: QUOTE_PRECISION
+pub fn get_margin_value(
+    amount: i128,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    margin_requirement_type: MarginRequirementType
+) -> SpedXSpotResult<i128> {
+    match margin_requirement_type {
+        MarginRequirementType::Initial => get_confidence_adjusted_strict_value(
+            amount,
+            market.decimals,
+            oracle_price_data,
+            market.historical_oracle_data.last_oracle_twap_5min,
+            market.confidence_interval_multiplier
+        ),
+        MarginRequirementType::Maintenance => get_token_value(
+            amount,
+            market.decimals,
+            oracle_price_data.price
+        )
+    }
+}
+
+/// Calculates the collateral and margin requirement of an isolated position. The token balance, including the base asset
+/// amount acquired through fills, is evaluated in its worst case, i.e after all of the position's open bids or all of its
+/// open asks are filled. The quote asset amount of fills is valued 1:1.
+pub fn calculate_position_margin(
+    position: &Position,
+    market: &Market,
+    oracle_price_data: &OraclePriceData,
+    margin_requirement_type: MarginRequirementType
+) -> SpedXSpotResult<PositionMarginCalculation> {
+    let token_amount = position.get_base_asset_exposure(market)?.cast::<i128>()?;
+
+    let [worst_case_token_amount, worst_case_orders_value] = position.get_token_amount_unstrict(
+        market,
+        oracle_price_data,
+        Some(market.historical_oracle_data.last_oracle_twap_5min),
+        Some(token_amount)
+    )?;
+
+    let worst_case_orders_value = worst_case_orders_value.safe_add(position.quote_asset_amount.cast()?)?;
+
+    let mut calculation = PositionMarginCalculation {
+        total_collateral: 0,
+        margin_requirement: position.margin_requirement()?,
+    };
+
+    let token_value = get_margin_value(
+        worst_case_token_amount,
+        market,
+        oracle_price_data,
+        margin_requirement_type
+    )?;
+
+    if token_value > 0 {
+        let weighted_token_value = token_value
+            .safe_mul(market.get_asset_weight(margin_requirement_type).cast()?)?
+            .safe_div(SPOT_WEIGHT_PRECISION_U128.cast()?)?;
+
+        calculation.total_collateral = calculation.total_collateral.safe_add(weighted_token_value)?;
+    } else {
+        let weighted_token_value = token_value
+            .unsigned_abs()
+            .safe_mul(market.get_liability_weight(margin_requirement_type).cast()?)?
+            .safe_div(SPOT_WEIGHT_PRECISION_U128)?;
+
+        calculation.margin_requirement = calculation.margin_requirement.safe_add(weighted_token_value)?;
+    }
+
+    // the quote leg of fills and open orders is valued 1:1
+    if worst_case_orders_value > 0 {
+        calculation.total_collateral = calculation.total_collateral.safe_add(worst_case_orders_value)?;
+    } else {
+        calculation.margin_requirement = calculation
+            .margin_requirement
+            .safe_add(worst_case_orders_value.unsigned_abs())?;
+    }
+
+    Ok(calculation)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        math::{
+            constants::{
+                CONFIDENCE_INTERVAL_MULTIPLIER_PRECISION,
+                PRICE_PRECISION_I64,
+                PRICE_PRECISION_U64,
+                QUOTE_PRECISION_I128,
+                QUOTE_PRECISION,
+                SPOT_BALANCE_PRECISION_U64,
+                SPOT_CUMULATIVE_INTEREST_PRECISION,
+                SPOT_WEIGHT_PRECISION
+            },
+            margin::calculate_position_margin
+        },
+        state::{
+            enums::{
+                MarginRequirementType,
+                SpotBalanceType
+            },
+            market::Market,
+            oracle::{
+                HistoricalPriceData,
+                OraclePriceData
+            },
+            user_position::Position
+        }
+    };
+
+    fn market(confidence_interval_multiplier: u32) -> Market {
+        Market {
+            decimals: 9,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION * 8 / 10,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION * 9 / 10,
+            initial_liability_weight: SPOT_WEIGHT_PRECISION * 12 / 10,
+            maintenance_liability_weight: SPOT_WEIGHT_PRECISION * 11 / 10,
+            historical_oracle_data: HistoricalPriceData::default_price(100 * PRICE_PRECISION_I64),
+            confidence_interval_multiplier,
+            ..Market::default()
+        }
+    }
+
+    /// $100 price with a 2% confidence interval
+    fn oracle_price_data() -> OraclePriceData {
+        OraclePriceData {
+            price: 100 * PRICE_PRECISION_I64,
+            confidence: 2 * PRICE_PRECISION_U64,
+            delay: 1,
+            has_sufficient_data_points: true,
+        }
+    }
+
+    #[test]
+    fn confidence_interval_only_affects_initial_margin() {
+        // 1 token deposited
+        let deposit = Position {
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            bal_type: SpotBalanceType::Deposits,
+            ..Position::default()
+        };
+
+        // without the confidence adjustment: $100 * 80%
+        let calculation = calculate_position_margin(&deposit, &market(0), &oracle_price_data(), MarginRequirementType::Initial).unwrap();
+        assert_eq!(calculation.total_collateral, 80 * QUOTE_PRECISION_I128);
+        assert_eq!(calculation.margin_requirement, 0);
+
+        // with k = 1: ($100 - $2) * 80%
+        let market = market(CONFIDENCE_INTERVAL_MULTIPLIER_PRECISION);
+        let calculation = calculate_position_margin(&deposit, &market, &oracle_price_data(), MarginRequirementType::Initial).unwrap();
+        assert_eq!(calculation.total_collateral, 784 * QUOTE_PRECISION_I128 / 10);
+
+        // maintenance uses the mid price: $100 * 90%
+        let calculation = calculate_position_margin(&deposit, &market, &oracle_price_data(), MarginRequirementType::Maintenance).unwrap();
+        assert_eq!(calculation.total_collateral, 90 * QUOTE_PRECISION_I128);
+
+        // 1 token borrowed
+        let borrow = Position {
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            bal_type: SpotBalanceType::Borrows,
+            ..Position::default()
+        };
+
+        // with k = 1: ($100 + $2) * 120%
+        let calculation = calculate_position_margin(&borrow, &market, &oracle_price_data(), MarginRequirementType::Initial).unwrap();
+        assert_eq!(calculation.total_collateral, 0);
+        assert_eq!(calculation.margin_requirement, 1224 * QUOTE_PRECISION / 10);
+        assert!(!calculation.meets_margin_requirement().unwrap());
+
+        // maintenance: $100 * 110%
+        let calculation = calculate_position_margin(&borrow, &market, &oracle_price_data(), MarginRequirementType::Maintenance).unwrap();
+        assert_eq!(calculation.margin_requirement, 110 * QUOTE_PRECISION);
+    }
+}
@@ -9,4 +9,7 @@ pub mod safe_unwrap;
 pub mod oracle_validity;
 pub mod twap;
 pub mod rolling_sum;
-pub mod price;
\ No newline at end of file
+pub mod price;
+pub mod margin;
+pub mod spot_withdraw;
+pub mod interest_rate;
\ No newline at end of file
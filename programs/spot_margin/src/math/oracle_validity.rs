@@ -43,6 +43,7 @@ use crate::{
 
 /// Function to check if the oracle data is valid for a specific action in the protocol
 /// Such as filling an order, adding an order, etc.
+/// `oracle_validity` only classifies the oracle, it is this per-action policy that decides what gets blocked.
 pub fn is_oracle_valid_for_action(
     action: Option<Actions>,
     validity: OracleValidity,
@@ -71,11 +72,13 @@ pub fn is_oracle_valid_for_action(
                 validity,
                 OracleValidity::Invalid | OracleValidity::Volatile | OracleValidity::Uncertain | OracleValidity::InsufficientDataPoints
             ),
-            // by optimizing for the most valid price for liquidations, we ensure that prices are always up-to-date
-            // and valid
+            // liquidations must be able to proceed during sharp moves, which is exactly when accounts become
+            // undercollateralized. Hence a volatile oracle is accepted as long as it is fresh, while stale
+            // or uncertain prices are still blocked
             Actions::Liquidate => !matches!(
                 validity,
-                OracleValidity::Invalid | OracleValidity::Volatile | OracleValidity::Uncertain | OracleValidity::InsufficientDataPoints
+                OracleValidity::Invalid | OracleValidity::Uncertain | OracleValidity::StaleForMargin
+                | OracleValidity::InsufficientDataPoints
             ),
         },
         None => {
@@ -90,7 +93,11 @@ pub fn is_oracle_valid_for_action(
 }
 
 /// Function to check the oracle validity. Returns the OracleValidity enum, with different outcomes ranging from descending
-/// order of severity of invalidity. 
+/// order of severity of invalidity. The function never errors on an unhealthy oracle, it only classifies it. 
+/// Errors are reserved for math failures.
+/// Note that `OracleValidity::Volatile` is only returned for prices that pass every other check. A stale, uncertain or
+/// thinly sourced price that also looks volatile is classified by that problem instead, as `Actions::Liquidate` accepts
+/// volatile prices and must not accept those.
 pub fn oracle_validity(
     last_oracle_twap: i64,
     oracle_price_data: &OraclePriceData,
@@ -104,11 +111,12 @@ pub fn oracle_validity(
         // ema: _oracle_ema
     } = *oracle_price_data;
 
-    // Checking if the oracle price can ever be -ve, if yes, the token just got rekt
+    // Checking if the oracle price can ever be -ve, if yes, the token just got rekt.
+    // We return early as none of the ratios below are meaningful for a non-positive price.
     let is_oracle_price_negative = oracle_price <= 0;
 
     if is_oracle_price_negative {
-        return Err(ErrorCode::OracleNegativeError.into())
+        return Ok(OracleValidity::Invalid)
     }
 
     // We first check which is the greater value of the oracle price and its last available TWAP.
@@ -119,10 +127,6 @@ pub fn oracle_validity(
         .safe_div(last_oracle_twap.min(oracle_price).max(1))?
         .gt(&oracle_validity_guard.max_volatility_ratio);
 
-    if is_oracle_price_too_volatile {
-        return Err(ErrorCode::OracleTooVolatile.into())
-    }
-
     // We try to calculate the confidence interval as a % of the oracle price. 
     // We do so by dividing the oracle confidence by the oracle price and multiplying it with BID_ASK_PRECISION which is 100.
     let confidence_as_pct_of_price = max(1, oracle_conf)
@@ -134,31 +138,22 @@ pub fn oracle_validity(
         &oracle_validity_guard.confidence_interval_max_accepted_divergence
     );
 
-    if is_confidence_too_wide {
-        return Err(ErrorCode::OracleConfidenceTooWide.into())
-    }
-
     // Returns true if the oracle price is stale for margin calculations
     let is_oracle_price_stale_for_margin = oracle_delay.gt(
         &oracle_validity_guard.slots_before_stale_for_margin
     );
 
-    if is_oracle_price_stale_for_margin {
-        return Err(ErrorCode::OraclePriceStaleForMargin.into())
-    }
-
     // If else if else loop returning different outcomes of the OracleValidity enum based on different oracle
-    // results
-    let oracle_validity = if is_oracle_price_negative {
-        OracleValidity::Invalid
-    } else if is_oracle_price_too_volatile {
-        OracleValidity::Volatile
-    } else if is_confidence_too_wide {
+    // results. Volatility is checked last, so that a volatile price never hides an uncertain, stale or thinly sourced
+    // one from the actions that accept volatile prices
+    let oracle_validity = if is_confidence_too_wide {
         OracleValidity::Uncertain
     } else if is_oracle_price_stale_for_margin {
         OracleValidity::StaleForMargin
     } else if !has_sufficient_data_points {
         OracleValidity::InsufficientDataPoints
+    } else if is_oracle_price_too_volatile {
+        OracleValidity::Volatile
     } else {
         OracleValidity::Valid
     };
@@ -167,6 +162,7 @@ pub fn oracle_validity(
 }
 
 /// Function to block an action(Action)
+/// `guard_rails` are the protocol-wide defaults from `State`, the market's overrides are applied on top of them.
 pub fn block_action(
     market: &Market,
     oracle_price_data: &OraclePriceData,
@@ -174,6 +170,8 @@ pub fn block_action(
     last_acceptable_price: Option<u64>,
     historical_oracle_data: &HistoricalPriceData
 ) -> SpedXSpotResult<bool> {
+    let guard_rails = &market.get_oracle_guard_rails(guard_rails);
+
     let OracleStatus {
         oracle_validity,
         is_mark_price_too_divergent,
@@ -279,82 +277,141 @@ pub fn calculate_oracle_twap_5min_mark_spread_pct(
 
 #[test]
 fn calculate_oracle_validity() {
-    // getting oracle price data
-    let mut oracle_price_data = OraclePriceData {
-        price: (34 * PRICE_PRECISION) as i64,
-        confidence: PRICE_PRECISION_U64 / 100,
-        delay: 1,
-        has_sufficient_data_points: true,
+    // guard rails used across the test: stale after 120 slots, 2% max confidence, 5x max volatility
+    let guard_rails = OracleGuardRails {
+        price_divergence_guard_rails: PriceDivergenceGuardRails { 
+            oracle_mark_pct_divergence: PERCENTAGE_PRECISION_U64 / 10, 
+            oracle_twap_5min_pct_divergence: PERCENTAGE_PRECISION_U64 / 2 
+        },
+        validity: ValidityGuardRails { 
+            slots_before_stale_for_margin: 120, 
+            confidence_interval_max_accepted_divergence: 20000, 
+            max_volatility_ratio: 5
+        }
     };
 
-    // getting historical oracle data
-    let mut historical_oracle_data = HistoricalPriceData {
+    let historical_oracle_data = HistoricalPriceData {
         last_oracle_twap: (34 * PRICE_PRECISION) as i64,
         last_oracle_twap_time_stamp: 1656682258,
         last_oracle_twap_5min: (34 * PRICE_PRECISION) as i64,
         ..HistoricalPriceData::default()
     };
 
-    // getting information of the guard rails for oracle and protocol validity
-    let state = State {
-        oracle_guard_rails: OracleGuardRails {
-            price_divergence_guard_rails: PriceDivergenceGuardRails { 
-                oracle_mark_pct_divergence: 1, 
-                oracle_twap_5min_pct_divergence: 10 
-            },
-            validity: ValidityGuardRails { 
-                slots_before_stale_for_margin: 120, 
-                confidence_interval_max_accepted_divergence: 20000, 
-                max_volatility_ratio: 5
-            }
-        },
-        ..State::default()
-    };
-
-    // getting the oracle status at its current lifetime
-    let mut oracle_status = get_oracle_status(&oracle_price_data, &state.oracle_guard_rails, &state.historical_price_data, None).unwrap();
-
-    // we want to check that the oracle is valid and that the mark price is not too divergent
-    assert!(oracle_status.oracle_validity == OracleValidity::Valid);
-    assert!(!oracle_status.is_mark_price_too_divergent);
-
-    // asserting that the oracle status is valid at different values of oracle price data
-    oracle_price_data = OraclePriceData {
+    let valid_oracle_price_data = OraclePriceData {
         price: (34 * PRICE_PRECISION) as i64,
         confidence: PRICE_PRECISION_U64 / 100,
-        delay: 11,
+        delay: 1,
         has_sufficient_data_points: true,
     };
-    oracle_status = get_oracle_status(&oracle_price_data, &state.oracle_guard_rails, &state.historical_price_data, None).unwrap();
-    assert!(oracle_status.oracle_validity != OracleValidity::Valid);
 
-    // asserting that the oracle status is valid if there is a delay in oracle updates
-    oracle_price_data.delay = 8;
-    oracle_status = get_oracle_status(&oracle_price_data, &state.oracle_guard_rails, &state.historical_price_data, None).unwrap();
+    // fresh, tight and in line with the twap
+    let validity = oracle_validity(
+        historical_oracle_data.last_oracle_twap,
+        &valid_oracle_price_data,
+        &guard_rails.validity
+    ).unwrap();
+    assert_eq!(validity, OracleValidity::Valid);
+
+    // the oracle status wraps the same classification
+    let oracle_status = get_oracle_status(
+        &valid_oracle_price_data,
+        &guard_rails,
+        &historical_oracle_data,
+        Some(34 * PRICE_PRECISION_U64)
+    ).unwrap();
     assert_eq!(oracle_status.oracle_validity, OracleValidity::Valid);
-    assert_eq!(!oracle_status.is_mark_price_too_divergent, false);
+    assert!(!oracle_status.is_mark_price_too_divergent);
 
-    // asserting that the oracle status is valid if there is a change in the 5min TWAP
-    historical_oracle_data.last_oracle_twap_5min = 29*PRICE_PRECISION as i64;
-    oracle_status = get_oracle_status(&oracle_price_data, &state.oracle_guard_rails, &state.historical_price_data, None).unwrap();
-    assert_eq!(oracle_status.is_mark_price_too_divergent, false);
-    assert_eq!(oracle_status.oracle_validity, OracleValidity::Valid);
+    // non-positive prices are classified as invalid instead of erroring
+    for price in [0, -(PRICE_PRECISION as i64)] {
+        let oracle_price_data = OraclePriceData { price, ..valid_oracle_price_data };
+        let validity = oracle_validity(
+            historical_oracle_data.last_oracle_twap,
+            &oracle_price_data,
+            &guard_rails.validity
+        ).unwrap();
+        assert_eq!(validity, OracleValidity::Invalid);
+    }
 
-    // asserting that the oracle status is valid with a certain confidence interval
-    oracle_price_data.confidence = PRICE_PRECISION_U64;
-    oracle_status = get_oracle_status(&oracle_price_data, &state.oracle_guard_rails, &state.historical_price_data, None).unwrap();
-    assert_eq!(oracle_status.is_mark_price_too_divergent, false);
-    assert_eq!(oracle_status.oracle_validity, OracleValidity::Volatile);
-
-    // asserting that the oracle status is valid with pre-defined values of historical oracle data
-    let price_precision = 32 * PRICE_PRECISION;
-    let previous_ts = 1656682258;
-    historical_oracle_data = HistoricalPriceData {
-        last_oracle_twap: price_precision as i64,
-        last_oracle_twap_time_stamp: previous_ts,
-        ..HistoricalPriceData::default()
+    // price is more than 5x the twap, but fresh
+    let validity = oracle_validity(
+        (5 * PRICE_PRECISION) as i64,
+        &valid_oracle_price_data,
+        &guard_rails.validity
+    ).unwrap();
+    assert_eq!(validity, OracleValidity::Volatile);
+
+    // confidence is 3% of the price
+    let oracle_price_data = OraclePriceData {
+        confidence: (34 * PRICE_PRECISION_U64) * 3 / 100,
+        ..valid_oracle_price_data
     };
-    oracle_status = get_oracle_status(&oracle_price_data, &state.oracle_guard_rails, &historical_oracle_data, None).unwrap();
-    assert_eq!(oracle_status.oracle_validity, OracleValidity::Valid);
-    assert_eq!(!oracle_status.is_mark_price_too_divergent, false);
+    let validity = oracle_validity(
+        historical_oracle_data.last_oracle_twap,
+        &oracle_price_data,
+        &guard_rails.validity
+    ).unwrap();
+    assert_eq!(validity, OracleValidity::Uncertain);
+
+    // price has not been updated for more than 120 slots
+    let oracle_price_data = OraclePriceData {
+        delay: 121,
+        ..valid_oracle_price_data
+    };
+    let validity = oracle_validity(
+        historical_oracle_data.last_oracle_twap,
+        &oracle_price_data,
+        &guard_rails.validity
+    ).unwrap();
+    assert_eq!(validity, OracleValidity::StaleForMargin);
+
+    // a stale price that looks volatile is classified by its staleness
+    let validity = oracle_validity(
+        (5 * PRICE_PRECISION) as i64,
+        &oracle_price_data,
+        &guard_rails.validity
+    ).unwrap();
+    assert_eq!(validity, OracleValidity::StaleForMargin);
+
+    // aggregate has too few publishers
+    let oracle_price_data = OraclePriceData {
+        has_sufficient_data_points: false,
+        ..valid_oracle_price_data
+    };
+    let validity = oracle_validity(
+        historical_oracle_data.last_oracle_twap,
+        &oracle_price_data,
+        &guard_rails.validity
+    ).unwrap();
+    assert_eq!(validity, OracleValidity::InsufficientDataPoints);
+
+    // a volatile price with too few publishers is classified by its data points
+    let validity = oracle_validity(
+        (5 * PRICE_PRECISION) as i64,
+        &oracle_price_data,
+        &guard_rails.validity
+    ).unwrap();
+    assert_eq!(validity, OracleValidity::InsufficientDataPoints);
+    assert!(!is_oracle_valid_for_action(Some(Actions::Liquidate), validity).unwrap());
+
+    // as is a volatile price with a wide confidence by its confidence
+    let oracle_price_data = OraclePriceData {
+        confidence: (34 * PRICE_PRECISION_U64) * 3 / 100,
+        ..valid_oracle_price_data
+    };
+    let validity = oracle_validity(
+        (5 * PRICE_PRECISION) as i64,
+        &oracle_price_data,
+        &guard_rails.validity
+    ).unwrap();
+    assert_eq!(validity, OracleValidity::Uncertain);
+    assert!(!is_oracle_valid_for_action(Some(Actions::Liquidate), validity).unwrap());
+
+    // liquidations proceed on a volatile-but-fresh oracle, margin calculations and fills do not
+    assert!(is_oracle_valid_for_action(Some(Actions::Liquidate), OracleValidity::Volatile).unwrap());
+    assert!(!is_oracle_valid_for_action(Some(Actions::Liquidate), OracleValidity::StaleForMargin).unwrap());
+    assert!(!is_oracle_valid_for_action(Some(Actions::Liquidate), OracleValidity::Uncertain).unwrap());
+    assert!(!is_oracle_valid_for_action(Some(Actions::MarginCalculation), OracleValidity::Volatile).unwrap());
+    assert!(!is_oracle_valid_for_action(Some(Actions::FillOrder), OracleValidity::Volatile).unwrap());
+    assert!(!is_oracle_valid_for_action(Some(Actions::UpdateTWAP), OracleValidity::Invalid).unwrap());
 }
@@ -0,0 +1,322 @@
+//! Withdraw guard of a market. Withdrawals and borrows are limited against the market's 24h deposit and borrow TWAPs,
+//! so that a bank run or a borrow against a manipulated oracle cannot drain the market within a day, and against the
+//! market's borrow caps.
+
+use solana_program::msg;
+
+use crate::{
+    error::{
+        SpedXSpotResult,
+        ErrorCode
+    },
+    math::{
+        balance::{
+            calculate_utilization,
+            get_amount_of_tokens
+        },
+        casting::Cast,
+        constants::{
+            PERCENTAGE_PRECISION,
+            SPOT_MARKET_MAX_BORROW_TWAP_PCT,
+            SPOT_MARKET_MAX_UTILIZATION,
+            SPOT_MARKET_MIN_DEPOSIT_TWAP_PCT
+        },
+        safe_math::SafeMath
+    },
+    state::{
+        enums::SpotBalanceType,
+        market::Market,
+        user_position::Position
+    },
+    validate
+};
+
+/// Lowest amount of deposits a withdrawal may leave in the market. Deposits may fall to a fraction of their TWAP, or by
+/// the market's withdraw limit if that is more.
+/// precision: token mint
+pub fn calculate_min_deposit_token_amount(
+    deposit_token_twap: u128,
+    withdraw_limit: u128
+) -> SpedXSpotResult<u128> {
+    let min_deposit_token_amount = deposit_token_twap
+        .safe_mul(SPOT_MARKET_MIN_DEPOSIT_TWAP_PCT)?
+        .safe_div(PERCENTAGE_PRECISION)?;
+
+    Ok(min_deposit_token_amount.min(deposit_token_twap.saturating_sub(withdraw_limit)))
+}
+
+/// Highest amount of borrows a borrow may leave in the market. Borrows may rise to a multiple of their TWAP, or by the
+/// market's withdraw limit if that is more.
+/// precision: token mint
+pub fn calculate_max_borrow_token_amount(
+    borrow_token_twap: u128,
+    withdraw_limit: u128
+) -> SpedXSpotResult<u128> {
+    let max_borrow_token_amount = borrow_token_twap
+        .safe_mul(SPOT_MARKET_MAX_BORROW_TWAP_PCT)?
+        .safe_div(PERCENTAGE_PRECISION)?;
+
+    Ok(max_borrow_token_amount.max(borrow_token_twap.safe_add(withdraw_limit)?))
+}
+
+/// Checks the market's balances after a withdrawal or a borrow against its withdraw limits: deposits may not fall below
+/// a fraction of their 24h TWAP, borrows may not rise above a multiple of their 24h TWAP, and utilization must stay
+/// under SPOT_MARKET_MAX_UTILIZATION. The TWAPs must be updated before the balances change, so that they do not include
+/// the withdrawal itself. Called by `withdraw` after the balances are updated, as withdrawals are the only path that
+/// decreases deposits or increases borrows: order fills only move a position's base and quote amounts.
+pub fn check_withdraw_limits(market: &Market) -> SpedXSpotResult {
+    let deposit_token_amount = get_amount_of_tokens(market.deposit_balance, market, &SpotBalanceType::Deposits)?;
+    let borrow_token_amount = get_amount_of_tokens(market.borrow_balance, market, &SpotBalanceType::Borrows)?;
+
+    let min_deposit_token_amount = calculate_min_deposit_token_amount(
+        market.token_deposit_twap.cast()?,
+        market.withdraw_limit.cast()?
+    )?;
+
+    validate!(
+        deposit_token_amount >= min_deposit_token_amount,
+        ErrorCode::WithdrawLimitExceeded,
+        "Market {} deposits {} would fall below {}",
+        market.market_index,
+        deposit_token_amount,
+        min_deposit_token_amount
+    )?;
+
+    let max_borrow_token_amount = calculate_max_borrow_token_amount(
+        market.token_borrow_twap.cast()?,
+        market.withdraw_limit.cast()?
+    )?;
+
+    validate!(
+        borrow_token_amount <= max_borrow_token_amount,
+        ErrorCode::WithdrawLimitExceeded,
+        "Market {} borrows {} would rise above {}",
+        market.market_index,
+        borrow_token_amount,
+        max_borrow_token_amount
+    )?;
+
+    let utilization = calculate_utilization(deposit_token_amount, borrow_token_amount)?;
+
+    validate!(
+        utilization <= SPOT_MARKET_MAX_UTILIZATION,
+        ErrorCode::WithdrawLimitExceeded,
+        "Market {} utilization {} would rise above {}",
+        market.market_index,
+        utilization,
+        SPOT_MARKET_MAX_UTILIZATION
+    )
+}
+
+/// Checks the market's borrows and the user's borrow against the market's borrow caps after a withdrawal that borrows.
+/// Only meant for paths that increase borrows, so that repaying is never blocked by a cap lowered below the current
+/// borrows.
+pub fn check_borrow_limits(market: &Market, position: &Position) -> SpedXSpotResult {
+    if market.max_borrow_limit != 0 {
+        let borrow_token_amount = get_amount_of_tokens(market.borrow_balance, market, &SpotBalanceType::Borrows)?;
+
+        validate!(
+            borrow_token_amount <= market.max_borrow_limit.cast()?,
+            ErrorCode::MarketBorrowLimitExceeded,
+            "Market {} borrows {} would exceed the borrow limit {}",
+            market.market_index,
+            borrow_token_amount,
+            market.max_borrow_limit
+        )?;
+    }
+
+    check_user_borrow_limit(market, position)
+}
+
+/// Checks the user's worst case borrow of the market's token against the market's per-user borrow cap. Selling tokens
+/// on the book that the position does not hold borrows them just like withdrawing them does, so this is also checked
+/// when asks are placed: their fills are settled whenever they happen and cannot be refused then. Fills never move the
+/// market's borrow balance, so its borrow cap is left to `check_borrow_limits`.
+pub fn check_user_borrow_limit(market: &Market, position: &Position) -> SpedXSpotResult {
+    if market.max_user_borrow_limit == 0 {
+        return Ok(());
+    }
+
+    let user_borrow_token_amount = calculate_user_borrow_token_amount(market, position)?;
+
+    validate!(
+        user_borrow_token_amount <= market.max_user_borrow_limit.cast()?,
+        ErrorCode::UserBorrowLimitExceeded,
+        "User borrows {} of market {} would exceed the per-user borrow limit {}",
+        user_borrow_token_amount,
+        market.market_index,
+        market.max_user_borrow_limit
+    )
+}
+
+/// The position's borrow of the market's token once all of its open asks are filled: its net short exposure, whether
+/// the tokens were borrowed through a withdrawal or sold on the book.
+/// precision: token mint
+pub fn calculate_user_borrow_token_amount(market: &Market, position: &Position) -> SpedXSpotResult<u128> {
+    let worst_case_exposure = position.get_base_asset_exposure(market)?.safe_add(position.open_asks)?;
+
+    worst_case_exposure.min(0).unsigned_abs().cast()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::ErrorCode,
+        math::{
+            constants::{
+                SPOT_BALANCE_PRECISION,
+                SPOT_CUMULATIVE_INTEREST_PRECISION
+            },
+            spot_withdraw::{
+                calculate_max_borrow_token_amount,
+                calculate_min_deposit_token_amount,
+                check_borrow_limits,
+                check_user_borrow_limit,
+                check_withdraw_limits
+            }
+        },
+        state::{
+            enums::SpotBalanceType,
+            market::Market,
+            user_position::Position
+        }
+    };
+
+    const ONE_TOKEN: u128 = 1_000_000_000;
+
+    /// Market with 1000 tokens deposited and 500 borrowed, with TWAPs at the same amounts
+    fn market() -> Market {
+        Market {
+            decimals: 9,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            deposit_balance: 1_000 * SPOT_BALANCE_PRECISION,
+            borrow_balance: 500 * SPOT_BALANCE_PRECISION,
+            token_deposit_twap: (1_000 * ONE_TOKEN) as u64,
+            token_borrow_twap: (500 * ONE_TOKEN) as u64,
+            ..Market::default()
+        }
+    }
+
+    #[test]
+    fn min_deposit_token_amount() {
+        // deposits may fall by 25% of their twap
+        assert_eq!(calculate_min_deposit_token_amount(1_000 * ONE_TOKEN, 0).unwrap(), 750 * ONE_TOKEN);
+
+        // or by the withdraw limit, if that is more
+        assert_eq!(calculate_min_deposit_token_amount(1_000 * ONE_TOKEN, 400 * ONE_TOKEN).unwrap(), 600 * ONE_TOKEN);
+        assert_eq!(calculate_min_deposit_token_amount(1_000 * ONE_TOKEN, 2_000 * ONE_TOKEN).unwrap(), 0);
+    }
+
+    #[test]
+    fn max_borrow_token_amount() {
+        // borrows may rise by 20% of their twap
+        assert_eq!(calculate_max_borrow_token_amount(500 * ONE_TOKEN, 0).unwrap(), 600 * ONE_TOKEN);
+
+        // or by the withdraw limit, if that is more
+        assert_eq!(calculate_max_borrow_token_amount(500 * ONE_TOKEN, 200 * ONE_TOKEN).unwrap(), 700 * ONE_TOKEN);
+        assert_eq!(calculate_max_borrow_token_amount(0, 10 * ONE_TOKEN).unwrap(), 10 * ONE_TOKEN);
+    }
+
+    #[test]
+    fn withdraw_limits() {
+        assert_eq!(check_withdraw_limits(&market()), Ok(()));
+
+        // withdrawing 300 of the 1000 tokens deposited
+        let market_after_withdraw = Market {
+            deposit_balance: 700 * SPOT_BALANCE_PRECISION,
+            ..market()
+        };
+        assert_eq!(check_withdraw_limits(&market_after_withdraw), Err(ErrorCode::WithdrawLimitExceeded));
+
+        // borrowing another 150 tokens
+        let market_after_borrow = Market {
+            borrow_balance: 650 * SPOT_BALANCE_PRECISION,
+            ..market()
+        };
+        assert_eq!(check_withdraw_limits(&market_after_borrow), Err(ErrorCode::WithdrawLimitExceeded));
+
+        // withdrawing 200 tokens with 750 borrowed stays within the twaps, but utilization would be 93.75%
+        let market_at_max_utilization = Market {
+            deposit_balance: 800 * SPOT_BALANCE_PRECISION,
+            borrow_balance: 750 * SPOT_BALANCE_PRECISION,
+            token_borrow_twap: (750 * ONE_TOKEN) as u64,
+            ..market()
+        };
+        assert_eq!(check_withdraw_limits(&market_at_max_utilization), Err(ErrorCode::WithdrawLimitExceeded));
+
+        // the withdraw limit lets the same withdrawal through on a market that allows moving 300 tokens a day
+        let market_with_withdraw_limit = Market {
+            withdraw_limit: (300 * ONE_TOKEN) as u64,
+            ..market_after_withdraw
+        };
+        assert_eq!(check_withdraw_limits(&market_with_withdraw_limit), Ok(()));
+    }
+
+    #[test]
+    fn borrow_limits() {
+        // the user borrows 100 of the market's 500 borrowed tokens
+        let position = Position {
+            scaled_balance: (100 * SPOT_BALANCE_PRECISION) as u64,
+            bal_type: SpotBalanceType::Borrows,
+            ..Position::default()
+        };
+
+        // no caps
+        assert_eq!(check_borrow_limits(&market(), &position), Ok(()));
+
+        let market_with_borrow_limits = Market {
+            max_borrow_limit: (500 * ONE_TOKEN) as u64,
+            max_user_borrow_limit: (100 * ONE_TOKEN) as u64,
+            ..market()
+        };
+        assert_eq!(check_borrow_limits(&market_with_borrow_limits, &position), Ok(()));
+
+        let market_above_borrow_limit = Market {
+            borrow_balance: 501 * SPOT_BALANCE_PRECISION,
+            ..market_with_borrow_limits
+        };
+        assert_eq!(
+            check_borrow_limits(&market_above_borrow_limit, &position),
+            Err(ErrorCode::MarketBorrowLimitExceeded)
+        );
+
+        let position_above_user_borrow_limit = Position {
+            scaled_balance: (101 * SPOT_BALANCE_PRECISION) as u64,
+            ..position
+        };
+        assert_eq!(
+            check_borrow_limits(&market_with_borrow_limits, &position_above_user_borrow_limit),
+            Err(ErrorCode::UserBorrowLimitExceeded)
+        );
+
+        // the per-user cap does not apply to deposits
+        let deposit_position = Position {
+            bal_type: SpotBalanceType::Deposits,
+            ..position_above_user_borrow_limit
+        };
+        assert_eq!(check_borrow_limits(&market_with_borrow_limits, &deposit_position), Ok(()));
+
+        // a deposit of 50 tokens with 120 sold on the book and asks open for 31 more borrows 101 once the asks fill
+        let short_position = Position {
+            scaled_balance: (50 * SPOT_BALANCE_PRECISION) as u64,
+            bal_type: SpotBalanceType::Deposits,
+            base_asset_amount: -(120 * ONE_TOKEN as i64),
+            open_asks: -(31 * ONE_TOKEN as i64),
+            ..Position::default()
+        };
+        assert_eq!(
+            check_user_borrow_limit(&market_with_borrow_limits, &short_position),
+            Err(ErrorCode::UserBorrowLimitExceeded)
+        );
+
+        let short_position = Position {
+            open_asks: -(30 * ONE_TOKEN as i64),
+            ..short_position
+        };
+        assert_eq!(check_user_borrow_limit(&market_with_borrow_limits, &short_position), Ok(()));
+
+        // the market's borrow cap is not the user's to check, fills do not move the market's borrows
+        assert_eq!(check_user_borrow_limit(&market_above_borrow_limit, &short_position), Ok(()));
+    }
+}
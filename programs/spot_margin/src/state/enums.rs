@@ -5,10 +5,14 @@ use std::fmt::{
 };
 use enumflags2::BitFlags;
 
-use borsh::{BorshDeserialize, BorshSerialize};
+use anchor_lang::prelude::{
+    borsh,
+    AnchorDeserialize,
+    AnchorSerialize
+};
 
 /// Enums for Position Directions(Longs and Shorts)
-#[derive(Clone, Copy, BorshDeserialize, BorshSerialize, PartialEq, Debug, Eq)]
+#[derive(Clone, Copy, AnchorDeserialize, AnchorSerialize, PartialEq, Debug, Eq)]
 pub enum PositionDirection {
     Long,
     Short,
@@ -34,7 +38,8 @@ impl PositionDirection {
     }
 }
 
-#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+/// Venue the orders of a market are placed on
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Debug, Eq)]
 pub enum SpotFulfillmentType {
     OpenBookV2,
     PhoenixV1
@@ -46,8 +51,24 @@ impl Default for SpotFulfillmentType {
     }
 }
 
+/// Curve that maps a market's utilization to its borrow rate
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Debug, Eq)]
+pub enum InterestRateModel {
+    /// Linear up to `optimal_utilization` at `optimal_borrow_rate`, then linear up to `max_borrow_rate` at 100%
+    SingleKink,
+    /// Piecewise linear through the market's `borrow_rate_kinks`, from 0% at 0% utilization to `max_borrow_rate` at
+    /// 100% utilization
+    MultiKink
+}
+
+impl Default for InterestRateModel {
+    fn default() -> Self {
+        InterestRateModel::SingleKink
+    }
+}
+
 /// Oracle types
-#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Debug, Eq)]
 pub enum OracleType {
     Pyth,
     Switchboard,
@@ -63,8 +84,23 @@ impl Default for OracleType {
     }
 }
 
+/// How a market combines its oracle sources(primary oracle, secondary oracle and the Phoenix mid) into one price
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Debug, Eq)]
+pub enum OracleSourcePolicy {
+    /// Use the first usable source in the order primary, secondary, Phoenix mid
+    PrimaryWithFallback,
+    /// Use the median price of all usable sources
+    MedianOfValid
+}
+
+impl Default for OracleSourcePolicy {
+    fn default() -> Self {
+        OracleSourcePolicy::PrimaryWithFallback
+    }
+}
+
 /// Balance of a user account can either be in Deposits or Borrows
-#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Debug)]
 pub enum SpotBalanceType {
     /// Balance can be in the form of deposits, i.e deposited by the user from his account
     Deposits,
@@ -87,7 +123,7 @@ impl Default for SpotBalanceType {
     }
 }
 
-#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Debug)]
 pub enum MarketStatus {
     /// Period succeeding market initialization, fills are paused
     Initialized,
@@ -119,7 +155,7 @@ impl Default for MarketStatus {
     }
 }
 
-#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, PartialOrd, Ord)]
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Debug, PartialOrd, Ord)]
 pub enum AssetTier {
     /// Free collateral, can be used for borrows
     Collateral,
@@ -164,7 +200,7 @@ impl ExchangeStatus {
 
 /// Different outcomes of oracle validity. Ranging from descending order of severity or invalidity.
 /// Invalid represents that the extreme state of invalidity whereas Valid represents the extreme state of validity.
-#[derive(Clone, Copy, BorshDeserialize, BorshSerialize, PartialEq, Debug, Eq)]
+#[derive(Clone, Copy, AnchorDeserialize, AnchorSerialize, PartialEq, Debug, Eq)]
 pub enum OracleValidity {
     Invalid,
     Volatile,
@@ -181,7 +217,7 @@ impl Default for OracleValidity {
 }
 
 /// Enum representing different actions(both client and server-side) on the protocol
-#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Debug, Eq)]
 pub enum Actions {
     PnLSettlement,
     OrderAdded,
@@ -191,8 +227,16 @@ pub enum Actions {
     UpdateTWAP,
 }
 
+/// Type of margin requirement being calculated. Initial margin gates opening positions and borrowing,
+/// maintenance margin gates liquidations.
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Debug, Eq)]
+pub enum MarginRequirementType {
+    Initial,
+    Maintenance
+}
+
 /// Enum representing the current user status, with descending order of severity
-#[derive(Clone, Copy, Debug, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, AnchorSerialize, AnchorDeserialize, PartialEq, Eq)]
 pub enum UserStatus {
     Active,
     Liquidatable,
@@ -213,14 +257,14 @@ impl super::traits::Size for UserStatus {
 }
 
 /// The asset class we would be referring to in a specific context, either the base or quote asset of a market
-#[derive(Clone, Copy, Debug, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, AnchorSerialize, AnchorDeserialize, PartialEq, Eq)]
 pub enum AssetClass {
     BaseAsset,
     QuoteAsset
 }
 
 /// Current status of an order
-#[derive(Clone, Copy, BorshDeserialize, BorshSerialize, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, AnchorDeserialize, AnchorSerialize, PartialEq, Eq, Debug)]
 pub enum OrderStatus {
     NotInitialized,
     Active,
@@ -229,14 +273,15 @@ pub enum OrderStatus {
 }
 
 /// Different order types
-#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Debug)]
 pub enum OrderType {
     ImmediateOrCancel,
     Limit,
     PostOnly,
     TriggerMarket, // Stop Loss, Take Profit
     TriggerLimit, // Stop Loss Limit, Take Profit Limit
-    OraclePegged
+    OraclePegged,
+    Twap // executed in timed immediate-or-cancel clips
 }
 
 impl Default for OrderType {
@@ -247,7 +292,7 @@ impl Default for OrderType {
 
 /// Returns whether a Trigger should be triggered above(take-profit) or below(stop-loss) a certain price. 
 /// If it's already triggered, return TriggeredAbove for orders triggered above the limit price, and TriggeredBelow for orders triggered below the limit price
-#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Debug)]
 pub enum OrderTriggerConditions {
     Above,
     Below,
@@ -262,7 +307,7 @@ impl Default for OrderTriggerConditions {
     }
 }
 
-#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Debug)]
 pub enum PostOnlyTypes {
     NotPostOnly, // order is not post-only
     MustBePostOnly, // order must be post-only, tx fails if it is not
@@ -276,6 +321,38 @@ impl Default for PostOnlyTypes {
     }
 }
 
+/// What happens when an order would match one of the user's own resting orders:
+/// - Abort: the order fails
+/// - CancelProvide: the resting order is cancelled
+/// - DecrementTake: both orders are reduced by the overlapping size, without a trade
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Debug)]
+pub enum SelfTradeBehaviorTypes {
+    Abort,
+    CancelProvide,
+    DecrementTake
+}
+
+impl Default for SelfTradeBehaviorTypes {
+    fn default() -> Self {
+        SelfTradeBehaviorTypes::CancelProvide
+    }
+}
+
+/// Role of an order in a one-cancels-other(OCO) group. The legs of a bracket, i.e its take-profit and stop-loss, only
+/// become triggerable once the entry order has been filled
+#[derive(Clone, Copy, AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Debug)]
+pub enum OcoRole {
+    None,
+    Entry,
+    Leg
+}
+
+impl Default for OcoRole {
+    fn default() -> Self {
+        OcoRole::None
+    }
+}
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub enum PriceType {
@@ -2,7 +2,14 @@
 
 use anchor_lang::prelude::*;
 
-use crate::math::constants::PERCENTAGE_PRECISION_U64;
+use crate::{
+    error::{
+        SpedXSpotResult,
+        ErrorCode
+    },
+    math::constants::PERCENTAGE_PRECISION_U64,
+    validate
+};
 
 #[derive(AnchorSerialize, AnchorDeserialize, Copy, Clone)]
 pub struct OracleGuardRails {
@@ -61,3 +68,133 @@ pub struct ValidityGuardRails {
     /// The maximum accepted volatility 
     pub max_volatility_ratio: i64
 }
+
+
+/// Per-market overrides of the protocol-wide `OracleGuardRails` stored in `State`.
+/// Lives inside the zero-copy `Market`, hence optionality is represented by zero values: a field set to 0
+/// falls back to the `State` default. This lets a stablecoin market run far tighter limits than a tail market.
+#[zero_copy]
+#[repr(C)]
+#[derive(Default, Eq, PartialEq, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct MarketOracleGuardRails {
+    /// Overrides `ValidityGuardRails::slots_before_stale_for_margin`. 0 uses the default.
+    pub slots_before_stale_for_margin: i64,
+
+    /// Overrides `ValidityGuardRails::confidence_interval_max_accepted_divergence`. 0 uses the default.
+    /// precision: PERCENTAGE_PRECISION
+    pub confidence_interval_max_accepted_divergence: u64,
+
+    /// Overrides `ValidityGuardRails::max_volatility_ratio`. 0 uses the default.
+    pub max_volatility_ratio: i64,
+
+    /// Overrides `PriceDivergenceGuardRails::oracle_mark_pct_divergence`. 0 uses the default.
+    /// precision: PERCENTAGE_PRECISION
+    pub oracle_mark_pct_divergence: u64,
+
+    /// Overrides `PriceDivergenceGuardRails::oracle_twap_5min_pct_divergence`. 0 uses the default.
+    /// precision: PERCENTAGE_PRECISION
+    pub oracle_twap_5min_pct_divergence: u64,
+}
+
+impl MarketOracleGuardRails {
+    /// Returns true if none of the fields are overridden
+    pub fn is_default(&self) -> bool {
+        *self == MarketOracleGuardRails::default()
+    }
+
+    /// Sanity checks the overrides before they are written to a market
+    pub fn validate(&self) -> SpedXSpotResult {
+        validate!(
+            self.slots_before_stale_for_margin >= 0,
+            ErrorCode::InvalidMarketOracleGuardRails,
+            "slots_before_stale_for_margin must be non-negative: {}",
+            self.slots_before_stale_for_margin
+        )?;
+
+        // a ratio of 1 would flag every price that is not exactly equal to the twap as volatile
+        validate!(
+            self.max_volatility_ratio == 0 || self.max_volatility_ratio > 1,
+            ErrorCode::InvalidMarketOracleGuardRails,
+            "max_volatility_ratio must be 0 or greater than 1: {}",
+            self.max_volatility_ratio
+        )?;
+
+        validate!(
+            self.confidence_interval_max_accepted_divergence <= PERCENTAGE_PRECISION_U64
+                && self.oracle_mark_pct_divergence <= PERCENTAGE_PRECISION_U64
+                && self.oracle_twap_5min_pct_divergence <= PERCENTAGE_PRECISION_U64,
+            ErrorCode::InvalidMarketOracleGuardRails,
+            "pct divergences must not exceed 100%"
+        )?;
+
+        Ok(())
+    }
+
+    /// Resolves the guard rails for a market, using the overridden fields and falling back to the `State` defaults
+    /// for every field that is 0.
+    pub fn resolve(&self, defaults: &OracleGuardRails) -> OracleGuardRails {
+        let resolve_i64 = |value: i64, default: i64| if value == 0 { default } else { value };
+        let resolve_u64 = |value: u64, default: u64| if value == 0 { default } else { value };
+
+        OracleGuardRails {
+            price_divergence_guard_rails: PriceDivergenceGuardRails {
+                oracle_mark_pct_divergence: resolve_u64(
+                    self.oracle_mark_pct_divergence,
+                    defaults.price_divergence_guard_rails.oracle_mark_pct_divergence
+                ),
+                oracle_twap_5min_pct_divergence: resolve_u64(
+                    self.oracle_twap_5min_pct_divergence,
+                    defaults.price_divergence_guard_rails.oracle_twap_5min_pct_divergence
+                ),
+            },
+            validity: ValidityGuardRails {
+                slots_before_stale_for_margin: resolve_i64(
+                    self.slots_before_stale_for_margin,
+                    defaults.validity.slots_before_stale_for_margin
+                ),
+                confidence_interval_max_accepted_divergence: resolve_u64(
+                    self.confidence_interval_max_accepted_divergence,
+                    defaults.validity.confidence_interval_max_accepted_divergence
+                ),
+                max_volatility_ratio: resolve_i64(
+                    self.max_volatility_ratio,
+                    defaults.validity.max_volatility_ratio
+                ),
+            }
+        }
+    }
+}
+
+#[test]
+fn resolve_market_oracle_guard_rails() {
+    let defaults = OracleGuardRails::default();
+
+    // no overrides resolves to the state defaults
+    let resolved = MarketOracleGuardRails::default().resolve(&defaults);
+    assert_eq!(resolved.validity.max_volatility_ratio, 5);
+    assert_eq!(resolved.validity.confidence_interval_max_accepted_divergence, 20_000);
+    assert_eq!(resolved.validity.slots_before_stale_for_margin, 120);
+    assert_eq!(resolved.price_divergence_guard_rails.oracle_mark_pct_divergence, PERCENTAGE_PRECISION_U64 / 10);
+
+    // stablecoin market: tight confidence and volatility, everything else inherited
+    let overrides = MarketOracleGuardRails {
+        confidence_interval_max_accepted_divergence: 1_000, // 0.1%
+        max_volatility_ratio: 2,
+        ..MarketOracleGuardRails::default()
+    };
+    assert!(overrides.validate().is_ok());
+    assert!(!overrides.is_default());
+
+    let resolved = overrides.resolve(&defaults);
+    assert_eq!(resolved.validity.confidence_interval_max_accepted_divergence, 1_000);
+    assert_eq!(resolved.validity.max_volatility_ratio, 2);
+    assert_eq!(resolved.validity.slots_before_stale_for_margin, 120);
+    assert_eq!(resolved.price_divergence_guard_rails.oracle_twap_5min_pct_divergence, PERCENTAGE_PRECISION_U64 / 2);
+
+    // invalid overrides are rejected
+    let overrides = MarketOracleGuardRails {
+        max_volatility_ratio: 1,
+        ..MarketOracleGuardRails::default()
+    };
+    assert_eq!(overrides.validate(), Err(ErrorCode::InvalidMarketOracleGuardRails));
+}
@@ -31,4 +31,9 @@ pub fn get_test_pyth_price(price: i64, exponent: i32) -> Price {
 /// Function to get the seeds of the signer. We use the "signer" seed.
 pub fn get_signer_seeds(nonce: &u8) -> [&[u8]; 2] {
     [b"signer".as_ref(), bytes_of(nonce)]
+}
+
+/// Seeds of the per-user PDA that holds a seat on Phoenix markets. Each user account, i.e each sub account, has its own.
+pub fn get_phoenix_trader_seeds<'a>(user: &'a Pubkey, bump: &'a u8) -> [&'a [u8]; 3] {
+    [b"phoenix_trader".as_ref(), user.as_ref(), bytes_of(bump)]
 }
\ No newline at end of file
@@ -5,6 +5,7 @@ use crate::{
         SpedXSpotResult,
         ErrorCode
     },
+    math::constants::MAX_BORROW_RATE_KINKS,
     state::{
         oracle::{
             HistoricalIndexData,
@@ -12,13 +13,21 @@ use crate::{
         },
         enums::{
             OracleType,
+            OracleSourcePolicy,
             MarketStatus,
-            AssetTier
+            MarginRequirementType,
+            AssetTier,
+            SpotFulfillmentType,
+            InterestRateModel
         },
         traits::{
             Size,
             MarketIndexOffset,
             SpotBalance
+        },
+        guard_rails::{
+            OracleGuardRails,
+            MarketOracleGuardRails
         }
     },
 };
@@ -197,6 +206,7 @@ pub struct Market {
     pub optimal_borrow_rate: u32,
 
     /// Maximum borrow rate one can incur at 100% utilization.
+    /// precision: SPOT_RATE_PRECISION
     pub max_borrow_rate: u32,
 
     /// The market's token mint's decimals. precision: 10^decimals
@@ -270,8 +280,90 @@ pub struct Market {
     pub number_of_users: u32,
 
     // pub contract_tier: ContractTier,
-    
-    pub padding: [u8; 56],
+
+    /// Market-specific overrides of the oracle guard rails in `State`. Zeroed fields fall back to the `State` defaults.
+    pub oracle_guard_rails: MarketOracleGuardRails,
+
+    /// Optional second oracle used alongside `oracle`. Pubkey::default() when the market has a single oracle.
+    pub secondary_oracle: Pubkey,
+
+    /// Oracle type of the secondary oracle
+    pub secondary_oracle_type: OracleType,
+
+    /// How the primary oracle, secondary oracle and Phoenix mid are combined into the market's oracle price
+    pub oracle_source_policy: OracleSourcePolicy,
+
+    /// Minimum number of sources that must agree on the price for it to have sufficient data points.
+    /// 0 and 1 are equivalent, i.e a single source is enough.
+    pub oracle_quorum: u8,
+
+    /// Whether the Phoenix mid price from `historical_index_data` is used as an oracle source. The index data is updated
+    /// from the Phoenix book by the `update_market_oracle_twap` crank.
+    pub use_phoenix_mid_oracle: bool,
+
+    /// Multiplier(k) applied to the oracle confidence interval when valuing balances for initial margin. Assets are valued
+    /// at price - k*conf and liabilities at price + k*conf, so wide-confidence assets give less borrowing power.
+    /// 0 disables the confidence adjustment.
+    /// precision: CONFIDENCE_INTERVAL_MULTIPLIER_PRECISION
+    pub confidence_interval_multiplier: u32,
+
+    /// The Phoenix market that orders against this market are placed on when it is fulfilled by Phoenix
+    pub phoenix_market: Pubkey,
+
+    /// The OpenBook V2 market that orders against this market are placed on when it is fulfilled by OpenBook V2
+    pub openbook_v2_market: Pubkey,
+
+    /// Venue the market's orders are placed on
+    pub fulfillment_type: SpotFulfillmentType,
+
+    /// Curve used to derive the borrow rate from the market's utilization
+    pub interest_rate_model: InterestRateModel,
+
+    pub padding: [u8; 2],
+
+    /// Floor of the borrow rate, whichever the interest rate model
+    /// precision: SPOT_RATE_PRECISION
+    pub min_borrow_rate: u32,
+
+    /// Kinks of the multi-kink interest rate curve, in increasing utilization. Zeroed kinks at the end are unused.
+    pub borrow_rate_kinks: [BorrowRateKink; MAX_BORROW_RATE_KINKS],
+
+    /// Share of the accrued borrow interest that is credited to `revenue_pool` instead of the depositors
+    /// precision: PERCENTAGE_PRECISION
+    pub interest_take_rate: u32,
+
+    pub padding2: [u8; 4],
+
+    /// The maximum amount of borrows of this market across all users. 0 means no cap.
+    /// precision: token mint
+    pub max_borrow_limit: u64,
+
+    /// The maximum amount a single user can borrow from this market. 0 means no cap.
+    /// precision: token mint
+    pub max_user_borrow_limit: u64,
+
+    /// Slot at which `historical_index_data` was last updated from the Phoenix book
+    pub last_index_update_slot: u64,
+}
+
+/// A point of the multi-kink interest rate curve
+#[zero_copy]
+#[repr(C)]
+#[derive(Default, Eq, PartialEq, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct BorrowRateKink {
+    /// precision: SPOT_UTILIZATION_PRECISION
+    pub utilization: u32,
+
+    /// Borrow rate at the kink's utilization
+    /// precision: SPOT_RATE_PRECISION
+    pub borrow_rate: u32,
+}
+
+impl BorrowRateKink {
+    /// Unused kinks are zeroed, a used kink always has a non-zero utilization
+    pub fn is_used(&self) -> bool {
+        self.utilization != 0
+    }
 }
 
 #[derive(Default, Eq, PartialEq, Debug)]
@@ -368,7 +460,25 @@ impl Default for Market {
             flash_loan_amount: 0,
             flash_loan_initial_token_amount: 0,
             total_swap_fee: 0,
-            padding: [0;56],
+            oracle_guard_rails: MarketOracleGuardRails::default(),
+            secondary_oracle: Pubkey::default(),
+            secondary_oracle_type: OracleType::default(),
+            oracle_source_policy: OracleSourcePolicy::default(),
+            oracle_quorum: 1,
+            use_phoenix_mid_oracle: false,
+            confidence_interval_multiplier: 0,
+            phoenix_market: Pubkey::default(),
+            openbook_v2_market: Pubkey::default(),
+            fulfillment_type: SpotFulfillmentType::default(),
+            interest_rate_model: InterestRateModel::default(),
+            padding: [0;2],
+            min_borrow_rate: 0,
+            borrow_rate_kinks: [BorrowRateKink::default(); MAX_BORROW_RATE_KINKS],
+            interest_take_rate: 0,
+            padding2: [0;4],
+            max_borrow_limit: 0,
+            max_user_borrow_limit: 0,
+            last_index_update_slot: 0,
             pnl_pool: PoolBalance::default(),
             unrealized_pnl_max_imbalance: 0,
             expiry_price: 0,
@@ -383,12 +493,12 @@ impl Default for Market {
 }
 
 impl Size for Market {
-    const SIZE: usize = 776;
+    const SIZE: usize = 920;
 }
 
 /// Offset for market index
 impl MarketIndexOffset for Market {
-    const MARKET_INDEX_OFFSET: usize = 684;
+    const MARKET_INDEX_OFFSET: usize = 628;
 }
 
 impl Market {
@@ -424,4 +534,46 @@ impl Market {
         )
     }
 
-}
\ No newline at end of file
+    /// Returns the oracle guard rails that apply to this market, i.e the market's overrides with the
+    /// protocol-wide defaults from `State` filling in any field that is not overridden.
+    pub fn get_oracle_guard_rails(&self, default_guard_rails: &OracleGuardRails) -> OracleGuardRails {
+        self.oracle_guard_rails.resolve(default_guard_rails)
+    }
+
+    /// Returns true if the market has a secondary oracle configured
+    pub fn has_secondary_oracle(&self) -> bool {
+        self.secondary_oracle != Pubkey::default()
+    }
+
+    /// Asset weight applied to deposits for the given margin requirement type
+    /// precision: SPOT_WEIGHT_PRECISION
+    pub fn get_asset_weight(&self, margin_requirement_type: MarginRequirementType) -> u32 {
+        match margin_requirement_type {
+            MarginRequirementType::Initial => self.initial_asset_weight,
+            MarginRequirementType::Maintenance => self.maintenance_asset_weight
+        }
+    }
+
+    /// Liability weight applied to borrows for the given margin requirement type
+    /// precision: SPOT_WEIGHT_PRECISION
+    pub fn get_liability_weight(&self, margin_requirement_type: MarginRequirementType) -> u32 {
+        match margin_requirement_type {
+            MarginRequirementType::Initial => self.initial_liability_weight,
+            MarginRequirementType::Maintenance => self.maintenance_liability_weight
+        }
+    }
+
+    /// Number of oracle sources configured for the market, including the primary oracle
+    pub fn number_of_oracle_sources(&self) -> u8 {
+        1 + self.has_secondary_oracle() as u8 + self.use_phoenix_mid_oracle as u8
+    }
+
+}
+
+/// `Market::SIZE` is the space allocated for a market account: the 8 byte account discriminator followed by the
+/// zero-copy struct, so it has to follow every change to the struct's layout
+#[test]
+fn market_size_matches_the_zero_copy_layout() {
+    assert_eq!(Market::SIZE, 8 + std::mem::size_of::<Market>());
+    assert_eq!(Market::MARKET_INDEX_OFFSET, 8 + std::mem::offset_of!(Market, market_index));
+}
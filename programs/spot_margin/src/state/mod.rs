@@ -1,4 +1,5 @@
 pub mod phoenix;
+pub mod openbook_v2;
 pub mod enums;
 pub mod oracle;
 pub mod market;
@@ -6,6 +7,7 @@ pub mod traits;
 pub mod config;
 pub mod guard_rails;
 pub mod user_position;
+pub mod user;
 pub mod types;
 pub mod order;
 pub mod helpers;
\ No newline at end of file
@@ -0,0 +1,949 @@
+use anchor_lang::prelude::{
+    borsh,
+    *
+};
+use anchor_spl::token::{
+    Token,
+    TokenAccount
+};
+use arrayref::array_ref;
+use bytemuck::{
+    cast_ref,
+    from_bytes,
+    try_from_bytes,
+    Pod
+};
+use std::{
+    cell::Ref,
+    mem::size_of
+};
+
+use crate::{
+    error::{
+        SpedXSpotResult,
+        ErrorCode
+    },
+    math::{
+        casting::Cast,
+        constants::{
+            PRICE_PRECISION,
+            QUOTE_PRECISION
+        },
+        safe_math::SafeMath,
+        safe_unwrap::SafeUnwrap
+    },
+    state::{
+        enums::PositionDirection,
+        phoenix::{
+            div_round,
+            is_limit_rounded_up,
+            is_payment_rounded_up
+        }
+    },
+    validate
+};
+
+pub mod openbook_v2_program {
+    anchor_lang::declare_id!("opnb2LAfJYbRMAHHvqjCwQxanZn7ReEHp1k81EohpZb");
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct OpenBookV2;
+
+impl Id for OpenBookV2 {
+    fn id() -> Pubkey {
+        openbook_v2_program::id()
+    }
+}
+
+/// Mirrors of the zero-copy accounts of the OpenBook V2 program, field for field as published in its IDL. The sizes
+/// exclude the 8 byte anchor discriminator. u128 fields are kept as little endian bytes, as u128 is 8 byte aligned on
+/// the chain but 16 byte aligned on the host.
+pub mod openbook_v2_accounts {
+    use anchor_lang::prelude::Pubkey;
+    use bytemuck::{
+        Pod,
+        Zeroable
+    };
+
+    /// Anchor discriminator of the `Market` account
+    pub const MARKET_DISCRIMINATOR: [u8; 8] = [219, 190, 213, 55, 0, 227, 198, 154];
+
+    /// Anchor discriminator of the `OpenOrdersAccount` account
+    pub const OPEN_ORDERS_ACCOUNT_DISCRIMINATOR: [u8; 8] = [255, 194, 78, 123, 16, 105, 208, 165];
+
+    /// Anchor discriminator of the `BookSide` account
+    pub const BOOK_SIDE_DISCRIMINATOR: [u8; 8] = [72, 44, 225, 141, 178, 130, 97, 57];
+
+    /// Number of nodes of an order tree
+    pub const MAX_ORDERTREE_NODES: usize = 1024;
+
+    /// Number of orders an open orders account can have on the book
+    pub const MAX_OPEN_ORDERS: usize = 24;
+
+    /// Tag of the order tree nodes that branch into two children
+    pub const INNER_NODE_TAG: u8 = 1;
+
+    /// Tag of the order tree nodes that hold an order
+    pub const LEAF_NODE_TAG: u8 = 2;
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct OracleConfig {
+        pub conf_filter: f64,
+        pub max_staleness_slots: i64,
+        pub reserved: [u8; 72]
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct Market {
+        pub bump: u8,
+        pub base_decimals: u8,
+        pub quote_decimals: u8,
+        pub padding1: [u8; 5],
+        pub market_authority: Pubkey,
+        pub time_expiry: i64,
+        pub collect_fee_admin: Pubkey,
+        /// Default pubkey if unset
+        pub open_orders_admin: Pubkey,
+        /// Default pubkey if unset
+        pub consume_events_admin: Pubkey,
+        /// Default pubkey if unset
+        pub close_market_admin: Pubkey,
+        pub name: [u8; 16],
+        pub bids: Pubkey,
+        pub asks: Pubkey,
+        pub event_heap: Pubkey,
+        /// Default pubkey if unset
+        pub oracle_a: Pubkey,
+        /// Default pubkey if unset
+        pub oracle_b: Pubkey,
+        pub oracle_config: OracleConfig,
+        pub quote_lot_size: i64,
+        pub base_lot_size: i64,
+        pub seq_num: u64,
+        pub registration_time: i64,
+        pub maker_fee: i64,
+        pub taker_fee: i64,
+        pub fees_accrued: [u8; 16],
+        pub fees_to_referrers: [u8; 16],
+        pub referrer_rebates_accrued: u64,
+        pub fees_available: u64,
+        pub maker_volume: [u8; 16],
+        pub taker_volume_wo_oo: [u8; 16],
+        pub base_mint: Pubkey,
+        pub quote_mint: Pubkey,
+        pub market_base_vault: Pubkey,
+        pub base_deposit_total: u64,
+        pub market_quote_vault: Pubkey,
+        pub quote_deposit_total: u64,
+        pub reserved: [u8; 128]
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct Position {
+        pub bids_base_lots: i64,
+        pub asks_base_lots: i64,
+        pub base_free_native: u64,
+        pub quote_free_native: u64,
+        pub locked_maker_fees: u64,
+        pub referrer_rebates_available: u64,
+        pub penalty_heap_count: u64,
+        pub maker_volume: [u8; 16],
+        pub taker_volume: [u8; 16],
+        pub bids_quote_lots: i64,
+        pub reserved: [u8; 64]
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct OpenOrder {
+        pub id: [u8; 16],
+        pub client_id: u64,
+        pub locked_price: i64,
+        pub is_free: u8,
+        pub side_and_tree: u8,
+        pub padding: [u8; 6]
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct OpenOrdersAccount {
+        pub owner: Pubkey,
+        pub market: Pubkey,
+        pub name: [u8; 32],
+        /// Default pubkey if unset
+        pub delegate: Pubkey,
+        pub account_num: u32,
+        pub bump: u8,
+        pub version: u8,
+        pub padding: [u8; 2],
+        pub position: Position,
+        pub open_orders: [OpenOrder; MAX_OPEN_ORDERS]
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct OrderTreeRoot {
+        /// Handle of the top node, meaningless if the tree has no leaves
+        pub maybe_node: u32,
+        pub leaf_count: u32
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct AnyNode {
+        pub tag: u8,
+        pub data: [u8; 79],
+        pub force_align: u64
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct InnerNode {
+        pub tag: u8,
+        pub padding: [u8; 3],
+        pub prefix_len: u32,
+        pub key: [u8; 16],
+        pub children: [u32; 2],
+        pub child_earliest_expiry: [u64; 2],
+        pub reserved: [u8; 40]
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct LeafNode {
+        pub tag: u8,
+        pub owner_slot: u8,
+        /// Seconds after the timestamp from which the order is no longer matched, 0 if it does not expire
+        pub time_in_force: u16,
+        pub padding: [u8; 4],
+        /// Order id: price in lots in the upper 64 bits, sequence number in the lower 64 bits
+        pub key: [u8; 16],
+        /// Open orders account that placed the order
+        pub owner: Pubkey,
+        pub quantity: i64,
+        pub timestamp: u64,
+        pub peg_limit: i64,
+        pub client_order_id: u64
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct OrderTreeNodes {
+        pub order_tree_type: u8,
+        pub padding: [u8; 3],
+        pub bump_index: u32,
+        pub free_list_len: u32,
+        pub free_list_head: u32,
+        pub reserved: [u8; 512],
+        pub nodes: [AnyNode; MAX_ORDERTREE_NODES]
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct BookSide {
+        /// Roots of the fixed price and the oracle pegged order trees
+        pub roots: [OrderTreeRoot; 2],
+        pub reserved_roots: [OrderTreeRoot; 4],
+        pub reserved: [u8; 256],
+        pub nodes: OrderTreeNodes
+    }
+
+    impl LeafNode {
+        pub fn key(&self) -> u128 {
+            u128::from_le_bytes(self.key)
+        }
+    }
+
+    #[cfg(target_endian="little")]
+    unsafe impl Pod for OracleConfig {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Zeroable for OracleConfig {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Pod for Market {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Zeroable for Market {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Pod for Position {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Zeroable for Position {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Pod for OpenOrder {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Zeroable for OpenOrder {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Pod for OpenOrdersAccount {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Zeroable for OpenOrdersAccount {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Pod for OrderTreeRoot {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Zeroable for OrderTreeRoot {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Pod for AnyNode {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Zeroable for AnyNode {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Pod for InnerNode {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Zeroable for InnerNode {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Pod for LeafNode {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Zeroable for LeafNode {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Pod for OrderTreeNodes {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Zeroable for OrderTreeNodes {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Pod for BookSide {}
+
+    #[cfg(target_endian="little")]
+    unsafe impl Zeroable for BookSide {}
+}
+
+/// Accounts required to place, cancel and settle orders on an OpenBook V2 market through the protocol's signer
+#[derive(Accounts)]
+pub struct OpenBookV2MarketAccounts<'info> {
+    pub openbook_v2_program: Program<'info, OpenBookV2>,
+
+    /// CHECK: Checked in load_openbook_v2_market and against the market's OpenBook V2 market
+    #[account(mut)]
+    pub openbook_v2_market: UncheckedAccount<'info>,
+
+    /// CHECK: PDA of the OpenBook V2 market that owns its vaults, checked by the OpenBook V2 program
+    pub openbook_v2_market_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Checked against the OpenBook V2 market's bids
+    #[account(mut)]
+    pub bids: UncheckedAccount<'info>,
+
+    /// CHECK: Checked against the OpenBook V2 market's asks
+    #[account(mut)]
+    pub asks: UncheckedAccount<'info>,
+
+    /// CHECK: Checked against the OpenBook V2 market's event heap
+    #[account(mut)]
+    pub event_heap: UncheckedAccount<'info>,
+
+    /// CHECK: Open orders account of the signer on the OpenBook V2 market, checked in load_openbook_v2_open_orders_account
+    #[account(mut)]
+    pub open_orders_account: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol PDA that trades on OpenBook V2 on behalf of users, checked against the state's signer. It pays
+    /// the event heap penalty when settling funds.
+    #[account(mut)]
+    pub signer: UncheckedAccount<'info>,
+
+    /// Protocol token account of the OpenBook V2 market's base mint, owned by the signer
+    #[account(mut, token::authority = signer)]
+    pub base_account: Box<Account<'info, TokenAccount>>,
+
+    /// Protocol token account of the OpenBook V2 market's quote mint, owned by the signer
+    #[account(mut, token::authority = signer)]
+    pub quote_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: Checked against the OpenBook V2 market's base vault
+    #[account(mut)]
+    pub openbook_v2_base_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Checked against the OpenBook V2 market's quote vault
+    #[account(mut)]
+    pub openbook_v2_quote_vault: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Fields of an OpenBook V2 market the protocol reads
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct OpenBookV2Market {
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+    pub market_authority: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub event_heap: Pubkey,
+    /// Quote atoms per quote lot
+    pub quote_lot_size: u64,
+    /// Base atoms per base lot
+    pub base_lot_size: u64,
+    /// Sequence number assigned to the next order placed on the market
+    pub seq_num: u64,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub market_base_vault: Pubkey,
+    pub market_quote_vault: Pubkey
+}
+
+/// Side of an order on OpenBook V2
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenBookV2Side {
+    Bid,
+    Ask
+}
+
+/// Order types of OpenBook V2's place_order instruction
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenBookV2PlaceOrderType {
+    Limit,
+    ImmediateOrCancel,
+    PostOnly,
+    Market,
+    PostOnlySlide,
+    FillOrKill
+}
+
+/// What OpenBook V2 does when an order would match an order of the same open orders account
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenBookV2SelfTradeBehavior {
+    DecrementTake,
+    CancelProvide,
+    AbortTransaction
+}
+
+/// Arguments of OpenBook V2's place_order instruction
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpenBookV2PlaceOrderArgs {
+    pub side: OpenBookV2Side,
+    pub price_lots: i64,
+    pub max_base_lots: i64,
+    pub max_quote_lots_including_fees: i64,
+    pub client_order_id: u64,
+    pub order_type: OpenBookV2PlaceOrderType,
+    pub expiry_timestamp: u64,
+    pub self_trade_behavior: OpenBookV2SelfTradeBehavior,
+    pub limit: u8
+}
+
+/// Checks the owner, discriminator and size of an account of the OpenBook V2 program and returns its data as the given
+/// account type, in place
+fn load_openbook_v2_account<'a, T: Pod>(
+    account_info: &'a AccountInfo,
+    discriminator: &[u8; 8]
+) -> SpedXSpotResult<Ref<'a, T>> {
+    if account_info.owner != &openbook_v2_program::id() {
+        msg!("Account {} is not owned by the OpenBook V2 program", account_info.key());
+        return Err(ErrorCode::FailedToDeserializeOpenBookV2Account)
+    }
+
+    let data = account_info.data.borrow();
+    let account_end = size_of::<T>().checked_add(8).safe_unwrap()?;
+
+    if data.len() < account_end || array_ref![data, 0, 8] != discriminator {
+        msg!("Account {} is not the expected OpenBook V2 account", account_info.key());
+        return Err(ErrorCode::FailedToDeserializeOpenBookV2Account)
+    }
+
+    try_from_bytes::<T>(&data[8..account_end]).map_err(|_| {
+        msg!("Failed to deserialize OpenBook V2 account {}", account_info.key());
+        ErrorCode::FailedToDeserializeOpenBookV2Account
+    })?;
+
+    Ok(Ref::map(data, |data| from_bytes::<T>(&data[8..account_end])))
+}
+
+/// Loads the fields of an OpenBook V2 market account the protocol reads
+pub fn load_openbook_v2_market(account_info: &AccountInfo) -> SpedXSpotResult<OpenBookV2Market> {
+    let market = load_openbook_v2_account::<openbook_v2_accounts::Market>(
+        account_info,
+        &openbook_v2_accounts::MARKET_DISCRIMINATOR
+    )?;
+
+    Ok(OpenBookV2Market {
+        base_decimals: market.base_decimals,
+        quote_decimals: market.quote_decimals,
+        market_authority: market.market_authority,
+        bids: market.bids,
+        asks: market.asks,
+        event_heap: market.event_heap,
+        quote_lot_size: market.quote_lot_size.cast()?,
+        base_lot_size: market.base_lot_size.cast()?,
+        seq_num: market.seq_num,
+        base_mint: market.base_mint,
+        quote_mint: market.quote_mint,
+        market_base_vault: market.market_base_vault,
+        market_quote_vault: market.market_quote_vault
+    })
+}
+
+/// Loads an open orders account, failing if it does not belong to the owner and the market
+pub fn load_openbook_v2_open_orders_account<'a>(
+    account_info: &'a AccountInfo,
+    owner: &Pubkey,
+    openbook_v2_market: &Pubkey
+) -> SpedXSpotResult<Ref<'a, openbook_v2_accounts::OpenOrdersAccount>> {
+    let open_orders_account = load_openbook_v2_account::<openbook_v2_accounts::OpenOrdersAccount>(
+        account_info,
+        &openbook_v2_accounts::OPEN_ORDERS_ACCOUNT_DISCRIMINATOR
+    )?;
+
+    if open_orders_account.owner != *owner || open_orders_account.market != *openbook_v2_market {
+        msg!("Open orders account {} does not belong to {} on market {}", account_info.key(), owner, openbook_v2_market);
+        return Err(ErrorCode::InvalidOpenBookV2Market)
+    }
+
+    Ok(open_orders_account)
+}
+
+/// Returns the base and quote atoms an open orders account holds on the market: its free funds, i.e filled or released
+/// but not yet settled to its owner's token accounts, plus the funds locked in its resting orders, maker fees included
+pub fn get_openbook_v2_open_orders_atoms(
+    open_orders_account: &openbook_v2_accounts::OpenOrdersAccount,
+    openbook_v2_market: &OpenBookV2Market
+) -> SpedXSpotResult<(u64, u64)> {
+    let position = &open_orders_account.position;
+
+    let base_atoms_locked = position.asks_base_lots.cast::<u64>()?.safe_mul(openbook_v2_market.base_lot_size)?;
+    let quote_atoms_locked = position
+        .bids_quote_lots
+        .cast::<u64>()?
+        .safe_mul(openbook_v2_market.quote_lot_size)?
+        .safe_add(position.locked_maker_fees)?;
+
+    Ok((
+        position.base_free_native.safe_add(base_atoms_locked)?,
+        position.quote_free_native.safe_add(quote_atoms_locked)?
+    ))
+}
+
+/// Returns the OpenBook V2 order id of an order placed with the given sequence number: the price in lots in the upper
+/// 64 bits and the sequence number, inverted for bids so that older bids sort first, in the lower 64 bits
+pub fn get_openbook_v2_order_id(side: OpenBookV2Side, price_lots: u64, seq_num: u64) -> u128 {
+    let lower = match side {
+        OpenBookV2Side::Bid => !seq_num,
+        OpenBookV2Side::Ask => seq_num
+    };
+
+    ((price_lots as u128) << 64) | lower as u128
+}
+
+/// Returns the number of base lots still resting on an OpenBook V2 book side for an order, 0 if the order is no longer on
+/// the book
+pub fn get_openbook_v2_resting_order_base_lots(
+    account_info: &AccountInfo,
+    order_id: u128
+) -> SpedXSpotResult<u64> {
+    let book_side = load_openbook_v2_book_side(account_info)?;
+
+    // the order is found by its key among the leaves, the tree itself does not need to be walked
+    let resting_order = book_side
+        .nodes
+        .nodes
+        .iter()
+        .filter(|node| node.tag == openbook_v2_accounts::LEAF_NODE_TAG)
+        .map(cast_ref::<openbook_v2_accounts::AnyNode, openbook_v2_accounts::LeafNode>)
+        .find(|leaf| leaf.key() == order_id);
+
+    match resting_order {
+        Some(leaf) => leaf.quantity.cast(),
+        None => Ok(0)
+    }
+}
+
+/// An order resting on an OpenBook V2 book side
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpenBookV2RestingOrder {
+    /// Price in lots in the upper 64 bits, sequence number in the lower 64 bits
+    pub order_id: u128,
+    /// Open orders account that placed the order
+    pub owner: Pubkey,
+    pub base_lots: u64,
+    /// Unix timestamp from which OpenBook V2 no longer matches the order, 0 if it does not expire
+    pub expiry_timestamp: u64
+}
+
+impl OpenBookV2RestingOrder {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expiry_timestamp != 0 && now >= self.expiry_timestamp
+    }
+}
+
+/// Returns the fixed price orders resting on an OpenBook V2 book side, in no particular order. Oracle pegged orders rest
+/// in a separate tree of the book side and are not returned.
+pub fn get_openbook_v2_fixed_orders(account_info: &AccountInfo) -> SpedXSpotResult<Vec<OpenBookV2RestingOrder>> {
+    let book_side = load_openbook_v2_book_side(account_info)?;
+
+    get_openbook_v2_tree_orders(&book_side, 0)
+}
+
+fn load_openbook_v2_book_side<'a>(
+    account_info: &'a AccountInfo
+) -> SpedXSpotResult<Ref<'a, openbook_v2_accounts::BookSide>> {
+    load_openbook_v2_account(account_info, &openbook_v2_accounts::BOOK_SIDE_DISCRIMINATOR)
+}
+
+/// Walks the order tree whose root is at the given index of a book side's roots and returns the orders of its leaves
+fn get_openbook_v2_tree_orders(
+    book_side: &openbook_v2_accounts::BookSide,
+    root_index: usize
+) -> SpedXSpotResult<Vec<OpenBookV2RestingOrder>> {
+    let root = book_side.roots.get(root_index).safe_unwrap()?;
+    let leaf_count: usize = root.leaf_count.cast()?;
+
+    let mut orders = Vec::with_capacity(leaf_count);
+
+    if leaf_count == 0 {
+        return Ok(orders)
+    }
+
+    let mut node_handles = vec![root.maybe_node];
+    let mut number_of_nodes_visited: usize = 0;
+
+    while let Some(node_handle) = node_handles.pop() {
+        // a tree with leaf_count leaves has leaf_count - 1 inner nodes, anything beyond that is a cycle
+        number_of_nodes_visited = number_of_nodes_visited.checked_add(1).safe_unwrap()?;
+
+        validate!(
+            number_of_nodes_visited < leaf_count.checked_mul(2).safe_unwrap()?,
+            ErrorCode::FailedToDeserializeOpenBookV2Account,
+            "OpenBook V2 order tree has more nodes than its {} leaves allow",
+            leaf_count
+        )?;
+
+        let node = book_side.nodes.nodes.get(node_handle.cast::<usize>()?).safe_unwrap()?;
+
+        match node.tag {
+            openbook_v2_accounts::INNER_NODE_TAG => {
+                let inner = cast_ref::<openbook_v2_accounts::AnyNode, openbook_v2_accounts::InnerNode>(node);
+                node_handles.extend_from_slice(&inner.children);
+            },
+            openbook_v2_accounts::LEAF_NODE_TAG => {
+                let leaf = cast_ref::<openbook_v2_accounts::AnyNode, openbook_v2_accounts::LeafNode>(node);
+                orders.push(OpenBookV2RestingOrder {
+                    order_id: leaf.key(),
+                    owner: leaf.owner,
+                    base_lots: leaf.quantity.cast()?,
+                    expiry_timestamp: match leaf.time_in_force {
+                        0 => 0,
+                        time_in_force => leaf.timestamp.safe_add(time_in_force.cast()?)?
+                    }
+                })
+            },
+            tag => {
+                msg!("Unexpected node tag {} in OpenBook V2 order tree", tag);
+                return Err(ErrorCode::FailedToDeserializeOpenBookV2Account)
+            }
+        }
+    }
+
+    Ok(orders)
+}
+
+/// Converts a limit price to OpenBook V2 price lots. Bids round down and asks round up to the nearest lot.
+/// price precision: PRICE_PRECISION
+pub fn get_openbook_v2_price_lots(
+    price: u64,
+    direction: PositionDirection,
+    openbook_v2_market: &OpenBookV2Market
+) -> SpedXSpotResult<u64> {
+    // quote lots per base lot = price * 10^quote decimals * base lot size / (PRICE_PRECISION * 10^base decimals * quote lot size)
+    let numerator = price
+        .cast::<u128>()?
+        .safe_mul(10_u128.checked_pow(openbook_v2_market.quote_decimals.cast()?).safe_unwrap()?)?
+        .safe_mul(openbook_v2_market.base_lot_size.cast()?)?;
+
+    let denominator = PRICE_PRECISION
+        .safe_mul(10_u128.checked_pow(openbook_v2_market.base_decimals.cast()?).safe_unwrap()?)?
+        .safe_mul(openbook_v2_market.quote_lot_size.cast()?)?;
+
+    div_round(numerator, denominator, is_limit_rounded_up(direction)?)?.cast()
+}
+
+/// Converts OpenBook V2 price lots to a limit price. Bids round down and asks round up to the nearest price unit.
+/// price precision: PRICE_PRECISION
+pub fn get_price_from_openbook_v2_price_lots(
+    price_lots: u64,
+    direction: PositionDirection,
+    openbook_v2_market: &OpenBookV2Market
+) -> SpedXSpotResult<u64> {
+    let numerator = price_lots
+        .cast::<u128>()?
+        .safe_mul(PRICE_PRECISION)?
+        .safe_mul(10_u128.checked_pow(openbook_v2_market.base_decimals.cast()?).safe_unwrap()?)?
+        .safe_mul(openbook_v2_market.quote_lot_size.cast()?)?;
+
+    let denominator = 10_u128
+        .checked_pow(openbook_v2_market.quote_decimals.cast()?)
+        .safe_unwrap()?
+        .safe_mul(openbook_v2_market.base_lot_size.cast()?)?;
+
+    div_round(numerator, denominator, is_limit_rounded_up(direction)?)?.cast()
+}
+
+/// Converts an amount of the OpenBook V2 market's quote token to a quote asset amount. The quote the user pays rounds up
+/// and the quote the user receives rounds down.
+/// quote asset amount precision: QUOTE_PRECISION
+pub fn get_quote_asset_amount_from_openbook_v2_atoms(
+    quote_atoms: u64,
+    direction: PositionDirection,
+    openbook_v2_market: &OpenBookV2Market
+) -> SpedXSpotResult<u64> {
+    div_round(
+        quote_atoms.cast::<u128>()?.safe_mul(QUOTE_PRECISION)?,
+        10_u128.checked_pow(openbook_v2_market.quote_decimals.cast()?).safe_unwrap()?,
+        is_payment_rounded_up(direction)?
+    )?
+    .cast()
+}
+
+/// Converts a quote asset amount to OpenBook V2 quote lots. The quote the user pays rounds up and the quote the user
+/// receives rounds down.
+/// quote asset amount precision: QUOTE_PRECISION
+pub fn get_openbook_v2_quote_lots(
+    quote_asset_amount: u64,
+    direction: PositionDirection,
+    openbook_v2_market: &OpenBookV2Market
+) -> SpedXSpotResult<u64> {
+    let numerator = quote_asset_amount
+        .cast::<u128>()?
+        .safe_mul(10_u128.checked_pow(openbook_v2_market.quote_decimals.cast()?).safe_unwrap()?)?;
+
+    let denominator = QUOTE_PRECISION.safe_mul(openbook_v2_market.quote_lot_size.cast()?)?;
+
+    div_round(numerator, denominator, is_payment_rounded_up(direction)?)?.cast()
+}
+
+/// Value of a fill of the given base asset amount at a price in lots. The quote the user pays rounds up and the quote
+/// the user receives rounds down.
+/// base asset amount precision: token mint precision, quote asset amount precision: QUOTE_PRECISION
+pub fn get_openbook_v2_fill_quote_asset_amount(
+    base_atoms: u64,
+    price_lots: u64,
+    direction: PositionDirection,
+    openbook_v2_market: &OpenBookV2Market
+) -> SpedXSpotResult<u64> {
+    // quote atoms = base atoms / base lot size * price lots * quote lot size
+    let numerator = base_atoms
+        .cast::<u128>()?
+        .safe_mul(price_lots.cast()?)?
+        .safe_mul(openbook_v2_market.quote_lot_size.cast()?)?
+        .safe_mul(QUOTE_PRECISION)?;
+
+    let denominator = openbook_v2_market
+        .base_lot_size
+        .cast::<u128>()?
+        .safe_mul(10_u128.checked_pow(openbook_v2_market.quote_decimals.cast()?).safe_unwrap()?)?;
+
+    div_round(numerator, denominator, is_payment_rounded_up(direction)?)?.cast()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytemuck::Zeroable;
+    use crate::state::helpers::create_account_info;
+
+    fn get_sol_usdc_market() -> OpenBookV2Market {
+        OpenBookV2Market {
+            base_decimals: 9,
+            quote_decimals: 6,
+            base_lot_size: 1_000_000,
+            quote_lot_size: 1,
+            ..OpenBookV2Market::default()
+        }
+    }
+
+    #[test]
+    fn price_lots() {
+        let openbook_v2_market = get_sol_usdc_market();
+
+        // a lot of 0.001 SOL at $100 costs 100_000 quote atoms, i.e 100_000 quote lots
+        assert_eq!(get_openbook_v2_price_lots(100_000_000, PositionDirection::Long, &openbook_v2_market).unwrap(), 100_000);
+        assert_eq!(get_price_from_openbook_v2_price_lots(100_000, PositionDirection::Long, &openbook_v2_market).unwrap(), 100_000_000);
+
+        // bids are rounded down and asks up to the nearest price lot
+        assert_eq!(get_openbook_v2_price_lots(100_000_999, PositionDirection::Long, &openbook_v2_market).unwrap(), 100_000);
+        assert_eq!(get_openbook_v2_price_lots(100_000_999, PositionDirection::Short, &openbook_v2_market).unwrap(), 100_001);
+        assert_eq!(get_openbook_v2_price_lots(999, PositionDirection::Long, &openbook_v2_market).unwrap(), 0);
+        assert_eq!(get_openbook_v2_price_lots(999, PositionDirection::Short, &openbook_v2_market).unwrap(), 1);
+
+        assert_eq!(get_quote_asset_amount_from_openbook_v2_atoms(1_500_000, PositionDirection::Long, &openbook_v2_market).unwrap(), 1_500_000);
+        assert_eq!(get_openbook_v2_quote_lots(1_500_000, PositionDirection::Long, &openbook_v2_market).unwrap(), 1_500_000);
+    }
+
+    #[test]
+    fn fill_quote_asset_amount() {
+        let openbook_v2_market = get_sol_usdc_market();
+
+        // 2 lots at 100_000 quote lots each
+        assert_eq!(get_openbook_v2_fill_quote_asset_amount(2_000_000, 100_000, PositionDirection::Long, &openbook_v2_market).unwrap(), 200_000);
+
+        // 1.5 lots at 100_001 is 150_001.5 quote atoms, the buyer pays the half atom and the seller does not receive it
+        assert_eq!(get_openbook_v2_fill_quote_asset_amount(1_500_000, 100_001, PositionDirection::Long, &openbook_v2_market).unwrap(), 150_002);
+        assert_eq!(get_openbook_v2_fill_quote_asset_amount(1_500_000, 100_001, PositionDirection::Short, &openbook_v2_market).unwrap(), 150_001);
+    }
+
+    #[test]
+    fn account_layouts_match_the_idl() {
+        use openbook_v2_accounts::*;
+
+        // sizes of the accounts without their discriminator, as allocated by the OpenBook V2 program
+        assert_eq!(size_of::<Market>(), 840);
+        assert_eq!(size_of::<BookSide>(), 90_944);
+        assert_eq!(size_of::<OpenOrdersAccount>(), 1_256);
+        assert_eq!(size_of::<AnyNode>(), 88);
+        assert_eq!(size_of::<InnerNode>(), 88);
+        assert_eq!(size_of::<LeafNode>(), 88);
+
+        assert_eq!(std::mem::offset_of!(Market, bids), 192);
+        assert_eq!(std::mem::offset_of!(Market, quote_lot_size), 440);
+        assert_eq!(std::mem::offset_of!(Market, seq_num), 456);
+        assert_eq!(std::mem::offset_of!(Market, market_quote_vault), 672);
+        assert_eq!(std::mem::offset_of!(OpenOrdersAccount, position), 136);
+        assert_eq!(std::mem::offset_of!(Position, bids_quote_lots), 88);
+        assert_eq!(std::mem::offset_of!(BookSide, nodes) + std::mem::offset_of!(OrderTreeNodes, nodes), 832);
+        assert_eq!(std::mem::offset_of!(LeafNode, owner), 24);
+        assert_eq!(std::mem::offset_of!(InnerNode, children), 24);
+    }
+
+    #[test]
+    fn market_is_loaded_in_place() {
+        let market = openbook_v2_accounts::Market {
+            base_decimals: 9,
+            quote_decimals: 6,
+            bids: Pubkey::new_unique(),
+            quote_lot_size: 1,
+            base_lot_size: 1_000_000,
+            seq_num: 42,
+            market_quote_vault: Pubkey::new_unique(),
+            ..Zeroable::zeroed()
+        };
+
+        // account data is 8 byte aligned on the chain
+        let mut data = vec![0_u64; (8 + size_of::<openbook_v2_accounts::Market>()) / 8];
+        let bytes = bytemuck::cast_slice_mut::<u64, u8>(&mut data);
+        bytes[..8].copy_from_slice(&openbook_v2_accounts::MARKET_DISCRIMINATOR);
+        bytes[8..].copy_from_slice(bytemuck::bytes_of(&market));
+
+        let key = Pubkey::new_unique();
+        let owner = openbook_v2_program::id();
+        let mut lamports = 0;
+        let account_info = create_account_info(&key, true, &mut lamports, bytes, &owner);
+
+        assert_eq!(
+            load_openbook_v2_market(&account_info).unwrap(),
+            OpenBookV2Market {
+                base_decimals: 9,
+                quote_decimals: 6,
+                bids: market.bids,
+                quote_lot_size: 1,
+                base_lot_size: 1_000_000,
+                seq_num: 42,
+                market_quote_vault: market.market_quote_vault,
+                ..OpenBookV2Market::default()
+            }
+        );
+
+        // the account is not read as a book side
+        assert!(load_openbook_v2_book_side(&account_info).is_err());
+
+        // nor as a market if another program owns it
+        let other_owner = Pubkey::new_unique();
+        let mut lamports = 0;
+        let account_info = create_account_info(&key, true, &mut lamports, bytemuck::cast_slice_mut(&mut data), &other_owner);
+        assert_eq!(load_openbook_v2_market(&account_info), Err(ErrorCode::FailedToDeserializeOpenBookV2Account));
+    }
+
+    fn inner_node(children: [u32; 2]) -> openbook_v2_accounts::AnyNode {
+        bytemuck::cast(openbook_v2_accounts::InnerNode {
+            tag: openbook_v2_accounts::INNER_NODE_TAG,
+            children,
+            ..Zeroable::zeroed()
+        })
+    }
+
+    fn leaf_node(order: &OpenBookV2RestingOrder) -> openbook_v2_accounts::AnyNode {
+        let (time_in_force, timestamp) = match order.expiry_timestamp {
+            0 => (0, 0),
+            expiry_timestamp => (10, expiry_timestamp - 10)
+        };
+
+        bytemuck::cast(openbook_v2_accounts::LeafNode {
+            tag: openbook_v2_accounts::LEAF_NODE_TAG,
+            time_in_force,
+            key: order.order_id.to_le_bytes(),
+            owner: order.owner,
+            quantity: order.base_lots as i64,
+            timestamp,
+            ..Zeroable::zeroed()
+        })
+    }
+
+    #[test]
+    fn tree_orders() {
+        // a book side is too large for the stack
+        let mut data = vec![0_u64; size_of::<openbook_v2_accounts::BookSide>() / 8];
+        let book_side = bytemuck::from_bytes_mut::<openbook_v2_accounts::BookSide>(bytemuck::cast_slice_mut(&mut data));
+
+        assert_eq!(get_openbook_v2_tree_orders(book_side, 0).unwrap(), vec![]);
+
+        let orders = [
+            OpenBookV2RestingOrder { order_id: get_openbook_v2_order_id(OpenBookV2Side::Ask, 100, 1), owner: Pubkey::new_unique(), base_lots: 5, expiry_timestamp: 0 },
+            OpenBookV2RestingOrder { order_id: get_openbook_v2_order_id(OpenBookV2Side::Ask, 101, 2), owner: Pubkey::new_unique(), base_lots: 7, expiry_timestamp: 0 },
+            OpenBookV2RestingOrder { order_id: get_openbook_v2_order_id(OpenBookV2Side::Ask, 102, 3), owner: Pubkey::new_unique(), base_lots: 9, expiry_timestamp: 1_000 },
+        ];
+
+        // 0 -> (1, 2 -> (3, 4))
+        book_side.nodes.nodes[0] = inner_node([1, 2]);
+        book_side.nodes.nodes[1] = leaf_node(&orders[0]);
+        book_side.nodes.nodes[2] = inner_node([3, 4]);
+        book_side.nodes.nodes[3] = leaf_node(&orders[1]);
+        book_side.nodes.nodes[4] = leaf_node(&orders[2]);
+        book_side.roots[0].leaf_count = 3;
+
+        let mut tree_orders = get_openbook_v2_tree_orders(book_side, 0).unwrap();
+        tree_orders.sort_by_key(|order| order.order_id);
+        assert_eq!(tree_orders, orders.to_vec());
+
+        // the expiry is the order's timestamp plus its time in force
+        assert!(!tree_orders[2].is_expired(999));
+        assert!(tree_orders[2].is_expired(1_000));
+        assert!(!tree_orders[0].is_expired(u64::MAX));
+
+        // the oracle pegged tree is empty
+        assert_eq!(get_openbook_v2_tree_orders(book_side, 1).unwrap(), vec![]);
+
+        // a cycle is rejected instead of walked forever
+        book_side.nodes.nodes[2] = inner_node([0, 4]);
+        assert!(get_openbook_v2_tree_orders(book_side, 0).is_err());
+
+        // so is a node that is not part of a tree
+        book_side.nodes.nodes[2] = inner_node([3, 4]);
+        book_side.nodes.nodes[4].tag = 3;
+        assert!(get_openbook_v2_tree_orders(book_side, 0).is_err());
+    }
+
+    #[test]
+    fn order_id() {
+        // the price is in the upper half, the sequence number of bids is inverted so that older bids sort first
+        assert_eq!(get_openbook_v2_order_id(OpenBookV2Side::Ask, 5, 7), (5_u128 << 64) | 7);
+        assert_eq!(get_openbook_v2_order_id(OpenBookV2Side::Bid, 5, 7), (5_u128 << 64) | !7_u64 as u128);
+
+        assert!(get_openbook_v2_order_id(OpenBookV2Side::Bid, 5, 7) > get_openbook_v2_order_id(OpenBookV2Side::Bid, 5, 8));
+    }
+}
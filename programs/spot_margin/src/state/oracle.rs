@@ -10,23 +10,35 @@ use crate::{
     math::{
         casting::Cast,
         constants::{
+            PERCENTAGE_PRECISION,
             PRICE_PRECISION,
             PRICE_PRECISION_I64,
             PRICE_PRECISION_U64,
             STALENESS_THRESHOLD,
             TEN_BPS_I64
         },
-        safe_math::SafeMath
+        safe_math::SafeMath,
+        oracle_validity::oracle_validity
     },
     state::{
-        enums::OracleType,
+        enums::{
+            OracleType,
+            OracleSourcePolicy,
+            OracleValidity
+        },
+        guard_rails::{
+            OracleGuardRails,
+            ValidityGuardRails
+        },
+        market::Market,
         helpers::{
             get_test_pyth_price,
             get_account_bytes,
             create_account_info
         }
     },
-    create_account_info
+    create_account_info,
+    validate
 };
 use bytes::BytesMut;
 
@@ -174,16 +186,25 @@ pub fn get_pyth_price(
     clock_slot: u64,
     multiple: u128
 ) -> SpedXSpotResult<OraclePriceData> {
-    // fetching price feed from pyth
-    let price_feed = pyth_sdk_solana::load_price_feed_from_account_info(price_oracle).unwrap();
+    // fetching price feed from pyth. We return errors instead of panicking, so that an unavailable feed can be
+    // skipped by the oracle fallback chain instead of aborting the transaction.
+    let price_feed = pyth_sdk_solana::load_price_feed_from_account_info(price_oracle).map_err(|_| {
+        msg!("Unable to load pyth price feed {}", price_oracle.key);
+        ErrorCode::UnableToLoadOracle
+    })?;
 
     // getting current timestamp using solana_program::Clock
-    let curr_timestamp = Clock::get().unwrap().unix_timestamp;
+    let curr_timestamp = Clock::get().map_err(|_| ErrorCode::UnableToCastUnixTimestamp)?.unix_timestamp;
 
     // getting current price from the price feed, and using a staleness parameter to it.
-    let curr_price = price_feed.get_price_no_older_than(curr_timestamp, STALENESS_THRESHOLD).unwrap();
+    let curr_price = price_feed.get_price_no_older_than(curr_timestamp, STALENESS_THRESHOLD).ok_or_else(|| {
+        msg!("Pyth price feed {} is older than {} seconds", price_oracle.key, STALENESS_THRESHOLD);
+        ErrorCode::UnableToLoadOracle
+    })?;
 
-    let curr_price_ema = price_feed.get_ema_price_no_older_than(curr_timestamp, STALENESS_THRESHOLD).unwrap();
+    let curr_price_ema = price_feed
+        .get_ema_price_no_older_than(curr_timestamp, STALENESS_THRESHOLD)
+        .ok_or(ErrorCode::UnableToLoadOracle)?;
 
     msg!("Current price: {}, Current price EMA: {}, Current confidence interval: {}", curr_price.price, curr_price_ema.price, curr_price.conf);
 
@@ -286,6 +307,262 @@ pub fn get_oracle_price(
     }
 }
 
+/// Builds oracle price data from the Phoenix mid price stored in the market's `HistoricalIndexData`.
+/// The confidence is half the bid-ask spread, and the delay is the number of slots since the index data was last
+/// updated, like the delay of the other oracle sources.
+/// Returns None if the index data has never been updated or the stored book is crossed.
+pub fn get_phoenix_mid_price_data(
+    historical_index_data: &HistoricalIndexData,
+    last_update_slot: u64,
+    clock_slot: u64
+) -> SpedXSpotResult<Option<OraclePriceData>> {
+    let HistoricalIndexData {
+        last_index_bid_price: bid_price,
+        last_index_ask_price: ask_price,
+        ..
+    } = *historical_index_data;
+
+    if bid_price == 0 || ask_price < bid_price || last_update_slot == 0 {
+        return Ok(None)
+    }
+
+    let mid_price = bid_price.safe_add(ask_price)?.safe_div(2)?;
+
+    Ok(Some(OraclePriceData {
+        price: mid_price.cast()?,
+        confidence: ask_price.safe_sub(bid_price)?.safe_div(2)?,
+        delay: clock_slot.saturating_sub(last_update_slot).cast()?,
+        has_sufficient_data_points: true,
+    }))
+}
+
+/// A source is usable for combining if it is not invalid, uncertain or stale.
+fn is_usable_oracle_source(
+    source: &OraclePriceData,
+    last_oracle_twap: i64,
+    validity_guard_rails: &ValidityGuardRails
+) -> SpedXSpotResult<bool> {
+    let validity = oracle_validity(last_oracle_twap, source, validity_guard_rails)?;
+
+    Ok(!matches!(
+        validity,
+        OracleValidity::Invalid | OracleValidity::Uncertain | OracleValidity::StaleForMargin
+    ))
+}
+
+/// Drops the Phoenix mid source unless it lies within the accepted confidence divergence of a reference price. The
+/// reference is the first usable oracle source, or the market's oracle TWAP if none is usable.
+/// 
+/// The Phoenix mid is a single snapshot of the book that anyone can crank, so a thin book moved in the same slot as
+/// the crank could otherwise set or swing the combined price. Anchoring it to the oracles bounds how far it can pull.
+pub fn filter_phoenix_mid_source(
+    phoenix_mid: Option<OraclePriceData>,
+    oracle_sources: &[Option<OraclePriceData>],
+    last_oracle_twap: i64,
+    validity_guard_rails: &ValidityGuardRails
+) -> SpedXSpotResult<Option<OraclePriceData>> {
+    let phoenix_mid = match phoenix_mid {
+        Some(phoenix_mid) => phoenix_mid,
+        None => return Ok(None)
+    };
+
+    let mut reference_price = last_oracle_twap;
+    for source in oracle_sources.iter().flatten() {
+        if is_usable_oracle_source(source, last_oracle_twap, validity_guard_rails)? {
+            reference_price = source.price;
+            break;
+        }
+    }
+
+    if reference_price <= 0 {
+        msg!("Ignoring the Phoenix mid price, there is no oracle price to check it against");
+        return Ok(None)
+    }
+
+    let divergence_pct = phoenix_mid.price
+        .safe_sub(reference_price)?
+        .unsigned_abs()
+        .cast::<u128>()?
+        .safe_mul(PERCENTAGE_PRECISION)?
+        .safe_div(reference_price.cast()?)?;
+
+    if divergence_pct > validity_guard_rails.confidence_interval_max_accepted_divergence.cast()? {
+        msg!(
+            "Ignoring the Phoenix mid price {}, it diverges from the oracle price {} by {}",
+            phoenix_mid.price,
+            reference_price,
+            divergence_pct
+        );
+        return Ok(None)
+    }
+
+    Ok(Some(phoenix_mid))
+}
+
+/// Combines up to three oracle sources into one `OraclePriceData` using the market's `OracleSourcePolicy`.
+/// `sources` are passed in fallback order(primary, secondary, Phoenix mid) and None represents an unavailable source.
+/// 
+/// A source is usable if it is not invalid, uncertain or stale. Volatility is judged on the combined price later on, as all
+/// sources will look volatile during a genuine move. A usable source agrees with the combined price if it lies within
+/// the accepted confidence divergence of it. The combined confidence covers every agreeing source's confidence band, and
+/// `has_sufficient_data_points` is only set if at least `quorum` agreeing sources have sufficient data points themselves.
+/// 
+/// Returns None if no source is usable.
+pub fn combine_oracle_sources(
+    sources: &[Option<OraclePriceData>],
+    policy: OracleSourcePolicy,
+    quorum: u8,
+    last_oracle_twap: i64,
+    validity_guard_rails: &ValidityGuardRails
+) -> SpedXSpotResult<Option<OraclePriceData>> {
+    let mut usable_sources: Vec<OraclePriceData> = Vec::with_capacity(sources.len());
+
+    for source in sources.iter().flatten() {
+        if is_usable_oracle_source(source, last_oracle_twap, validity_guard_rails)? {
+            usable_sources.push(*source);
+        }
+    }
+
+    if usable_sources.is_empty() {
+        return Ok(None)
+    }
+
+    let price = match policy {
+        OracleSourcePolicy::PrimaryWithFallback => usable_sources[0].price,
+        OracleSourcePolicy::MedianOfValid => {
+            let mut prices: Vec<i64> = usable_sources.iter().map(|source| source.price).collect();
+            prices.sort_unstable();
+
+            let middle = prices.len() / 2;
+            if prices.len() % 2 == 0 {
+                prices[middle - 1].safe_add(prices[middle])?.safe_div(2)?
+            } else {
+                prices[middle]
+            }
+        }
+    };
+
+    // distance of each usable source from the combined price, and whether it agrees with it
+    let mut divergences: Vec<(u64, bool)> = Vec::with_capacity(usable_sources.len());
+    for source in usable_sources.iter() {
+        let divergence = source.price.safe_sub(price)?.unsigned_abs();
+
+        let divergence_pct = divergence
+            .cast::<u128>()?
+            .safe_mul(PERCENTAGE_PRECISION)?
+            .safe_div(price.cast()?)?;
+
+        let agrees = divergence_pct <= validity_guard_rails.confidence_interval_max_accepted_divergence.cast()?;
+
+        divergences.push((divergence, agrees));
+    }
+
+    // the median of an even number of diverging sources can sit away from all of them. In that case we fall back to
+    // using every usable source, which leaves a wide confidence that flags the price as uncertain downstream
+    let any_source_agrees = divergences.iter().any(|(_, agrees)| *agrees);
+
+    let mut confidence = 0_u64;
+    let mut delay = 0_i64;
+    let mut agreeing_sources = 0_u8;
+
+    for (source, (divergence, agrees)) in usable_sources.iter().zip(divergences.iter()) {
+        if any_source_agrees && !agrees {
+            continue;
+        }
+
+        confidence = confidence.max(source.confidence.safe_add(*divergence)?);
+        delay = delay.max(source.delay);
+
+        if *agrees && source.has_sufficient_data_points {
+            agreeing_sources = agreeing_sources.safe_add(1)?;
+        }
+    }
+
+    Ok(Some(OraclePriceData {
+        price,
+        confidence,
+        delay,
+        has_sufficient_data_points: agreeing_sources >= quorum.max(1),
+    }))
+}
+
+/// Fetches the market's oracle price by combining all of its configured sources, so that a single feed outage
+/// does not block every action on the market. `guard_rails` are the protocol-wide defaults from `State`, the market's
+/// overrides are applied on top of them.
+/// 
+/// If no source is usable, the first source that could be loaded is returned as-is, so that its classification blocks
+/// the actions that it is unfit for.
+pub fn get_market_oracle_price_data(
+    market: &Market,
+    oracle_account_info: Option<&AccountInfo>,
+    secondary_oracle_account_info: Option<&AccountInfo>,
+    guard_rails: &OracleGuardRails,
+    clock_slot: u64
+) -> SpedXSpotResult<OraclePriceData> {
+    let primary = match oracle_account_info {
+        Some(account_info) => {
+            validate!(
+                account_info.key == &market.oracle,
+                ErrorCode::InvalidOracle,
+                "oracle {} does not match market oracle {}",
+                account_info.key,
+                market.oracle
+            )?;
+
+            get_oracle_price(&market.oracle_type, account_info, clock_slot).ok()
+        },
+        None => None
+    };
+
+    let secondary = match secondary_oracle_account_info {
+        Some(account_info) if market.has_secondary_oracle() => {
+            validate!(
+                account_info.key == &market.secondary_oracle,
+                ErrorCode::InvalidOracle,
+                "oracle {} does not match market secondary oracle {}",
+                account_info.key,
+                market.secondary_oracle
+            )?;
+
+            get_oracle_price(&market.secondary_oracle_type, account_info, clock_slot).ok()
+        },
+        _ => None
+    };
+
+    let phoenix_mid = if market.use_phoenix_mid_oracle {
+        get_phoenix_mid_price_data(&market.historical_index_data, market.last_index_update_slot, clock_slot)?
+    } else {
+        None
+    };
+
+    let guard_rails = market.get_oracle_guard_rails(guard_rails);
+
+    let phoenix_mid = filter_phoenix_mid_source(
+        phoenix_mid,
+        &[primary, secondary],
+        market.historical_oracle_data.last_oracle_twap,
+        &guard_rails.validity
+    )?;
+
+    let sources = [primary, secondary, phoenix_mid];
+
+    let combined = combine_oracle_sources(
+        &sources,
+        market.oracle_source_policy,
+        market.oracle_quorum,
+        market.historical_oracle_data.last_oracle_twap,
+        &guard_rails.validity
+    )?;
+
+    match combined {
+        Some(oracle_price_data) => Ok(oracle_price_data),
+        None => sources.iter().flatten().next().copied().ok_or_else(|| {
+            msg!("No oracle source could be loaded for market {}", market.market_index);
+            ErrorCode::UnableToLoadOracle
+        })
+    }
+}
+
 #[derive(Default, Clone, Copy)]
 #[repr(C)]
 pub struct Price { 
@@ -403,6 +680,79 @@ fn pyth_1m() {
     assert_eq!(oracle_price_data.price, 839400);
 }
 
+/// Test for combining oracle sources with both policies
+#[test]
+fn combine_oracle_sources_with_fallback_and_median() {
+    let validity_guard_rails = OracleGuardRails::default().validity;
+    let last_oracle_twap = 100 * PRICE_PRECISION_I64;
+
+    let source = |price: i64, delay: i64| OraclePriceData {
+        price,
+        confidence: PRICE_PRECISION_U64 / 10,
+        delay,
+        has_sufficient_data_points: true,
+    };
+
+    // primary is stale, so the fallback chain moves on to the secondary
+    let sources = [
+        Some(source(90 * PRICE_PRECISION_I64, 500)),
+        Some(source(101 * PRICE_PRECISION_I64, 1)),
+        Some(source(100 * PRICE_PRECISION_I64, 1)),
+    ];
+    let combined = combine_oracle_sources(
+        &sources,
+        OracleSourcePolicy::PrimaryWithFallback,
+        2,
+        last_oracle_twap,
+        &validity_guard_rails
+    ).unwrap().unwrap();
+    assert_eq!(combined.price, 101 * PRICE_PRECISION_I64);
+    // phoenix mid is 1% away, so it agrees and widens the confidence
+    assert_eq!(combined.confidence, PRICE_PRECISION_U64 / 10 + PRICE_PRECISION_U64);
+    assert!(combined.has_sufficient_data_points);
+
+    // an unavailable primary behaves the same as a stale one
+    let sources = [None, Some(source(101 * PRICE_PRECISION_I64, 1)), None];
+    let combined = combine_oracle_sources(
+        &sources,
+        OracleSourcePolicy::PrimaryWithFallback,
+        2,
+        last_oracle_twap,
+        &validity_guard_rails
+    ).unwrap().unwrap();
+    assert_eq!(combined.price, 101 * PRICE_PRECISION_I64);
+    // a single source does not meet the quorum of 2
+    assert!(!combined.has_sufficient_data_points);
+
+    // median ignores the outlier
+    let sources = [
+        Some(source(100 * PRICE_PRECISION_I64, 1)),
+        Some(source(150 * PRICE_PRECISION_I64, 1)),
+        Some(source(101 * PRICE_PRECISION_I64, 1)),
+    ];
+    let combined = combine_oracle_sources(
+        &sources,
+        OracleSourcePolicy::MedianOfValid,
+        2,
+        last_oracle_twap,
+        &validity_guard_rails
+    ).unwrap().unwrap();
+    assert_eq!(combined.price, 101 * PRICE_PRECISION_I64);
+    assert_eq!(combined.confidence, PRICE_PRECISION_U64 / 10 + PRICE_PRECISION_U64);
+    assert!(combined.has_sufficient_data_points);
+
+    // no usable sources
+    let sources = [Some(source(100 * PRICE_PRECISION_I64, 500)), None, None];
+    let combined = combine_oracle_sources(
+        &sources,
+        OracleSourcePolicy::MedianOfValid,
+        1,
+        last_oracle_twap,
+        &validity_guard_rails
+    ).unwrap();
+    assert!(combined.is_none());
+}
+
 /// Macro that can be called to create account info using available data such as
 /// - Account Pubkey
 /// - lamports
@@ -422,4 +772,74 @@ macro_rules! create_account_info {
         let mut data = get_account_bytes(&mut $account);
         let $name = create_account_info($pubkey, true, &mut lamports, &mut data[..], $owner);
     };
-}
\ No newline at end of file
+}
+
+/// Test that the Phoenix mid only counts as a source while it agrees with the oracles
+#[test]
+fn filter_phoenix_mid_source_anchors_to_the_oracles() {
+    let validity_guard_rails = OracleGuardRails::default().validity;
+    let last_oracle_twap = 100 * PRICE_PRECISION_I64;
+
+    let source = |price: i64, delay: i64| OraclePriceData {
+        price,
+        confidence: PRICE_PRECISION_U64 / 10,
+        delay,
+        has_sufficient_data_points: true,
+    };
+
+    // within 2% of the primary
+    let phoenix_mid = Some(source(101 * PRICE_PRECISION_I64, 1));
+    let filtered = filter_phoenix_mid_source(
+        phoenix_mid,
+        &[Some(source(100 * PRICE_PRECISION_I64, 1)), None],
+        last_oracle_twap,
+        &validity_guard_rails
+    ).unwrap();
+    assert_eq!(filtered.unwrap().price, 101 * PRICE_PRECISION_I64);
+
+    // a book pushed 10% away from the primary is ignored
+    let phoenix_mid = Some(source(110 * PRICE_PRECISION_I64, 1));
+    let filtered = filter_phoenix_mid_source(
+        phoenix_mid,
+        &[Some(source(100 * PRICE_PRECISION_I64, 1)), None],
+        last_oracle_twap,
+        &validity_guard_rails
+    ).unwrap();
+    assert!(filtered.is_none());
+
+    // with a stale primary the usable secondary is the reference
+    let phoenix_mid = Some(source(104 * PRICE_PRECISION_I64, 1));
+    let filtered = filter_phoenix_mid_source(
+        phoenix_mid,
+        &[Some(source(100 * PRICE_PRECISION_I64, 500)), Some(source(105 * PRICE_PRECISION_I64, 1))],
+        last_oracle_twap,
+        &validity_guard_rails
+    ).unwrap();
+    assert_eq!(filtered.unwrap().price, 104 * PRICE_PRECISION_I64);
+
+    // without a usable oracle the mid is checked against the oracle twap, so it can still stand in during an outage
+    let filtered = filter_phoenix_mid_source(
+        Some(source(101 * PRICE_PRECISION_I64, 1)),
+        &[None, None],
+        last_oracle_twap,
+        &validity_guard_rails
+    ).unwrap();
+    assert_eq!(filtered.unwrap().price, 101 * PRICE_PRECISION_I64);
+
+    let filtered = filter_phoenix_mid_source(
+        Some(source(90 * PRICE_PRECISION_I64, 1)),
+        &[None, None],
+        last_oracle_twap,
+        &validity_guard_rails
+    ).unwrap();
+    assert!(filtered.is_none());
+
+    // and dropped if the market has no oracle twap yet
+    let filtered = filter_phoenix_mid_source(
+        Some(source(101 * PRICE_PRECISION_I64, 1)),
+        &[None, None],
+        0,
+        &validity_guard_rails
+    ).unwrap();
+    assert!(filtered.is_none());
+}
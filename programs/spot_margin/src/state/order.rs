@@ -1,5 +1,12 @@
-use anchor_lang::zero_copy;
-use borsh::{BorshDeserialize, BorshSerialize};
+use anchor_lang::{
+    prelude::{
+        borsh,
+        AnchorDeserialize,
+        AnchorSerialize
+    },
+    zero_copy
+};
+use phoenix::state::Side;
 
 use crate::error::{SpedXSpotResult, ErrorCode};
 use crate::math::{
@@ -12,17 +19,20 @@ use crate::math::{
 };
 
 use super::enums::{
+    OcoRole,
     PositionDirection,
     OrderType,
     OrderStatus,
-    OrderTriggerConditions
+    OrderTriggerConditions,
+    PostOnlyTypes,
+    SelfTradeBehaviorTypes
 };
 use solana_program::msg;
 use std::panic::Location; 
 
 #[zero_copy]
 #[repr(C)]
-#[derive(BorshDeserialize, BorshSerialize, PartialEq, Debug, Eq)]
+#[derive(AnchorDeserialize, AnchorSerialize, PartialEq, Debug, Eq)]
 pub struct Order {
     pub slot: u64,
 
@@ -49,13 +59,23 @@ pub struct Order {
     /// precision: PRICE_PRECISION
     pub price_for_trigger_orders: u64,
 
-    /// Represents a time-period for which the order shall stay active, after which it expires. 
+    /// Unix timestamp after which the order expires and can be cancelled by the expire_orders crank.
     /// A value of 0 for this field indicates that this order never expires.
-    /// Time-in-force orders can be of precisely two typeS: slot and a unix timestamp.
-    /// The order will be voided after the the specifided slot and the same shall be true if the curr_ts eclipses the unix_ts.
-    /// precision: TIME_IN_FORCE_PRECISION 
     pub time_in_force: i64,
 
+    /// Price of the order on the Phoenix book, in ticks. Together with the sequence number it forms the Phoenix order id.
+    /// 0 if the order is not resting on Phoenix. In markets fulfilled by OpenBook V2, the price of the order in price lots.
+    pub phoenix_price_in_ticks: u64,
+
+    /// Sequence number of the order on the Phoenix book. Phoenix inverts the sequence number of bids, as does OpenBook V2
+    /// for the lower half of its order ids, which this holds in markets fulfilled by OpenBook V2.
+    pub phoenix_order_sequence_number: u64,
+
+    /// Size shown on Phoenix at a time for iceberg orders, the rest of the order stays hidden in the order slot.
+    /// For TWAP orders, the size of each clip. 0 if the order is neither an iceberg nor a TWAP order.
+    /// precision: token mint precision
+    pub iceberg_clip_size: u64,
+
     /// A field inspired from Ellipsis Labs' Phoenix Spot DEX, which silently cancel's an order if there are insufficient funds for the order to execute.
     pub fail_silently_on_insufficient_funds_error: bool,
 
@@ -99,8 +119,11 @@ pub struct Order {
     /// by simply reducing the size of the position, and not placing an order on the other side of the book.
     pub reduce_only: bool,
 
-    /// If set to true, the order will always be a maker. PostOnly orders are 
-    pub post_only: bool,
+    /// Whether the order must be a maker, and what happens if it would cross the book:
+    /// - MustBePostOnly: the transaction fails
+    /// - CanBePostOnly: the order is cancelled silently
+    /// - PostOnlySlide: the order's price is moved one tick behind the best price on the other side
+    pub post_only: PostOnlyTypes,
 
     /// If set to true, the order will be cancelled if it cannot be filled upto min_base_lots/min_quote_lots, i.e of the type ImmediateOrCancel
     pub immediate_or_cancel: bool,
@@ -115,7 +138,22 @@ pub struct Order {
     /// Whether the order is triggered above or below the set limit price. Only relevant for Trigger order types.
     pub trigger_conditions: OrderTriggerConditions,
 
-    pub padding: [u8;3],
+    /// One-cancels-other group of the order, unique among the user's open orders. When a leg of the group triggers, the
+    /// other open orders of the group are cancelled. 0 if the order is not part of a group.
+    pub oco_group_id: u8,
+
+    /// Whether the order is the entry or a leg of its one-cancels-other group
+    pub oco_role: OcoRole,
+
+    /// Seconds between two clips of a TWAP order. The clips are scheduled backwards from the order's expiry, the
+    /// first one being due when the order is placed. 0 for other orders.
+    pub twap_clip_interval: u32,
+
+    /// What happens when the order would match one of the user's own resting orders. Matches with other users' orders
+    /// are trades between the two users.
+    pub self_trade_behavior: SelfTradeBehaviorTypes,
+
+    pub padding: [u8;7],
 }
 
 impl Default for Order {
@@ -133,7 +171,7 @@ impl Default for Order {
             quote_asset_filled: 0,
             pos_direction: PositionDirection::Long,
             reduce_only: false,
-            post_only: false,
+            post_only: PostOnlyTypes::NotPostOnly,
             immediate_or_cancel: false,
             price_for_trigger_orders: 0,
             trigger_conditions: OrderTriggerConditions::Above,
@@ -142,13 +180,205 @@ impl Default for Order {
             treat_ioc_as_market: true,
             fill_or_kill: false,
             time_in_force: 0,
+            phoenix_price_in_ticks: 0,
+            phoenix_order_sequence_number: 0,
+            iceberg_clip_size: 0,
             market_index: 0,
-            padding: [0;3]
+            oco_group_id: 0,
+            oco_role: OcoRole::None,
+            twap_clip_interval: 0,
+            self_trade_behavior: SelfTradeBehaviorTypes::CancelProvide,
+            padding: [0;7]
         }
     }
 }
 
+/// Parameters of an order as submitted by a user
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct OrderParams {
+    pub order_type: OrderType,
+
+    /// Long places a bid, Short places an ask. TwoWay orders cannot be placed directly
+    pub direction: PositionDirection,
+
+    /// Client-generated order ID
+    pub user_order_id: u8,
+
+    /// precision: token mint precision
+    pub base_asset_amount: u64,
+
+    /// Limit price. Ignored for oracle pegged orders
+    /// precision: PRICE_PRECISION
+    pub price: u64,
+
+    pub market_index: u16,
+
+    pub reduce_only: bool,
+
+    /// PostOnly orders default to MustBePostOnly
+    pub post_only: PostOnlyTypes,
+
+    /// Spread to the oracle price for oracle pegged orders
+    /// precision: PRICE_PRECISION
+    pub oracle_limit_spread: Option<i32>,
+
+    /// Oracle price at which a trigger order is triggered
+    /// precision: PRICE_PRECISION
+    pub trigger_price: Option<u64>,
+
+    /// Whether a trigger order is triggered when the oracle price rises above or falls below the trigger price
+    pub trigger_condition: OrderTriggerConditions,
+
+    /// Size to show on Phoenix at a time, making the order an iceberg order
+    /// precision: token mint precision
+    pub iceberg_clip_size: Option<u64>,
+
+    /// Immediate-or-cancel orders only: fail unless the whole order fills
+    pub fill_or_kill: bool,
+
+    /// Immediate-or-cancel orders only: fail unless at least this size fills
+    /// precision: token mint precision
+    pub min_base_asset_amount_to_fill: Option<u64>,
+
+    /// Immediate-or-cancel orders only: fail unless at least this quote amount fills
+    /// precision: QUOTE_PRECISION
+    pub min_quote_asset_amount_to_fill: Option<u64>,
+
+    /// Number of seconds the order stays active for. Immediate-or-cancel orders default to the state's
+    /// default_market_order_time_in_force, other orders never expire by default. For TWAP orders, the duration over
+    /// which the order is executed
+    pub time_in_force: Option<i64>,
+
+    /// TWAP orders only: number of equal clips the order is executed in, spread evenly over the time in force
+    pub twap_num_clips: Option<u16>,
+
+    /// What happens when the order would match one of the user's own resting orders
+    pub self_trade_behavior: SelfTradeBehaviorTypes,
+}
+
+/// Changes to an open order. Fields left as None keep their current value
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct ModifyOrderParams {
+    /// New total size of the order, including the part that has already been filled
+    /// precision: token mint precision
+    pub base_asset_amount: Option<u64>,
+
+    /// New limit price. Ignored for oracle pegged orders
+    /// precision: PRICE_PRECISION
+    pub price: Option<u64>,
+
+    /// New spread to the oracle price for oracle pegged orders
+    /// precision: PRICE_PRECISION
+    pub oracle_limit_spread: Option<i32>,
+
+    /// New trigger price of a trigger order that has not been triggered yet
+    /// precision: PRICE_PRECISION
+    pub trigger_price: Option<u64>,
+
+    pub reduce_only: Option<bool>,
+
+    pub post_only: Option<PostOnlyTypes>,
+
+    /// Number of seconds from now the order stays active for, 0 for an order that never expires
+    pub time_in_force: Option<i64>,
+}
+
 impl Order {
+    /// Returns the side of the order on the Phoenix book
+    pub fn get_phoenix_side(&self) -> Side {
+        match self.pos_direction {
+            PositionDirection::Short => Side::Ask,
+            _ => Side::Bid
+        }
+    }
+
+    /// Returns true if the order is currently resting on the Phoenix book
+    pub fn is_resting_on_phoenix(&self) -> bool {
+        self.phoenix_price_in_ticks != 0
+    }
+
+    /// Returns true if the order's limit price follows the oracle
+    pub fn is_oracle_pegged(&self) -> bool {
+        self.order_type == OrderType::OraclePegged
+    }
+
+    /// Unfilled size of the order, without any reduce-only adjustments
+    /// precision: token mint precision
+    pub fn get_base_asset_amount_unfilled(&self) -> SpedXSpotResult<u64> {
+        self.base_asset_amount.safe_sub(self.base_asset_filled)
+    }
+
+    /// Returns true if the order may only rest on the book as a maker
+    pub fn is_post_only(&self) -> bool {
+        self.post_only != PostOnlyTypes::NotPostOnly
+    }
+
+    /// Returns true if the order only shows a clip of its size on Phoenix
+    pub fn is_iceberg(&self) -> bool {
+        self.iceberg_clip_size != 0 && !self.is_twap()
+    }
+
+    /// Returns true if the order is executed in timed clips
+    pub fn is_twap(&self) -> bool {
+        self.order_type == OrderType::Twap
+    }
+
+    /// Size of a TWAP order that should have been filled by now: one clip for every clip interval that has started,
+    /// capped at the order size. Clips that did not fill are caught up on with the next clip.
+    /// precision: token mint precision
+    pub fn get_twap_base_asset_amount_due(&self, now: i64) -> SpedXSpotResult<u64> {
+        let num_clips = self.base_asset_amount.safe_ceil_div(self.iceberg_clip_size)?;
+        let twap_start_ts = self.time_in_force.safe_sub(num_clips.safe_mul(self.twap_clip_interval.cast()?)?.cast()?)?;
+
+        if now < twap_start_ts {
+            return Ok(0)
+        }
+
+        let num_clips_due = now
+            .safe_sub(twap_start_ts)?
+            .safe_div(self.twap_clip_interval.cast()?)?
+            .cast::<u64>()?
+            .safe_add(1)?
+            .min(num_clips);
+
+        Ok(num_clips_due.safe_mul(self.iceberg_clip_size)?.min(self.base_asset_amount))
+    }
+
+    /// Worst price a clip of a TWAP order may fill at: the oracle price moved against the order by the oracle limit
+    /// spread
+    /// precision: PRICE_PRECISION
+    pub fn get_twap_limit_price(
+        &self,
+        oracle_price: i64,
+        tick_size: u64
+    ) -> SpedXSpotResult<u64> {
+        let max_slippage = self.oracle_limit_spread.unsigned_abs().cast::<i64>()?;
+
+        let limit_price = match self.pos_direction {
+            PositionDirection::Short => oracle_price.safe_sub(max_slippage)?,
+            _ => oracle_price.safe_add(max_slippage)?
+        };
+
+        if limit_price <= 0 {
+            msg!("Limit price cannot be equal to or lesser than zero: {}", limit_price);
+            return Err(ErrorCode::InvalidOracleSpreadLimitPrice)
+        }
+
+        standardize_price(limit_price.cast()?, tick_size, self.pos_direction)
+    }
+
+    /// Size of the order to show on Phoenix: the unfilled size, capped at the clip size for iceberg orders
+    /// precision: token mint precision
+    pub fn get_base_asset_amount_to_show(&self) -> SpedXSpotResult<u64> {
+        let base_asset_amount_unfilled = self.get_base_asset_amount_unfilled()?;
+
+        if self.is_iceberg() {
+            Ok(base_asset_amount_unfilled.min(self.iceberg_clip_size))
+        } else {
+            Ok(base_asset_amount_unfilled)
+        }
+    }
+
     pub fn does_order_have_oracle_price_offset(&self) -> bool {
         self.oracle_limit_spread !=0
     }
@@ -214,10 +444,12 @@ impl Order {
             self.does_order_have_oracle_price_offset())
     }
 
+    /// Unfilled size of the order. Reduce-only orders are capped at the size of the existing position, and are 0 if
+    /// they would increase it.
+    /// precision: token mint precision
     pub fn get_unfilled_base_amount(
         &self,
         existing_position: Option<i64>,
-        num_lots_to_fill: u64,
     ) -> SpedXSpotResult<u64> {
 
         let unfilled_base_amt = self.base_asset_amount.safe_sub(self.base_asset_filled)?;
@@ -229,7 +461,7 @@ impl Order {
             }
         };
 
-        if !self.reduce_only || self.post_only {
+        if !self.reduce_only {
             return Ok(unfilled_base_amt);
         }
 
@@ -237,20 +469,6 @@ impl Order {
             return Ok(0)
         }
 
-        if self.fill_or_kill {
-            return Ok(0)
-        }
-
-        if self.immediate_or_cancel {
-            if self.base_asset_filled < num_lots_to_fill {
-                return Err(ErrorCode::IOCOrderCancelledDueToNumBaseLotsNotFilled);
-            } else if self.base_asset_filled > num_lots_to_fill {
-                return Ok(num_lots_to_fill)
-            } else {
-                return Ok(num_lots_to_fill)
-            }
-        }
-
         match self.pos_direction {
             PositionDirection::Long => {
                 if existing_position > 0 {
@@ -281,11 +499,10 @@ impl Order {
     pub fn get_standardized_base_amount_unfilled(
         &self,
         existing_position: Option<i64>,
-        num_base_lots_to_fill: u64,
         order_step_size: u64,
     ) -> SpedXSpotResult<u64> {
         standardize_base_asset_amt(
-            self.get_unfilled_base_amount(existing_position, num_base_lots_to_fill)?,
+            self.get_unfilled_base_amount(existing_position)?,
             order_step_size
         )
     }
@@ -332,6 +549,41 @@ impl Order {
         )
     }
 
+    /// Returns true if the order is a trigger order that has not been triggered yet and whose trigger condition is met
+    /// at the oracle price
+    pub fn should_trigger(&self, oracle_price: i64) -> SpedXSpotResult<bool> {
+        if !self.order_has_trigger() || self.has_trigger_order_triggered() {
+            return Ok(false)
+        }
+
+        let trigger_price = self.price_for_trigger_orders.cast::<i64>()?;
+
+        Ok(match self.trigger_conditions {
+            OrderTriggerConditions::Above => oracle_price > trigger_price,
+            OrderTriggerConditions::Below => oracle_price < trigger_price,
+            _ => false
+        })
+    }
+
+    /// Marks a trigger order as triggered
+    pub fn set_triggered(&mut self) {
+        self.trigger_conditions = match self.trigger_conditions {
+            OrderTriggerConditions::Above => OrderTriggerConditions::TriggeredAbove,
+            OrderTriggerConditions::Below => OrderTriggerConditions::TriggeredBelow,
+            trigger_conditions => trigger_conditions
+        };
+    }
+
+    /// Returns true if the order is an open order of the given one-cancels-other group
+    pub fn is_in_oco_group(&self, oco_group_id: u8) -> bool {
+        oco_group_id != 0 && self.oco_group_id == oco_group_id && self.order_status == OrderStatus::Active
+    }
+
+    /// Returns true if the order has an expiry and it has passed
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.time_in_force != 0 && now >= self.time_in_force
+    }
+
     pub fn is_order_open(&self, market_index: u16) -> bool {
         self.market_index == market_index && self.order_status == OrderStatus::Active
     }
@@ -359,6 +611,165 @@ impl Order {
     }
 
     pub fn is_resting_limit_order(&self) -> bool {
-        self.is_limit_order() && self.post_only && !self.fill_or_kill && !self.immediate_or_cancel
+        self.is_limit_order() && self.is_post_only() && !self.fill_or_kill && !self.immediate_or_cancel
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trigger_conditions() {
+        let mut order = Order {
+            order_type: OrderType::TriggerMarket,
+            order_status: OrderStatus::Active,
+            price_for_trigger_orders: 100_000_000,
+            trigger_conditions: OrderTriggerConditions::Below,
+            ..Order::default()
+        };
+
+        // stop-loss triggers once the oracle falls below the trigger price
+        assert!(!order.should_trigger(100_000_000).unwrap());
+        assert!(order.should_trigger(99_999_999).unwrap());
+
+        order.set_triggered();
+        assert_eq!(order.trigger_conditions, OrderTriggerConditions::TriggeredBelow);
+        assert!(!order.should_trigger(99_999_999).unwrap());
+
+        // take-profit triggers once the oracle rises above the trigger price
+        order.trigger_conditions = OrderTriggerConditions::Above;
+        assert!(!order.should_trigger(100_000_000).unwrap());
+        assert!(order.should_trigger(100_000_001).unwrap());
+
+        // orders without a trigger never trigger
+        order.order_type = OrderType::Limit;
+        assert!(!order.should_trigger(100_000_001).unwrap());
+    }
+
+    #[test]
+    fn twap_schedule() {
+        // 10_000 in clips of 3_000 every 60 seconds: 4 clips, the last one smaller, ending at 1_240
+        let order = Order {
+            order_type: OrderType::Twap,
+            base_asset_amount: 10_000,
+            iceberg_clip_size: 3_000,
+            twap_clip_interval: 60,
+            time_in_force: 1_240,
+            ..Order::default()
+        };
+
+        assert!(order.is_twap());
+        assert!(!order.is_iceberg());
+
+        assert_eq!(order.get_twap_base_asset_amount_due(999).unwrap(), 0);
+        assert_eq!(order.get_twap_base_asset_amount_due(1_000).unwrap(), 3_000);
+        assert_eq!(order.get_twap_base_asset_amount_due(1_059).unwrap(), 3_000);
+        assert_eq!(order.get_twap_base_asset_amount_due(1_060).unwrap(), 6_000);
+        assert_eq!(order.get_twap_base_asset_amount_due(1_180).unwrap(), 10_000);
+        assert_eq!(order.get_twap_base_asset_amount_due(2_000).unwrap(), 10_000);
+    }
+
+    #[test]
+    fn twap_limit_price() {
+        let mut order = Order {
+            order_type: OrderType::Twap,
+            pos_direction: PositionDirection::Long,
+            oracle_limit_spread: 1_000_000,
+            ..Order::default()
+        };
+
+        // the slippage is always against the order, whatever the sign of the spread
+        assert_eq!(order.get_twap_limit_price(100_000_000, 1_000).unwrap(), 101_000_000);
+
+        order.oracle_limit_spread = -1_000_000;
+        assert_eq!(order.get_twap_limit_price(100_000_000, 1_000).unwrap(), 101_000_000);
+
+        order.pos_direction = PositionDirection::Short;
+        assert_eq!(order.get_twap_limit_price(100_000_000, 1_000).unwrap(), 99_000_000);
+
+        assert!(order.get_twap_limit_price(1_000_000, 1_000).is_err());
+    }
+
+    #[test]
+    fn oco_group_membership() {
+        let mut order = Order {
+            order_status: OrderStatus::Active,
+            oco_group_id: 3,
+            oco_role: OcoRole::Leg,
+            ..Order::default()
+        };
+
+        assert!(order.is_in_oco_group(3));
+        assert!(!order.is_in_oco_group(4));
+
+        // 0 is not a group, orders outside of a group are never linked
+        order.oco_group_id = 0;
+        assert!(!order.is_in_oco_group(0));
+
+        // closed orders no longer belong to their group
+        order.oco_group_id = 3;
+        order.order_status = OrderStatus::Cancelled;
+        assert!(!order.is_in_oco_group(3));
+    }
+
+    #[test]
+    fn iceberg_clip() {
+        let mut order = Order {
+            base_asset_amount: 10_000,
+            iceberg_clip_size: 3_000,
+            ..Order::default()
+        };
+
+        assert!(order.is_iceberg());
+        assert_eq!(order.get_base_asset_amount_to_show().unwrap(), 3_000);
+
+        // the last clip only shows what is left
+        order.base_asset_filled = 9_000;
+        assert_eq!(order.get_base_asset_amount_to_show().unwrap(), 1_000);
+
+        order.iceberg_clip_size = 0;
+        order.base_asset_filled = 0;
+        assert!(!order.is_iceberg());
+        assert_eq!(order.get_base_asset_amount_to_show().unwrap(), 10_000);
+    }
+
+    #[test]
+    fn reduce_only_unfilled_base_amount() {
+        let mut order = Order {
+            base_asset_amount: 10_000,
+            base_asset_filled: 2_000,
+            pos_direction: PositionDirection::Short,
+            reduce_only: true,
+            ..Order::default()
+        };
+
+        // a reduce-only ask is capped at the size of a long position
+        assert_eq!(order.get_unfilled_base_amount(Some(5_000)).unwrap(), 5_000);
+        assert_eq!(order.get_unfilled_base_amount(Some(20_000)).unwrap(), 8_000);
+
+        // and cannot open or increase a short position
+        assert_eq!(order.get_unfilled_base_amount(Some(0)).unwrap(), 0);
+        assert_eq!(order.get_unfilled_base_amount(Some(-5_000)).unwrap(), 0);
+
+        // post-only orders are no exception
+        order.post_only = PostOnlyTypes::MustBePostOnly;
+        assert_eq!(order.get_unfilled_base_amount(Some(-5_000)).unwrap(), 0);
+
+        order.reduce_only = false;
+        assert_eq!(order.get_unfilled_base_amount(Some(-5_000)).unwrap(), 8_000);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn order_expiry() {
+        let mut order = Order::default();
+
+        // orders without an expiry never expire
+        assert!(!order.is_expired(i64::MAX));
+
+        order.time_in_force = 1_000;
+        assert!(!order.is_expired(999));
+        assert!(order.is_expired(1_000));
+        assert!(order.is_expired(1_001));
+    }
+}
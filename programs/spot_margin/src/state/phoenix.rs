@@ -2,18 +2,26 @@
 
 use anchor_lang::prelude::*;
 use crate::{
-    state::oracle::*,
+    state::{
+        enums::PositionDirection,
+        oracle::*
+    },
     math::constants::*, 
     math::safe_math::SafeMath,
     error::{
         SpedXSpotResult,
         ErrorCode
     },
-    math::casting::Cast
+    math::{
+        casting::Cast,
+        safe_unwrap::SafeUnwrap
+    },
+    validate
 };
 
 use phoenix::{
     program::{
+        load_with_dispatch,
         new_order::{
             CondensedOrder,
             MultipleOrderPacket
@@ -22,6 +30,7 @@ use phoenix::{
         CancelOrderParams,
         // CancelOrWithdrawContext,
         MarketHeader,
+        status::MarketStatus as PhoenixMarketStatus
     },
     quantities::WrapperU64,
     state::{
@@ -36,6 +45,11 @@ use phoenix::{
         SelfTradeBehavior
     }
 };
+use anchor_spl::token::{
+    self,
+    Token,
+    TokenAccount
+};
 use bytemuck::try_from_bytes;
 use std::mem::size_of;
 
@@ -48,33 +62,839 @@ impl Id for Phoenix {
     }
 }
 
+/// Accounts required to place and cancel a user's orders on a Phoenix market. The orders are placed under the user's
+/// trader PDA and its seat, with funds the protocol's signer moves to the trader for the duration of each CPI.
+#[derive(Accounts)]
+pub struct PhoenixMarketAccounts<'info> {
+    pub phoenix_program: Program<'info, Phoenix>,
+
+    /// CHECK: Phoenix log authority, checked against its address
+    #[account(address = phoenix::phoenix_log_authority::id())]
+    pub phoenix_log_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Checked in fetch_market_header and against the market's phoenix market
+    #[account(mut)]
+    pub phoenix_market: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol PDA whose token accounts fund the users' orders, checked against the state's signer
+    pub signer: UncheckedAccount<'info>,
+
+    /// CHECK: PDA of the user the orders are placed under, checked against its address
+    pub trader: UncheckedAccount<'info>,
+
+    /// CHECK: Phoenix seat of the trader, checked by the Phoenix program
+    pub phoenix_seat: UncheckedAccount<'info>,
+
+    /// Protocol token account of the Phoenix market's base mint, owned by the signer
+    #[account(mut, token::authority = signer)]
+    pub base_account: Box<Account<'info, TokenAccount>>,
+
+    /// Protocol token account of the Phoenix market's quote mint, owned by the signer
+    #[account(mut, token::authority = signer)]
+    pub quote_account: Box<Account<'info, TokenAccount>>,
+
+    /// Token account of the Phoenix market's base mint owned by the trader, only holding funds during a Phoenix CPI
+    #[account(mut, token::authority = trader)]
+    pub trader_base_account: Box<Account<'info, TokenAccount>>,
+
+    /// Token account of the Phoenix market's quote mint owned by the trader, only holding funds during a Phoenix CPI
+    #[account(mut, token::authority = trader)]
+    pub trader_quote_account: Box<Account<'info, TokenAccount>>,
+
+    /// Market of the protocol's quote asset, checked against its market index
+    pub quote_market: AccountLoader<'info, crate::state::market::Market>,
+
+    /// CHECK: Phoenix base vault, checked by the Phoenix program
+    #[account(mut)]
+    pub phoenix_base_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Phoenix quote vault, checked by the Phoenix program
+    #[account(mut)]
+    pub phoenix_quote_vault: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+/// Accounts required to manage the Phoenix seat of a user's trader PDA. The protocol's signer is the authority of the
+/// Phoenix market, which lets it approve and evict seats.
+#[derive(Accounts)]
+pub struct PhoenixSeatAccounts<'info> {
+    pub phoenix_program: Program<'info, Phoenix>,
+
+    /// CHECK: Phoenix log authority, checked against its address
+    #[account(address = phoenix::phoenix_log_authority::id())]
+    pub phoenix_log_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Checked against the market's phoenix market
+    #[account(mut)]
+    pub phoenix_market: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol PDA and authority of the Phoenix market, checked against the state's signer
+    pub signer: UncheckedAccount<'info>,
+
+    /// CHECK: Per-user PDA the user's orders are placed under, checked against its address
+    pub trader: UncheckedAccount<'info>,
+
+    /// CHECK: Phoenix seat of the trader, checked against its address
+    #[account(mut)]
+    pub seat: UncheckedAccount<'info>,
+}
+
+/// Returns the address and bump of the per-user PDA that holds a seat on Phoenix markets
+pub fn get_phoenix_trader_address(user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"phoenix_trader", user.as_ref()], &crate::id())
+}
+
+/// Returns true if the trader is registered on the Phoenix market, i.e it holds a seat that has not been evicted
+pub fn is_phoenix_trader_registered(
+    account_info: &AccountInfo,
+    trader: &Pubkey
+) -> SpedXSpotResult<bool> {
+    let market_data = account_info.data.borrow();
+
+    Ok(load_phoenix_market(&market_data)?.get_trader_state(trader).is_some())
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
 pub struct PhoenixOrderIDs {
     pub price_in_ticks: u64,
     pub unique_order_num: u64,
 }
 
+/// Loads the header of a Phoenix market account. The account must be owned by the Phoenix program and hold a Phoenix
+/// market, a spoofed account fails instead of being read.
 pub fn fetch_market_header(account_info: &AccountInfo) -> SpedXSpotResult<MarketHeader> {
-    assert_eq!(account_info.owner, &phoenix::id());
+    validate!(
+        account_info.owner == &phoenix::id(),
+        ErrorCode::CannotLoadPhoenixProgram,
+        "Phoenix market {} is owned by {}, not the Phoenix program",
+        account_info.key,
+        account_info.owner
+    )?;
+
     let market = account_info.data.borrow();
+
+    validate!(
+        market.len() >= size_of::<MarketHeader>(),
+        ErrorCode::FailedToDeserializePhoenixMarket,
+        "Phoenix market {} is too small to hold a market header",
+        account_info.key
+    )?;
+
     let market_header = try_from_bytes::<MarketHeader>(&market[..size_of::<MarketHeader>()]).map_err(|_| {
         msg!("Failed to deserialize phoenix market");
         ErrorCode::FailedToDeserializePhoenixMarket
     })?;
 
-    assert_eq!(market_header.discriminant, PHOENIX_MARKET_DISCRIMINANT);
+    validate!(
+        market_header.discriminant == PHOENIX_MARKET_DISCRIMINANT,
+        ErrorCode::FailedToDeserializePhoenixMarket,
+        "Phoenix market {} has an invalid discriminant",
+        account_info.key
+    )?;
 
     Ok(*market_header)
 }
 
-pub fn get_price(
-    base_price: u128,
-    quote_price: u128,
-    market_header: &MarketHeader
-) -> u64 {
-    let price = (base_price * (u64::pow(10, market_header.quote_params.decimals as u32) as u128)
-        * (market_header.raw_base_units_per_base_unit as u128)
-        / market_header.get_tick_size_in_quote_atoms_per_base_unit().as_u128() / quote_price) as u64;
+/// Validates a Phoenix market header against the market it is registered for: its base mint must be the market's token
+/// mint, its quote mint the protocol's quote mint, and the Phoenix market must be initialized and not tombstoned
+pub fn validate_phoenix_market_header(
+    market: &crate::state::market::Market,
+    market_header: &MarketHeader,
+    quote_mint: &Pubkey
+) -> SpedXSpotResult {
+    validate!(
+        market_header.base_params.mint_key == market.token_mint,
+        ErrorCode::InvalidPhoenixMarket,
+        "Phoenix market base mint {} does not match market mint {}",
+        market_header.base_params.mint_key,
+        market.token_mint
+    )?;
+
+    validate!(
+        market_header.quote_params.mint_key == *quote_mint,
+        ErrorCode::InvalidPhoenixMarket,
+        "Phoenix market quote mint {} does not match quote mint {}",
+        market_header.quote_params.mint_key,
+        quote_mint
+    )?;
+
+    let status = get_phoenix_market_status(market_header)?;
+
+    validate!(
+        !matches!(status, PhoenixMarketStatus::Uninitialized | PhoenixMarketStatus::Tombstoned),
+        ErrorCode::InvalidPhoenixMarket,
+        "Phoenix market status {:?} is not usable",
+        status
+    )
+}
+
+/// Returns the status of a Phoenix market. Unlike Phoenix's own conversion, an unknown status fails instead of panicking.
+pub fn get_phoenix_market_status(market_header: &MarketHeader) -> SpedXSpotResult<PhoenixMarketStatus> {
+    match market_header.status {
+        0 => Ok(PhoenixMarketStatus::Uninitialized),
+        1 => Ok(PhoenixMarketStatus::Active),
+        2 => Ok(PhoenixMarketStatus::PostOnly),
+        3 => Ok(PhoenixMarketStatus::Paused),
+        4 => Ok(PhoenixMarketStatus::Closed),
+        5 => Ok(PhoenixMarketStatus::Tombstoned),
+        status => {
+            msg!("Phoenix market has an unknown status {}", status);
+            Err(ErrorCode::FailedToDeserializePhoenixMarket)
+        }
+    }
+}
+
+/// Loads the Phoenix market(without its header) from the market account's data
+pub fn load_phoenix_market(
+    market_data: &[u8]
+) -> SpedXSpotResult<&dyn Market<Pubkey, FIFOOrderId, FIFORestingOrder, OrderPacket>> {
+    validate!(
+        market_data.len() >= size_of::<MarketHeader>(),
+        ErrorCode::FailedToDeserializePhoenixMarket,
+        "Phoenix market is too small to hold a market header"
+    )?;
+
+    let (header_bytes, market_bytes) = market_data.split_at(size_of::<MarketHeader>());
+
+    let market_header = try_from_bytes::<MarketHeader>(header_bytes).map_err(|_| {
+        msg!("Failed to deserialize phoenix market header");
+        ErrorCode::FailedToDeserializePhoenixMarket
+    })?;
+
+    let market = load_with_dispatch(&market_header.market_size_params, market_bytes).map_err(|_| {
+        msg!("Failed to deserialize phoenix market");
+        ErrorCode::FailedToDeserializePhoenixMarket
+    })?;
+
+    Ok(market.inner)
+}
+
+/// Returns the sequence number that Phoenix will assign to the next order placed on the given side.
+/// Phoenix inverts the sequence numbers of bids, so that older bids sort first at the same price.
+pub fn get_next_phoenix_order_sequence_number(
+    account_info: &AccountInfo,
+    side: Side
+) -> SpedXSpotResult<u64> {
+    let market_data = account_info.data.borrow();
+    let sequence_number = load_phoenix_market(&market_data)?.get_sequence_number();
+
+    Ok(match side {
+        Side::Bid => !sequence_number,
+        Side::Ask => sequence_number
+    })
+}
+
+/// Returns the number of base lots still resting on the Phoenix book for an order, 0 if the order is no longer on the book
+pub fn get_phoenix_resting_order_base_lots(
+    account_info: &AccountInfo,
+    side: Side,
+    price_in_ticks: u64,
+    order_sequence_number: u64
+) -> SpedXSpotResult<u64> {
+    let market_data = account_info.data.borrow();
+    let market = load_phoenix_market(&market_data)?;
+
+    let order_id = FIFOOrderId::new_from_untyped(price_in_ticks, order_sequence_number);
+
+    Ok(market
+        .get_book(side)
+        .get(&order_id)
+        .map(|resting_order| resting_order.num_base_lots.as_u64())
+        .unwrap_or(0))
+}
+
+/// Returns the price in ticks and the number of base lots still resting of the order with the given sequence number on
+/// a side of the Phoenix book, None if the order is no longer on the book. Used for orders whose price Phoenix may have
+/// amended, e.g post-only quotes that crossed the book.
+pub fn find_phoenix_resting_order(
+    account_info: &AccountInfo,
+    side: Side,
+    order_sequence_number: u64
+) -> SpedXSpotResult<Option<(u64, u64)>> {
+    let market_data = account_info.data.borrow();
+    let market = load_phoenix_market(&market_data)?;
+
+    let resting_order = market
+        .get_book(side)
+        .iter()
+        .find(|(order_id, _)| order_id.order_sequence_number == order_sequence_number)
+        .map(|(order_id, resting_order)| (order_id.price_in_ticks.as_u64(), resting_order.num_base_lots.as_u64()));
+
+    Ok(resting_order)
+}
+
+/// An order resting on the Phoenix book that an incoming order would match
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhoenixCrossingOrder {
+    pub price_in_ticks: u64,
+    pub order_sequence_number: u64,
+    pub num_base_lots: u64,
+    /// Whether the order was placed by the given trader
+    pub is_trader: bool
+}
+
+/// Returns the resting orders an order on the given side would match up to its limit price, in the order Phoenix
+/// matches them: best price first, then oldest first. Without a limit price, the whole opposite side is returned.
+pub fn get_phoenix_crossing_orders(
+    account_info: &AccountInfo,
+    side: Side,
+    limit_price_in_ticks: Option<u64>,
+    trader: &Pubkey
+) -> SpedXSpotResult<Vec<PhoenixCrossingOrder>> {
+    let market_data = account_info.data.borrow();
+    let market = load_phoenix_market(&market_data)?;
+
+    let trader_index = market.get_trader_index(trader).map(|trader_index| trader_index as u64);
+
+    let mut crossing_orders: Vec<PhoenixCrossingOrder> = market
+        .get_book(side.opposite())
+        .iter()
+        .filter(|(order_id, _)| match (side, limit_price_in_ticks) {
+            (Side::Bid, Some(limit_price_in_ticks)) => order_id.price_in_ticks.as_u64() <= limit_price_in_ticks,
+            (Side::Ask, Some(limit_price_in_ticks)) => order_id.price_in_ticks.as_u64() >= limit_price_in_ticks,
+            (_, None) => true
+        })
+        .map(|(order_id, resting_order)| PhoenixCrossingOrder {
+            price_in_ticks: order_id.price_in_ticks.as_u64(),
+            order_sequence_number: order_id.order_sequence_number,
+            num_base_lots: resting_order.num_base_lots.as_u64(),
+            is_trader: trader_index == Some(resting_order.trader_index)
+        })
+        .collect();
+
+    // a bid matches the lowest asks first, an ask the highest bids. Bid sequence numbers are inverted, so the larger one
+    // is the older bid
+    crossing_orders.sort_by(|a, b| match side {
+        Side::Bid => (a.price_in_ticks, a.order_sequence_number).cmp(&(b.price_in_ticks, b.order_sequence_number)),
+        Side::Ask => (b.price_in_ticks, b.order_sequence_number).cmp(&(a.price_in_ticks, a.order_sequence_number))
+    });
+
+    Ok(crossing_orders)
+}
+
+/// Returns the best price on the opposite side of the Phoenix book for an order on the given side, i.e the best ask for
+/// a bid and the best bid for an ask. None if that side of the book is empty.
+pub fn get_phoenix_best_opposite_price_in_ticks(
+    account_info: &AccountInfo,
+    side: Side
+) -> SpedXSpotResult<Option<u64>> {
+    let market_data = account_info.data.borrow();
+    let market = load_phoenix_market(&market_data)?;
+
+    let opposite_prices = market
+        .get_book(side.opposite())
+        .iter()
+        .map(|(order_id, _)| order_id.price_in_ticks.as_u64());
+
+    Ok(match side {
+        Side::Bid => opposite_prices.min(),
+        Side::Ask => opposite_prices.max()
+    })
+}
+
+/// Converts between the protocol's units and the units of a Phoenix market. All conversions are checked and round
+/// with a single division at the end, so that they never favour the user:
+/// - limit prices are never loosened, i.e bids round down and asks round up
+/// - amounts the user pays round up and amounts the user receives round down
+/// - order sizes round down, so that an order is never larger than requested
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PhoenixMarketConverter {
+    /// Base atoms per base lot
+    pub base_lot_size: u64,
+    /// Quote atoms per quote lot
+    pub quote_lot_size: u64,
+    /// Quote atoms per base unit per tick
+    pub tick_size: u64,
+    /// Whole base tokens per Phoenix base unit
+    pub raw_base_units_per_base_unit: u64,
+    pub base_decimals: u32,
+    pub quote_decimals: u32
+}
+
+impl PhoenixMarketConverter {
+    pub fn new(market_header: &MarketHeader) -> SpedXSpotResult<Self> {
+        let converter = PhoenixMarketConverter {
+            base_lot_size: market_header.get_base_lot_size().as_u64(),
+            quote_lot_size: market_header.get_quote_lot_size().as_u64(),
+            tick_size: market_header.get_tick_size_in_quote_atoms_per_base_unit().as_u64(),
+            raw_base_units_per_base_unit: market_header.raw_base_units_per_base_unit.cast()?,
+            base_decimals: market_header.base_params.decimals,
+            quote_decimals: market_header.quote_params.decimals
+        };
+
+        converter.validate()?;
+
+        Ok(converter)
+    }
+
+    /// Rejects markets whose lot sizes, tick size or base unit are zero, as every conversion divides by them
+    pub fn validate(&self) -> SpedXSpotResult {
+        validate!(
+            self.base_lot_size > 0
+                && self.quote_lot_size > 0
+                && self.tick_size > 0
+                && self.raw_base_units_per_base_unit > 0,
+            ErrorCode::InvalidPhoenixMarket,
+            "Phoenix market has a zero lot size, tick size or base unit"
+        )?;
+
+        validate!(
+            10_u128.checked_pow(self.base_decimals).is_some() && 10_u128.checked_pow(self.quote_decimals).is_some(),
+            ErrorCode::InvalidPhoenixMarket,
+            "Phoenix market has invalid decimals"
+        )
+    }
+
+    /// Converts a limit price to Phoenix ticks. Bids round down and asks round up to the nearest tick.
+    /// price precision: PRICE_PRECISION
+    pub fn price_to_ticks(
+        &self,
+        price: u64,
+        direction: PositionDirection
+    ) -> SpedXSpotResult<u64> {
+        // ticks = price * raw base units per base unit * 10^quote decimals / (PRICE_PRECISION * tick size)
+        let numerator = price
+            .cast::<u128>()?
+            .safe_mul(self.raw_base_units_per_base_unit.cast()?)?
+            .safe_mul(self.quote_atoms_per_quote_unit()?)?;
+
+        let denominator = PRICE_PRECISION.safe_mul(self.tick_size.cast()?)?;
+
+        div_round(numerator, denominator, is_limit_rounded_up(direction)?)?.cast()
+    }
+
+    /// Converts Phoenix ticks to a limit price. Bids round down and asks round up to the nearest price unit.
+    /// price precision: PRICE_PRECISION
+    pub fn ticks_to_price(
+        &self,
+        price_in_ticks: u64,
+        direction: PositionDirection
+    ) -> SpedXSpotResult<u64> {
+        let numerator = price_in_ticks
+            .cast::<u128>()?
+            .safe_mul(self.tick_size.cast()?)?
+            .safe_mul(PRICE_PRECISION)?;
+
+        let denominator = self
+            .quote_atoms_per_quote_unit()?
+            .safe_mul(self.raw_base_units_per_base_unit.cast()?)?;
+
+        div_round(numerator, denominator, is_limit_rounded_up(direction)?)?.cast()
+    }
+
+    /// Converts a base asset amount to Phoenix base lots, rounding down to the nearest lot
+    /// base asset amount precision: token mint precision
+    pub fn base_atoms_to_lots(&self, base_atoms: u64) -> SpedXSpotResult<u64> {
+        base_atoms.safe_div(self.base_lot_size)
+    }
 
-    price
-}
\ No newline at end of file
+    /// Converts Phoenix base lots to a base asset amount
+    /// base asset amount precision: token mint precision
+    pub fn base_lots_to_atoms(&self, num_base_lots: u64) -> SpedXSpotResult<u64> {
+        num_base_lots.safe_mul(self.base_lot_size)
+    }
+
+    /// Converts a base asset amount to Phoenix base lots, rounding down to the nearest lot
+    /// base asset amount precision: BASE_PRECISION
+    pub fn base_amount_to_lots(&self, base_asset_amount: u64) -> SpedXSpotResult<u64> {
+        let numerator = base_asset_amount.cast::<u128>()?.safe_mul(self.base_atoms_per_base_token()?)?;
+        let denominator = BASE_PRECISION.safe_mul(self.base_lot_size.cast()?)?;
+
+        div_round(numerator, denominator, false)?.cast()
+    }
+
+    /// Converts Phoenix base lots to a base asset amount. The base the user receives, i.e when going long, rounds down
+    /// and the base the user delivers rounds up.
+    /// base asset amount precision: BASE_PRECISION
+    pub fn base_lots_to_amount(
+        &self,
+        num_base_lots: u64,
+        direction: PositionDirection
+    ) -> SpedXSpotResult<u64> {
+        let numerator = num_base_lots
+            .cast::<u128>()?
+            .safe_mul(self.base_lot_size.cast()?)?
+            .safe_mul(BASE_PRECISION)?;
+
+        div_round(numerator, self.base_atoms_per_base_token()?, !is_payment_rounded_up(direction)?)?.cast()
+    }
+
+    /// Converts quote atoms to Phoenix quote lots. The quote the user pays, i.e when going long, rounds up and the quote
+    /// the user receives rounds down.
+    pub fn quote_atoms_to_lots(
+        &self,
+        quote_atoms: u64,
+        direction: PositionDirection
+    ) -> SpedXSpotResult<u64> {
+        div_round(quote_atoms.cast()?, self.quote_lot_size.cast()?, is_payment_rounded_up(direction)?)?.cast()
+    }
+
+    /// Converts Phoenix quote lots to quote atoms
+    pub fn quote_lots_to_atoms(&self, num_quote_lots: u64) -> SpedXSpotResult<u64> {
+        num_quote_lots.safe_mul(self.quote_lot_size)
+    }
+
+    /// Converts quote atoms to a quote asset amount. The quote the user pays rounds up and the quote the user receives
+    /// rounds down.
+    /// quote asset amount precision: QUOTE_PRECISION
+    pub fn quote_atoms_to_amount(
+        &self,
+        quote_atoms: u64,
+        direction: PositionDirection
+    ) -> SpedXSpotResult<u64> {
+        let numerator = quote_atoms.cast::<u128>()?.safe_mul(QUOTE_PRECISION)?;
+
+        div_round(numerator, self.quote_atoms_per_quote_unit()?, is_payment_rounded_up(direction)?)?.cast()
+    }
+
+    /// Converts a quote asset amount to Phoenix quote lots. The quote the user pays rounds up and the quote the user
+    /// receives rounds down.
+    /// quote asset amount precision: QUOTE_PRECISION
+    pub fn quote_amount_to_lots(
+        &self,
+        quote_asset_amount: u64,
+        direction: PositionDirection
+    ) -> SpedXSpotResult<u64> {
+        let numerator = quote_asset_amount.cast::<u128>()?.safe_mul(self.quote_atoms_per_quote_unit()?)?;
+        let denominator = QUOTE_PRECISION.safe_mul(self.quote_lot_size.cast()?)?;
+
+        div_round(numerator, denominator, is_payment_rounded_up(direction)?)?.cast()
+    }
+
+    /// Value of a fill of the given base asset amount at a price in ticks. The quote the user pays rounds up and the
+    /// quote the user receives rounds down.
+    /// base asset amount precision: token mint precision, quote asset amount precision: QUOTE_PRECISION
+    pub fn fill_quote_amount(
+        &self,
+        base_atoms: u64,
+        price_in_ticks: u64,
+        direction: PositionDirection
+    ) -> SpedXSpotResult<u64> {
+        // quote atoms = base atoms * ticks * tick size / (raw base units per base unit * 10^base decimals)
+        let numerator = base_atoms
+            .cast::<u128>()?
+            .safe_mul(price_in_ticks.cast()?)?
+            .safe_mul(self.tick_size.cast()?)?
+            .safe_mul(QUOTE_PRECISION)?;
+
+        let denominator = self
+            .base_atoms_per_base_token()?
+            .safe_mul(self.raw_base_units_per_base_unit.cast()?)?
+            .safe_mul(self.quote_atoms_per_quote_unit()?)?;
+
+        div_round(numerator, denominator, is_payment_rounded_up(direction)?)?.cast()
+    }
+
+    fn base_atoms_per_base_token(&self) -> SpedXSpotResult<u128> {
+        10_u128.checked_pow(self.base_decimals).safe_unwrap()
+    }
+
+    fn quote_atoms_per_quote_unit(&self) -> SpedXSpotResult<u128> {
+        10_u128.checked_pow(self.quote_decimals).safe_unwrap()
+    }
+}
+
+/// Limit prices are rounded so that they are never loosened: bids round down and asks round up
+pub fn is_limit_rounded_up(direction: PositionDirection) -> SpedXSpotResult<bool> {
+    match direction {
+        PositionDirection::Long => Ok(false),
+        PositionDirection::Short => Ok(true),
+        PositionDirection::TwoWay => Err(ErrorCode::InvalidOrderDirection)
+    }
+}
+
+/// Amounts paid by the user, i.e the quote of a long, round up. Amounts received by the user round down.
+pub fn is_payment_rounded_up(direction: PositionDirection) -> SpedXSpotResult<bool> {
+    match direction {
+        PositionDirection::Long => Ok(true),
+        PositionDirection::Short => Ok(false),
+        PositionDirection::TwoWay => Err(ErrorCode::InvalidOrderDirection)
+    }
+}
+
+pub fn div_round(numerator: u128, denominator: u128, round_up: bool) -> SpedXSpotResult<u128> {
+    if round_up {
+        numerator.safe_ceil_div(denominator)
+    } else {
+        numerator.safe_div(denominator)
+    }
+}
+
+/// Returns the base and quote atoms a trader holds in its Phoenix seat: its free funds, i.e deposited on the market but
+/// not locked, plus the funds locked in its resting orders
+pub fn get_phoenix_trader_atoms(
+    account_info: &AccountInfo,
+    trader: &Pubkey,
+    converter: &PhoenixMarketConverter
+) -> SpedXSpotResult<(u64, u64)> {
+    let market_data = account_info.data.borrow();
+    let market = load_phoenix_market(&market_data)?;
+
+    match market.get_trader_state(trader) {
+        Some(trader_state) => Ok((
+            converter.base_lots_to_atoms(
+                trader_state.base_lots_free.as_u64().safe_add(trader_state.base_lots_locked.as_u64())?
+            )?,
+            converter.quote_lots_to_atoms(
+                trader_state.quote_lots_free.as_u64().safe_add(trader_state.quote_lots_locked.as_u64())?
+            )?
+        )),
+        None => Ok((0, 0))
+    }
+}
+
+/// Returns the base and quote atoms behind a user's orders: the token accounts of the protocol's signer and of the
+/// user's trader, plus the trader's free and locked funds on Phoenix. Funds moved between the signer and the trader,
+/// or locked in or released from resting orders, do not change it, only trades do.
+pub fn get_phoenix_signer_atoms(
+    phoenix_accounts: &PhoenixMarketAccounts,
+    converter: &PhoenixMarketConverter
+) -> SpedXSpotResult<(u64, u64)> {
+    let (base_atoms_on_market, quote_atoms_on_market) = get_phoenix_trader_atoms(
+        &phoenix_accounts.phoenix_market,
+        &phoenix_accounts.trader.key(),
+        converter
+    )?;
+
+    let mut base_token_amount = base_atoms_on_market;
+    let mut quote_token_amount = quote_atoms_on_market;
+
+    for (base_account, quote_account) in [
+        (&phoenix_accounts.base_account, &phoenix_accounts.quote_account),
+        (&phoenix_accounts.trader_base_account, &phoenix_accounts.trader_quote_account)
+    ] {
+        base_token_amount = base_token_amount.safe_add(
+            token::accessor::amount(&base_account.to_account_info()).map_err(|_| ErrorCode::PhoenixCpiFailed)?
+        )?;
+        quote_token_amount = quote_token_amount.safe_add(
+            token::accessor::amount(&quote_account.to_account_info()).map_err(|_| ErrorCode::PhoenixCpiFailed)?
+        )?;
+    }
+
+    Ok((base_token_amount, quote_token_amount))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// SOL/USDC: 0.001 SOL lots, 1 USDC atom quote lots and a $0.001 tick
+    fn get_sol_usdc_converter() -> PhoenixMarketConverter {
+        PhoenixMarketConverter {
+            base_lot_size: 1_000_000,
+            quote_lot_size: 1,
+            tick_size: 1_000,
+            raw_base_units_per_base_unit: 1,
+            base_decimals: 9,
+            quote_decimals: 6
+        }
+    }
+
+    fn get_converters() -> Vec<PhoenixMarketConverter> {
+        vec![
+            get_sol_usdc_converter(),
+            // BONK/USDC: 1000 BONK base units, lots and ticks finer than a price unit
+            PhoenixMarketConverter {
+                base_lot_size: 100_000,
+                quote_lot_size: 1,
+                tick_size: 1,
+                raw_base_units_per_base_unit: 1_000,
+                base_decimals: 5,
+                quote_decimals: 6
+            },
+            // ETH/USDC with 8 decimal base and 10 atom quote lots
+            PhoenixMarketConverter {
+                base_lot_size: 1_000,
+                quote_lot_size: 10,
+                tick_size: 10_000,
+                raw_base_units_per_base_unit: 1,
+                base_decimals: 8,
+                quote_decimals: 6
+            },
+            // quote token with more decimals than QUOTE_PRECISION and a tick that does not divide evenly
+            PhoenixMarketConverter {
+                base_lot_size: 7,
+                quote_lot_size: 3_000,
+                tick_size: 33_333,
+                raw_base_units_per_base_unit: 3,
+                base_decimals: 6,
+                quote_decimals: 9
+            }
+        ]
+    }
+
+    #[test]
+    fn zero_market_fields_are_rejected() {
+        assert_eq!(get_sol_usdc_converter().validate(), Ok(()));
+
+        let zero_field_converters = [
+            PhoenixMarketConverter { base_lot_size: 0, ..get_sol_usdc_converter() },
+            PhoenixMarketConverter { quote_lot_size: 0, ..get_sol_usdc_converter() },
+            PhoenixMarketConverter { tick_size: 0, ..get_sol_usdc_converter() },
+            PhoenixMarketConverter { raw_base_units_per_base_unit: 0, ..get_sol_usdc_converter() },
+            PhoenixMarketConverter { quote_decimals: 40, ..get_sol_usdc_converter() },
+        ];
+
+        for converter in zero_field_converters {
+            assert_eq!(converter.validate(), Err(ErrorCode::InvalidPhoenixMarket));
+        }
+
+        // the conversions themselves error instead of panicking, even on a converter that skipped validation
+        let converter = PhoenixMarketConverter { tick_size: 0, ..get_sol_usdc_converter() };
+        assert!(converter.price_to_ticks(PRICE_PRECISION_U64, PositionDirection::Long).is_err());
+
+        // and on values that overflow
+        assert!(get_sol_usdc_converter().ticks_to_price(u64::MAX, PositionDirection::Long).is_err());
+    }
+
+    /// Deterministic xorshift generator, so that the properties are checked on the same values on every run
+    struct Generator(u64);
+
+    impl Generator {
+        fn next_below(&mut self, max: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % max
+        }
+    }
+
+    const NUM_SAMPLES: usize = 2_000;
+
+    #[test]
+    fn sol_usdc_conversions() {
+        let converter = get_sol_usdc_converter();
+
+        // $100.0005 is between two ticks
+        assert_eq!(converter.price_to_ticks(100_000_500, PositionDirection::Long).unwrap(), 100_000);
+        assert_eq!(converter.price_to_ticks(100_000_500, PositionDirection::Short).unwrap(), 100_001);
+        assert_eq!(converter.ticks_to_price(100_000, PositionDirection::Long).unwrap(), 100_000_000);
+
+        assert_eq!(converter.base_atoms_to_lots(1_500_999).unwrap(), 1);
+        assert_eq!(converter.base_lots_to_atoms(2).unwrap(), 2_000_000);
+        assert_eq!(converter.base_amount_to_lots(1_500_999).unwrap(), 1);
+        assert_eq!(converter.base_lots_to_amount(2, PositionDirection::Long).unwrap(), 2_000_000);
+
+        assert_eq!(converter.quote_amount_to_lots(1_500_000, PositionDirection::Long).unwrap(), 1_500_000);
+        assert_eq!(converter.quote_atoms_to_amount(1_500_000, PositionDirection::Short).unwrap(), 1_500_000);
+
+        // 0.0015 SOL at $100.001 is worth $0.1500015
+        assert_eq!(converter.fill_quote_amount(1_500_000, 100_001, PositionDirection::Long).unwrap(), 150_002);
+        assert_eq!(converter.fill_quote_amount(1_500_000, 100_001, PositionDirection::Short).unwrap(), 150_001);
+
+        assert_eq!(converter.price_to_ticks(100_000_000, PositionDirection::TwoWay), Err(ErrorCode::InvalidOrderDirection));
+    }
+
+    #[test]
+    fn price_round_trips_never_loosen_the_limit() {
+        let mut generator = Generator(0x2545_f491_4f6c_dd1d);
+
+        for converter in get_converters() {
+            for _ in 0..NUM_SAMPLES {
+                let price = generator.next_below(1_000_000_000_000);
+                let price_in_ticks = generator.next_below(1_000_000_000);
+
+                let bid_ticks = converter.price_to_ticks(price, PositionDirection::Long).unwrap();
+                let ask_ticks = converter.price_to_ticks(price, PositionDirection::Short).unwrap();
+                assert!(converter.ticks_to_price(bid_ticks, PositionDirection::Long).unwrap() <= price);
+                assert!(converter.ticks_to_price(ask_ticks, PositionDirection::Short).unwrap() >= price);
+                assert!(ask_ticks.safe_sub(bid_ticks).unwrap() <= 1);
+
+                let bid_price = converter.ticks_to_price(price_in_ticks, PositionDirection::Long).unwrap();
+                let ask_price = converter.ticks_to_price(price_in_ticks, PositionDirection::Short).unwrap();
+                assert!(converter.price_to_ticks(bid_price, PositionDirection::Long).unwrap() <= price_in_ticks);
+                assert!(converter.price_to_ticks(ask_price, PositionDirection::Short).unwrap() >= price_in_ticks);
+            }
+        }
+    }
+
+    #[test]
+    fn base_round_trips_never_exceed_the_order_size() {
+        let mut generator = Generator(0x9e37_79b9_7f4a_7c15);
+
+        for converter in get_converters() {
+            let base_atoms_per_base_token = converter.base_atoms_per_base_token().unwrap();
+
+            for _ in 0..NUM_SAMPLES {
+                let base_atoms = generator.next_below(1_000_000_000_000_000);
+
+                let num_base_lots = converter.base_atoms_to_lots(base_atoms).unwrap();
+                let standardized_base_atoms = converter.base_lots_to_atoms(num_base_lots).unwrap();
+                assert!(standardized_base_atoms <= base_atoms);
+                assert!(base_atoms - standardized_base_atoms < converter.base_lot_size);
+
+                let base_asset_amount = generator.next_below(1_000_000_000_000_000);
+                let num_base_lots = converter.base_amount_to_lots(base_asset_amount).unwrap();
+                assert!(converter.base_lots_to_amount(num_base_lots, PositionDirection::Long).unwrap() <= base_asset_amount);
+
+                // the base a user receives is never more, and the base a user delivers never less, than the lots
+                let exact_base_asset_amount = (num_base_lots as u128) * (converter.base_lot_size as u128) * BASE_PRECISION;
+                let received = converter.base_lots_to_amount(num_base_lots, PositionDirection::Long).unwrap() as u128;
+                let delivered = converter.base_lots_to_amount(num_base_lots, PositionDirection::Short).unwrap() as u128;
+                assert!(received * base_atoms_per_base_token <= exact_base_asset_amount);
+                assert!(delivered * base_atoms_per_base_token >= exact_base_asset_amount);
+            }
+        }
+    }
+
+    #[test]
+    fn quote_round_trips_never_favour_the_user() {
+        let mut generator = Generator(0xd1b5_4a32_d192_ed03);
+
+        for converter in get_converters() {
+            let quote_atoms_per_quote_unit = converter.quote_atoms_per_quote_unit().unwrap();
+
+            for _ in 0..NUM_SAMPLES {
+                let quote_asset_amount = generator.next_below(1_000_000_000_000);
+                let exact_quote_atoms = (quote_asset_amount as u128) * quote_atoms_per_quote_unit;
+
+                // a long pays at least the amount, a short receives at most the amount
+                let paid_lots = converter.quote_amount_to_lots(quote_asset_amount, PositionDirection::Long).unwrap();
+                let received_lots = converter.quote_amount_to_lots(quote_asset_amount, PositionDirection::Short).unwrap();
+                assert!((converter.quote_lots_to_atoms(paid_lots).unwrap() as u128) * QUOTE_PRECISION >= exact_quote_atoms);
+                assert!((converter.quote_lots_to_atoms(received_lots).unwrap() as u128) * QUOTE_PRECISION <= exact_quote_atoms);
+
+                let quote_atoms = generator.next_below(1_000_000_000_000_000);
+                let paid = converter.quote_atoms_to_amount(quote_atoms, PositionDirection::Long).unwrap() as u128;
+                let received = converter.quote_atoms_to_amount(quote_atoms, PositionDirection::Short).unwrap() as u128;
+                assert!(paid * quote_atoms_per_quote_unit >= (quote_atoms as u128) * QUOTE_PRECISION);
+                assert!(received * quote_atoms_per_quote_unit <= (quote_atoms as u128) * QUOTE_PRECISION);
+                assert!(paid - received <= 1);
+
+                let paid_lots = converter.quote_atoms_to_lots(quote_atoms, PositionDirection::Long).unwrap();
+                let received_lots = converter.quote_atoms_to_lots(quote_atoms, PositionDirection::Short).unwrap();
+                assert!(converter.quote_lots_to_atoms(paid_lots).unwrap() >= quote_atoms);
+                assert!(converter.quote_lots_to_atoms(received_lots).unwrap() <= quote_atoms);
+            }
+        }
+    }
+
+    #[test]
+    fn fills_never_favour_the_user() {
+        let mut generator = Generator(0x94d0_49bb_1331_11eb);
+
+        for converter in get_converters() {
+            let denominator = converter.base_atoms_per_base_token().unwrap()
+                * (converter.raw_base_units_per_base_unit as u128)
+                * converter.quote_atoms_per_quote_unit().unwrap();
+
+            for _ in 0..NUM_SAMPLES {
+                let base_atoms = generator.next_below(1_000_000_000_000_000);
+                let price_in_ticks = generator.next_below(10_000_000);
+
+                let exact_quote_asset_amount = (base_atoms as u128)
+                    * (price_in_ticks as u128)
+                    * (converter.tick_size as u128)
+                    * QUOTE_PRECISION;
+
+                let paid = converter.fill_quote_amount(base_atoms, price_in_ticks, PositionDirection::Long).unwrap() as u128;
+                let received = converter.fill_quote_amount(base_atoms, price_in_ticks, PositionDirection::Short).unwrap() as u128;
+
+                assert!(paid * denominator >= exact_quote_asset_amount);
+                assert!(received * denominator <= exact_quote_asset_amount);
+                assert!(paid - received <= 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! User account holding a user's isolated positions and open orders.
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    error::{
+        SpedXSpotResult,
+        ErrorCode
+    },
+    math::constants::{
+        MAX_OPEN_ORDERS,
+        MAX_SPOT_POSITIONS
+    },
+    state::{
+        enums::{
+            OrderStatus,
+            UserStatus
+        },
+        order::Order,
+        traits::Size,
+        user_position::Position
+    }
+};
+
+/// A user's margin account. A wallet can open multiple accounts, each identified by a sub account id.
+/// Each position in the account is isolated, i.e it is only margined against its own market.
+#[account(zero_copy)]
+#[derive(Default, Eq, PartialEq, Debug)]
+#[repr(C)]
+pub struct User {
+    /// The wallet that owns the account and signs for it
+    pub authority: Pubkey,
+
+    /// Positions of the user, at most one per market
+    pub positions: [Position; MAX_SPOT_POSITIONS as usize],
+
+    /// Orders of the user. A slot is free when its status is NotInitialized
+    pub orders: [Order; MAX_OPEN_ORDERS as usize],
+
+    /// Order id assigned to the next order placed by the user
+    pub next_order_id: u32,
+
+    /// Identifier of the account among the authority's accounts
+    pub sub_account_id: u16,
+
+    /// Current status of the user
+    pub status: UserStatus,
+
+    pub padding: [u8; 9],
+}
+
+impl Size for User {
+    const SIZE: usize = 8 + 32 + 88 * MAX_SPOT_POSITIONS as usize + 120 * MAX_OPEN_ORDERS as usize + 16;
+}
+
+impl User {
+    /// Returns the index of the user's position in a market
+    pub fn get_position_index(&self, market_index: u16) -> SpedXSpotResult<usize> {
+        self.positions
+            .iter()
+            .position(|position| position.market_index == market_index && !position.is_empty())
+            .ok_or(ErrorCode::UserHasNoPositionInMarket)
+    }
+
+    /// Returns the user's position in a market
+    pub fn get_position(&self, market_index: u16) -> SpedXSpotResult<&Position> {
+        Ok(&self.positions[self.get_position_index(market_index)?])
+    }
+
+    /// Returns the user's position in a market mutably
+    pub fn get_position_mut(&mut self, market_index: u16) -> SpedXSpotResult<&mut Position> {
+        let position_index = self.get_position_index(market_index)?;
+        Ok(&mut self.positions[position_index])
+    }
+
+    /// Returns the user's position in a market, opening an empty one in a free slot if the user has none
+    pub fn force_get_position_mut(&mut self, market_index: u16) -> SpedXSpotResult<&mut Position> {
+        let position_index = match self.get_position_index(market_index) {
+            Ok(position_index) => position_index,
+            Err(_) => {
+                let position_index = self
+                    .positions
+                    .iter()
+                    .position(|position| position.is_empty())
+                    .ok_or(ErrorCode::NoPositionAvailable)?;
+
+                self.positions[position_index] = Position {
+                    market_index,
+                    ..Position::default()
+                };
+
+                position_index
+            }
+        };
+
+        Ok(&mut self.positions[position_index])
+    }
+
+    /// Returns the index of an open order by its order id
+    pub fn get_order_index(&self, order_id: u32) -> SpedXSpotResult<usize> {
+        self.orders
+            .iter()
+            .position(|order| order.order_id == order_id && order.order_status == OrderStatus::Active)
+            .ok_or(ErrorCode::OrderDoesNotExist)
+    }
+
+    /// Returns an open order by its order id
+    pub fn get_order(&self, order_id: u32) -> Option<&Order> {
+        self.orders
+            .iter()
+            .find(|order| order.order_id == order_id && order.order_status == OrderStatus::Active)
+    }
+
+    /// Returns the index of the first free order slot
+    pub fn get_next_order_index(&self) -> SpedXSpotResult<usize> {
+        self.orders
+            .iter()
+            .position(|order| order.order_status != OrderStatus::Active)
+            .ok_or(ErrorCode::MaxNumberOfOrders)
+    }
+
+    /// Returns the order id for the next order and increments it. Order ids start at 1 and wrap around to 1.
+    pub fn get_next_order_id(&mut self) -> u32 {
+        let order_id = self.next_order_id.max(1);
+        self.next_order_id = order_id.checked_add(1).unwrap_or(1);
+        order_id
+    }
+
+    /// Returns the lowest one-cancels-other group id that none of the user's open orders belongs to
+    pub fn get_next_oco_group_id(&self) -> SpedXSpotResult<u8> {
+        (1..=u8::MAX)
+            .find(|oco_group_id| !self.orders.iter().any(|order| order.is_in_oco_group(*oco_group_id)))
+            .ok_or(ErrorCode::MaxNumberOfOrders)
+    }
+
+    /// Returns true if the user is only allowed to reduce their positions
+    pub fn is_reduce_only(&self) -> bool {
+        self.status == UserStatus::ReduceOnly
+    }
+}
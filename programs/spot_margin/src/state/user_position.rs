@@ -44,7 +44,7 @@ pub struct Position {
     pub scaled_balance: u64,
 
     /// The size of the user's position. Represents the number of base tokens the user has bought in a position.
-    /// precision: BASE_PRECISION
+    /// precision: token mint
     pub base_asset_amount: i64,
 
     /// The amount used to value the base asset for a position. Calculated using (base_asset_amount*avg_entry_price)-applicable_fees
@@ -123,9 +123,9 @@ impl SpotBalance for Position {
 }
 
 impl Position {
-    /// Returns true if the user's balance for a market is 0 or he has no open orders
+    /// Returns true if the user's balance for a market is 0, he has no open orders and nothing traded is left unsettled
     pub fn is_empty(&self) -> bool {
-        self.scaled_balance == 0 && self.num_open_orders == 0
+        self.scaled_balance == 0 && self.num_open_orders == 0 && self.base_asset_amount == 0 && self.quote_asset_amount == 0
     }
 
     /// Returns true if the user has open orders in a market
@@ -210,6 +210,54 @@ impl Position {
         }
     }
 
+    /// Returns the position's net exposure to the market's token: the deposited(positive) or borrowed(negative) balance
+    /// plus the base asset amount traded on the book
+    /// precision: token mint precision
+    pub fn get_base_asset_exposure(&self, market: &Market) -> SpedXSpotResult<i64> {
+        self.get_token_amount_signed(market)?
+            .safe_add(self.base_asset_amount.cast()?)?
+            .cast()
+    }
+
+    /// Adds the unfilled size of an order to the position's open bids(positive) or open asks(negative)
+    pub fn increase_open_orders(&mut self, direction: PositionDirection, base_asset_amount: u64) -> SpedXSpotResult {
+        match direction {
+            PositionDirection::Short => self.open_asks = self.open_asks.safe_sub(base_asset_amount.cast()?)?,
+            _ => self.open_bids = self.open_bids.safe_add(base_asset_amount.cast()?)?
+        }
+        Ok(())
+    }
+
+    /// Removes filled or cancelled size of an order from the position's open bids or open asks
+    pub fn decrease_open_orders(&mut self, direction: PositionDirection, base_asset_amount: u64) -> SpedXSpotResult {
+        match direction {
+            PositionDirection::Short => self.open_asks = self.open_asks.safe_add(base_asset_amount.cast()?)?,
+            _ => self.open_bids = self.open_bids.safe_sub(base_asset_amount.cast()?)?
+        }
+        Ok(())
+    }
+
+    /// Applies a fill to the position. A bid increases the base asset amount and pays the quote asset amount,
+    /// an ask does the opposite.
+    pub fn apply_fill(
+        &mut self,
+        direction: PositionDirection,
+        base_asset_amount: u64,
+        quote_asset_amount: u64
+    ) -> SpedXSpotResult {
+        match direction {
+            PositionDirection::Short => {
+                self.base_asset_amount = self.base_asset_amount.safe_sub(base_asset_amount.cast()?)?;
+                self.quote_asset_amount = self.quote_asset_amount.safe_add(quote_asset_amount.cast()?)?;
+            },
+            _ => {
+                self.base_asset_amount = self.base_asset_amount.safe_add(base_asset_amount.cast()?)?;
+                self.quote_asset_amount = self.quote_asset_amount.safe_sub(quote_asset_amount.cast()?)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Function to get the direction of the position. If base asset amount is greater than 0, it indicates a long position, and vice versa.
     /// If the position is of two-way type, it must have a corresponding opposite position, hence the base asset amount is 0.
     pub fn get_position_direction(&self) -> PositionDirection {